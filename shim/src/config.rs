@@ -12,6 +12,21 @@ pub const OS_MEMTRIM_LIMIT: usize = 200000000;
 /// Minimum size before a block is worthy to memtrim.
 pub const OS_MEMTRIM_WORTHY: usize = 4000;
 
+/// The default decay interval, in ticks of the decay clock.
+///
+/// Free pages of the global allocator, which are not reused for this long, are given back to the
+/// OS (see `ralloc::set_decay`).
+pub const DECAY_TICKS: usize = 10;
+/// The number of operations between two ticks of the decay clock driven by them.
+///
+/// The housekeeping ticks the clock too, so this only matters without it.
+pub const DECAY_EVENTS: usize = 1 << 16;
+
+/// The default length of the windows of the heap growth governor, in milliseconds.
+///
+/// See `ralloc::set_growth_governor`.
+pub const GROWTH_WINDOW: usize = 1000;
+
 /// The fragmentation scale constant.
 ///
 /// This is used for determining the minimum avarage block size before locally memtrimming.
@@ -20,20 +35,134 @@ pub const FRAGMENTATION_SCALE: usize = 10;
 ///
 /// Whenever an local allocator has more free bytes than this value, it will be memtrimmed.
 pub const LOCAL_MEMTRIM_LIMIT: usize = 16384;
+/// The local memtrim block limit.
+///
+/// Whenever an local allocator has more free blocks than this value, it will be memtrimmed.
+pub const LOCAL_MEMTRIM_BLOCKS: usize = 256;
 /// The local memtrim chock.
 ///
 /// The local memtrimming will continue until the allocator has less memory (in bytes, of course)
 /// than this value.
 pub const LOCAL_MEMTRIM_STOP: usize = 1024;
 
+/// The minimum split.
+///
+/// When allocating through `alloc_excess`, remainders of the free block smaller than this are
+/// handed out along with the buffer, instead of being kept in the pool.
+pub const MIN_SPLIT: usize = 64;
+
+/// The size of the address segments (as a power of two).
+///
+/// The block pool is partitioned into segments of `1 << SEGMENT_SHIFT` bytes, each keeping its
+/// own list of free blocks. Smaller segments means shorter lists, but more metadata.
+pub const SEGMENT_SHIFT: usize = 20;
+
+/// The number of operations between two full consistency checks.
+///
+/// In debug mode, every operation on a bookkeeper checks the entries it touched, and every
+/// `FULL_CHECK_INTERVAL` operations, the whole pool is checked. Lower values catch corruption
+/// closer to its cause, but slow down debug builds on big heaps.
+pub const FULL_CHECK_INTERVAL: usize = 256;
+
+/// The minimum size of the chunks of the metadata arena.
+///
+/// The bookkeepers' own structures are carved from chunks of (at least) this size, mapped in
+/// their own region.
+pub const META_CHUNK_SIZE: usize = 65536;
+
+/// The size of the buffer of the static pool storage.
+///
+/// Pools using `StaticStorage` take their metadata from a buffer of this size in the data
+/// segment, instead of mapping it. This must be a multiple of eight.
+pub const STATIC_META_SIZE: usize = 65536;
+
+/// The size of the range reserved for an emulated program break.
+///
+/// Where there is no program break (or with the `brk_emulation` feature), the break is emulated
+/// over a range of address space of this size, reserved up front, and committed as it grows.
+#[cfg(target_pointer_width = "64")]
+pub const EMULATED_BRK_SIZE: usize = 1 << 36;
+/// The size of the range reserved for an emulated program break.
+///
+/// Where there is no program break (or with the `brk_emulation` feature), the break is emulated
+/// over a range of address space of this size, reserved up front, and committed as it grows.
+#[cfg(not(target_pointer_width = "64"))]
+pub const EMULATED_BRK_SIZE: usize = 1 << 28;
+
+/// The number of objects carved at once, when a fast bin runs dry.
+pub const FAST_BIN_REFILL: usize = 64;
+
+/// The maximum number of times a futex lock is tried, before the thread is parked.
+///
+/// The locks spin up to twice their recent average, but no more than this.
+pub const LOCK_SPINS: usize = 100;
+
+/// The page size.
+///
+/// This is the granularity of the guard pages around the metadata.
+pub const PAGE_SIZE: usize = 4096;
+
+/// The default size above which the hybrid breaker maps fresh memory.
+///
+/// Smaller requests extend the program break instead.
+pub const MMAP_THRESHOLD: usize = 1 << 20;
+
+/// The capacity of the free list of a shared arena.
+///
+/// The free list lives in the shared segment, so its capacity is fixed when the arena is created.
+/// If it fills up, freed memory is leaked.
+pub const SHARED_RANGES: usize = 1024;
+
+/// The number of fitting blocks to randomly choose among.
+///
+/// With the `randomize` feature, allocations are placed in a random one of the first
+/// `RANDOM_CANDIDATES` fitting blocks. More candidates means less predictable heap layouts, but
+/// more fragmentation.
+pub const RANDOM_CANDIDATES: usize = 8;
+
+/// The average number of allocations between two sampled allocations.
+///
+/// With the `sampling` feature, a random one in `SAMPLE_RATE` allocations is served from a
+/// guarded slot, catching overflows and use-after-frees of it.
+pub const SAMPLE_RATE: usize = 5000;
+/// The number of guarded slots for sampled allocations.
+///
+/// This bounds the number of sampled allocations alive at a time.
+pub const SAMPLE_SLOTS: usize = 16;
+
+/// The number of allocations per recorded stack trace.
+///
+/// With the `leak_tracking` feature, the stack trace of one in `LEAK_SAMPLE_RATE` allocations is
+/// recorded, until it is freed. Raising it lowers the overhead, but makes the attribution coarser.
+pub const LEAK_SAMPLE_RATE: usize = 1;
+
+/// The number of frozen ranges.
+///
+/// This bounds the number of allocations frozen (made read-only) at a time.
+pub const FROZEN_RANGES: usize = 64;
+
+/// The alignment of the regions taken from the platform allocator.
+///
+/// This is merely a hint, as the arenas align their blocks themselves.
+pub const SYSTEM_ALIGN: usize = 16;
+
+/// The byte filling fresh buffers of the `Poison` layer.
+pub const POISON_ALLOC: u8 = 0xAA;
+/// The byte filling freed buffers of the `Poison` layer.
+pub const POISON_FREE: u8 = 0xDD;
+/// The number of freed buffers kept by the `Quarantine` layer.
+pub const QUARANTINE_LEN: usize = 64;
+
 /// The minimum log level.
 pub const MIN_LOG_LEVEL: u8 = 0;
 
 /// The default OOM handler.
+///
+/// This is called after the cause of the failure has been logged.
 #[cold]
 pub fn default_oom_handler() -> ! {
     // Log some message.
-    log("\x1b[31;1mThe application ran out of memory. Aborting.\x1b[m\n");
+    log("\x1b[31;1mThe application failed to allocate memory. Aborting.\x1b[m\n");
 
     unsafe {
         intrinsics::abort();
@@ -43,7 +172,7 @@ pub fn default_oom_handler() -> ! {
 /// Write to the log.
 ///
 /// This points to stderr, but could be changed arbitrarily.
-#[cfg(not(target_os = "redox"))]
+#[cfg(all(not(target_os = "redox"), not(feature = "enclave"), not(feature = "seccomp")))]
 pub fn log(s: &str) -> usize {
     unsafe { syscall!(WRITE, 2, s.as_ptr(), s.len()) }
 }
@@ -51,11 +180,20 @@ pub fn log(s: &str) -> usize {
 /// Write to the log.
 ///
 /// This points to stderr, but could be changed arbitrarily.
-#[cfg(target_os = "redox")]
+#[cfg(all(target_os = "redox", not(feature = "enclave"), not(feature = "seccomp")))]
 pub fn log(s: &str) -> usize {
     ::syscall::write(2, s.as_bytes()).unwrap_or(!0)
 }
 
+/// Write to the log.
+///
+/// An enclave has no stderr (and makes no syscalls), and a seccomp filter might not allow
+/// `write`, so the log is discarded.
+#[cfg(any(feature = "enclave", feature = "seccomp"))]
+pub fn log(s: &str) -> usize {
+    s.len()
+}
+
 /// Canonicalize a fresh allocation.
 ///
 /// The return value specifies how much _more_ space is requested to the fresh allocator.
@@ -0,0 +1,51 @@
+//! Environment variables.
+//!
+//! The environment is read directly from `environ`, as the libc functions give no guarantees
+//! about allocations.
+
+/// The environment of the process, if linked with a libc.
+#[cfg(not(target_os = "redox"))]
+extern {
+    #[linkage = "extern_weak"]
+    static environ: *const *const *const u8;
+}
+
+/// Get the value of an environment variable.
+///
+/// `None` is returned if the variable is not set, or the environment is unavailable.
+#[cfg(not(target_os = "redox"))]
+pub fn var(name: &str) -> Option<&'static [u8]> {
+    unsafe {
+        // The weak symbol is null, if there is no `environ`.
+        if environ.is_null() || (*environ).is_null() {
+            return None;
+        }
+
+        // The environment is a null-terminated array of null-terminated `NAME=value` strings.
+        let mut entry = *environ;
+        while !(*entry).is_null() {
+            let mut len = 0;
+            while *(*entry).offset(len) != 0 {
+                len += 1;
+            }
+
+            let var = ::core::slice::from_raw_parts(*entry, len as usize);
+            if var.len() > name.len() && var.starts_with(name.as_bytes())
+               && var[name.len()] == b'=' {
+                return Some(&var[name.len() + 1..]);
+            }
+
+            entry = entry.offset(1);
+        }
+    }
+
+    None
+}
+
+/// Get the value of an environment variable.
+///
+/// The environment is unavailable on Redox, so this always returns `None`.
+#[cfg(target_os = "redox")]
+pub fn var(_: &str) -> Option<&'static [u8]> {
+    None
+}
@@ -0,0 +1,37 @@
+//! The global variables of the program.
+//!
+//! Their region is found through the symbols defined by the linker and the C runtime. These are
+//! linked weakly, so where they are missing, the region is simply unknown.
+
+#[cfg(not(target_os = "redox"))]
+extern {
+    /// The start of the initialized data.
+    #[linkage = "extern_weak"]
+    static __data_start: *const u8;
+    /// The end of the uninitialized data.
+    #[linkage = "extern_weak"]
+    static _end: *const u8;
+}
+
+/// Get the region holding the global variables (`.data` and `.bss`).
+///
+/// `None` is returned, if the region is unknown.
+#[cfg(not(target_os = "redox"))]
+pub fn region() -> Option<(*const u8, usize)> {
+    unsafe {
+        // The weak symbols are null, if they are not defined.
+        if __data_start.is_null() || _end.is_null() || _end < __data_start {
+            None
+        } else {
+            Some((__data_start, _end as usize - __data_start as usize))
+        }
+    }
+}
+
+/// Get the region holding the global variables.
+///
+/// This is unknown on Redox.
+#[cfg(target_os = "redox")]
+pub fn region() -> Option<(*const u8, usize)> {
+    None
+}
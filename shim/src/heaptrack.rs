@@ -0,0 +1,57 @@
+//! The report API of heaptrack.
+//!
+//! Custom allocators report their operations to heaptrack through `heaptrack_report_alloc`,
+//! `heaptrack_report_realloc` and `heaptrack_report_free` (see `heaptrack_api.h`), which the
+//! heaptrack library defines, when it is preloaded or injected into the process. The symbols are
+//! linked weakly, so the reports are dropped, when heaptrack is not attached.
+
+use core::mem;
+
+extern {
+    #[linkage = "extern_weak"]
+    static heaptrack_report_alloc: *const u8;
+    #[linkage = "extern_weak"]
+    static heaptrack_report_realloc: *const u8;
+    #[linkage = "extern_weak"]
+    static heaptrack_report_free: *const u8;
+}
+
+/// Report an allocation of `size` bytes at `ptr`.
+pub fn report_alloc(ptr: *mut u8, size: usize) {
+    /// The signature of `heaptrack_report_alloc`.
+    type ReportAlloc = unsafe extern fn(ptr: *mut u8, size: usize);
+
+    unsafe {
+        // The weak symbol is null, if heaptrack is not attached.
+        if !heaptrack_report_alloc.is_null() {
+            mem::transmute::<*const u8, ReportAlloc>(heaptrack_report_alloc)(ptr, size);
+        }
+    }
+}
+
+/// Report the buffer at `old_ptr` being reallocated to `size` bytes at `ptr`.
+pub fn report_realloc(old_ptr: *mut u8, size: usize, ptr: *mut u8) {
+    /// The signature of `heaptrack_report_realloc`.
+    type ReportRealloc = unsafe extern fn(ptr_in: *mut u8, size: usize, ptr_out: *mut u8);
+
+    unsafe {
+        // The weak symbol is null, if heaptrack is not attached.
+        if !heaptrack_report_realloc.is_null() {
+            mem::transmute::<*const u8, ReportRealloc>(heaptrack_report_realloc)(old_ptr, size,
+                                                                                 ptr);
+        }
+    }
+}
+
+/// Report the buffer at `ptr` being freed.
+pub fn report_free(ptr: *mut u8) {
+    /// The signature of `heaptrack_report_free`.
+    type ReportFree = unsafe extern fn(ptr: *mut u8);
+
+    unsafe {
+        // The weak symbol is null, if heaptrack is not attached.
+        if !heaptrack_report_free.is_null() {
+            mem::transmute::<*const u8, ReportFree>(heaptrack_report_free)(ptr);
+        }
+    }
+}
@@ -18,7 +18,17 @@ extern crate sc;
 #[cfg(target_os = "redox")]
 extern crate syscall;
 
+/// The `log` crate facade, which ralloc's log can be routed to.
+#[cfg(feature = "log")]
+pub extern crate log;
+
 pub mod config;
+pub mod env;
+pub mod globals;
+pub mod heaptrack;
 pub mod thread_destructor;
 pub mod debug;
 pub mod syscalls;
+pub mod system;
+#[cfg(target_os = "fuchsia")]
+pub mod zircon;
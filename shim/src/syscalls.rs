@@ -1,4 +1,7 @@
 //! System calls.
+//!
+//! With the `seccomp` feature, only `brk`, `mmap` and `munmap` are compiled in. The other wrappers
+//! fail (or do nothing) without making a syscall, so ralloc cannot trip a tight seccomp filter.
 
 /// Change the data segment. See `man brk`.
 ///
@@ -7,13 +10,25 @@
 /// # Note
 ///
 /// This is the `brk` **syscall**, not the library function.
-#[cfg(not(target_os = "redox"))]
+#[cfg(not(any(target_os = "redox", target_os = "freebsd", target_os = "netbsd",
+              target_os = "openbsd", target_os = "fuchsia")))]
 pub unsafe fn brk(ptr: *const u8) -> *const u8 {
     syscall!(BRK, ptr) as *const u8
 }
 
+/// Change the data segment.
+///
+/// The `break` syscall of the BSDs doesn't report the program break (and is gone on newer
+/// architectures), so there is no usable break, and null is returned. ralloc emulates the break
+/// over `mmap` then (see the `brk` module of ralloc). Fuchsia has no break at all.
+#[cfg(any(target_os = "freebsd", target_os = "netbsd", target_os = "openbsd",
+          target_os = "fuchsia"))]
+pub unsafe fn brk(_: *const u8) -> *const u8 {
+    ::core::ptr::null()
+}
+
 /// Voluntarily give a time slice to the scheduler.
-#[cfg(not(target_os = "redox"))]
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
 pub fn sched_yield() -> usize {
     unsafe { syscall!(SCHED_YIELD) }
 }
@@ -36,3 +51,595 @@ pub unsafe fn brk(ptr: *const u8) -> *const u8 {
 pub fn sched_yield() -> usize {
     ::syscall::Error::mux(::syscall::sched_yield())
 }
+
+/// Get the time of the monotonic clock in nanoseconds. See `man clock_gettime`.
+///
+/// The clock starts at some arbitrary point. On failure, zero is returned.
+#[cfg(all(target_os = "linux", not(feature = "seccomp")))]
+pub fn clock_monotonic() -> u64 {
+    /// The monotonic clock.
+    const CLOCK_MONOTONIC: usize = 1;
+
+    // The seconds and the nanoseconds.
+    let mut time = [0isize; 2];
+    unsafe {
+        if syscall!(CLOCK_GETTIME, CLOCK_MONOTONIC, time.as_mut_ptr()) != 0 {
+            return 0;
+        }
+    }
+
+    time[0] as u64 * 1_000_000_000 + time[1] as u64
+}
+
+/// Get the time of the monotonic clock in nanoseconds.
+///
+/// The clock starts at some arbitrary point. On failure, zero is returned.
+#[cfg(target_os = "redox")]
+pub fn clock_monotonic() -> u64 {
+    let mut time = ::syscall::TimeSpec::default();
+    match ::syscall::clock_gettime(::syscall::CLOCK_MONOTONIC, &mut time) {
+        Ok(_) => time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64,
+        Err(_) => 0,
+    }
+}
+
+/// Get the time of the monotonic clock in nanoseconds.
+///
+/// The clock is not supported here (or, with the `seccomp` feature, not allowed), so this always
+/// returns zero.
+#[cfg(not(any(all(target_os = "linux", not(feature = "seccomp")), target_os = "redox")))]
+pub fn clock_monotonic() -> u64 {
+    0
+}
+
+/// Wait on a futex, while it holds `expected`. See `man futex`.
+///
+/// The wait might end spuriously, so the futex must be checked again.
+#[cfg(all(target_os = "linux", not(feature = "seccomp")))]
+pub unsafe fn futex_wait(addr: *const u32, expected: u32) {
+    /// Wait, where the futex is private to the process.
+    const FUTEX_WAIT_PRIVATE: usize = 128;
+
+    syscall!(FUTEX, addr, FUTEX_WAIT_PRIVATE, expected as usize, 0);
+}
+
+/// Wake up to `n` threads waiting on a futex. See `man futex`.
+#[cfg(all(target_os = "linux", not(feature = "seccomp")))]
+pub unsafe fn futex_wake(addr: *const u32, n: usize) {
+    /// Wake, where the futex is private to the process.
+    const FUTEX_WAKE_PRIVATE: usize = 129;
+
+    syscall!(FUTEX, addr, FUTEX_WAKE_PRIVATE, n);
+}
+
+/// Wait on a futex, while it holds `expected`.
+///
+/// The wait might end spuriously, so the futex must be checked again.
+#[cfg(target_os = "redox")]
+pub unsafe fn futex_wait(addr: *const u32, expected: u32) {
+    let _ = ::syscall::futex(addr as *mut i32, ::syscall::FUTEX_WAIT, expected as i32, 0,
+                             0 as *mut i32);
+}
+
+/// Wake up to `n` threads waiting on a futex.
+#[cfg(target_os = "redox")]
+pub unsafe fn futex_wake(addr: *const u32, n: usize) {
+    let _ = ::syscall::futex(addr as *mut i32, ::syscall::FUTEX_WAKE, n as i32, 0, 0 as *mut i32);
+}
+
+/// Wait on a futex, while it holds `expected`.
+///
+/// There are no futexes here, so this yields the time slice instead, and the wait ends
+/// spuriously.
+#[cfg(not(any(all(target_os = "linux", not(feature = "seccomp")), target_os = "redox")))]
+pub unsafe fn futex_wait(_: *const u32, _: u32) {
+    sched_yield();
+}
+
+/// Wake up to `n` threads waiting on a futex.
+///
+/// There are no futexes here, so this is NOOP (the waits end on their own).
+#[cfg(not(any(all(target_os = "linux", not(feature = "seccomp")), target_os = "redox")))]
+pub unsafe fn futex_wake(_: *const u32, _: usize) {}
+
+/// Pages may not be accessed.
+pub const PROT_NONE: usize = 0;
+/// Pages may be read.
+pub const PROT_READ: usize = 1;
+/// Pages may be written.
+pub const PROT_WRITE: usize = 2;
+/// Pages have their memory tags checked (AArch64 MTE).
+pub const PROT_MTE: usize = 0x20;
+
+/// The error number of unsupported functions.
+#[cfg(target_os = "redox")]
+const ENOSYS: usize = ::syscall::ENOSYS as usize;
+/// The error number of unsupported functions.
+#[cfg(all(not(target_os = "redox"), not(target_os = "linux")))]
+const ENOSYS: usize = 78;
+/// The error number of unsupported functions.
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+const ENOSYS: usize = 38;
+
+/// Resolve relative paths from the working directory.
+#[cfg(all(not(target_os = "redox"), target_os = "linux"))]
+const AT_FDCWD: usize = -100isize as usize;
+/// Resolve relative paths from the working directory.
+#[cfg(all(not(target_os = "redox"), not(target_os = "linux")))]
+const AT_FDCWD: usize = -2isize as usize;
+/// Open for reading and writing.
+#[cfg(not(target_os = "redox"))]
+const O_RDWR: usize = 2;
+/// Create the file, if it doesn't exist.
+#[cfg(all(not(target_os = "redox"), target_os = "linux"))]
+const O_CREAT: usize = 0o100;
+/// Create the file, if it doesn't exist.
+#[cfg(all(not(target_os = "redox"), not(target_os = "linux")))]
+const O_CREAT: usize = 0x200;
+/// Close the file on `exec`.
+#[cfg(all(not(target_os = "redox"), target_os = "linux"))]
+const O_CLOEXEC: usize = 0o2000000;
+/// Close the file on `exec`.
+#[cfg(all(not(target_os = "redox"), not(target_os = "linux")))]
+const O_CLOEXEC: usize = 0x1000000;
+
+/// The mapping is shared with other processes mapping the same file.
+#[cfg(not(target_os = "redox"))]
+const MAP_SHARED: usize = 1;
+/// The mapping is private (copy-on-write).
+#[cfg(not(target_os = "redox"))]
+const MAP_PRIVATE: usize = 2;
+/// The mapping is not backed by any file.
+#[cfg(all(not(target_os = "redox"), target_os = "linux"))]
+const MAP_ANONYMOUS: usize = 0x20;
+/// The mapping is not backed by any file.
+#[cfg(all(not(target_os = "redox"), not(target_os = "linux")))]
+const MAP_ANONYMOUS: usize = 0x1000;
+
+/// Do not reserve swap space for the mapping.
+#[cfg(all(not(target_os = "redox"), target_os = "linux"))]
+const MAP_NORESERVE: usize = 0x4000;
+/// Do not reserve swap space for the mapping.
+///
+/// FreeBSD rejects the flag, and OpenBSD lacks it, as neither reserves swap space anyway.
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+const MAP_NORESERVE: usize = 0;
+/// Do not reserve swap space for the mapping.
+#[cfg(all(not(target_os = "redox"), not(target_os = "linux"), not(target_os = "freebsd"),
+          not(target_os = "openbsd")))]
+const MAP_NORESERVE: usize = 0x40;
+
+/// Exclude the mapping from core dumps (and, on OpenBSD, from `fork`ed children).
+#[cfg(target_os = "openbsd")]
+const MAP_CONCEAL: usize = 0x8000;
+/// Exclude the mapping from core dumps (`MAP_NOCORE`).
+#[cfg(target_os = "freebsd")]
+const MAP_CONCEAL: usize = 0x20000;
+/// Exclude the mapping from core dumps.
+///
+/// There is no such flag elsewhere. On Linux, the pages are advised not to be dumped instead.
+#[cfg(all(not(target_os = "redox"), not(target_os = "freebsd"), not(target_os = "openbsd")))]
+const MAP_CONCEAL: usize = 0;
+/// Exclude the pages from core dumps.
+#[cfg(target_os = "linux")]
+const MADV_DONTDUMP: usize = 16;
+
+/// Map some anonymous, private, readable and writable memory. See `man mmap`.
+///
+/// On success, the start of the mapping is returned. On failure, the error number is returned.
+#[cfg(not(target_os = "redox"))]
+pub unsafe fn mmap(size: usize) -> Result<*mut u8, usize> {
+    let res = syscall!(MMAP, 0, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, !0usize, 0);
+
+    // Errors are returned as negated error numbers.
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res as *mut u8) }
+}
+
+/// Map some anonymous, private, readable and writable memory, concealed from core dumps.
+///
+/// This is meant for memory, which must not leak through a crash (e.g. the metadata of the
+/// allocator). Where the platform cannot conceal memory, it is mapped as by `mmap`. On success,
+/// the start of the mapping is returned. On failure, the error number is returned.
+#[cfg(not(target_os = "redox"))]
+pub unsafe fn mmap_concealed(size: usize) -> Result<*mut u8, usize> {
+    let res = syscall!(MMAP, 0, size, PROT_READ | PROT_WRITE,
+                       MAP_PRIVATE | MAP_ANONYMOUS | MAP_CONCEAL, !0usize, 0);
+
+    // Errors are returned as negated error numbers.
+    if res > !4095 {
+        return Err(res.wrapping_neg());
+    }
+
+    // The mapping is usable, even if the advice is not taken.
+    #[cfg(target_os = "linux")]
+    let _ = madvise(res as *mut u8, size, MADV_DONTDUMP);
+
+    Ok(res as *mut u8)
+}
+
+/// Reserve some address space, without committing any memory to it. See `man mmap`.
+///
+/// The pages are inaccessible, until they are committed by making them readable and writable
+/// (see `mprotect`). On success, the start of the reservation is returned. On failure, the error
+/// number is returned.
+#[cfg(not(target_os = "redox"))]
+pub unsafe fn reserve(size: usize) -> Result<*mut u8, usize> {
+    let res = syscall!(MMAP, 0, size, PROT_NONE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE,
+                       !0usize, 0);
+
+    // Errors are returned as negated error numbers.
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res as *mut u8) }
+}
+
+/// Create an anonymous file, which can be shared between processes. See `man memfd_create`.
+///
+/// `name` must be null-terminated. On success, the file descriptor is returned. On failure, the
+/// error number is returned.
+#[cfg(all(target_os = "linux", not(feature = "seccomp")))]
+pub unsafe fn memfd_create(name: &[u8]) -> Result<usize, usize> {
+    let res = syscall!(MEMFD_CREATE, name.as_ptr(), 0);
+
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res) }
+}
+
+/// Open a file for reading and writing, creating it if it doesn't exist. See `man open`.
+///
+/// `path` must be null-terminated. On success, the file descriptor is returned. On failure, the
+/// error number is returned.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn open(path: &[u8]) -> Result<usize, usize> {
+    let res = syscall!(OPENAT, AT_FDCWD, path.as_ptr(), O_RDWR | O_CREAT | O_CLOEXEC, 0o600);
+
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res) }
+}
+
+/// Get the size of a file. See `man lseek`.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn file_size(fd: usize) -> Result<usize, usize> {
+    /// Seek relative to the end of the file.
+    const SEEK_END: usize = 2;
+
+    let res = syscall!(LSEEK, fd, 0, SEEK_END);
+
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res) }
+}
+
+/// Flush a mapping to its file. See `man msync`.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn msync(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    /// Flush synchronously.
+    const MS_SYNC: usize = 4;
+
+    if syscall!(MSYNC, ptr, size, MS_SYNC) == 0 { Ok(()) } else { Err(()) }
+}
+
+/// Set the size of a file. See `man ftruncate`.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn ftruncate(fd: usize, size: usize) -> Result<(), usize> {
+    let res = syscall!(FTRUNCATE, fd, size);
+
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(()) }
+}
+
+/// Write to a file descriptor. See `man write`.
+///
+/// On success, the number of bytes written is returned. On failure, the error number is
+/// returned.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn write(fd: usize, buf: &[u8]) -> Result<usize, usize> {
+    let res = syscall!(WRITE, fd, buf.as_ptr(), buf.len());
+
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res) }
+}
+
+/// Close a file descriptor. See `man close`.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn close(fd: usize) {
+    syscall!(CLOSE, fd);
+}
+
+/// Map a file into memory, shared with the other processes mapping it. See `man mmap`.
+///
+/// On success, the start of the mapping is returned. On failure, the error number is returned.
+#[cfg(not(target_os = "redox"))]
+pub unsafe fn mmap_shared(fd: usize, size: usize) -> Result<*mut u8, usize> {
+    let res = syscall!(MMAP, 0, size, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+
+    if res > !4095 { Err(res.wrapping_neg()) } else { Ok(res as *mut u8) }
+}
+
+/// No special treatment.
+pub const MADV_NORMAL: usize = 0;
+/// Expect random page references.
+pub const MADV_RANDOM: usize = 1;
+/// Expect sequential page references.
+pub const MADV_SEQUENTIAL: usize = 2;
+/// Expect access in the near future.
+pub const MADV_WILLNEED: usize = 3;
+/// Do not expect access in the near future (the content of anonymous pages is discarded).
+pub const MADV_DONTNEED: usize = 4;
+/// The pages may be reclaimed lazily, when memory runs low (the content is discarded then).
+#[cfg(not(any(target_os = "freebsd", target_os = "macos", target_os = "netbsd",
+              target_os = "openbsd")))]
+pub const MADV_FREE: usize = 8;
+/// The pages may be reclaimed lazily, when memory runs low (the content is discarded then).
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+pub const MADV_FREE: usize = 5;
+/// The pages may be reclaimed lazily, when memory runs low (the content is discarded then).
+#[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+pub const MADV_FREE: usize = 6;
+
+/// Give advice about the use of some pages. See `man madvise`.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn madvise(ptr: *mut u8, size: usize, advice: usize) -> Result<(), ()> {
+    if syscall!(MADVISE, ptr, size, advice) == 0 { Ok(()) } else { Err(()) }
+}
+
+/// Unmap some pages. See `man munmap`.
+#[cfg(not(target_os = "redox"))]
+pub unsafe fn munmap(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    if syscall!(MUNMAP, ptr, size) == 0 { Ok(()) } else { Err(()) }
+}
+
+/// Change the protection of some pages. See `man mprotect`.
+#[cfg(all(not(target_os = "redox"), not(feature = "seccomp")))]
+pub unsafe fn mprotect(ptr: *mut u8, size: usize, prot: usize) -> Result<(), ()> {
+    if syscall!(MPROTECT, ptr, size, prot) == 0 { Ok(()) } else { Err(()) }
+}
+
+/// Enable memory tag checking (AArch64 MTE) for the current thread. See `man prctl`.
+///
+/// Tagged pointers are accepted by the kernel, and tag check faults are reported synchronously.
+/// Threads spawned afterwards inherit this.
+#[cfg(all(target_os = "linux", target_arch = "aarch64", not(feature = "seccomp")))]
+pub unsafe fn enable_mte() -> Result<(), ()> {
+    /// Set the tagged address control.
+    const PR_SET_TAGGED_ADDR_CTRL: usize = 55;
+    /// Accept tagged addresses in syscalls.
+    const PR_TAGGED_ADDR_ENABLE: usize = 1;
+    /// Report tag check faults synchronously.
+    const PR_MTE_TCF_SYNC: usize = 1 << 1;
+    /// The tags generated by `irg` (all but zero).
+    const PR_MTE_TAG_MASK: usize = 0xfffe << 3;
+
+    let flags = PR_TAGGED_ADDR_ENABLE | PR_MTE_TCF_SYNC | PR_MTE_TAG_MASK;
+    if syscall!(PRCTL, PR_SET_TAGGED_ADDR_CTRL, flags, 0, 0, 0) == 0 { Ok(()) } else { Err(()) }
+}
+
+/// The information passed to a signal handler. See `man sigaction`.
+///
+/// Only the leading fields are included.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+#[repr(C)]
+pub struct SigInfo {
+    /// The signal number.
+    pub signo: i32,
+    /// The error number.
+    pub errno: i32,
+    /// The signal code.
+    pub code: i32,
+    /// Padding.
+    _pad: i32,
+    /// The faulting address (for `SIGSEGV`).
+    pub addr: usize,
+}
+
+/// A handler of segmentation faults.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub type FaultHandler = unsafe extern fn(i32, *const SigInfo, *mut u8);
+
+/// Set the handler of segmentation faults. See `man sigaction`.
+///
+/// If `handler` is `None`, the default action (terminating) is restored. The handler must not
+/// return (except after restoring the default action), as no signal trampoline is provided.
+#[cfg(all(target_os = "linux", target_pointer_width = "64", not(feature = "seccomp")))]
+pub unsafe fn set_fault_handler(handler: Option<FaultHandler>) -> Result<(), ()> {
+    /// The segmentation fault signal.
+    const SIGSEGV: usize = 11;
+    /// Pass a `SigInfo` to the handler.
+    const SA_SIGINFO: u64 = 4;
+
+    /// The kernel's `struct sigaction`.
+    #[repr(C)]
+    struct SigAction {
+        handler: usize,
+        flags: u64,
+        restorer: usize,
+        mask: u64,
+    }
+
+    let action = SigAction {
+        handler: handler.map_or(0, |handler| handler as usize),
+        flags: SA_SIGINFO,
+        restorer: 0,
+        mask: 0,
+    };
+
+    if syscall!(RT_SIGACTION, SIGSEGV, &action as *const SigAction, 0, 8) == 0 {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+/// Map some anonymous, private, readable and writable memory.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn mmap(_: usize) -> Result<*mut u8, usize> {
+    Err(ENOSYS)
+}
+
+/// Map some anonymous, private, readable and writable memory, concealed from core dumps.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn mmap_concealed(_: usize) -> Result<*mut u8, usize> {
+    Err(ENOSYS)
+}
+
+/// Reserve some address space, without committing any memory to it.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn reserve(_: usize) -> Result<*mut u8, usize> {
+    Err(ENOSYS)
+}
+
+/// Create an anonymous file, which can be shared between processes.
+///
+/// This is only supported on Linux (without the `seccomp` feature), and always fails (with
+/// `ENOSYS`) elsewhere.
+#[cfg(any(not(target_os = "linux"), feature = "seccomp"))]
+pub unsafe fn memfd_create(_: &[u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Open a file for reading and writing, creating it if it doesn't exist.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn open(_: &[u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Get the size of a file.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn file_size(_: usize) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Flush a mapping to its file.
+///
+/// This is not supported on Redox, and always fails.
+#[cfg(target_os = "redox")]
+pub unsafe fn msync(_: *mut u8, _: usize) -> Result<(), ()> {
+    Err(())
+}
+
+/// Set the size of a file.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn ftruncate(_: usize, _: usize) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Write to a file descriptor.
+///
+/// On success, the number of bytes written is returned. On failure, the error number is
+/// returned.
+#[cfg(target_os = "redox")]
+pub unsafe fn write(fd: usize, buf: &[u8]) -> Result<usize, usize> {
+    ::syscall::write(fd, buf).map_err(|err| err.errno as usize)
+}
+
+/// Close a file descriptor.
+#[cfg(target_os = "redox")]
+pub unsafe fn close(fd: usize) {
+    let _ = ::syscall::close(fd);
+}
+
+/// Map a file into memory, shared with the other processes mapping it.
+///
+/// This is not supported on Redox, and always fails (with `ENOSYS`).
+#[cfg(target_os = "redox")]
+pub unsafe fn mmap_shared(_: usize, _: usize) -> Result<*mut u8, usize> {
+    Err(ENOSYS)
+}
+
+/// Give advice about the use of some pages.
+///
+/// This is not supported on Redox, and always fails.
+#[cfg(target_os = "redox")]
+pub unsafe fn madvise(_: *mut u8, _: usize, _: usize) -> Result<(), ()> {
+    Err(())
+}
+
+/// Unmap some pages.
+///
+/// This is not supported on Redox, and always fails.
+#[cfg(target_os = "redox")]
+pub unsafe fn munmap(_: *mut u8, _: usize) -> Result<(), ()> {
+    Err(())
+}
+
+/// Change the protection of some pages.
+///
+/// This is not supported on Redox, and always fails.
+#[cfg(target_os = "redox")]
+pub unsafe fn mprotect(_: *mut u8, _: usize, _: usize) -> Result<(), ()> {
+    Err(())
+}
+
+/// Voluntarily give a time slice to the scheduler.
+///
+/// With the `seccomp` feature, this is NOOP (only `brk`, `mmap` and `munmap` are issued).
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub fn sched_yield() -> usize {
+    0
+}
+
+/// Open a file for reading and writing, creating it if it doesn't exist.
+///
+/// With the `seccomp` feature, this always fails (with `ENOSYS`).
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn open(_: &[u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Get the size of a file.
+///
+/// With the `seccomp` feature, this always fails (with `ENOSYS`).
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn file_size(_: usize) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Flush a mapping to its file.
+///
+/// With the `seccomp` feature, this always fails.
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn msync(_: *mut u8, _: usize) -> Result<(), ()> {
+    Err(())
+}
+
+/// Set the size of a file.
+///
+/// With the `seccomp` feature, this always fails (with `ENOSYS`).
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn ftruncate(_: usize, _: usize) -> Result<(), usize> {
+    Err(ENOSYS)
+}
+
+/// Write to a file descriptor.
+///
+/// With the `seccomp` feature, this always fails (with `ENOSYS`).
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn write(_: usize, _: &[u8]) -> Result<usize, usize> {
+    Err(ENOSYS)
+}
+
+/// Close a file descriptor.
+///
+/// With the `seccomp` feature, this is NOOP, as no file is ever opened.
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn close(_: usize) {}
+
+/// Give advice about the use of some pages.
+///
+/// With the `seccomp` feature, this always fails.
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn madvise(_: *mut u8, _: usize, _: usize) -> Result<(), ()> {
+    Err(())
+}
+
+/// Change the protection of some pages.
+///
+/// With the `seccomp` feature, this always fails.
+#[cfg(all(not(target_os = "redox"), feature = "seccomp"))]
+pub unsafe fn mprotect(_: *mut u8, _: usize, _: usize) -> Result<(), ()> {
+    Err(())
+}
@@ -0,0 +1,40 @@
+//! The platform allocator.
+//!
+//! Where the program break is unavailable (e.g. in some sandboxes), memory can be taken from the
+//! allocator of the C library instead. The symbols are linked weakly, so this fails gracefully,
+//! when no C library is linked.
+//!
+//! This must not be used, if ralloc itself provides `malloc`.
+
+use core::mem;
+
+extern {
+    #[linkage = "extern_weak"]
+    static aligned_alloc: *const u8;
+}
+
+/// Allocate a buffer with the platform allocator. See `man aligned_alloc`.
+///
+/// The alignment must be a power of two. `None` is returned, if the allocation fails, or there
+/// is no platform allocator.
+pub unsafe fn alloc(size: usize, align: usize) -> Option<*mut u8> {
+    /// The signature of `aligned_alloc`.
+    type AlignedAlloc = unsafe extern fn(align: usize, size: usize) -> *mut u8;
+
+    if aligned_alloc.is_null() {
+        return None;
+    }
+
+    // The size must be a multiple of the alignment.
+    let size = match size.checked_add(align - 1) {
+        Some(size) => size & !(align - 1),
+        None => return None,
+    };
+
+    let res = mem::transmute::<*const u8, AlignedAlloc>(aligned_alloc)(align, size);
+    if res.is_null() {
+        None
+    } else {
+        Some(res)
+    }
+}
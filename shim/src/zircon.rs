@@ -0,0 +1,71 @@
+//! Zircon (the Fuchsia kernel) calls.
+//!
+//! Fuchsia has no `brk` and no syscall numbers; the kernel is called through the vDSO of
+//! `libzircon`. Memory is acquired by creating a virtual memory object (VMO), and mapping it into
+//! the root address space region (VMAR) of the process.
+
+/// A handle to a kernel object.
+pub type Handle = u32;
+
+/// The status of a kernel call.
+type Status = i32;
+
+/// The status of a successful call.
+const ZX_OK: Status = 0;
+/// The mapping can be read.
+const ZX_VM_PERM_READ: u32 = 1 << 0;
+/// The mapping can be written.
+const ZX_VM_PERM_WRITE: u32 = 1 << 1;
+/// Commit the pages of a VMO.
+const ZX_VMO_OP_COMMIT: u32 = 1;
+/// Decommit the pages of a VMO (their contents are lost).
+const ZX_VMO_OP_DECOMMIT: u32 = 2;
+
+#[link(name = "zircon")]
+extern {
+    fn zx_vmo_create(size: u64, options: u32, out: *mut Handle) -> Status;
+    fn zx_vmar_root_self() -> Handle;
+    fn zx_vmar_map(vmar: Handle, options: u32, vmar_offset: usize, vmo: Handle, vmo_offset: u64,
+                   len: usize, mapped_addr: *mut usize) -> Status;
+    fn zx_vmo_op_range(vmo: Handle, op: u32, offset: u64, size: u64, buffer: *mut u8,
+                       buffer_size: usize) -> Status;
+    fn zx_handle_close(handle: Handle) -> Status;
+}
+
+/// Create a VMO of `size` bytes, and map it readable and writable. See `zx_vmo_create`.
+///
+/// The pages are committed lazily, when first touched. On success, the handle to the VMO and the
+/// start of the mapping are returned. On failure, the status is returned.
+pub unsafe fn vmo_map(size: usize) -> Result<(Handle, *mut u8), i32> {
+    let mut vmo = 0;
+    let res = zx_vmo_create(size as u64, 0, &mut vmo);
+    if res != ZX_OK {
+        return Err(res);
+    }
+
+    let mut addr = 0;
+    let res = zx_vmar_map(zx_vmar_root_self(), ZX_VM_PERM_READ | ZX_VM_PERM_WRITE, 0, vmo, 0, size,
+                          &mut addr);
+    if res != ZX_OK {
+        zx_handle_close(vmo);
+        return Err(res);
+    }
+
+    Ok((vmo, addr as *mut u8))
+}
+
+/// Commit `size` bytes of a VMO at `offset`. See `zx_vmo_op_range`.
+pub unsafe fn vmo_commit(vmo: Handle, offset: usize, size: usize) -> Result<(), ()> {
+    let res = zx_vmo_op_range(vmo, ZX_VMO_OP_COMMIT, offset as u64, size as u64,
+                              0 as *mut u8, 0);
+    if res == ZX_OK { Ok(()) } else { Err(()) }
+}
+
+/// Decommit `size` bytes of a VMO at `offset`. See `zx_vmo_op_range`.
+///
+/// The memory goes back to the kernel, and the pages read as zeros afterwards.
+pub unsafe fn vmo_decommit(vmo: Handle, offset: usize, size: usize) -> Result<(), ()> {
+    let res = zx_vmo_op_range(vmo, ZX_VMO_OP_DECOMMIT, offset as u64, size as u64,
+                              0 as *mut u8, 0);
+    if res == ZX_OK { Ok(()) } else { Err(()) }
+}
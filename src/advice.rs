@@ -0,0 +1,58 @@
+//! Paging advice.
+//!
+//! Applications can tell the kernel how they are going to use big buffers (see `ralloc::advise`),
+//! which is forwarded through `madvise`.
+
+use shim::syscalls;
+
+use fence::{page_down, page_up};
+
+/// Advice about the use of a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    /// No special treatment.
+    Normal,
+    /// The buffer will be accessed sequentially, so it can be read ahead aggressively.
+    Sequential,
+    /// The buffer will be accessed randomly, so reading ahead is useless.
+    Random,
+    /// The buffer will be accessed soon, so it should be paged in.
+    WillNeed,
+    /// The buffer will not be accessed soon, so its pages can be reclaimed.
+    ///
+    /// The content of the whole pages of the buffer is discarded (they read as zero afterwards).
+    DontNeed,
+}
+
+/// Forward advice about a buffer to the kernel.
+///
+/// Advice applies to whole pages. The buffer is rounded outwards to pages, except for
+/// `DontNeed`, where it is rounded inwards, so the content of neighboring buffers is never
+/// discarded.
+///
+/// # Safety
+///
+/// The buffer must be valid. With `DontNeed`, its content is discarded.
+pub unsafe fn advise(ptr: *mut u8, size: usize, advice: Advice) -> Result<(), ()> {
+    let (start, end) = if advice == Advice::DontNeed {
+        (page_up(ptr as usize), page_down(ptr as usize + size))
+    } else {
+        (page_down(ptr as usize), page_up(ptr as usize + size))
+    };
+
+    // Logging.
+    log!(DEBUG, "Advising {:?} for 0x{:x}[{}].", advice, start, end.saturating_sub(start));
+
+    if start >= end {
+        // No whole pages.
+        return Ok(());
+    }
+
+    syscalls::madvise(start as *mut u8, end - start, match advice {
+        Advice::Normal => syscalls::MADV_NORMAL,
+        Advice::Sequential => syscalls::MADV_SEQUENTIAL,
+        Advice::Random => syscalls::MADV_RANDOM,
+        Advice::WillNeed => syscalls::MADV_WILLNEED,
+        Advice::DontNeed => syscalls::MADV_DONTNEED,
+    })
+}
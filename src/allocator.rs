@@ -4,15 +4,38 @@
 
 use prelude::*;
 
-use core::{mem, ops};
+use core::{cmp, isize, mem, ops, ptr};
 
-use {brk, sync};
-use bookkeeper::{self, Bookkeeper, Allocator};
+use {advice, bins, conf, decay, detach, fail, fence, freeze, hook, housekeeping, pressure, regions,
+     stats, sync, timeline};
+use advice::Advice;
+use arena::Arena;
+use bookkeeper::{Allocator, Bookkeeper};
+use breaker::{Breaker, Brk};
+#[cfg(feature = "system_fallback")]
+use breaker::{Chain, System};
+use governor::Governor;
+use hook::{Event, Growth};
+use options::AllocOptions;
 
 use shim::config;
 
+#[cfg(feature = "tls")]
+use budget;
 #[cfg(feature = "tls")]
 use tls;
+#[cfg(feature = "leak_tracking")]
+use debug;
+#[cfg(feature = "failure_injection")]
+use inject::{self, Injected};
+#[cfg(feature = "interior_pointers")]
+use interior;
+#[cfg(feature = "mte")]
+use mte;
+#[cfg(feature = "sampling")]
+use sample;
+#[cfg(feature = "usdt")]
+use usdt;
 
 /// Alias for the wrapper type of the thread-local variable holding the local allocator.
 #[cfg(feature = "tls")]
@@ -21,7 +44,7 @@ type ThreadLocalAllocator = MoveCell<Option<LazyInit<fn() -> LocalAllocator, Loc
 /// The global default allocator.
 // TODO: Remove these filthy function pointers.
 static GLOBAL_ALLOCATOR: sync::Mutex<LazyInit<fn() -> GlobalAllocator, GlobalAllocator>> =
-    sync::Mutex::new(LazyInit::new(GlobalAllocator::init));
+    sync::Mutex::new(LazyInit::new(init_global));
 #[cfg(feature = "tls")]
 tls! {
     /// The thread-local allocator.
@@ -88,6 +111,7 @@ macro_rules! get_allocator {
 /// Derives `Deref` and `DerefMut` to the `inner` field.
 ///
 /// This requires importing `core::ops`.
+#[cfg(feature = "tls")]
 macro_rules! derive_deref {
     ($imp:ty, $target:ty) => {
         impl ops::Deref for $imp {
@@ -106,94 +130,120 @@ macro_rules! derive_deref {
     };
 }
 
-/// Global SBRK-based allocator.
+/// The breaker of the global allocator.
 ///
-/// This will extend the data segment whenever new memory is needed. Since this includes leaving
+/// This extends the data segment whenever new memory is needed. Since this includes leaving
 /// userspace, this shouldn't be used when other allocators are available (i.e. the bookkeeper is
 /// local).
-struct GlobalAllocator {
-    // The inner bookkeeper.
-    inner: Bookkeeper,
+///
+/// The heap is kept within the heap size limit (see `set_limit`), and its growth is governed (see
+/// `set_growth_governor`).
+struct GlobalBreaker {
+    /// The source of the memory.
+    source: GlobalSource,
+    /// The number of bytes acquired, and not released.
+    acquired: usize,
+    /// The growth governor.
+    governor: Governor,
 }
 
-impl GlobalAllocator {
-    /// Initialize the global allocator.
-    fn init() -> GlobalAllocator {
-        /// Logging...
-        log!(NOTE, "Initializing the global allocator.");
-
-        // The initial acquired segment.
-        let (aligner, initial_segment, excessive) =
-            brk::lock().canonical_brk(4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(), mem::align_of::<Block>());
-
-        // Initialize the new allocator.
-        let mut res = GlobalAllocator {
-            inner: Bookkeeper::new(unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+/// The source of the memory of the global allocator.
+#[cfg(not(feature = "system_fallback"))]
+type GlobalSource = Brk;
 
-                Vec::from_raw_parts(initial_segment, 0)
-            }),
-        };
+/// The source of the memory of the global allocator.
+///
+/// With the `system_fallback` feature, the platform allocator is used, if the program break is
+/// unsupported.
+#[cfg(feature = "system_fallback")]
+type GlobalSource = Chain<Brk, System>;
 
-        // Free the secondary space.
-        res.push(aligner);
-        res.push(excessive);
+/// Create the source of the memory of the global allocator.
+#[cfg(not(feature = "system_fallback"))]
+fn global_source() -> GlobalSource {
+    Brk
+}
 
-        res
-    }
+/// Create the source of the memory of the global allocator.
+#[cfg(feature = "system_fallback")]
+fn global_source() -> GlobalSource {
+    Chain::new(Brk, System)
 }
 
-derive_deref!(GlobalAllocator, Bookkeeper);
+unsafe impl Breaker for GlobalBreaker {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        let limit = conf::limit();
+        if size > limit.saturating_sub(self.acquired) {
+            // Logging.
+            log!(WARNING, "Acquiring {} bytes would exceed the heap size limit.", size);
 
-impl Allocator for GlobalAllocator {
-    #[inline]
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
-        // Obtain what you need.
-        let (alignment_block, res, excessive) = brk::lock().canonical_brk(size, align);
+            pressure::signal();
+            return Err(0);
+        }
+        if !self.governor.admit(size, self.acquired) {
+            pressure::signal();
+            return Err(0);
+        }
+
+        // Regions handed out right at the old break extend the data segment.
+        let old_end = self.source.next();
+        let res = self.source.fresh(size);
+        if let Ok((ptr, size)) = res {
+            self.acquired += size;
+            self.governor.grew(size);
+            stats::grow_heap(size);
+            regions::acquire(ptr, size);
+            hook::grow(ptr, unsafe {
+                // The region is in bounds.
+                ptr.offset(size as isize)
+            }, if old_end == Some(ptr) { Growth::Break } else { Growth::Fallback });
+            timeline::brk(ptr, size);
+            #[cfg(feature = "usdt")]
+            usdt::brk(ptr, size);
 
-        // Add it to the list. This will not change the order, since the pointer is higher than all
-        // the previous blocks (BRK extends the data segment). Although, it is worth noting that
-        // the stack is higher than the program break.
-        self.push(alignment_block);
-        self.push(excessive);
+            // Warn when the heap comes within an eighth of the limit.
+            if self.acquired > limit - limit / 8 {
+                pressure::signal();
+            }
+        } else {
+            pressure::signal();
+        }
 
         res
     }
 
-    fn on_new_memory(&mut self) {
-        if self.total_bytes() > config::OS_MEMTRIM_LIMIT {
-            // memtrim the fack outta 'em.
-
-            // Pop the last block.
-            let block = self.pop().expect("The byte count on the global allocator is invalid.");
-
-            // Check if the memtrim is worth it.
-            if block.size() >= config::OS_MEMTRIM_WORTHY {
-                /// Logging...
-                log!(NOTE, "Memtrimming the global allocator.");
-
-                // Release the block to the OS.
-                if let Err(block) = brk::lock().release(block) {
-                    // It failed, put the block back.
-                    // TODO: This can be done faster.
-                    self.push(block);
-                }
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        self.source.release(ptr, size)?;
+        self.acquired -= size;
+        stats::shrink_heap(size);
+        regions::release(ptr, size);
+        timeline::trim(ptr, size);
+        #[cfg(feature = "usdt")]
+        usdt::trim(ptr, size);
 
-                // Note that this block is the only block next to the program break, due to the
-                // segments being as long as possible. For that reason, repeating to push and
-                // release would fail.
-            } else {
-                /// Logging...
-                log!(WARNING, "Memtrimming for the global allocator failed.");
+        Ok(())
+    }
 
-                // Push the block back.
-                // TODO: This can be done faster.
-                self.push(block);
-            }
-        }
+    fn next(&mut self) -> Option<*mut u8> {
+        self.source.next()
     }
 }
 
+/// The global allocator.
+type GlobalAllocator = Arena<GlobalBreaker>;
+
+/// Initialize the global allocator.
+fn init_global() -> GlobalAllocator {
+    /// Logging...
+    log!(NOTE, "Initializing the global allocator.");
+
+    Arena::new(GlobalBreaker {
+        source: global_source(),
+        acquired: 0,
+        governor: Governor::new(),
+    })
+}
+
 /// A local allocator.
 ///
 /// This acquires memory from the upstream (global) allocator, which is protected by a `Mutex`.
@@ -201,6 +251,12 @@ impl Allocator for GlobalAllocator {
 pub struct LocalAllocator {
     // The inner bookkeeper.
     inner: Bookkeeper,
+    /// Is the thread cache disabled?
+    ///
+    /// If so, freed memory is given back to the global allocator right away.
+    disabled: bool,
+    /// The last decay epoch seen (see the `housekeeping` module).
+    epoch: usize,
 }
 
 #[cfg(feature = "tls")]
@@ -230,27 +286,37 @@ impl LocalAllocator {
             // TODO: we know this is sorted, so we could abuse that fact to faster insertion in the
             // global allocator.
 
-            alloc.into_inner().inner.for_each(move |block| global_alloc.free(block));
+            alloc.into_inner().inner.for_each(move |block| Allocator::free(global_alloc, block));
         }
 
         /// Logging...
         log!(NOTE, "Initializing the local allocator.");
 
-        // The initial acquired segment.
-        let initial_segment = GLOBAL_ALLOCATOR
-            .lock()
-            .get()
-            .alloc(4 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(), mem::align_of::<Block>());
-
         unsafe {
             // LAST AUDIT: 2016-08-21 (Ticki).
 
             // Register the thread destructor on the current thread.
             THREAD_ALLOCATOR.register_thread_destructor(dtor);
+        }
 
-            LocalAllocator {
-                inner: Bookkeeper::new(Vec::from_raw_parts(initial_segment, 0)),
-            }
+        LocalAllocator {
+            inner: Bookkeeper::new(),
+            disabled: false,
+            epoch: housekeeping::epoch(),
+        }
+    }
+
+    /// Give every free block of the local allocator back to the global allocator.
+    fn flush(&mut self) {
+        // Logging.
+        log!(NOTE, "Flushing the local allocator.");
+
+        // Lock the global allocator.
+        let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+        let global_alloc = global_alloc.get();
+
+        while let Some(block) = self.pop() {
+            Allocator::free(global_alloc, block);
         }
     }
 }
@@ -262,17 +328,44 @@ derive_deref!(LocalAllocator, Bookkeeper);
 impl Allocator for LocalAllocator {
     #[inline]
     fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
+        match self.try_alloc_fresh(size, align) {
+            Some(res) => res,
+            // The global allocator tells, why its breaker failed.
+            None => fail::oom(GLOBAL_ALLOCATOR.lock().get().error(size)),
+        }
+    }
+
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
         // Get the block from the global allocator. Please note that we cannot canonicalize `size`,
         // due to freeing excessive blocks would change the order.
-        GLOBAL_ALLOCATOR.lock().get().alloc(size, align)
+        let res = Allocator::try_alloc(GLOBAL_ALLOCATOR.lock().get(), size, align);
+
+        if res.is_none() && self.len() != 0 {
+            // The global allocator is out of memory, so we give it our cache and try again.
+            self.flush();
+            Allocator::try_alloc(GLOBAL_ALLOCATOR.lock().get(), size, align)
+        } else {
+            res
+        }
     }
 
     #[inline]
     fn on_new_memory(&mut self) {
+        // A disabled cache keeps nothing, and a cache seeing a new decay epoch is memtrimmed, as
+        // if it were over its limit.
+        let (max_bytes, max_blocks) = if self.disabled {
+            (0, 0)
+        } else if self.epoch != housekeeping::epoch() {
+            self.epoch = housekeeping::epoch();
+            (cmp::min(self.total_bytes() / 2, conf::cache_bytes()), conf::cache_blocks())
+        } else {
+            (conf::cache_bytes(), conf::cache_blocks())
+        };
+
         // The idea is to free memory to the global allocator to unify small stubs and avoid
         // fragmentation and thread accumulation.
         if self.total_bytes() < config::FRAGMENTATION_SCALE * self.len()
-           || self.total_bytes() > config::LOCAL_MEMTRIM_LIMIT {
+           || self.total_bytes() > max_bytes || self.len() > max_blocks {
             // Log stuff.
             log!(NOTE, "Memtrimming the local allocator.");
 
@@ -282,25 +375,677 @@ impl Allocator for LocalAllocator {
 
             while let Some(block) = self.pop() {
                 // Pop'n'free.
-                global_alloc.free(block);
+                Allocator::free(global_alloc, block);
 
                 // Memtrim 'till we won't memtrim anymore.
-                if self.total_bytes() < config::LOCAL_MEMTRIM_STOP { break; }
+                if self.total_bytes() < cmp::min(config::LOCAL_MEMTRIM_STOP, max_bytes)
+                   && self.len() <= max_blocks { break; }
             }
         }
     }
 }
 
+/// Give the kernel advice about the use of a buffer.
+///
+/// The buffer is checked to be allocated, after which the advice is forwarded to the kernel (see
+/// `Advice`). `Err(())` is returned, if the buffer is (partly) free or was never handed out by the
+/// allocator, or the kernel rejects the advice.
+///
+/// # Safety
+///
+/// The buffer must be valid. With `Advice::DontNeed`, its content (or rather, the content of its
+/// whole pages) is discarded.
+pub unsafe fn advise(ptr: *mut u8, size: usize, advice: Advice) -> Result<(), ()> {
+    log!(CALL, "Advising {:?} for buffer of size {}.", advice, size);
+
+    if size == 0 {
+        return Ok(());
+    }
+
+    if !is_allocated(ptr, size) {
+        log!(WARNING, "Advice for 0x{:x}[{}], which is not allocated.", ptr as usize, size);
+
+        return Err(());
+    }
+
+    #[cfg(feature = "mte")]
+    let ptr = mte::untag(ptr);
+
+    advice::advise(ptr, size, advice)
+}
+
+/// Freeze a buffer, making it read-only.
+///
+/// The whole pages spanned by the buffer are protected, so writes to them fault. The pages are
+/// made writable again, when the buffer is freed or reallocated.
+///
+/// `Err(())` is returned, if the buffer is not allocated, or its pages cannot be protected.
+///
+/// # Safety
+///
+/// No part of the buffer may be written afterwards (e.g. through a `&mut`), until it is freed.
+pub unsafe fn freeze(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    log!(CALL, "Freezing buffer of size {}.", size);
+
+    if size == 0 {
+        return Ok(());
+    }
+
+    if !is_allocated(ptr, size) {
+        log!(WARNING, "Freezing 0x{:x}[{}], which is not allocated.", ptr as usize, size);
+
+        return Err(());
+    }
+
+    #[cfg(feature = "mte")]
+    let ptr = mte::untag(ptr);
+
+    freeze::freeze(ptr, size)
+}
+
+/// Flush the thread cache.
+///
+/// The free memory cached by the allocator of the current thread is given back to the global
+/// allocator, so other threads can use it. This happens automatically when the thread exits, but
+/// long-lived threads, which are done allocating (e.g. idle workers of a thread pool), can call
+/// this to avoid stranding memory.
+///
+/// This is NOOP without the `tls` feature.
+pub fn flush_thread_cache() {
+    log!(CALL, "Flushing the thread cache.");
+
+    #[cfg(feature = "tls")]
+    THREAD_ALLOCATOR.with(|thread_alloc| {
+        // If the local allocator is deinitialized, there is nothing to flush.
+        if let Some(mut alloc) = thread_alloc.replace(None) {
+            alloc.get().flush();
+            thread_alloc.replace(Some(alloc));
+        }
+    });
+}
+
+/// Disable the thread cache of the current thread.
+///
+/// The cache is flushed (see `flush_thread_cache`), and from now on, memory freed by the thread
+/// is given back to the global allocator right away, rather than cached. This is useful for
+/// threads, which allocate rarely, as their caches would mostly hold memory idle.
+///
+/// This is NOOP without the `tls` feature.
+pub fn disable_thread_cache() {
+    log!(CALL, "Disabling the thread cache.");
+
+    #[cfg(feature = "tls")]
+    THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut alloc) = thread_alloc.replace(None) {
+            {
+                let alloc = alloc.get();
+                alloc.disabled = true;
+                alloc.flush();
+            }
+            thread_alloc.replace(Some(alloc));
+        }
+    });
+}
+
+/// Give the last free block of the heap back to the OS.
+///
+/// `false` is returned, if there is nothing (more) to give back. See `Arena::trim_one`.
+pub fn trim_one() -> bool {
+    GLOBAL_ALLOCATOR.lock().get().trim_one()
+}
+
+/// Give the free memory at the end of the heap back to the OS, but `pad` bytes.
+///
+/// The thread cache is flushed first, so its memory is given back as well. `true` is returned,
+/// if any memory was given back. See `Arena::trim_keeping`.
+pub fn trim(pad: usize) -> bool {
+    log!(CALL, "Trimming the heap, keeping {} bytes.", pad);
+
+    flush_thread_cache();
+
+    GLOBAL_ALLOCATOR.lock().get().trim_keeping(pad)
+}
+
+/// Perform a full consistency check of the allocator.
+///
+/// This checks the allocator of the current thread, as well as the global allocator. Violations
+/// are handled according to the violation policy.
+///
+/// This is NOOP in release mode, unless the checks are enabled at runtime (see `set_checks`).
+pub fn check() {
+    get_allocator!(|alloc| alloc.check_all());
+
+    #[cfg(feature = "tls")]
+    GLOBAL_ALLOCATOR.lock().get().check_all();
+}
+
+/// Block the global allocator, until `enable` is called.
+///
+/// Allocations in need of the global allocator wait meanwhile, so its state stays as is (e.g.
+/// around a `fork`, or while the heap is inspected).
+pub fn disable() {
+    mem::forget(GLOBAL_ALLOCATOR.lock());
+}
+
+/// Unblock the global allocator, after `disable`.
+///
+/// # Safety
+///
+/// This must be paired with a call to `disable`.
+pub unsafe fn enable() {
+    GLOBAL_ALLOCATOR.force_unlock();
+}
+
+/// Inspect the pool of the global allocator.
+///
+/// The global allocator is locked meanwhile, so the closure must not use the entry points (e.g.
+/// to allocate), or take locks the allocator might take. Blocks held in thread caches are not
+/// part of the pool.
+pub fn with_global<T, F: FnOnce(&Bookkeeper) -> T>(f: F) -> T {
+    let mut global = GLOBAL_ALLOCATOR.lock();
+    f(global.get())
+}
+
+/// Inspect the pool of the global allocator, if it is not locked.
+///
+/// This is like `with_global`, but never blocks: `None` is returned, if the global allocator is
+/// locked (e.g. by the caller, when called from the OOM handler).
+pub fn try_with_global<T, F: FnOnce(&Bookkeeper) -> T>(f: F) -> Option<T> {
+    GLOBAL_ALLOCATOR.try_lock().map(|mut global| f(global.get()))
+}
+
+/// Is an allocation of some size and alignment possible at all?
+///
+/// The alignment must be nonzero, and the size (even after aligning) must fit in an `isize`, as
+/// pointer offsets are signed.
+#[inline]
+pub fn is_possible(size: usize, align: usize) -> bool {
+    align != 0 && size.checked_add(align).map_or(false, |x| x <= isize::MAX as usize)
+}
+
+/// Call the OOM handler due to an impossible request.
+#[cold]
+pub fn impossible(size: usize, align: usize) -> ! {
+    // Logging.
+    log!(ERROR, "Impossible request of {} bytes (align {}).", size, align);
+
+    fail::oom(fail::Error::LimitExceeded)
+}
+
 /// Allocate a block of memory.
 ///
+/// Zero-sized allocations take no memory, and return a dangling pointer aligned to `align`.
+///
 /// # Errors
 ///
-/// The OOM handler handles out-of-memory conditions.
+/// The OOM handler handles out-of-memory conditions, as well as impossible requests (see
+/// `is_possible`).
 #[inline]
 pub fn alloc(size: usize, align: usize) -> *mut u8 {
+    let fresh = injected(size).unwrap_or_else(|err| fail::oom(err));
+    let res = relieved_alloc(size, align, fresh).unwrap_or_else(|| oom(size));
+
+    report(Event::Alloc {
+        ptr: res,
+        size: size,
+        align: align,
+    });
+
+    res
+}
+
+/// Try to allocate a block of memory.
+///
+/// This is like `alloc`, but impossible requests, exceeding the heap size limit (see
+/// `set_limit`), and injected failures (see `ralloc::set_injection`) are returned as errors,
+/// instead of calling the OOM handler.
+#[inline]
+pub fn try_alloc(size: usize, align: usize) -> Result<*mut u8, fail::Error> {
+    if !is_possible(size, align) {
+        return Err(fail::Error::LimitExceeded);
+    }
+
+    let res = match relieved_alloc(size, align, injected(size)?) {
+        Some(res) => res,
+        None => return Err(GLOBAL_ALLOCATOR.lock().get().error(size)),
+    };
+
+    report(Event::Alloc {
+        ptr: res,
+        size: size,
+        align: align,
+    });
+
+    Ok(res)
+}
+
+/// Decide the failure injected into an allocation.
+///
+/// `Err` is returned, if the allocation is to fail, and `Ok(true)`, if it is to take the
+/// fresh-allocation path.
+#[inline]
+#[cfg(feature = "failure_injection")]
+fn injected(size: usize) -> Result<bool, fail::Error> {
+    match inject::check(size) {
+        Some(Injected::Fail) => Err(fail::Error::OutOfMemory {
+            requested: size,
+            available: 0,
+        }),
+        Some(Injected::Fresh) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+/// Decide the failure injected into an allocation.
+///
+/// Without the `failure_injection` feature, nothing is injected.
+#[inline]
+#[cfg(not(feature = "failure_injection"))]
+fn injected(_: usize) -> Result<bool, fail::Error> {
+    Ok(false)
+}
+
+/// Report an operation performed through the entry points to the statistics and the hook.
+#[inline]
+fn report(event: Event) {
+    stats::record(&event);
+    #[cfg(feature = "tls")]
+    budget::record(&event);
+    #[cfg(feature = "leak_tracking")]
+    debug::record(&event);
+    #[cfg(feature = "interior_pointers")]
+    interior::record(&event);
+    #[cfg(feature = "usdt")]
+    usdt::emit(&event);
+    #[cfg(feature = "heaptrack")]
+    hook::profile(&event);
+    decay::record(&event);
+    hook::emit(event);
+
+    // No locks are held, so the log can be forwarded to the application's logger.
+    #[cfg(feature = "std")]
+    ::log::internal::forward();
+}
+
+/// Forget a buffer in the table of the live allocations (see `interior::forget`).
+///
+/// This must be done before the memory is given back.
+#[inline]
+#[cfg(feature = "interior_pointers")]
+fn forget(ptr: *mut u8, size: usize) {
+    interior::forget(ptr, size);
+}
+
+/// Forget a buffer in the table of the live allocations.
+///
+/// Without the `interior_pointers` feature, there is no such table.
+#[inline]
+#[cfg(not(feature = "interior_pointers"))]
+fn forget(_: *mut u8, _: usize) {}
+
+/// Record a buffer in the table of the live allocations again, after a failed reallocation.
+#[inline]
+#[cfg(feature = "interior_pointers")]
+fn remember(ptr: *mut u8, size: usize) {
+    interior::remember(ptr, size);
+}
+
+/// Record a buffer in the table of the live allocations again.
+///
+/// Without the `interior_pointers` feature, there is no such table.
+#[inline]
+#[cfg(not(feature = "interior_pointers"))]
+fn remember(_: *mut u8, _: usize) {}
+
+/// Allocate a block of memory, calling the memory pressure handler if needed.
+///
+/// If the allocation fails, and the handler (see `ralloc::set_pressure_handler`) is called, the
+/// allocation is retried once.
+#[inline]
+fn relieved_alloc(size: usize, align: usize, fresh: bool) -> Option<*mut u8> {
+    let res = match raw_alloc(size, align, fresh) {
+        Some(res) => Some(res),
+        None if pressure::relieve() => raw_alloc(size, align, fresh),
+        None => None,
+    };
+
+    pressure::poll();
+
+    res
+}
+
+/// Call the OOM handler due to an allocation exceeding the heap size limit.
+#[cold]
+fn oom(size: usize) -> ! {
+    fail::oom(fail::Error::OutOfMemory {
+        requested: size,
+        available: 0,
+    })
+}
+
+/// Allocate a block of memory, without reporting it to the hook.
+///
+/// If `fresh` is set, the free blocks are bypassed, and the block is taken from fresh memory.
+/// `None` is returned, if no fresh memory is available (e.g. due to the heap size limit).
+#[inline]
+fn raw_alloc(size: usize, align: usize, fresh: bool) -> Option<*mut u8> {
     log!(CALL, "Allocating buffer of size {} (align {}).", size, align);
 
-    get_allocator!(|alloc| Pointer::from(alloc.alloc(size, align)).get())
+    if !is_possible(size, align) {
+        impossible(size, align);
+    }
+    if size == 0 {
+        return Some(align as *mut u8);
+    }
+
+    if cfg!(feature = "electric_fence") {
+        return Some(fence::alloc(size, align));
+    }
+
+    // Maybe serve it from a guarded slot.
+    #[cfg(feature = "sampling")]
+    {
+        if let Some(res) = sample::alloc(size, align) {
+            return Some(res);
+        }
+    }
+
+    // With memory tagging, allocations consist of whole granules.
+    #[cfg(feature = "mte")]
+    let (size, align) = (mte::round(size), cmp::max(align, mte::GRANULE));
+
+    // Binned buffers consist of whole objects, aligned to the granule, wherever they come from,
+    // so they can go to the bins, when freed.
+    let (size, align) = if bins::serves(size) {
+        (bins::round(size), cmp::max(align, bins::GRANULE))
+    } else {
+        (size, align)
+    };
+
+    // Maybe serve it from a fast bin, without taking the lock.
+    if bins::serves(size) && align == bins::GRANULE && !fresh {
+        if let Some(res) = bins::pop(size) {
+            return Some(res);
+        }
+
+        // The bin ran dry, so a batch of objects is carved from the pool.
+        let chunk = get_allocator!(|alloc| {
+            Allocator::try_alloc(alloc, size * config::FAST_BIN_REFILL, align)
+        });
+        if let Some(chunk) = chunk {
+            let res = Pointer::from(chunk).get();
+            unsafe {
+                // The chunk was just allocated, and is given to the bins for good.
+                bins::refill(res, size, config::FAST_BIN_REFILL);
+            }
+
+            return Some(res);
+        }
+    }
+
+    let res = get_allocator!(|alloc| {
+        if fresh {
+            Allocator::try_alloc_external(alloc, size, align)
+        } else {
+            Allocator::try_alloc(alloc, size, align)
+        }
+    });
+    let res = match res {
+        Some(res) => Pointer::from(res).get(),
+        None => return None,
+    };
+
+    #[cfg(feature = "mte")]
+    let res = unsafe {
+        // The buffer was just allocated, and consists of whole granules.
+        mte::tag(res, size)
+    };
+
+    Some(res)
+}
+
+/// Allocate a buffer at a fixed address.
+///
+/// This succeeds only if the `size` bytes at `ptr` are free, or can be obtained from the program
+/// break (i.e. the range lies right above it). This is useful for emulators and JITs, which need
+/// memory at specific addresses.
+///
+/// # Errors
+///
+/// `Error::OutOfMemory` is returned, if the range is not free, and `Error::LimitExceeded`, if the
+/// request is impossible, `ptr` is not aligned to `align`, or fixed addresses are unsupported
+/// (with the `electric_fence` and `mte` features, and for the sizes of the fast bins).
+pub fn alloc_at(ptr: *mut u8, size: usize, align: usize) -> Result<*mut u8, fail::Error> {
+    log!(CALL, "Allocating buffer of size {} at 0x{:x}.", size, ptr as usize);
+
+    // Binned buffers are objects of the fast bins, which are never at fixed addresses.
+    if cfg!(any(feature = "electric_fence", feature = "mte")) || bins::serves(size) {
+        return Err(fail::Error::LimitExceeded);
+    }
+
+    // The range might be free in the thread cache.
+    #[cfg(feature = "tls")]
+    {
+        if is_possible(size, align) && size != 0 && ptr as usize % align == 0
+           && (ptr as usize).checked_add(size).is_some() {
+            let res = get_allocator!(|alloc| Allocator::alloc_at(alloc, unsafe {
+                // The block is only used for searching, until it is found free.
+                Block::from_raw_parts(Pointer::new(ptr), size)
+            }));
+
+            if let Ok(res) = res {
+                let res = Pointer::from(res).get();
+                report(Event::Alloc {
+                    ptr: res,
+                    size: size,
+                    align: align,
+                });
+
+                return Ok(res);
+            }
+        }
+    }
+
+    let res = GLOBAL_ALLOCATOR.lock().get().alloc_at(ptr, size, align)?;
+
+    report(Event::Alloc {
+        ptr: res,
+        size: size,
+        align: align,
+    });
+
+    Ok(res)
+}
+
+/// Donate a region of memory to the allocator.
+///
+/// The region (e.g. the slack of a big mapped file, or a static buffer, which is no longer used)
+/// is added to the pool of the global allocator as free memory.
+///
+/// `Err(())` is returned, if the region overlaps free memory of the pools (which would be
+/// handed out twice), or memory tagging is enabled (as the region might not support tags).
+///
+/// # Safety
+///
+/// The region must be valid for reads and writes, and must not be used by anything else
+/// afterwards. In particular, it must not overlap any live allocation.
+pub unsafe fn donate(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    log!(CALL, "Donating {} bytes at 0x{:x}.", size, ptr as usize);
+
+    if cfg!(feature = "mte") {
+        return Err(());
+    }
+
+    // The region must not overlap the thread cache either.
+    #[cfg(feature = "tls")]
+    {
+        if size != 0 && (ptr as usize).checked_add(size).is_some() {
+            let block = Block::from_raw_parts(Pointer::new(ptr), size);
+
+            if !get_allocator!(|alloc| alloc.is_allocated(&block)) {
+                log!(WARNING, "Refusing the donation of {:?}, which overlaps the cache.", block);
+
+                return Err(());
+            }
+        }
+    }
+
+    GLOBAL_ALLOCATOR.lock().get().donate(ptr, size)?;
+    regions::acquire(ptr, size);
+
+    Ok(())
+}
+
+/// Allocate a block of memory with some options.
+///
+/// This is like `alloc`, but the options (e.g. zeroing or pre-faulting) are applied to the
+/// buffer, which is aligned to `options.min_align`.
+///
+/// With `options.cacheline`, the buffer is to be freed with the rounded size (see
+/// `AllocOptions::size`).
+///
+/// # Errors
+///
+/// See `alloc`.
+#[inline]
+pub fn alloc_with(size: usize, options: &AllocOptions) -> *mut u8 {
+    let size = options.size(size);
+    let res = alloc(size, options.align(1));
+
+    unsafe {
+        // The buffer was just allocated.
+        options.apply(res, size);
+    }
+
+    res
+}
+
+/// Allocate a buffer on cache lines of its own.
+///
+/// The buffer is aligned to `CACHE_LINE`, and padded to a multiple of it, so it shares no cache
+/// line with other buffers (see `AllocOptions::cacheline`). It must be freed (or reallocated)
+/// with its size rounded up to a multiple of `CACHE_LINE`.
+///
+/// # Errors
+///
+/// See `alloc`.
+#[inline]
+pub fn alloc_cacheline(size: usize) -> *mut u8 {
+    alloc_with(size, &AllocOptions {
+        cacheline: true,
+        ..AllocOptions::new()
+    })
+}
+
+/// Allocate a block of memory, and get its real size.
+///
+/// This is like `alloc`, but a remainder of the free block too small to be worth keeping (see
+/// `config::MIN_SPLIT`) is granted along with the buffer. The pointer is returned along with the
+/// granted size, which is at least `size`, so the caller can make use of the slack instead of
+/// reallocating.
+///
+/// The buffer is freed with the granted size (or a partial free of the slack and the rest).
+///
+/// # Errors
+///
+/// See `alloc`.
+pub fn alloc_excess(size: usize, align: usize) -> (*mut u8, usize) {
+    // Zero-sized, binned, fenced, sampled, tagged, and injected allocations do not come from the
+    // pools, so they are granted exactly what they asked for.
+    if size == 0 || bins::serves(size) || !is_possible(size, align)
+       || cfg!(any(feature = "electric_fence", feature = "failure_injection", feature = "mte",
+                   feature = "sampling")) {
+        return (alloc(size, align), size);
+    }
+
+    log!(CALL, "Allocating buffer of size {} with excess (align {}).", size, align);
+
+    let res = match get_allocator!(|alloc| Allocator::alloc_excess(alloc, size, align)) {
+        Some(res) => res,
+        // Take the usual route, which relieves the memory pressure, or calls the OOM handler.
+        None => return (alloc(size, align), size),
+    };
+    pressure::poll();
+
+    let granted = res.size();
+    let res = Pointer::from(res).get();
+
+    report(Event::Alloc {
+        ptr: res,
+        size: granted,
+        align: align,
+    });
+
+    (res, granted)
+}
+
+/// Allocate a batch of equally sized blocks of memory.
+///
+/// The first `n` entries of `out` are set to `n` buffers of `size` bytes, aligned to `align`.
+/// The buffers are carved out of a single block, so a whole batch takes a single pool search (or
+/// fresh allocation), which amortizes the cost when filling object pools. Each buffer is freed on
+/// its own.
+///
+/// # Errors
+///
+/// See `alloc`.
+///
+/// # Panics
+///
+/// This panics, if `out` holds fewer than `n` entries.
+pub fn alloc_batch(size: usize, align: usize, n: usize, out: &mut [*mut u8]) {
+    assert!(n <= out.len(), "The batch of {} does not fit into {} entries.", n, out.len());
+
+    log!(CALL, "Allocating a batch of {} buffers of size {} (align {}).", n, size, align);
+
+    if !is_possible(size, align) {
+        impossible(size, align);
+    }
+
+    // Zero-sized, binned, fenced, sampled, tagged, and injected allocations do not come from the
+    // pools, so they are allocated one by one.
+    if n == 0 || size == 0 || bins::serves(size)
+       || cfg!(any(feature = "electric_fence", feature = "failure_injection", feature = "mte",
+                   feature = "sampling")) {
+        for ptr in &mut out[..n] {
+            *ptr = alloc(size, align);
+        }
+
+        return;
+    }
+
+    // Keep every buffer aligned.
+    let stride = (size + align - 1) / align * align;
+    let total = match stride.checked_mul(n) {
+        Some(total) if is_possible(total, align) => total,
+        _ => impossible(size, align),
+    };
+
+    let res = relieved_alloc(total, align, false).unwrap_or_else(|| oom(total));
+
+    for (i, ptr) in out[..n].iter_mut().enumerate() {
+        *ptr = unsafe {
+            // The offset is within the block.
+            res.offset((i * stride) as isize)
+        };
+
+        report(Event::Alloc {
+            ptr: *ptr,
+            size: size,
+            align: align,
+        });
+    }
+
+    // The padding between the buffers (if the size is not a multiple of the alignment) is given
+    // back to the pool, as it need not be an object of the fast bins.
+    if stride > size {
+        for ptr in &out[..n] {
+            unsafe {
+                // The padding is part of the block, but of no buffer.
+                raw_free_with(ptr.offset(size as isize), stride - size, false);
+            }
+        }
+    }
 }
 
 /// Free a buffer.
@@ -325,9 +1070,412 @@ pub fn alloc(size: usize, align: usize) -> *mut u8 {
 /// Secondly, freeing an used buffer can introduce use-after-free.
 #[inline]
 pub unsafe fn free(ptr: *mut u8, size: usize) {
+    forget(ptr, size);
+    raw_free(ptr, size);
+
+    report(Event::Free {
+        ptr: ptr,
+        size: size,
+    });
+}
+
+/// Free a sub-range of a buffer.
+///
+/// The bytes `range` of the buffer of `size` bytes at `ptr` are given back, which splits the
+/// buffer into up to two remaining buffers: The part before the range, and the part after it.
+/// These are returned as `(ptr, size)` pairs (either might be empty), and are freed on their own.
+///
+/// This is useful for giving back the unused tail (or middle) of an over-allocated buffer. With
+/// the `fast_bins` feature, parts of buffers served by the fast bins must be whole objects (i.e.
+/// starting at a multiple of 16 bytes into the buffer, and ending at such a multiple or at the end
+/// of the buffer). Other parts are reported as a violation (see `set_violation_policy`), and are
+/// left be, in which case the whole buffer is returned as the part before the range. Parts of
+/// other buffers always go back to the pool.
+///
+/// # Panics
+///
+/// This panics, if the range is not within the buffer.
+///
+/// # Safety
+///
+/// See `free`.
+pub unsafe fn free_part(ptr: *mut u8, size: usize, range: ops::Range<usize>)
+                        -> ((*mut u8, usize), (*mut u8, usize)) {
+    assert!(range.start <= range.end && range.end <= size, "The range {:?} is not within the \
+            buffer of size {}.", range, size);
+
+    log!(CALL, "Freeing {:?} of buffer of size {}.", range, size);
+
+    let (part, len) = (ptr.offset(range.start as isize), range.end - range.start);
+
+    // Parts of binned buffers go back to the bins, so they must be objects of their own.
+    let whole = !bins::serves(size)
+        || (range.start % bins::GRANULE == 0
+            && (range.end == size || range.end % bins::GRANULE == 0));
+    debug_assert!(whole, "The range {:?} of the binned buffer of size {} is not a whole object.",
+                  range, size);
+    if !invariant!(whole, "free_part", Some(&Block::from_raw_parts(Pointer::new(part), len)),
+                   "A part of a binned buffer is not a whole object") {
+        return ((ptr, size), (ptr.offset(size as isize), 0));
+    }
+
+    forget(part, len);
+    // Parts of buffers of the pools need not be objects of the fast bins.
+    raw_free_with(part, len, bins::serves(size));
+
+    report(Event::Free {
+        ptr: part,
+        size: len,
+    });
+
+    ((ptr, range.start), (ptr.offset(range.end as isize), size - range.end))
+}
+
+/// Split a buffer in two.
+///
+/// The buffer of `size` bytes at `ptr` becomes two buffers, of the first `at` bytes and of the
+/// rest, which are returned as `(ptr, size)` pairs. These are freed (or merged again, see
+/// `merge_allocs`) on their own. No memory is moved.
+///
+/// `Err(())` is returned, if the buffer is not an allocation of the pools, or is served by the
+/// fast bins.
+///
+/// # Panics
+///
+/// This panics, if `at` exceeds the size.
+pub unsafe fn split_alloc(ptr: *mut u8, size: usize, at: usize)
+                          -> Result<((*mut u8, usize), (*mut u8, usize)), ()> {
+    assert!(at <= size, "Splitting the buffer of size {} at {}.", size, at);
+
+    log!(CALL, "Splitting buffer of size {} at {}.", size, at);
+
+    if !is_pooled(ptr, size) {
+        log!(WARNING, "Splitting 0x{:x}[{}], which is not allocated.", ptr as usize, size);
+
+        return Err(());
+    }
+
+    Ok(((ptr, at), (ptr.offset(at as isize), size - at)))
+}
+
+/// Merge two adjacent buffers.
+///
+/// The buffers, given as `(ptr, size)` pairs, become a single buffer, starting at the first, which
+/// is returned along with its size. No memory is moved.
+///
+/// `Err(())` is returned, if the second buffer does not start right where the first ends, or
+/// either is not an allocation of the pools, or is served by the fast bins.
+///
+/// # Safety
+///
+/// The buffers must not be used through the old pointers afterwards.
+pub unsafe fn merge_allocs(a: (*mut u8, usize), b: (*mut u8, usize))
+                           -> Result<(*mut u8, usize), ()> {
+    log!(CALL, "Merging buffers of size {} and {}.", a.1, b.1);
+
+    #[cfg(feature = "mte")]
+    let (start, end) = (mte::untag(a.0) as usize + a.1, mte::untag(b.0) as usize);
+    #[cfg(not(feature = "mte"))]
+    let (start, end) = (a.0 as usize + a.1, b.0 as usize);
+
+    if start != end || !is_pooled(a.0, a.1) || !is_pooled(b.0, b.1) {
+        log!(WARNING, "Unable to merge 0x{:x}[{}] and 0x{:x}[{}].", a.0 as usize, a.1,
+             b.0 as usize, b.1);
+
+        return Err(());
+    }
+
+    // The second buffer takes the tag of the first.
+    #[cfg(feature = "mte")]
+    mte::set(a.0.offset(a.1 as isize), mte::round(b.1));
+
+    Ok((a.0, a.1 + b.1))
+}
+
+/// Is a buffer allocated from the pools?
+///
+/// That is, the buffer is live (see `is_live`), is not served by the fast bins, and is neither
+/// fenced nor sampled. Binned buffers are fixed in their class, and a live object of the bins
+/// cannot be told apart from a free one.
+unsafe fn is_pooled(ptr: *mut u8, size: usize) -> bool {
+    if cfg!(feature = "electric_fence") || bins::serves(size) {
+        return false;
+    }
+
+    #[cfg(feature = "sampling")]
+    {
+        if sample::owns(ptr) {
+            return false;
+        }
+    }
+
+    is_live(ptr, size)
+}
+
+/// Is a buffer allocated?
+///
+/// Fenced and sampled buffers live apart from the pools, and are taken as they are. Other buffers
+/// must be live (see `is_live`).
+unsafe fn is_allocated(ptr: *mut u8, size: usize) -> bool {
+    if cfg!(feature = "electric_fence") {
+        return true;
+    }
+
+    #[cfg(feature = "sampling")]
+    {
+        if sample::owns(ptr) {
+            return true;
+        }
+    }
+
+    is_live(ptr, size)
+}
+
+/// Is a buffer of the pools (or the fast bins) live?
+///
+/// That is, the buffer lies in the regions of the global allocator, and overlaps no free block of
+/// the pool of the current thread, nor of the global pool. The free blocks cached by other threads
+/// are not seen.
+unsafe fn is_live(ptr: *mut u8, size: usize) -> bool {
+    #[cfg(feature = "mte")]
+    let ptr = mte::untag(ptr);
+
+    // Foreign buffers were never handed out.
+    if !regions::owns(ptr, size) {
+        return false;
+    }
+
+    let block = Block::from_raw_parts(Pointer::new(ptr), size);
+    if !get_allocator!(|alloc| alloc.is_allocated(&block)) {
+        return false;
+    }
+
+    // Without the `tls` feature, the pool of the thread is the global pool.
+    #[cfg(feature = "tls")]
+    {
+        if !GLOBAL_ALLOCATOR.lock().get().is_allocated(&block) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Free a buffer of known size and alignment.
+///
+/// Sizes are never looked up (there are no headers), so this is merely `free`, with the alignment
+/// checked against the pointer in debug builds. It suits callers handing both over anyway (e.g.
+/// C++ sized deallocation).
+///
+/// # Safety
+///
+/// See `free`.
+#[inline]
+pub unsafe fn free_sized(ptr: *mut u8, size: usize, align: usize) {
+    debug_assert!(align != 0 && ptr as usize % align == 0, "Freeing 0x{:x}, which is not aligned \
+                  to {}.", ptr as usize, align);
+
+    free(ptr, size)
+}
+
+/// Free a buffer, without reporting it to the hook.
+#[inline]
+unsafe fn raw_free(ptr: *mut u8, size: usize) {
+    raw_free_with(ptr, size, true);
+}
+
+/// Free a buffer, or a part of one, without reporting it to the hook.
+///
+/// Unless `binned` is set, the memory goes back to the pool, even if its size is served by the
+/// fast bins: The bins round the size up to their class, and expect the alignment of their
+/// objects, so padding or parts of a buffer of the pools would overlap its neighbors there.
+unsafe fn raw_free_with(ptr: *mut u8, size: usize, binned: bool) {
     log!(CALL, "Freeing buffer of size {}.", size);
 
-    get_allocator!(|alloc| alloc.free(Block::from_raw_parts(Pointer::new(ptr), size)))
+    // Zero-sized buffers were never allocated.
+    if size == 0 {
+        return;
+    }
+
+    // Detached buffers are never given back.
+    if detached(ptr, size, "free") {
+        return;
+    }
+
+    // Frozen pages are made writable, before they are given back.
+    thaw(ptr, size);
+
+    if cfg!(feature = "electric_fence") {
+        return fence::free(ptr, size);
+    }
+
+    #[cfg(feature = "sampling")]
+    {
+        if sample::free(ptr) {
+            return;
+        }
+    }
+
+    // Retag the buffer, so the stale (tagged) pointers to it trap.
+    #[cfg(feature = "mte")]
+    let (ptr, size) = (mte::untag(ptr), mte::round(size));
+    #[cfg(feature = "mte")]
+    mte::clear(ptr, size);
+
+    // Foreign buffers would corrupt the pool.
+    if !regions::check(ptr, size, "free") {
+        return;
+    }
+
+    // Binned buffers go back to their bin, without taking the lock.
+    if binned && bins::serves(size) {
+        return bins::push(ptr, size);
+    }
+
+    get_allocator!(|alloc| Allocator::free(alloc, Block::from_raw_parts(Pointer::new(ptr), size)))
+}
+
+/// Free a batch of buffers.
+///
+/// This is like calling `free` on each `(ptr, size)` pair, but far cheaper for many buffers: The
+/// buffers are sorted, runs of adjacent buffers are merged, and the runs are given back to the
+/// pool in a single pass.
+///
+/// The order and contents of `bufs` are unspecified afterwards.
+///
+/// # Safety
+///
+/// See `free`.
+pub unsafe fn free_batch(bufs: &mut [(*mut u8, usize)]) {
+    log!(CALL, "Freeing a batch of {} buffers.", bufs.len());
+
+    // Binned, fenced, sampled, and tagged buffers do not go back to the pools, so they are freed
+    // one by one.
+    if cfg!(any(feature = "electric_fence", feature = "fast_bins", feature = "mte",
+                feature = "sampling")) {
+        for &(ptr, size) in bufs.iter() {
+            free(ptr, size);
+        }
+
+        return;
+    }
+
+    for buf in bufs.iter_mut() {
+        let (ptr, size) = *buf;
+
+        // Detached buffers are never given back, and foreign buffers would corrupt the pool, so
+        // these are left be (as empty buffers).
+        if detached(ptr, size, "free_batch") || !regions::check(ptr, size, "free_batch") {
+            buf.1 = 0;
+            continue;
+        }
+
+        if size != 0 {
+            // Frozen pages are made writable, before they are given back.
+            thaw(ptr, size);
+        }
+
+        forget(ptr, size);
+        report(Event::Free {
+            ptr: ptr,
+            size: size,
+        });
+    }
+
+    bufs.sort_unstable();
+
+    // Merge the runs of adjacent buffers into the first buffer of each.
+    let mut run = 0;
+    for i in 1..bufs.len() {
+        if bufs[run].0 as usize + bufs[run].1 == bufs[i].0 as usize {
+            bufs[run].1 += bufs[i].1;
+            bufs[i].1 = 0;
+        } else {
+            run = i;
+        }
+    }
+
+    get_allocator!(|alloc| {
+        Allocator::free_sorted(alloc, bufs.iter().map(|&(ptr, size)| {
+            // Zero-sized buffers give empty blocks, which are skipped.
+            Block::from_raw_parts(Pointer::new(ptr), size)
+        }))
+    })
+}
+
+/// Detach a buffer from the allocator.
+///
+/// The buffer is no longer managed by the allocator: It is handed to the caller for good, and is
+/// never reused. This is needed, when the memory is handed to a subsystem, which never gives it
+/// back (e.g. when it is mapped into another process). To the statistics, the hooks and the leak
+/// tracking, the buffer is freed.
+///
+/// The buffer must not be freed or reallocated afterwards. Doing so is reported as a violation
+/// (see `set_violation_policy`), and the buffer is left be.
+///
+/// `Err(())` is returned, if the buffer is not an allocation of the pools, or is served by the
+/// fast bins (see `split_alloc`).
+///
+/// # Safety
+///
+/// The buffer must not be in use by anything else.
+pub unsafe fn detach(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    log!(CALL, "Detaching buffer of size {}.", size);
+
+    if size == 0 {
+        return Ok(());
+    }
+
+    if !is_pooled(ptr, size) {
+        log!(WARNING, "Detaching 0x{:x}[{}], which is not allocated.", ptr as usize, size);
+
+        return Err(());
+    }
+
+    #[cfg(feature = "mte")]
+    detach::detach(mte::untag(ptr), size);
+    #[cfg(not(feature = "mte"))]
+    detach::detach(ptr, size);
+
+    forget(ptr, size);
+    report(Event::Free {
+        ptr: ptr,
+        size: size,
+    });
+
+    Ok(())
+}
+
+/// Check if an operation involves a detached buffer (see `detach`).
+///
+/// If so, a violation is reported, and `true` is returned, in which case the operation is to
+/// leave the buffer be.
+#[inline]
+fn detached(ptr: *mut u8, size: usize, place: &'static str) -> bool {
+    #[cfg(feature = "mte")]
+    let ptr = mte::untag(ptr);
+
+    if !detach::overlaps(ptr, size) {
+        return false;
+    }
+
+    fail::violation(&fail::Violation {
+        description: "The buffer was detached",
+        place: place,
+        addr: ptr as usize,
+        size: size,
+    });
+
+    true
+}
+
+/// Thaw the frozen pages of a buffer (see `freeze`).
+#[inline]
+fn thaw(ptr: *mut u8, size: usize) {
+    #[cfg(feature = "mte")]
+    let ptr = mte::untag(ptr);
+
+    freeze::thaw(ptr, size);
 }
 
 /// Reallocate memory.
@@ -342,7 +1490,8 @@ pub unsafe fn free(ptr: *mut u8, size: usize) {
 ///
 /// # Errors
 ///
-/// The OOM handler handles out-of-memory conditions.
+/// The OOM handler handles out-of-memory conditions, as well as impossible requests (see
+/// `is_possible`).
 ///
 /// # Safety
 ///
@@ -350,10 +1499,79 @@ pub unsafe fn free(ptr: *mut u8, size: usize) {
 /// this is marked unsafe.
 #[inline]
 pub unsafe fn realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
+    // The old buffer might be freed by the reallocation.
+    forget(ptr, old_size);
+    let res = raw_realloc(ptr, old_size, size, align);
+
+    report(Event::Realloc {
+        old_ptr: ptr,
+        old_size: old_size,
+        ptr: res,
+        size: size,
+        align: align,
+    });
+
+    res
+}
+
+/// Reallocate memory, without reporting it to the hook.
+#[inline]
+unsafe fn raw_realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
     log!(CALL, "Reallocating buffer of size {} to new size {}.", old_size, size);
 
+    if !is_possible(size, align) {
+        impossible(size, align);
+    }
+    // Zero-sized buffers take no memory.
+    if old_size == 0 {
+        return raw_alloc(size, align, false).unwrap_or_else(|| oom(size));
+    }
+    if size == 0 {
+        raw_free(ptr, old_size);
+        return align as *mut u8;
+    }
+
+    // Detached buffers are left be, so the contents are copied to a new buffer.
+    if detached(ptr, old_size, "realloc") {
+        let res = raw_alloc(size, align, false).unwrap_or_else(|| oom(size));
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+
+        return res;
+    }
+
+    // Frozen pages are made writable, as the buffer is changing.
+    thaw(ptr, old_size);
+
+    // The bookkeeper copies through untagged pointers, which would trap on tagged memory, and
+    // knows nothing about sampled, fenced or binned allocations, so we reallocate through the
+    // entry points instead.
+    #[cfg(feature = "sampling")]
+    let by_hand = cfg!(feature = "mte") || cfg!(feature = "electric_fence") || sample::owns(ptr);
+    #[cfg(not(feature = "sampling"))]
+    let by_hand = cfg!(feature = "mte") || cfg!(feature = "electric_fence");
+    if by_hand || bins::serves(old_size) || bins::serves(size) {
+        if (ptr as usize) % align == 0 && raw_realloc_inplace(ptr, old_size, size).is_ok() {
+            return ptr;
+        }
+
+        let res = raw_alloc(size, align, false).unwrap_or_else(|| oom(size));
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+        raw_free(ptr, old_size);
+
+        return res;
+    }
+
+    // Foreign buffers would corrupt the pool, so they are left be, like detached buffers.
+    if !regions::check(ptr, old_size, "realloc") {
+        let res = raw_alloc(size, align, false).unwrap_or_else(|| oom(size));
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+
+        return res;
+    }
+
     get_allocator!(|alloc| {
-        Pointer::from(alloc.realloc(
+        Pointer::from(Allocator::realloc(
+            alloc,
             Block::from_raw_parts(Pointer::new(ptr), old_size),
             size,
             align
@@ -372,10 +1590,76 @@ pub unsafe fn realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize)
 /// Due to being able to shrink (and thus free) the buffer, this is marked unsafe.
 #[inline]
 pub unsafe fn realloc_inplace(ptr: *mut u8, old_size: usize, size: usize) -> Result<(), ()> {
+    // The tail of the buffer might be freed by the reallocation.
+    forget(ptr, old_size);
+    let res = raw_realloc_inplace(ptr, old_size, size);
+
+    if res.is_ok() {
+        report(Event::Realloc {
+            old_ptr: ptr,
+            old_size: old_size,
+            ptr: ptr,
+            size: size,
+            align: 1,
+        });
+    } else {
+        // The buffer is left as it was.
+        remember(ptr, old_size);
+    }
+
+    res
+}
+
+/// Try to reallocate the buffer _inplace_, without reporting it to the hook.
+#[inline]
+unsafe fn raw_realloc_inplace(ptr: *mut u8, old_size: usize, size: usize) -> Result<(), ()> {
     log!(CALL, "Inplace reallocating buffer of size {} to new size {}.", old_size, size);
 
-    get_allocator!(|alloc| {
-        if alloc.realloc_inplace(
+    // Zero-sized buffers are dangling, and cannot be extended.
+    if old_size == 0 || size == 0 || size > isize::MAX as usize {
+        return if old_size == size { Ok(()) } else { Err(()) };
+    }
+
+    // Detached buffers are left be.
+    if detached(ptr, old_size, "realloc_inplace") {
+        return Err(());
+    }
+
+    // Frozen pages are made writable, as the buffer is changing.
+    thaw(ptr, old_size);
+
+    // Fenced allocations are fixed in their pages.
+    if cfg!(feature = "electric_fence") {
+        return Err(());
+    }
+
+    // Sampled allocations are fixed in their slots.
+    #[cfg(feature = "sampling")]
+    {
+        if sample::owns(ptr) {
+            return Err(());
+        }
+    }
+
+    // Binned allocations are fixed in their class.
+    if bins::serves(old_size) || bins::serves(size) {
+        return if bins::same_class(old_size, size) { Ok(()) } else { Err(()) };
+    }
+
+    #[cfg(feature = "mte")]
+    let (tagged, ptr, old_size, size) = (ptr, mte::untag(ptr), mte::round(old_size),
+                                         mte::round(size));
+    // The truncated part is given back, so it is retagged first.
+    #[cfg(feature = "mte")]
+    {
+        if size < old_size {
+            mte::clear(ptr.offset(size as isize), old_size - size);
+        }
+    }
+
+    let res = get_allocator!(|alloc| {
+        if Allocator::realloc_inplace(
+            alloc,
             Block::from_raw_parts(Pointer::new(ptr), old_size),
             size
         ).is_ok() {
@@ -383,5 +1667,15 @@ pub unsafe fn realloc_inplace(ptr: *mut u8, old_size: usize, size: usize) -> Res
         } else {
             Err(())
         }
-    })
+    });
+
+    // The extension takes the tag of the buffer.
+    #[cfg(feature = "mte")]
+    {
+        if res.is_ok() && size > old_size {
+            mte::set(tagged.offset(old_size as isize), size - old_size);
+        }
+    }
+
+    res
 }
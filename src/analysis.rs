@@ -0,0 +1,322 @@
+//! Offline analysis of heap snapshots.
+//!
+//! A snapshot is a compact binary record of the state of the global allocator, written by
+//! `debug::write_snapshot`. It can be taken in one process (e.g. a service in production), and
+//! loaded in another (e.g. a companion tool, or a test), to compute the fragmentation and the
+//! size histogram of the free blocks, or to compare two snapshots.
+//!
+//! # Format
+//!
+//! A snapshot starts with the magic bytes `RASN` and the version byte (currently 1), followed by
+//! the extents of the heap (start and end), the bytes in use, the bytes taken from the OS, and
+//! the number of free blocks. These are followed by the free blocks in address order, each given
+//! by the distance from the end of the previous block (from zero for the first block) and its
+//! size. All the integers are encoded as LEB128.
+
+use core::mem;
+
+/// The magic bytes starting a snapshot.
+pub const MAGIC: &'static [u8] = b"RASN";
+/// The version of the snapshot format.
+///
+/// This is bumped whenever the format changes, and snapshots of other versions are rejected.
+pub const VERSION: u8 = 1;
+/// The number of size classes of a histogram.
+///
+/// Class `n` holds the blocks of `2^n` to `2^(n + 1) - 1` bytes.
+pub const CLASSES: usize = 64;
+
+/// An error loading a snapshot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    /// The magic bytes are missing, so this is not a snapshot.
+    Magic,
+    /// The snapshot is of another version of the format.
+    Version(u8),
+    /// The snapshot is truncated or corrupt.
+    Malformed,
+}
+
+/// A reader of a snapshot.
+#[derive(Clone)]
+struct Reader<'a> {
+    /// The rest of the snapshot.
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Read a byte.
+    fn byte(&mut self) -> Result<u8, ParseError> {
+        match self.bytes.split_first() {
+            Some((&byte, rest)) => {
+                self.bytes = rest;
+                Ok(byte)
+            },
+            None => Err(ParseError::Malformed),
+        }
+    }
+
+    /// Read an LEB128 integer.
+    fn int(&mut self) -> Result<usize, ParseError> {
+        let mut res = 0usize;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            if shift >= mem::size_of::<usize>() * 8 {
+                // The integer overflows.
+                return Err(ParseError::Malformed);
+            }
+
+            res |= ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+    }
+
+    /// Read a block, given the end of the previous one.
+    fn block(&mut self, prev: usize) -> Result<(usize, usize), ParseError> {
+        let addr = prev.checked_add(self.int()?).ok_or(ParseError::Malformed)?;
+        let size = self.int()?;
+        if addr.checked_add(size).is_none() {
+            return Err(ParseError::Malformed);
+        }
+
+        Ok((addr, size))
+    }
+}
+
+/// A loaded snapshot.
+#[derive(Clone, Debug)]
+pub struct Snapshot<'a> {
+    /// The start of the heap.
+    pub start: usize,
+    /// The end of the heap.
+    pub end: usize,
+    /// The number of bytes in use.
+    pub in_use: usize,
+    /// The number of bytes taken from the OS.
+    pub extent: usize,
+    /// The number of free blocks.
+    pub blocks: usize,
+    /// The number of free bytes.
+    pub free: usize,
+    /// The size of the largest free block.
+    pub largest: usize,
+    /// The encoded blocks.
+    data: &'a [u8],
+}
+
+impl<'a> Snapshot<'a> {
+    /// Load a snapshot.
+    ///
+    /// The whole snapshot is validated, so the blocks can be iterated without errors.
+    pub fn parse(bytes: &'a [u8]) -> Result<Snapshot<'a>, ParseError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ParseError::Magic);
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes[MAGIC.len()..],
+        };
+        match reader.byte()? {
+            VERSION => (),
+            version => return Err(ParseError::Version(version)),
+        }
+
+        let mut res = Snapshot {
+            start: reader.int()?,
+            end: reader.int()?,
+            in_use: reader.int()?,
+            extent: reader.int()?,
+            blocks: reader.int()?,
+            free: 0,
+            largest: 0,
+            data: &[],
+        };
+        res.data = reader.bytes;
+
+        if res.end < res.start {
+            return Err(ParseError::Malformed);
+        }
+
+        let mut prev = 0;
+        for _ in 0..res.blocks {
+            let (addr, size) = reader.block(prev)?;
+            res.free = res.free.checked_add(size).ok_or(ParseError::Malformed)?;
+            if size > res.largest {
+                res.largest = size;
+            }
+
+            prev = addr + size;
+        }
+
+        if !reader.bytes.is_empty() {
+            // Trailing garbage.
+            return Err(ParseError::Malformed);
+        }
+
+        Ok(res)
+    }
+
+    /// Iterate over the free blocks, as `(address, size)` pairs in address order.
+    pub fn iter(&self) -> Blocks<'a> {
+        Blocks {
+            reader: Reader {
+                bytes: self.data,
+            },
+            prev: 0,
+            left: self.blocks,
+        }
+    }
+
+    /// Get the fragmentation of the free space.
+    ///
+    /// This is the share of the free bytes, which are not in the largest free block, ranging from
+    /// 0 (all the free space is contiguous) to almost 1 (the free space is scattered in many small
+    /// blocks).
+    #[allow(cast_precision_loss)]
+    pub fn fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest as f64 / self.free as f64
+        }
+    }
+
+    /// Get the size histogram of the free blocks.
+    pub fn histogram(&self) -> Histogram {
+        let mut res = Histogram {
+            counts: [0; CLASSES],
+            bytes: [0; CLASSES],
+        };
+
+        for (_, size) in self.iter().filter(|&(_, size)| size != 0) {
+            let class = mem::size_of::<usize>() * 8 - 1 - size.leading_zeros() as usize;
+            res.counts[class] += 1;
+            res.bytes[class] += size;
+        }
+
+        res
+    }
+
+    /// Compare this snapshot to a later one.
+    ///
+    /// The differences are positive, where the later snapshot has more.
+    #[allow(cast_possible_wrap)]
+    pub fn diff(&self, later: &Snapshot) -> Diff {
+        let old = self.histogram();
+        let new = later.histogram();
+
+        let mut histogram = [0; CLASSES];
+        for class in 0..CLASSES {
+            histogram[class] = new.counts[class] as isize - old.counts[class] as isize;
+        }
+
+        Diff {
+            heap: (later.end - later.start) as isize - (self.end - self.start) as isize,
+            in_use: later.in_use as isize - self.in_use as isize,
+            extent: later.extent as isize - self.extent as isize,
+            blocks: later.blocks as isize - self.blocks as isize,
+            free: later.free as isize - self.free as isize,
+            fragmentation: later.fragmentation() - self.fragmentation(),
+            histogram: histogram,
+        }
+    }
+}
+
+/// An iterator over the free blocks of a snapshot.
+pub struct Blocks<'a> {
+    /// The reader of the blocks.
+    reader: Reader<'a>,
+    /// The end of the previous block.
+    prev: usize,
+    /// The number of blocks left.
+    left: usize,
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        if self.left == 0 {
+            return None;
+        }
+
+        let (addr, size) = self.reader.block(self.prev).expect("The snapshot was validated.");
+        self.prev = addr + size;
+        self.left -= 1;
+
+        Some((addr, size))
+    }
+}
+
+/// A size histogram of free blocks.
+#[derive(Clone, Copy)]
+pub struct Histogram {
+    /// The number of blocks of each size class.
+    pub counts: [usize; CLASSES],
+    /// The number of bytes in the blocks of each size class.
+    pub bytes: [usize; CLASSES],
+}
+
+/// The differences between two snapshots.
+#[derive(Clone, Copy)]
+pub struct Diff {
+    /// The difference in the extent of the heap (end minus start).
+    pub heap: isize,
+    /// The difference in bytes in use.
+    pub in_use: isize,
+    /// The difference in bytes taken from the OS.
+    pub extent: isize,
+    /// The difference in free blocks.
+    pub blocks: isize,
+    /// The difference in free bytes.
+    pub free: isize,
+    /// The difference in fragmentation (see `Snapshot::fragmentation`).
+    pub fragmentation: f64,
+    /// The difference in free blocks of each size class.
+    pub histogram: [isize; CLASSES],
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // A heap from 0x1000 to 0x2000 with free blocks 0x1000[0x10] and 0x1100[0x100].
+        let a = b"RASN\x01\x80\x20\x80\x40\x10\x80\x20\x02\x80\x20\x10\xf0\x01\x80\x02";
+        let a = Snapshot::parse(a).unwrap();
+        assert_eq!(a.start, 0x1000);
+        assert_eq!(a.end, 0x2000);
+        assert_eq!(a.free, 0x110);
+        assert_eq!(a.largest, 0x100);
+        let mut blocks = a.iter();
+        assert_eq!(blocks.next(), Some((0x1000, 0x10)));
+        assert_eq!(blocks.next(), Some((0x1100, 0x100)));
+        assert_eq!(blocks.next(), None);
+
+        let hist = a.histogram();
+        assert_eq!(hist.counts[4], 1);
+        assert_eq!(hist.counts[8], 1);
+        assert_eq!(hist.bytes[8], 0x100);
+
+        // The same heap with the first block gone.
+        let b = b"RASN\x01\x80\x20\x80\x40\x10\x80\x20\x01\x80\x22\x80\x02";
+        let b = Snapshot::parse(b).unwrap();
+        assert_eq!(b.fragmentation(), 0.0);
+        let diff = a.diff(&b);
+        assert_eq!(diff.blocks, -1);
+        assert_eq!(diff.free, -0x10);
+        assert_eq!(diff.histogram[4], -1);
+        assert_eq!(diff.histogram[8], 0);
+
+        assert_eq!(Snapshot::parse(b"nope").unwrap_err(), ParseError::Magic);
+        assert_eq!(Snapshot::parse(b"RASN\x07").unwrap_err(), ParseError::Version(7));
+        assert_eq!(Snapshot::parse(b"RASN\x01\x80").unwrap_err(), ParseError::Malformed);
+    }
+}
@@ -0,0 +1,253 @@
+//! Android integration.
+//!
+//! With the `android` feature, the entry points of bionic's `MallocDispatch` are exported with
+//! the `ralloc_` prefix (like jemalloc's `je_` and Scudo's `scudo_`), so ralloc can be wired into
+//! bionic as the native allocator. Besides the usual `malloc` family, this includes
+//! `malloc_iterate`, `malloc_disable` and `malloc_enable`, which the debugging infrastructure
+//! (e.g. libmemunreachable and `fork`) relies on.
+//!
+//! C frees without the size, so the live allocations are recorded (see the `interior` module),
+//! and looked up on free.
+
+use core::{cmp, mem, ptr};
+
+use shim::config;
+
+use {allocator, interior};
+use stats::Stats;
+
+/// The alignment of buffers, which do not specify one.
+///
+/// This is the alignment of `max_align_t` on Android.
+const DEFAULT_ALIGN: usize = 16;
+/// The error number of invalid arguments.
+const EINVAL: i32 = 22;
+/// The error number of exhausted memory.
+const ENOMEM: i32 = 12;
+
+/// The statistics of `mallinfo`, as laid out by bionic.
+#[repr(C)]
+pub struct MallInfo {
+    /// The number of bytes taken from the OS.
+    pub arena: usize,
+    /// The number of free chunks (unused).
+    pub ordblks: usize,
+    /// The number of free fastbin blocks (unused).
+    pub smblks: usize,
+    /// The number of mapped regions (unused).
+    pub hblks: usize,
+    /// The number of bytes in mapped regions (unused).
+    pub hblkhd: usize,
+    /// The peak number of bytes taken from the OS.
+    pub usmblks: usize,
+    /// The number of bytes in free fastbin blocks (unused).
+    pub fsmblks: usize,
+    /// The number of bytes in use.
+    pub uordblks: usize,
+    /// The number of bytes taken from the OS, which are not in use.
+    pub fordblks: usize,
+    /// The number of releasable bytes at the top of the heap (unused).
+    pub keepcost: usize,
+}
+
+/// Allocate a buffer, recording it, so it can be freed without the size.
+///
+/// Empty buffers are one byte long, so they are recorded, and distinct. A null pointer is
+/// returned on failure.
+fn alloc(size: usize, align: usize) -> *mut u8 {
+    allocator::try_alloc(cmp::max(size, 1), align).unwrap_or(ptr::null_mut())
+}
+
+/// Allocate a buffer of `size` bytes.
+#[no_mangle]
+pub extern fn ralloc_malloc(size: usize) -> *mut u8 {
+    alloc(size, DEFAULT_ALIGN)
+}
+
+/// Allocate a zeroed buffer of `n` elements of `size` bytes.
+#[no_mangle]
+pub extern fn ralloc_calloc(n: usize, size: usize) -> *mut u8 {
+    let size = match n.checked_mul(size) {
+        Some(size) => size,
+        None => return ptr::null_mut(),
+    };
+
+    let res = alloc(size, DEFAULT_ALIGN);
+    if !res.is_null() {
+        unsafe {
+            // The buffer was just allocated.
+            ptr::write_bytes(res, 0, size);
+        }
+    }
+
+    res
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align`.
+#[no_mangle]
+pub extern fn ralloc_memalign(align: usize, size: usize) -> *mut u8 {
+    if !align.is_power_of_two() {
+        return ptr::null_mut();
+    }
+
+    alloc(size, cmp::max(align, DEFAULT_ALIGN))
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align` (C11).
+#[no_mangle]
+pub extern fn ralloc_aligned_alloc(align: usize, size: usize) -> *mut u8 {
+    ralloc_memalign(align, size)
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align`, and store it in `out`.
+///
+/// `EINVAL` is returned, if the alignment is not a power of two multiple of the pointer size,
+/// and `ENOMEM`, if the buffer cannot be allocated.
+#[no_mangle]
+pub unsafe extern fn ralloc_posix_memalign(out: *mut *mut u8, align: usize, size: usize) -> i32 {
+    if !align.is_power_of_two() || align % mem::size_of::<usize>() != 0 {
+        return EINVAL;
+    }
+
+    match ralloc_memalign(align, size) {
+        res if res.is_null() => ENOMEM,
+        res => {
+            *out = res;
+
+            0
+        },
+    }
+}
+
+/// Allocate a page-aligned buffer of `size` bytes.
+#[no_mangle]
+pub extern fn ralloc_valloc(size: usize) -> *mut u8 {
+    alloc(size, config::PAGE_SIZE)
+}
+
+/// Allocate a page-aligned buffer of `size` bytes rounded up to whole pages.
+#[no_mangle]
+pub extern fn ralloc_pvalloc(size: usize) -> *mut u8 {
+    match size.checked_add(config::PAGE_SIZE - 1) {
+        Some(size) => alloc(size & !(config::PAGE_SIZE - 1), config::PAGE_SIZE),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Free a buffer.
+///
+/// Null pointers are ignored, as are pointers not allocated through these entry points.
+#[no_mangle]
+pub unsafe extern fn ralloc_free(ptr: *mut u8) {
+    if !ptr.is_null() && interior::free_interior(ptr).is_err() {
+        log!(WARNING, "Freeing 0x{:x}, which is not a live allocation.", ptr as usize);
+    }
+}
+
+/// Resize a buffer to `size` bytes.
+///
+/// A null pointer allocates, and a size of zero frees (returning null). On failure, null is
+/// returned, and the buffer is left as is.
+#[no_mangle]
+pub unsafe extern fn ralloc_realloc(ptr: *mut u8, size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return ralloc_malloc(size);
+    }
+    if size == 0 {
+        ralloc_free(ptr);
+        return ptr::null_mut();
+    }
+
+    match interior::resolve_interior(ptr) {
+        Some((start, old_size)) if start == ptr => {
+            if allocator::realloc_inplace(ptr, old_size, size).is_ok() {
+                return ptr;
+            }
+
+            let res = alloc(size, DEFAULT_ALIGN);
+            if !res.is_null() {
+                // The buffers are distinct, and both hold at least the copied bytes.
+                ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+                allocator::free(ptr, old_size);
+            }
+
+            res
+        },
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Get the usable size of a buffer.
+///
+/// Zero is returned for pointers not allocated through these entry points.
+#[no_mangle]
+pub extern fn ralloc_malloc_usable_size(ptr: *const u8) -> usize {
+    match interior::resolve_interior(ptr) {
+        Some((start, size)) if start as *const u8 == ptr => size,
+        _ => 0,
+    }
+}
+
+/// Get the statistics of the allocator.
+#[no_mangle]
+pub extern fn ralloc_mallinfo() -> MallInfo {
+    let usage = Stats::snapshot().usage;
+
+    MallInfo {
+        arena: usage.extent,
+        ordblks: 0,
+        smblks: 0,
+        hblks: 0,
+        hblkhd: 0,
+        usmblks: usage.peak_extent,
+        fsmblks: 0,
+        uordblks: usage.in_use,
+        fordblks: usage.extent.saturating_sub(usage.in_use),
+        keepcost: 0,
+    }
+}
+
+/// Set a tuning parameter.
+///
+/// No parameters are supported, so zero (failure) is returned.
+#[no_mangle]
+pub extern fn ralloc_mallopt(_: i32, _: i32) -> i32 {
+    0
+}
+
+/// Call `callback` with the start and size of each live allocation starting in the `size` bytes
+/// at `base`, and `arg`.
+///
+/// This must be called between `ralloc_malloc_disable` and `ralloc_malloc_enable`, and the
+/// callback must not allocate.
+#[no_mangle]
+pub unsafe extern fn ralloc_malloc_iterate(base: usize, size: usize,
+                                           callback: extern fn(usize, usize, *mut u8),
+                                           arg: *mut u8) -> i32 {
+    interior::for_each_live(base, base.saturating_add(size), |ptr, size| {
+        callback(ptr as usize, size, arg);
+    });
+
+    0
+}
+
+/// Block the global allocator and the record of the live allocations, until
+/// `ralloc_malloc_enable` is called.
+///
+/// The pool of the global allocator and the live allocations (see `ralloc_malloc_iterate`) stay
+/// as they are meanwhile, so they can be iterated, or the process forked. Frees wait before
+/// giving the memory back. Allocations served by the thread caches or the fast bins take no lock
+/// of the allocator, so they still take their memory from those (which is not part of the
+/// iterated state), and only wait for `ralloc_malloc_enable` before being recorded and returned.
+#[no_mangle]
+pub extern fn ralloc_malloc_disable() {
+    allocator::disable();
+    interior::disable();
+}
+
+/// Unblock the allocations, after `ralloc_malloc_disable`.
+#[no_mangle]
+pub unsafe extern fn ralloc_malloc_enable() {
+    interior::enable();
+    allocator::enable();
+}
@@ -0,0 +1,1107 @@
+//! Arenas.
+//!
+//! An arena is a bookkeeper fed by some breaker. The global allocator is the arena of the program
+//! break, but arenas can be created over any memory source, and used independently of the global
+//! allocator.
+
+use prelude::*;
+
+use core::{cmp, mem, ops, ptr};
+
+use allocator::{is_possible, impossible};
+use bookkeeper::{Bookkeeper, Allocator};
+use breaker::Breaker;
+use leak::Leak;
+use options::AllocOptions;
+use policy::{DefaultPolicy, GrowthPolicy, Policy};
+use vec::Vec;
+use {fail, meta};
+
+use shim::config;
+
+/// An arena.
+///
+/// This serves allocations from its own pool, which is fed by the breaker, `B`. Unlike the
+/// entry points of the crate, allocations from an arena are not sampled, fenced, or tagged.
+///
+/// The bookkeeping is tuned by the policy, `P` (see `Arena::with_policy`).
+///
+/// Dropping an arena does not give its memory back to the breaker.
+pub struct Arena<B: Breaker, P: Policy = DefaultPolicy> {
+    /// The inner bookkeeper.
+    inner: Bookkeeper<P>,
+    /// The source of fresh memory.
+    breaker: B,
+    /// The options of the allocations.
+    options: AllocOptions,
+    /// The maximum number of bytes acquired from the breaker.
+    budget: usize,
+    /// The number of bytes acquired from the breaker, and not released.
+    acquired: usize,
+    /// The error of the last failure to acquire memory from the breaker (see `Breaker::fresh`).
+    errno: usize,
+    /// The regions acquired from the breaker since the oldest live snapshot.
+    ///
+    /// This is only maintained while there are live snapshots.
+    fresh: Vec<Extent>,
+    /// The number of live snapshots.
+    snapshots: usize,
+    /// The buffers allocated in the live epochs, in allocation order.
+    ///
+    /// This is only maintained while there are live epochs.
+    live: Vec<Extent>,
+    /// The live epochs.
+    ///
+    /// Each entry is the number of buffers in `live` allocated before the epoch was pushed.
+    epochs: Vec<usize>,
+}
+
+/// A region of memory.
+///
+/// Unlike a block, this carries no ownership, and is merely a record of where some memory is.
+#[derive(Clone, Copy)]
+struct Extent {
+    /// The address of the region.
+    addr: usize,
+    /// The size of the region.
+    size: usize,
+}
+
+impl Extent {
+    /// Get the extent of a block.
+    fn of(block: &Block) -> Extent {
+        Extent {
+            addr: block.addr(),
+            size: block.size(),
+        }
+    }
+
+    /// Get a block of the region.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid and unused, and no other block of it may exist.
+    unsafe fn block(&self) -> Block {
+        Block::from_raw_parts(Pointer::new(self.addr as *mut u8), self.size)
+    }
+}
+
+/// A snapshot of the state of an arena.
+///
+/// See `Arena::snapshot`. A snapshot must be given back to its arena, through `Arena::restore` or
+/// `Arena::discard`, as the arena keeps track of its fresh memory while it is live.
+pub struct Snapshot {
+    /// The free blocks of the arena, when the snapshot was taken.
+    free: Vec<Extent>,
+    /// The number of fresh regions recorded, when the snapshot was taken.
+    mark: usize,
+}
+
+impl<B: Breaker> Arena<B> {
+    /// Create a new arena, taking fresh memory from some breaker.
+    pub fn new(breaker: B) -> Arena<B> {
+        Arena::with_policy(breaker)
+    }
+}
+
+impl<B: Breaker, P: Policy> Arena<B, P> {
+    /// Create a new arena with some policy, taking fresh memory from some breaker.
+    ///
+    /// The policy is given by the type, e.g.
+    /// `let arena: Arena<_, HardenedPolicy> = Arena::with_policy(breaker);`.
+    pub fn with_policy(breaker: B) -> Arena<B, P> {
+        Arena {
+            inner: Bookkeeper::new(),
+            breaker: breaker,
+            options: AllocOptions::new(),
+            budget: !0,
+            acquired: 0,
+            errno: 0,
+            fresh: Vec::default(),
+            snapshots: 0,
+            live: Vec::default(),
+            epochs: Vec::default(),
+        }
+    }
+
+    /// Set the options applied to the allocations of the arena.
+    ///
+    /// This applies to `alloc`, `try_alloc` and `realloc`, but not `alloc_with`, which takes its
+    /// own options. The options should be set before allocating, as `AllocOptions::cacheline`
+    /// changes the sizes the buffers are freed with.
+    pub fn set_options(&mut self, options: AllocOptions) {
+        self.options = options;
+    }
+
+    /// Set the budget of the arena.
+    ///
+    /// The arena takes at most `bytes` bytes from its breaker, so code confined to the arena
+    /// cannot exhaust the memory source. When an allocation would exceed the budget, the arena
+    /// first gives unused memory back to the breaker, and then fails: `try_alloc` returns an
+    /// error, and the other methods call the OOM handler.
+    ///
+    /// Memory already acquired is kept, if the budget is lowered below it. Arenas have no budget
+    /// by default.
+    pub fn set_budget(&mut self, bytes: usize) {
+        // Logging.
+        log!(NOTE, "Setting the budget of the arena to {} bytes.", bytes);
+
+        self.budget = bytes;
+    }
+
+    /// Get the number of bytes the arena has acquired from its breaker, and not released.
+    ///
+    /// This includes free memory, and is what the budget (see `set_budget`) limits.
+    pub fn acquired(&self) -> usize {
+        self.acquired
+    }
+
+    /// Get the breaker of the arena.
+    pub fn breaker(&mut self) -> &mut B {
+        &mut self.breaker
+    }
+
+    /// Allocate a block of memory.
+    ///
+    /// See `ralloc::alloc`.
+    pub fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let options = AllocOptions {
+            min_align: self.options.align(align),
+            ..self.options
+        };
+        self.alloc_with(size, &options)
+    }
+
+    /// Try to allocate a block of memory.
+    ///
+    /// This is like `alloc`, but if the memory cannot be acquired (e.g. due to the budget), an
+    /// error is returned, instead of calling the OOM handler. See `ralloc::try_alloc`.
+    pub fn try_alloc(&mut self, size: usize, align: usize) -> Result<*mut u8, fail::Error> {
+        let size = self.options.size(size);
+        let align = self.options.align(align);
+        if !is_possible(size, align) {
+            return Err(fail::Error::LimitExceeded);
+        }
+        if size == 0 {
+            return Ok(align as *mut u8);
+        }
+
+        let res = match Allocator::try_alloc(self, size, align) {
+            Some(res) => Pointer::from(res).get(),
+            None => return Err(self.error(size)),
+        };
+
+        unsafe {
+            // The buffer was just allocated.
+            self.options.apply(res, size);
+        }
+
+        self.track(res, size);
+
+        Ok(res)
+    }
+
+    /// Allocate a buffer at a fixed address.
+    ///
+    /// This succeeds only if the `size` bytes at `ptr` are free in the pool, or the breaker can
+    /// tell that its next region covers them (see `Breaker::next`), in which case the region is
+    /// acquired. See `ralloc::alloc_at`.
+    pub fn alloc_at(&mut self, ptr: *mut u8, size: usize, align: usize)
+                    -> Result<*mut u8, fail::Error> {
+        let size = self.options.size(size);
+        let align = self.options.align(align);
+        if !is_possible(size, align) || ptr as usize % align != 0
+           || (ptr as usize).checked_add(size).is_none() {
+            return Err(fail::Error::LimitExceeded);
+        }
+        if size == 0 {
+            return Ok(ptr);
+        }
+
+        let block = unsafe {
+            // The block is only used for searching, until it is found free.
+            Block::from_raw_parts(Pointer::new(ptr), size)
+        };
+        let block = match Allocator::alloc_at(self, block) {
+            Ok(res) => res,
+            Err(block) => {
+                // Acquire the memory up to the end of the range, if the breaker hands it out next.
+                let next = self.breaker.next().map_or(!0, |next| next as usize);
+                if next > ptr as usize {
+                    return Err(fail::Error::OutOfMemory {
+                        requested: size,
+                        available: self.total_bytes(),
+                    });
+                }
+
+                let (fresh, fresh_size) = match self.acquire(ptr as usize + size - next) {
+                    Some(res) => res,
+                    None => return Err(self.error(size)),
+                };
+
+                if self.snapshots > 0 {
+                    push(&mut self.fresh, Extent {
+                        addr: fresh as usize,
+                        size: fresh_size,
+                    });
+                }
+
+                self.push(unsafe {
+                    // The breaker guarantees that the region is valid and unused.
+                    Block::from_raw_parts(Pointer::new(fresh), fresh_size)
+                });
+
+                // The region might have been placed elsewhere after all.
+                Allocator::alloc_at(self, block).map_err(|_| fail::Error::OutOfMemory {
+                    requested: size,
+                    available: 0,
+                })?
+            },
+        };
+
+        let res = Pointer::from(block).get();
+
+        unsafe {
+            // The buffer was just allocated.
+            self.options.apply(res, size);
+        }
+
+        self.track(res, size);
+
+        Ok(res)
+    }
+
+    /// Donate a region of memory to the arena.
+    ///
+    /// The region is added to the pool as free memory. `Err(())` is returned, if it overlaps the
+    /// free memory of the arena. See `ralloc::donate`.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, and not used by anything else, as long as
+    /// the arena (and the memory it handed out) is used.
+    pub unsafe fn donate(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        if size == 0 {
+            return Ok(());
+        }
+        if (ptr as usize).checked_add(size).is_none() {
+            return Err(());
+        }
+
+        let block = Block::from_raw_parts(Pointer::new(ptr), size);
+        if !self.is_allocated(&block) {
+            log!(WARNING, "Refusing the donation of {:?}, which overlaps the pool.", block);
+
+            return Err(());
+        }
+
+        // Logging.
+        log!(NOTE, "Taking the donation of {:?}.", block);
+
+        self.push(block);
+
+        Ok(())
+    }
+
+    /// Allocate a block of memory with some options.
+    ///
+    /// See `ralloc::alloc_with`.
+    pub fn alloc_with(&mut self, size: usize, options: &AllocOptions) -> *mut u8 {
+        let size = options.size(size);
+        let align = options.align(1);
+        if !is_possible(size, align) {
+            impossible(size, align);
+        }
+        if size == 0 {
+            return align as *mut u8;
+        }
+
+        let res = Pointer::from(Allocator::alloc(self, size, align)).get();
+
+        unsafe {
+            // The buffer was just allocated.
+            options.apply(res, size);
+        }
+
+        self.track(res, size);
+
+        res
+    }
+
+    /// Free a buffer allocated from the arena.
+    ///
+    /// See `ralloc::free`.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated from this arena, and must not be used afterwards.
+    pub unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        let size = self.options.size(size);
+        if size == 0 {
+            return;
+        }
+
+        // Freed early, the buffer is no longer freed along with its epoch.
+        self.untrack(ptr);
+
+        Allocator::free(self, Block::from_raw_parts(Pointer::new(ptr), size));
+    }
+
+    /// Hand a buffer allocated from the arena over to another arena.
+    ///
+    /// From now on, the buffer belongs to `target`, and is to be freed (or reallocated) through
+    /// it. If possible, the target adopts the buffer in place, taking over its share of the
+    /// acquired memory, and the pointer is returned as is. Otherwise (this arena has live
+    /// snapshots, or the buffer would exceed the budget of the target), the content is copied to
+    /// a new buffer allocated from the target, which is returned.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated from this arena, aligned to `align`, and both arenas
+    /// must have the same options (see `set_options`). An adopted buffer is the target's for
+    /// good, as with `donate`: Trimming might give it to the breaker of the target, so that
+    /// breaker must either take any region back (like `Mmap`), or refuse foreign ones (like
+    /// `Fixed`).
+    pub unsafe fn transfer<C: Breaker, Q: Policy>(&mut self, ptr: *mut u8, size: usize,
+                                                  align: usize, target: &mut Arena<C, Q>)
+                                                  -> *mut u8 {
+        let size = self.options.size(size);
+        // Zero-sized buffers are dangling, and belong to no arena.
+        if size == 0 {
+            return ptr;
+        }
+
+        if self.give(ptr, size, target) {
+            return ptr;
+        }
+
+        // Logging.
+        log!(NOTE, "Copying 0x{:x}[{}] to another arena.", ptr as usize, size);
+
+        let res = target.alloc(size, align);
+        ptr::copy_nonoverlapping(ptr, res, size);
+        self.free(ptr, size);
+
+        res
+    }
+
+    /// Reallocate a buffer allocated from the arena into another arena.
+    ///
+    /// This is `transfer` followed by `realloc` through the target, but the content is copied at
+    /// most once.
+    ///
+    /// # Safety
+    ///
+    /// See `transfer`.
+    pub unsafe fn realloc_into<C: Breaker, Q: Policy>(&mut self, ptr: *mut u8, old_size: usize,
+                                                      size: usize, align: usize,
+                                                      target: &mut Arena<C, Q>) -> *mut u8 {
+        let old_size = self.options.size(old_size);
+        if old_size == 0 {
+            return target.alloc(size, align);
+        }
+
+        if self.give(ptr, old_size, target) {
+            return target.realloc(ptr, old_size, size, align);
+        }
+
+        // Logging.
+        log!(NOTE, "Reallocating 0x{:x}[{}] into another arena.", ptr as usize, old_size);
+
+        let res = target.alloc(size, align);
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, target.options.size(size)));
+        self.free(ptr, old_size);
+
+        res
+    }
+
+    /// Let another arena adopt a buffer in place.
+    ///
+    /// `false` is returned, if the target cannot adopt it (see `transfer`), in which case it stays
+    /// in this arena.
+    fn give<C: Breaker, Q: Policy>(&mut self, ptr: *mut u8, size: usize,
+                                   target: &mut Arena<C, Q>) -> bool {
+        // The snapshots of this arena might roll back over the buffer.
+        if self.snapshots > 0 || size > target.budget.saturating_sub(target.acquired) {
+            return false;
+        }
+
+        // Logging.
+        log!(NOTE, "Handing 0x{:x}[{}] over to another arena.", ptr as usize, size);
+
+        self.untrack(ptr);
+        self.acquired = self.acquired.saturating_sub(size);
+        target.acquired += size;
+        target.track(ptr, size);
+
+        true
+    }
+
+    /// Reallocate a buffer allocated from the arena.
+    ///
+    /// See `ralloc::realloc`.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated from this arena.
+    pub unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                          -> *mut u8 {
+        let old_size = self.options.size(old_size);
+        let size = self.options.size(size);
+        let align = self.options.align(align);
+        if !is_possible(size, align) {
+            impossible(size, align);
+        }
+        // Zero-sized buffers take no memory.
+        if old_size == 0 {
+            return self.alloc(size, align);
+        }
+        if size == 0 {
+            self.free(ptr, old_size);
+            return align as *mut u8;
+        }
+
+        let res = Pointer::from(Allocator::realloc(
+            self,
+            Block::from_raw_parts(Pointer::new(ptr), old_size),
+            size,
+            align
+        )).get();
+
+        // The options apply to the new part of the buffer.
+        if size > old_size {
+            self.options.apply(res.offset(old_size as isize), size - old_size);
+        }
+
+        // The buffer stays in its epoch.
+        if let Some(i) = self.find_live(ptr) {
+            self.live[i] = Extent {
+                addr: res as usize,
+                size: size,
+            };
+        }
+
+        res
+    }
+
+    /// Try to reallocate a buffer allocated from the arena _inplace_.
+    ///
+    /// See `ralloc::realloc_inplace`.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated from this arena.
+    pub unsafe fn realloc_inplace(&mut self, ptr: *mut u8, old_size: usize, size: usize)
+                                  -> Result<(), ()> {
+        let old_size = self.options.size(old_size);
+        let size = self.options.size(size);
+        // Zero-sized buffers are dangling, and cannot be extended.
+        if old_size == 0 || size == 0 || !is_possible(size, 1) {
+            return if old_size == size { Ok(()) } else { Err(()) };
+        }
+
+        if Allocator::realloc_inplace(
+            self,
+            Block::from_raw_parts(Pointer::new(ptr), old_size),
+            size
+        ).is_ok() {
+            if let Some(i) = self.find_live(ptr) {
+                self.live[i].size = size;
+            }
+
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Take a snapshot of the state of the arena.
+    ///
+    /// The snapshot records the free memory of the arena, and from now on, the arena records the
+    /// memory it acquires from the breaker. With `restore`, the arena can later be rolled back to
+    /// this state, e.g. to undo the allocations of a test or a failed transaction.
+    ///
+    /// Trimming is suspended while there are live snapshots, so the memory of the arena stays
+    /// around.
+    pub fn snapshot(&mut self) -> Snapshot {
+        // Logging.
+        log!(NOTE, "Taking a snapshot of the arena.");
+
+        let mut free = unsafe {
+            // The block is fresh metadata, and the vector is empty.
+            Vec::from_raw_parts(meta::alloc(self.len() * mem::size_of::<Extent>(),
+                                            mem::align_of::<Extent>()), 0)
+        };
+        for block in self.inner.iter() {
+            free.push(Extent::of(block)).expect("The snapshot was allocated too small.");
+        }
+
+        self.snapshots += 1;
+
+        Snapshot {
+            free: free,
+            mark: self.fresh.len(),
+        }
+    }
+
+    /// Roll the arena back to the state of some snapshot.
+    ///
+    /// Every buffer allocated since the snapshot is freed, and every buffer freed since is
+    /// allocated again. For this to be sound, none of the buffers allocated since the snapshot
+    /// may be live, which is verified: If the memory free at the snapshot (or acquired since) is
+    /// not entirely free now, the arena is left as is, and `Err(())` is returned.
+    ///
+    /// The snapshot must have been taken of this arena.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), ()> {
+        // Logging.
+        log!(NOTE, "Restoring a snapshot of the arena.");
+
+        let res = {
+            // Check that nothing allocated since the snapshot is live.
+            let inner = &mut self.inner;
+            let fresh = &self.fresh[snapshot.mark..];
+
+            if snapshot.free.iter().chain(fresh.iter()).all(|e| inner.covers(&unsafe {
+                // The block is only used for searching.
+                e.block()
+            })) {
+                Ok(())
+            } else {
+                Err(())
+            }
+        };
+
+        if res.is_ok() {
+            // Rebuild the pool from the free memory of the snapshot, and the memory acquired
+            // since.
+            self.inner.clear();
+
+            for e in snapshot.free.iter() {
+                Allocator::free(self, unsafe {
+                    // The pool was cleared, and the region was free at the snapshot, and is not
+                    // used since.
+                    e.block()
+                });
+            }
+            for i in snapshot.mark..self.fresh.len() {
+                let e = self.fresh[i];
+                Allocator::free(self, unsafe {
+                    // The region was acquired since the snapshot, and is not used.
+                    e.block()
+                });
+            }
+        } else {
+            log!(WARNING, "Unable to restore the snapshot, as buffers allocated since are live.");
+        }
+
+        self.discard(snapshot);
+
+        res
+    }
+
+    /// Discard a snapshot of the arena, without restoring it.
+    pub fn discard(&mut self, snapshot: Snapshot) {
+        self.snapshots -= 1;
+        if self.snapshots == 0 {
+            // No snapshot needs the record anymore.
+            meta::free(Block::from(mem::replace(&mut self.fresh, Vec::default())));
+        }
+
+        meta::free(Block::from(snapshot.free));
+    }
+
+    /// Push an epoch.
+    ///
+    /// Every buffer allocated from the arena from now on belongs to the epoch, and is freed when
+    /// the epoch is popped, unless it was freed before. Epochs nest, and buffers belong to the
+    /// innermost live epoch.
+    pub fn epoch_push(&mut self) {
+        // Logging.
+        log!(DEBUG, "Pushing epoch {}.", self.epochs.len());
+
+        let mark = self.live.len();
+        push(&mut self.epochs, mark);
+    }
+
+    /// Pop the innermost epoch, freeing every buffer allocated in it, which is not freed yet.
+    ///
+    /// The buffers must not be used afterwards.
+    ///
+    /// # Panics
+    ///
+    /// This panics, if there is no live epoch.
+    pub fn epoch_pop(&mut self) {
+        let mark = self.epochs.pop().expect("Popping an epoch, but none was pushed.");
+
+        // Logging.
+        log!(DEBUG, "Popping epoch {}, freeing {} buffers.", self.epochs.len(),
+             self.live.len() - mark);
+
+        while self.live.len() > mark {
+            let e = self.live.pop().unwrap();
+            Allocator::free(self, unsafe {
+                // The buffer was allocated in the epoch, and was not freed since.
+                e.block()
+            });
+        }
+
+        if self.epochs.is_empty() {
+            // No epoch needs the records anymore.
+            meta::free(Block::from(mem::replace(&mut self.live, Vec::default())));
+            meta::free(Block::from(mem::replace(&mut self.epochs, Vec::default())));
+        }
+    }
+
+    /// Record a new buffer in the innermost live epoch, if any.
+    fn track(&mut self, ptr: *mut u8, size: usize) {
+        if !self.epochs.is_empty() {
+            push(&mut self.live, Extent {
+                addr: ptr as usize,
+                size: size,
+            });
+        }
+    }
+
+    /// Forget a buffer leaving the arena, if it was allocated in a live epoch.
+    fn untrack(&mut self, ptr: *mut u8) {
+        if let Some(i) = self.find_live(ptr) {
+            self.live.remove(i);
+            for mark in self.epochs.iter_mut().filter(|mark| **mark > i) {
+                *mark -= 1;
+            }
+        }
+    }
+
+    /// Acquire a region of at least `size` bytes from the breaker.
+    ///
+    /// `None` is returned, if the breaker fails, or the region would exceed the budget. The cause
+    /// is kept for `error`.
+    fn acquire(&mut self, size: usize) -> Option<(*mut u8, usize)> {
+        if size > self.budget.saturating_sub(self.acquired) {
+            // Logging.
+            log!(WARNING, "Acquiring {} bytes would exceed the budget of the arena.", size);
+
+            self.errno = 0;
+            return None;
+        }
+
+        match self.breaker.fresh(size) {
+            Ok((ptr, got)) if got >= size => {
+                self.acquired += got;
+
+                Some((ptr, got))
+            },
+            Ok(_) => {
+                self.errno = 0;
+                None
+            },
+            Err(err) => {
+                // Logging.
+                log!(WARNING, "The breaker failed with error {}.", err);
+
+                self.errno = err;
+                None
+            },
+        }
+    }
+
+    /// Get the error of a failed allocation of `size` bytes.
+    ///
+    /// If the breaker failed with an OS error, this is `Error::BreakerFailed`, and otherwise
+    /// `Error::OutOfMemory` (see `Breaker::fresh`).
+    pub fn error(&self, size: usize) -> fail::Error {
+        fail::acquire_error(self.errno, size, self.total_bytes())
+    }
+
+    /// Give a block of the pool back to the breaker.
+    ///
+    /// If the breaker refuses it, it is pushed back to the pool, and `Err(())` is returned.
+    fn release(&mut self, block: Block) -> Result<(), ()> {
+        let size = block.size();
+        let ptr = Pointer::from(block).get();
+
+        if self.breaker.release(ptr, size).is_ok() {
+            self.acquired -= size;
+
+            Ok(())
+        } else {
+            // Put it back.
+            // TODO: This can be done faster.
+            self.push(unsafe {
+                // The block was just popped from the pool.
+                Block::from_raw_parts(Pointer::new(ptr), size)
+            });
+
+            Err(())
+        }
+    }
+
+    /// Give as much free memory back to the breaker as possible.
+    ///
+    /// The last blocks of the pool are released, until the breaker refuses one. This is NOOP
+    /// while there are live snapshots.
+    pub fn trim(&mut self) {
+        // Logging.
+        log!(NOTE, "Trimming the arena.");
+
+        while self.trim_one() {}
+    }
+
+    /// Give the last free block back to the breaker.
+    ///
+    /// `false` is returned, if there is none, the breaker refuses it, or there are live
+    /// snapshots.
+    pub fn trim_one(&mut self) -> bool {
+        if self.snapshots > 0 {
+            return false;
+        }
+
+        match self.pop() {
+            Some(block) => self.release(block).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Give the free memory at the end of the pool back to the breaker, but the first `pad`
+    /// bytes of it.
+    ///
+    /// Without a pad, this is `trim`. Otherwise, only the last free block is trimmed, as the pad
+    /// is kept at the end. The block is split at a page boundary, so the breaker can release the
+    /// rest. `true` is returned, if any memory was given back.
+    pub fn trim_keeping(&mut self, pad: usize) -> bool {
+        if pad == 0 {
+            let res = self.trim_one();
+            self.trim();
+
+            return res;
+        }
+
+        if self.snapshots > 0 {
+            return false;
+        }
+
+        let block = match self.pop() {
+            Some(block) => block,
+            None => return false,
+        };
+
+        // Keep the pad, rounded up to whole pages.
+        let end = block.addr().saturating_add(pad).saturating_add(config::PAGE_SIZE - 1)
+            & !(config::PAGE_SIZE - 1);
+        let at = cmp::min(end - block.addr(), block.size());
+        let (keep, rest) = block.split(at);
+
+        if !keep.is_empty() {
+            self.push(keep);
+        }
+
+        !rest.is_empty() && self.release(rest).is_ok()
+    }
+
+    /// Find a buffer allocated in the live epochs.
+    ///
+    /// Recent buffers are more likely to be freed first, so the search starts from the end.
+    fn find_live(&self, ptr: *mut u8) -> Option<usize> {
+        self.live.iter().rposition(|e| e.addr == ptr as usize)
+    }
+}
+
+/// Push an element to a vector of metadata, growing it if needed.
+pub fn push<T: Leak + Copy>(vec: &mut Vec<T>, elem: T) {
+    if vec.push(elem).is_err() {
+        let cap = cmp::max(2 * vec.capacity(), 8);
+        let block = meta::alloc(cap * mem::size_of::<T>(), mem::align_of::<T>());
+        meta::free(vec.refill(block));
+
+        vec.push(elem).expect("The vector was grown too little.");
+    }
+}
+
+impl<B: Breaker, P: Policy> ops::Deref for Arena<B, P> {
+    type Target = Bookkeeper<P>;
+
+    fn deref(&self) -> &Bookkeeper<P> {
+        &self.inner
+    }
+}
+
+impl<B: Breaker, P: Policy> ops::DerefMut for Arena<B, P> {
+    fn deref_mut(&mut self) -> &mut Bookkeeper<P> {
+        &mut self.inner
+    }
+}
+
+impl<B: Breaker, P: Policy> Allocator<P> for Arena<B, P> {
+    #[inline]
+    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
+        match self.try_alloc_fresh(size, align) {
+            Some(res) => res,
+            None => fail::oom(self.error(size)),
+        }
+    }
+
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
+        // Acquire more than needed (as the growth policy says), to limit the number of calls to
+        // the breaker, as well as room for aligning the block.
+        let fresh_size = size + P::Growth::extra(size) + align;
+
+        // Logging.
+        log!(NOTE, "Acquiring {} fresh bytes.", fresh_size);
+
+        let (ptr, fresh_size) = match self.acquire(fresh_size) {
+            Some(res) => res,
+            None => {
+                // Give the free memory we can back, and settle for what is needed.
+                self.trim();
+
+                match self.acquire(size + align) {
+                    Some(res) => res,
+                    None => {
+                        log!(WARNING, "Unable to acquire {} fresh bytes ({} bytes free).",
+                             size + align, self.total_bytes());
+
+                        return None;
+                    },
+                }
+            },
+        };
+
+        if self.snapshots > 0 {
+            push(&mut self.fresh, Extent {
+                addr: ptr as usize,
+                size: fresh_size,
+            });
+        }
+
+        // Split it into the aligner, the result, and the excessive space.
+        let (alignment_block, rest) = unsafe {
+            // The breaker guarantees that the region is valid and unused.
+            Block::from_raw_parts(Pointer::new(ptr), fresh_size)
+        }.align(align).unwrap();
+        let (res, excessive) = rest.split(size);
+
+        // Add the rest to the pool.
+        self.push(alignment_block);
+        self.push(excessive);
+
+        Some(res)
+    }
+
+    fn on_new_memory(&mut self) {
+        // The snapshots rely on the memory staying around.
+        if self.total_bytes() > config::OS_MEMTRIM_LIMIT && self.snapshots == 0 {
+            // memtrim the fack outta 'em.
+
+            // Pop the last block.
+            let block = self.pop().expect("The byte count of the arena is invalid.");
+
+            // Check if the memtrim is worth it.
+            if block.size() >= config::OS_MEMTRIM_WORTHY {
+                /// Logging...
+                log!(NOTE, "Memtrimming the arena.");
+
+                // Give the block back to the breaker. If it fails, the block is put back.
+                let _ = self.release(block);
+
+                // Note that the last block is the only one, which the program break can take
+                // back, due to the segments being as long as possible. For that reason,
+                // repeating to push and release would fail.
+            } else {
+                /// Logging...
+                log!(WARNING, "Memtrimming the arena failed.");
+
+                // Push the block back.
+                // TODO: This can be done faster.
+                self.push(block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use sim::Simulated;
+
+    #[test]
+    fn test_alloc_free() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+
+        let a = arena.alloc(100, 8);
+        let b = arena.alloc(3000, 64);
+        assert!(b as usize % 64 == 0);
+
+        unsafe {
+            *a.offset(99) = 1;
+            *b.offset(2999) = 2;
+
+            let b = arena.realloc(b, 3000, 6000, 64);
+            assert_eq!(*b.offset(2999), 2);
+            assert!(arena.realloc_inplace(a, 100, 50).is_ok());
+
+            arena.free(a, 50);
+            arena.free(b, 6000);
+        }
+
+        arena.check_all();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+
+        let a = arena.alloc(100, 8);
+        let snapshot = arena.snapshot();
+        let b = arena.alloc(200, 8);
+        unsafe {
+            arena.free(b, 200);
+        }
+        assert!(arena.restore(snapshot).is_ok());
+
+        let snapshot = arena.snapshot();
+        let c = arena.alloc(300, 8);
+        assert!(arena.restore(snapshot).is_err());
+
+        unsafe {
+            arena.free(a, 100);
+            arena.free(c, 300);
+        }
+        arena.check_all();
+    }
+
+    #[test]
+    fn test_epochs() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+
+        arena.epoch_push();
+        let a = arena.alloc(100, 8);
+        let _ = arena.alloc(200, 8);
+        unsafe {
+            arena.free(a, 100);
+        }
+        arena.epoch_pop();
+
+        // Everything is free again.
+        assert!(arena.fresh.is_empty() && arena.live.is_empty());
+        arena.check_all();
+    }
+
+    #[test]
+    fn test_trim_keeping() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+
+        let a = arena.alloc(100, 8);
+        let b = arena.alloc(1 << 16, 8);
+        unsafe {
+            arena.free(b, 1 << 16);
+        }
+
+        let acquired = arena.acquired();
+        assert!(arena.trim_keeping(4096));
+        assert!(arena.acquired() < acquired);
+        // The pad is kept.
+        assert!(arena.total_bytes() >= 4096);
+
+        unsafe {
+            arena.free(a, 100);
+        }
+        arena.check_all();
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+        arena.set_budget(1 << 16);
+
+        let a = arena.try_alloc(1000, 8).unwrap();
+        assert!(arena.acquired() <= 1 << 16);
+        assert!(arena.try_alloc(1 << 16, 8).is_err());
+
+        unsafe {
+            arena.free(a, 1000);
+        }
+        arena.check_all();
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut a = Arena::new(Simulated::new(1 << 20));
+        let mut b = Arena::new(Simulated::new(1 << 20));
+
+        let x = a.alloc(1000, 8);
+        unsafe {
+            *x.offset(999) = 1;
+
+            // Adopted in place.
+            let acquired = a.acquired();
+            assert_eq!(a.transfer(x, 1000, 8, &mut b), x);
+            assert_eq!(a.acquired(), acquired - 1000);
+
+            // Moved along with the reallocation.
+            let x = b.realloc_into(x, 1000, 2000, 8, &mut a);
+            assert_eq!(*x.offset(999), 1);
+
+            // Copied, as the snapshot of `a` might roll back over the buffer.
+            let snapshot = a.snapshot();
+            let y = a.transfer(x, 2000, 8, &mut b);
+            assert!(y != x);
+            assert_eq!(*y.offset(999), 1);
+            a.discard(snapshot);
+
+            b.free(y, 2000);
+        }
+
+        a.check_all();
+        b.check_all();
+    }
+
+    #[test]
+    fn test_alloc_at() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+
+        // Nothing is acquired yet, so the range is taken from the breaker.
+        let next = arena.breaker().next().unwrap();
+        let a = unsafe { next.offset(256) };
+        assert_eq!(arena.alloc_at(a, 100, 4), Ok(a));
+
+        // The space before it is free, but it is taken now.
+        let b = unsafe { next.offset(128) };
+        assert_eq!(arena.alloc_at(b, 64, 8), Ok(b));
+        assert!(arena.alloc_at(b, 64, 8).is_err());
+        assert!(arena.alloc_at(unsafe { a.offset(50) }, 100, 1).is_err());
+
+        // Misaligned.
+        assert!(arena.alloc_at(unsafe { next.offset(1) }, 4, 2).is_err());
+
+        unsafe {
+            arena.free(a, 100);
+            arena.free(b, 64);
+        }
+        arena.check_all();
+    }
+
+    #[test]
+    fn test_small() {
+        let mut arena = Arena::new(Simulated::new(1 << 20));
+
+        let a = arena.alloc(1000, 8);
+        unsafe {
+            arena.free(a, 1000);
+        }
+
+        // Word-aligned chunks are carved from the front of the free block, one after another.
+        let b = arena.alloc(24, 8);
+        let c = arena.alloc(8, 1);
+        if !cfg!(feature = "randomize") {
+            assert_eq!(b, a);
+            assert_eq!(c, unsafe { a.offset(24) });
+        }
+
+        unsafe {
+            arena.free(b, 24);
+            arena.free(c, 8);
+        }
+        arena.check_all();
+    }
+}
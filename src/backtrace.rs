@@ -0,0 +1,97 @@
+//! Stack traces.
+//!
+//! The stack is walked through the frame pointers, so traces are only accurate when the program
+//! is compiled with frame pointers. No symbols are resolved; the trace consists of the raw return
+//! addresses.
+
+use core::mem;
+
+/// The number of frames recorded in a stack trace.
+pub const TRACE_LEN: usize = 8;
+
+/// An empty stack trace.
+pub const EMPTY_TRACE: Trace = Trace {
+    frames: [0; TRACE_LEN],
+};
+
+/// A stack trace.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Trace {
+    /// The return addresses, innermost first, zero-padded.
+    pub frames: [usize; TRACE_LEN],
+}
+
+impl Trace {
+    /// Capture the stack trace of the caller.
+    ///
+    /// The walk stops as soon as the chain looks invalid.
+    #[inline(never)]
+    pub fn capture() -> Trace {
+        let mut res = EMPTY_TRACE;
+
+        let mut fp = frame_pointer();
+        for frame in res.frames.iter_mut() {
+            // The frame records are aligned, and the stack grows downwards, so the chain must be
+            // ascending.
+            if fp == 0 || fp % mem::size_of::<usize>() != 0 {
+                break;
+            }
+
+            let (next, ret) = unsafe {
+                // The frame record holds the caller's frame pointer and the return address.
+                let record = fp as *const usize;
+                (*record, *record.offset(1))
+            };
+
+            *frame = ret;
+
+            if next <= fp {
+                break;
+            }
+            fp = next;
+        }
+
+        res
+    }
+
+    /// Get the recorded frames, innermost first.
+    pub fn frames(&self) -> &[usize] {
+        let len = self.frames.iter().take_while(|&&frame| frame != 0).count();
+        &self.frames[..len]
+    }
+}
+
+/// Get the frame pointer of the caller.
+#[inline(always)]
+#[cfg(target_arch = "x86_64")]
+fn frame_pointer() -> usize {
+    let res: usize;
+    unsafe {
+        // Reading a register has no side effects.
+        asm!("mov %rbp, $0" : "=r"(res));
+    }
+
+    res
+}
+
+/// Get the frame pointer of the caller.
+#[inline(always)]
+#[cfg(target_arch = "aarch64")]
+fn frame_pointer() -> usize {
+    let res: usize;
+    unsafe {
+        // Reading a register has no side effects.
+        asm!("mov $0, x29" : "=r"(res));
+    }
+
+    res
+}
+
+/// Get the frame pointer of the caller.
+///
+/// Stack traces are not supported on this platform.
+#[inline(always)]
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn frame_pointer() -> usize {
+    0
+}
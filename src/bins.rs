@@ -0,0 +1,334 @@
+//! Lock-free fast bins.
+//!
+//! With the `fast_bins` feature, the smallest buffers (up to `MAX_SIZE` bytes, aligned to no more
+//! than `GRANULE`) are kept in per-size-class free lists, which are Treiber stacks: Both allocating
+//! and freeing such a buffer is a single compare-and-swap on the head of its bin, so tiny objects
+//! never take the lock of the bookkeeper. Only when a bin is empty, a batch of objects is carved
+//! from the bookkeeper (see `allocator::raw_alloc`).
+//!
+//! The heads are tagged: Besides the address of the first object, they hold a counter, which is
+//! bumped by every push and pop. This way, a pop racing with a pop and a push of the same object
+//! (the ABA problem) fails its compare-and-swap, rather than installing a stale successor. The
+//! counter takes the bits above the addresses and the low bits, which are zero in the addresses
+//! of the objects, as they are aligned to `GRANULE`. On 64-bit targets, this gives 20 bits, so
+//! the counter only wraps after a million operations on the bin. The heads hold addresses rather
+//! than pointers, so this is unsupported where pointers are capabilities (e.g. CHERI).
+//!
+//! The stacks never block, so they are also the pools of interrupt handlers (see the `irq`
+//! module).
+//!
+//! Objects are never given back to the bookkeeper, so the successor read by a racing pop is
+//! always mapped, even if the object was taken in the meantime.
+
+use core::ptr;
+use core::sync::atomic::Ordering;
+#[cfg(target_pointer_width = "64")]
+use core::sync::atomic::AtomicUsize;
+#[cfg(not(target_pointer_width = "64"))]
+use core::sync::atomic::AtomicU64;
+
+/// The size of the objects of the smallest class, and the step between classes.
+///
+/// The objects are aligned to this.
+pub const GRANULE: usize = 16;
+/// The number of size classes.
+pub const CLASSES: usize = 4;
+/// The size of the objects of the largest class.
+pub const MAX_SIZE: usize = GRANULE * CLASSES;
+
+/// A tagged head: The address of the first object in the low bits, and the tag in the high bits
+/// and the alignment bits of the address.
+///
+/// Addresses take no more than 48 bits on 64-bit targets (i.e. without 5-level paging), leaving 16
+/// bits and the alignment bits to the tag.
+#[cfg(target_pointer_width = "64")]
+type Head = usize;
+/// A tagged head: The address of the first object in the low bits, and the tag in the high bits
+/// and the alignment bits of the address.
+#[cfg(not(target_pointer_width = "64"))]
+type Head = u64;
+/// An atomic tagged head.
+#[cfg(target_pointer_width = "64")]
+type AtomicHead = AtomicUsize;
+/// An atomic tagged head.
+#[cfg(not(target_pointer_width = "64"))]
+type AtomicHead = AtomicU64;
+
+/// The position of the tag in the heads.
+#[cfg(target_pointer_width = "64")]
+const TAG_SHIFT: u32 = 48;
+/// The position of the tag in the heads.
+#[cfg(not(target_pointer_width = "64"))]
+const TAG_SHIFT: u32 = 32;
+/// The number of alignment bits of the objects (i.e. the logarithm of `GRANULE`), which hold the
+/// low bits of the tag.
+const LOW_BITS: u32 = 4;
+/// The mask of the low bits of the tag.
+const LOW_MASK: Head = (1 << LOW_BITS) - 1;
+
+/// The bins, one per size class.
+static BINS: [Bin; CLASSES] = [Bin::new(), Bin::new(), Bin::new(), Bin::new()];
+
+/// A bin of free objects.
+pub struct Bin {
+    /// The tagged head of the stack (with a zero address if empty).
+    ///
+    /// Each free object stores the address of the next in its first word.
+    head: AtomicHead,
+}
+
+impl Bin {
+    /// Create an empty bin.
+    pub const fn new() -> Bin {
+        Bin {
+            head: AtomicHead::new(0),
+        }
+    }
+
+    /// Push an object.
+    ///
+    /// # Safety
+    ///
+    /// The object must be unused, of the size of the bin, and aligned to `GRANULE`.
+    pub unsafe fn push(&self, ptr: *mut u8) {
+        debug_assert!(addr(pack(ptr as usize, 0)) == ptr as usize, "The address 0x{:x} does not \
+                      fit in a tagged head.", ptr as usize);
+
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            *(ptr as *mut usize) = addr(head);
+
+            let new = pack(ptr as usize, head);
+            let prev = self.head.compare_and_swap(head, new, Ordering::Release);
+            if prev == head {
+                return;
+            }
+
+            head = prev;
+        }
+    }
+
+    /// Pop an object, if any.
+    pub fn pop(&self) -> Option<*mut u8> {
+        let mut head = self.head.load(Ordering::Acquire);
+
+        loop {
+            let ptr = addr(head);
+            if ptr == 0 {
+                return None;
+            }
+
+            let next = unsafe {
+                // The object stays mapped, as objects are never given back. If it was taken
+                // meanwhile, the value read is garbage, but the tag has changed, so it is
+                // discarded by the failing exchange.
+                ptr::read_volatile(ptr as *const usize)
+            };
+
+            let prev = self.head.compare_and_swap(head, pack(next, head), Ordering::Acquire);
+            if prev == head {
+                return Some(ptr as *mut u8);
+            }
+
+            head = prev;
+        }
+    }
+
+    /// Is the bin empty?
+    pub fn is_empty(&self) -> bool {
+        addr(self.head.load(Ordering::Acquire)) == 0
+    }
+
+    /// Count the objects.
+    ///
+    /// This walks the stack, and is only exact, if the bin is not used meanwhile.
+    pub fn len(&self) -> usize {
+        let mut res = 0;
+        let mut ptr = addr(self.head.load(Ordering::Acquire));
+
+        while ptr != 0 {
+            res += 1;
+            ptr = unsafe {
+                // Objects are never given back, so they stay mapped.
+                ptr::read_volatile(ptr as *const usize)
+            };
+        }
+
+        res
+    }
+}
+
+/// Get the address of a head.
+///
+/// The address is sign-extended, as the addresses of higher-half kernels have their upper bits
+/// set.
+#[inline]
+fn addr(head: Head) -> usize {
+    ((((head & !LOW_MASK) << (64 - TAG_SHIFT)) as i64) >> (64 - TAG_SHIFT)) as usize
+}
+
+/// Get the tag of a head.
+#[inline]
+fn tag(head: Head) -> Head {
+    (head >> TAG_SHIFT) << LOW_BITS | (head & LOW_MASK)
+}
+
+/// Make a head pointing to `addr`, with the tag following the one of `old`.
+///
+/// The alignment bits of `addr` are dropped.
+#[inline]
+fn pack(addr: usize, old: Head) -> Head {
+    let tag = tag(old).wrapping_add(1);
+
+    (tag >> LOW_BITS) << TAG_SHIFT | (addr as Head & ((1 << TAG_SHIFT) - 1) & !LOW_MASK)
+        | (tag & LOW_MASK)
+}
+
+/// Get the size class of buffers of `size` bytes, if binned.
+#[inline]
+fn class(size: usize) -> Option<usize> {
+    if cfg!(feature = "fast_bins") && size != 0 && size <= MAX_SIZE {
+        Some((size - 1) / GRANULE)
+    } else {
+        None
+    }
+}
+
+/// Are buffers of `size` bytes binned?
+///
+/// Such buffers are always whole objects (see `round`): They come from the bins, and are fixed in
+/// their class, so they are never resized in place (unless they stay in the class), split, or
+/// merged.
+#[inline]
+pub fn serves(size: usize) -> bool {
+    class(size).is_some()
+}
+
+/// Round a size up to the objects of its class.
+///
+/// Sizes which are not binned are left as they are.
+#[inline]
+pub fn round(size: usize) -> usize {
+    match class(size) {
+        Some(class) => (class + 1) * GRANULE,
+        None => size,
+    }
+}
+
+/// Do buffers of `a` and `b` bytes take the same objects?
+#[inline]
+pub fn same_class(a: usize, b: usize) -> bool {
+    class(a).is_some() && class(a) == class(b)
+}
+
+/// Pop an object of `size` bytes (as rounded by `round`) from its bin.
+///
+/// `None` is returned, if the bin is empty, or the size is not binned.
+#[inline]
+pub fn pop(size: usize) -> Option<*mut u8> {
+    class(size).and_then(|class| BINS[class].pop())
+}
+
+/// Push a buffer of `size` bytes to its bin.
+///
+/// # Safety
+///
+/// The buffer must be unused, binned (see `serves`), and a whole object, aligned to `GRANULE`.
+#[inline]
+pub unsafe fn push(ptr: *mut u8, size: usize) {
+    debug_assert!(ptr as usize % GRANULE == 0, "Binning 0x{:x}, which is not aligned.",
+                  ptr as usize);
+
+    let class = class(size).expect("Binning a buffer, which is too large.");
+    BINS[class].push(ptr);
+}
+
+/// Fill the bin of objects of `size` bytes with a chunk of `n` objects, but the first.
+///
+/// # Safety
+///
+/// The chunk must be unused, aligned to `GRANULE`, and given to the bins for good.
+pub unsafe fn refill(chunk: *mut u8, size: usize, n: usize) {
+    let size = round(size);
+
+    for i in 1..n {
+        push(chunk.offset((i * size) as isize), size);
+    }
+}
+
+/// Count the free objects in the fast bins.
+///
+/// Entry `n` counts the objects of `16 * (n + 1)` bytes. Without the `fast_bins` feature, the bins
+/// are always empty.
+pub fn fast_bins() -> [usize; CLASSES] {
+    let mut res = [0; CLASSES];
+
+    for (class, bin) in BINS.iter().enumerate() {
+        res[class] = bin.len();
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tagged_stack() {
+        // Room for three objects aligned to the granule.
+        let mut buf = [0u64; 8];
+        let ptr = ((&mut buf[0] as *mut u64 as usize + GRANULE - 1) & !(GRANULE - 1)) as *mut u8;
+        let bin = Bin::new();
+
+        assert!(bin.pop().is_none());
+
+        unsafe {
+            bin.push(ptr);
+            bin.push(ptr.offset(16));
+            bin.push(ptr.offset(32));
+        }
+        assert_eq!(bin.len(), 3);
+
+        // Last in, first out.
+        assert_eq!(bin.pop(), Some(unsafe { ptr.offset(32) }));
+        assert_eq!(bin.pop(), Some(unsafe { ptr.offset(16) }));
+
+        // Every exchange bumps the tag.
+        let head = bin.head.load(Ordering::Relaxed);
+        assert_eq!(addr(head), ptr as usize);
+        assert_eq!(tag(head), 5);
+
+        assert_eq!(bin.pop(), Some(ptr));
+        assert!(bin.pop().is_none());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_higher_half() {
+        let head = pack(0xffff_8000_0000_1000, 0);
+        assert_eq!(tag(head), 1);
+        assert_eq!(addr(head), 0xffff_8000_0000_1000);
+        assert_eq!(addr(pack(0x7fff_0000_1000, head)), 0x7fff_0000_1000);
+    }
+
+    #[test]
+    fn test_tag_carry() {
+        // The tag carries from the alignment bits into the high bits.
+        let head = pack(0x1000, LOW_MASK);
+        assert_eq!(tag(head), LOW_MASK + 1);
+        assert_eq!(head & LOW_MASK, 0);
+        assert_eq!(addr(head), 0x1000);
+        assert_eq!(tag(pack(0x2000, head)), LOW_MASK + 2);
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(round(1), if cfg!(feature = "fast_bins") { 16 } else { 1 });
+        assert_eq!(round(MAX_SIZE + 1), MAX_SIZE + 1);
+        assert!(!serves(0));
+        assert_eq!(same_class(17, 32), cfg!(feature = "fast_bins"));
+        assert!(!same_class(16, 17));
+    }
+}
@@ -9,6 +9,66 @@ use prelude::*;
 
 use core::{ptr, cmp, mem, fmt};
 
+/// The checksum of a block.
+///
+/// This is used for detecting stomped metadata. It is zero-sized, unless the `checksum` feature
+/// is enabled.
+#[cfg(feature = "checksum")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Checksum(usize);
+
+/// The checksum of a block.
+///
+/// This is used for detecting stomped metadata. It is zero-sized, unless the `checksum` feature
+/// is enabled.
+#[cfg(not(feature = "checksum"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Checksum;
+
+impl Checksum {
+    /// The checksum of empty blocks.
+    #[inline]
+    #[cfg(feature = "checksum")]
+    const fn empty() -> Checksum {
+        Checksum(0)
+    }
+
+    /// The checksum of empty blocks.
+    #[inline]
+    #[cfg(not(feature = "checksum"))]
+    const fn empty() -> Checksum {
+        Checksum
+    }
+
+    /// Calculate the checksum of a block with some size and pointer.
+    ///
+    /// The fields are mixed with a key, which is randomized by the address of this very function
+    /// (when the binary is position-independent), so an attacker cannot easily forge an entry.
+    #[inline]
+    #[cfg(feature = "checksum")]
+    fn new(ptr: &Pointer<u8>, size: usize) -> Checksum {
+        /// The multiplier spreading the bits of the size.
+        const MULTIPLIER: u64 = 0x9E3779B97F4A7C15;
+
+        if size == 0 {
+            Checksum::empty()
+        } else {
+            let key = Checksum::new as usize;
+
+            // The lowest bit is set, so it never equals the checksum of empty blocks.
+            Checksum((size.wrapping_mul(MULTIPLIER as usize) ^ ptr.addr().rotate_left(17)
+                      ^ key) | 1)
+        }
+    }
+
+    /// Calculate the checksum of a block with some size and pointer.
+    #[inline]
+    #[cfg(not(feature = "checksum"))]
+    fn new(_: &Pointer<u8>, _: usize) -> Checksum {
+        Checksum
+    }
+}
+
 /// A contiguous memory block.
 ///
 /// This provides a number of guarantees,
@@ -31,6 +91,8 @@ pub struct Block {
     size: usize,
     /// The pointer to the start of this block.
     ptr: Pointer<u8>,
+    /// The checksum of the size and the pointer.
+    checksum: Checksum,
 }
 
 impl Block {
@@ -39,17 +101,19 @@ impl Block {
     pub unsafe fn from_raw_parts(ptr: Pointer<u8>, size: usize) -> Block {
         Block {
             size: size,
+            checksum: Checksum::new(&ptr, size),
             ptr: ptr,
         }
     }
 
     /// Create an empty block starting at `ptr`.
     #[inline]
-    pub fn empty(ptr: Pointer<u8>) -> Block {
+    pub const fn empty(ptr: Pointer<u8>) -> Block {
         Block {
             size: 0,
             // This won't alias `ptr`, since the block is empty.
             ptr: ptr,
+            checksum: Checksum::empty(),
         }
     }
 
@@ -63,16 +127,13 @@ impl Block {
     #[inline]
     #[allow(cast_possible_wrap)]
     pub fn empty_right(&self) -> Block {
-        Block {
-            size: 0,
-            ptr: unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+        Block::empty(unsafe {
+            // LAST AUDIT: 2016-08-21 (Ticki).
 
-                // By the invariants of this type (the end is addressable), this conversion isn't
-                // overflowing.
-                self.ptr.clone().offset(self.size as isize)
-            },
-        }
+            // By the invariants of this type (the end is addressable), this conversion isn't
+            // overflowing.
+            self.ptr.clone().offset(self.size as isize)
+        })
     }
 
     /// Merge this block with a block to the right.
@@ -91,6 +152,7 @@ impl Block {
             // overflow.
             self.size += block.pop().size;
             // We pop it to make sure it isn't aliased.
+            self.checksum = Checksum::new(&self.ptr, self.size);
 
             Ok(())
         } else { Err(()) }
@@ -107,10 +169,42 @@ impl Block {
         self.size
     }
 
+    /// Get the address of the start of the block.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.ptr.addr()
+    }
+
+    /// Verify the checksum of this block.
+    ///
+    /// If the block was overwritten (e.g. by a buffer overflow in the program), a violation is
+    /// reported from `place`, and `false` is returned (if the policy is to quarantine). Unless the
+    /// `checksum` feature is enabled, this always succeeds.
+    #[inline]
+    pub fn verify(&self, place: &'static str) -> bool {
+        #[cfg(feature = "checksum")]
+        {
+            let expected = Checksum::new(&self.ptr, self.size);
+
+            // Logging.
+            if self.checksum != expected {
+                log!(ERROR, "The checksum of {:?} is 0x{:x}, expected 0x{:x}.", self,
+                     self.checksum.0, expected.0);
+            }
+
+            invariant!(self.checksum == expected, place, Some(self), "Corrupted bookkeeper entry")
+        }
+        #[cfg(not(feature = "checksum"))]
+        {
+            let _ = place;
+            true
+        }
+    }
+
     /// Is this block aligned to `align`?
     #[inline]
     pub fn aligned_to(&self, align: usize) -> bool {
-        self.ptr.get() as usize % align == 0
+        self.ptr.addr() % align == 0
     }
 
     /// memcpy the block to another pointer.
@@ -133,20 +227,20 @@ impl Block {
         }
     }
 
-    /// Volatile zero this memory if the `security` feature is set.
+    /// Volatile zero this memory.
+    ///
+    /// This is done to freed blocks, if the security policy says so (see `policy`).
     pub fn sec_zero(&mut self) {
         use core::intrinsics;
 
-        if cfg!(feature = "security") {
-            log!(INTERNAL, "Zeroing {:?}", *self);
+        log!(INTERNAL, "Zeroing {:?}", *self);
 
-            unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+        unsafe {
+            // LAST AUDIT: 2016-08-21 (Ticki).
 
-                // Since the memory of the block is inaccessible (read-wise), zeroing it is fully
-                // safe.
-                intrinsics::volatile_set_memory(self.ptr.get(), 0, self.size);
-            }
+            // Since the memory of the block is inaccessible (read-wise), zeroing it is fully
+            // safe.
+            intrinsics::volatile_set_memory(self.ptr.get(), 0, self.size);
         }
     }
 
@@ -162,7 +256,7 @@ impl Block {
     #[inline]
     pub fn left_to(&self, to: &Block) -> bool {
         // This won't overflow due to the end being bounded by the address space.
-        self.size + self.ptr.get() as usize == to.ptr.get() as usize
+        self.size + self.ptr.addr() == to.ptr.addr()
     }
 
     /// Split the block at some position.
@@ -175,22 +269,17 @@ impl Block {
     pub fn split(self, pos: usize) -> (Block, Block) {
         assert!(pos <= self.size, "Split {} out of bound (size is {})!", pos, self.size);
 
-        (
-            Block {
-                size: pos,
-                ptr: self.ptr.clone(),
-            },
-            Block {
-                size: self.size - pos,
-                ptr: unsafe {
-                    // LAST AUDIT: 2016-08-21 (Ticki).
-
-                    // This won't overflow due to the assertion above, ensuring that it is bounded
-                    // by the address space. See the `split_at_mut` source from libcore.
-                    self.ptr.offset(pos as isize)
-                },
-            }
-        )
+        unsafe {
+            // LAST AUDIT: 2016-08-21 (Ticki).
+
+            // This won't overflow due to the assertion above, ensuring that it is bounded by the
+            // address space. See the `split_at_mut` source from libcore. The two blocks are
+            // disjoint parts of `self`, hence they don't alias.
+            (
+                Block::from_raw_parts(self.ptr.clone(), pos),
+                Block::from_raw_parts(self.ptr.offset(pos as isize), self.size - pos),
+            )
+        }
     }
 
     /// Split this block, such that the second block is aligned to `align`.
@@ -207,8 +296,8 @@ impl Block {
 
         // Calculate the aligner, which defines the smallest size required as precursor to align
         // the block to `align`.
-        let aligner = (align - self.ptr.get() as usize % align) % align;
-        //                                                 ^^^^^^^^
+        let aligner = (align - self.ptr.addr() % align) % align;
+        //                                       ^^^^^^^^
         // To avoid wasting space on the case where the block is already aligned, we calculate it
         // modulo `align`.
 
@@ -217,22 +306,17 @@ impl Block {
             // Invalidate the old block.
             let old = self.pop();
 
-            Some((
-                Block {
-                    size: aligner,
-                    ptr: old.ptr.clone(),
-                },
-                Block {
-                    size: old.size - aligner,
-                    ptr: unsafe {
-                        // LAST AUDIT: 2016-08-21 (Ticki).
-
-                        // The aligner is bounded by the size, which itself is bounded by the
-                        // address space. Therefore, this conversion cannot overflow.
-                        old.ptr.offset(aligner as isize)
-                    },
-                }
-            ))
+            unsafe {
+                // LAST AUDIT: 2016-08-21 (Ticki).
+
+                // The aligner is bounded by the size, which itself is bounded by the address
+                // space. Therefore, this conversion cannot overflow. The two blocks are disjoint
+                // parts of `old`, hence they don't alias.
+                Some((
+                    Block::from_raw_parts(old.ptr.clone(), aligner),
+                    Block::from_raw_parts(old.ptr.offset(aligner as isize), old.size - aligner),
+                ))
+            }
         } else {
             // Logging.
             log!(INTERNAL, "Unable to align block.");
@@ -298,7 +382,58 @@ impl cmp::Eq for Block {}
 
 impl fmt::Debug for Block {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "0x{:x}[{}]", self.ptr.get() as usize, self.size)
+        write!(f, "0x{:x}[{}]", self.ptr.addr(), self.size)
+    }
+}
+
+impl Block {
+    /// Get a hex dump of the first `len` bytes (at most) of the block.
+    ///
+    /// The dump is formatted through `Debug`, sixteen bytes per line, after the block itself.
+    /// This shows what stomped some memory (e.g. a string or pointers), without a debugger.
+    ///
+    /// # Safety
+    ///
+    /// The bytes dumped must be valid for reads, while the dump is formatted.
+    pub unsafe fn dump(&self, len: usize) -> Dump {
+        Dump {
+            block: self,
+            len: cmp::min(len, self.size),
+        }
+    }
+}
+
+/// A bounded hex dump of a block.
+///
+/// See `Block::dump`.
+pub struct Dump<'a> {
+    /// The block.
+    block: &'a Block,
+    /// The number of bytes to dump.
+    len: usize,
+}
+
+impl<'a> fmt::Debug for Dump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.block)?;
+
+        for offset in 0..self.len {
+            if offset % 16 == 0 {
+                write!(f, "\n    +0x{:04x}:", offset)?;
+            }
+
+            let byte = unsafe {
+                // The creator of the dump guarantees that the bytes are readable.
+                ptr::read_volatile(self.block.ptr.get().offset(offset as isize))
+            };
+            write!(f, " {:02x}", byte)?;
+        }
+
+        if self.len < self.block.size {
+            write!(f, "\n    ... ({} more bytes)", self.block.size - self.len)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -306,6 +441,18 @@ impl fmt::Debug for Block {
 mod test {
     use prelude::*;
 
+    #[test]
+    fn test_dump() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+
+        // The dump is bounded by the block.
+        assert_eq!(unsafe { block.dump(18) }.len, 18);
+        assert_eq!(unsafe { block.dump(100) }.len, 26);
+    }
+
     #[test]
     fn test_array() {
         let arr = b"Lorem ipsum dolor sit amet";
@@ -367,6 +514,24 @@ mod test {
         assert_eq!(arr, [0, 2, 0, 2, 255, 255]);
     }
 
+    #[test]
+    fn test_checksum() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), arr.len())
+        };
+
+        let (mut lorem, mut rest) = block.split(5);
+        assert!(lorem.verify("test"));
+        assert!(rest.verify("test"));
+
+        // The checksum follows the size.
+        lorem.merge_right(&mut rest).unwrap();
+        assert!(lorem.verify("test"));
+        assert!(rest.verify("test"));
+        assert!(lorem.align(4).unwrap().1.verify("test"));
+    }
+
     #[test]
     fn test_empty_lr() {
         let arr = b"Lorem ipsum dolor sit amet";
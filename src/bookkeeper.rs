@@ -2,23 +2,18 @@
 
 use prelude::*;
 
-use core::ops::Range;
-use core::{ptr, mem, ops};
+use core::{cmp, fmt, mem, ops};
+use core::fmt::Write;
+use core::marker::PhantomData;
 
-use shim::config;
+use conf;
+use fail::ReportWriter;
+use policy::{DefaultPolicy, FitPolicy, SecurityPolicy, Policy};
+use rand::Rng;
+use segment::{self, Iter, Pool, Position};
+use stats::{self, PoolOp};
 
-/// Elements required _more_ than the length as capacity.
-///
-/// This represents how many elements that are needed to conduct a `reserve` without the
-/// stack overflowing, plus one (representing the new element):
-///
-/// 1. Aligner.
-/// 2. Excessive space.
-/// 3. The old buffer.
-/// 4. The pushed or inserted block.
-///
-/// See assumption 4.
-pub const EXTRA_ELEMENTS: usize = 4;
+use shim::config;
 
 #[cfg(feature = "alloc_id")]
 use core::sync::atomic::{self, AtomicUsize};
@@ -28,149 +23,150 @@ use core::sync::atomic::{self, AtomicUsize};
 #[cfg(feature = "alloc_id")]
 static BOOKKEEPER_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+/// The maximum number of entries shown by the `Debug` implementation of the bookkeeper.
+const DEBUG_ENTRIES: usize = 64;
+/// The maximum number of bytes of a block dumped along with a violation found by `check_all`.
+const DUMP_BYTES: usize = 64;
+
 /// The memory bookkeeper.
 ///
 /// This stores data about the state of the allocator, and in particular, the free memory.
 ///
-/// The actual functionality is provided by [`Allocator`](./trait.Allocator.html).
-pub struct Bookkeeper {
+/// The actual functionality is provided by [`Allocator`](./trait.Allocator.html). The bookkeeper
+/// is tuned at compile time by the policy, `P` (see the `policy` module).
+pub struct Bookkeeper<P: Policy = DefaultPolicy> {
     /// The internal block pool.
     ///
-    /// Entries in the block pool can be "empty", meaning that you can overwrite the entry without
-    /// breaking consistency.
+    /// The block pool is dense, that is, every entry is a free block. It is partitioned into
+    /// address segments, see the `segment` module.
+    ///
+    /// Emptied entries are removed right away (see `remove_at`). The segments emptied this way are
+    /// kept, but the pool indexes the non-empty ones, so going to a neighbor never scans over
+    /// empty entries.
     ///
     /// # Assumptions
     ///
     /// Certain assumptions are made:
     ///
-    /// 1. The list is always sorted with respect to the block's pointers.
-    /// 2. No two consecutive or empty block delimited blocks are adjacent, except if the right
-    ///    block is empty.
-    /// 3. There are no trailing empty blocks.
-    /// 4. The capacity is always `EXTRA_ELEMENTS` blocks more than the length (this is due to
-    ///    reallocation pushing at maximum two elements, so we reserve two or more extra to allow
-    ///    pushing one additional element without unbounded recursion).
+    /// 1. The pool is always sorted with respect to the block's pointers.
+    /// 2. No two consecutive blocks are adjacent.
+    /// 3. There are no empty blocks.
     ///
     /// These are **not** invariants: If these assumpptions are not held, it will simply act strange
     /// (e.g. logic bugs), but not memory unsafety.
-    pool: Vec<Block>,
+    pool: Pool<P::Storage>,
     /// The total number of bytes in the pool.
     total_bytes: usize,
-    /// Is this bookkeeper currently reserving?
+    /// The random number generator.
     ///
-    /// This is used to avoid unbounded metacircular reallocation (reservation).
+    /// This is only used by random fit policies.
+    rng: Rng,
+    /// The number of operations since the last full consistency check.
     ///
-    // TODO: Find a replacement for this "hack".
-    reserving: bool,
+    /// This is only maintained in debug mode.
+    ops: usize,
     /// The allocator ID.
     ///
     /// This is simply to be able to distinguish allocators in the locks.
     #[cfg(feature = "alloc_id")]
     id: usize,
+    /// The policy.
+    policy: PhantomData<P>,
 }
 
 #[allow(len_without_is_empty)]
-impl Bookkeeper {
-    /// Create a new bookkeeper with some initial vector.
-    pub fn new(vec: Vec<Block>) -> Bookkeeper {
-        // Make sure the assumptions are satisfied.
-        debug_assert!(vec.capacity() >= EXTRA_ELEMENTS, "Not enough initial capacity of the vector.");
-        debug_assert!(vec.is_empty(), "Initial vector isn't empty.");
-
+impl<P: Policy> Bookkeeper<P> {
+    /// Create a new, empty bookkeeper.
+    pub fn new() -> Bookkeeper<P> {
         // TODO: When added use expr field attributes.
         #[cfg(feature = "alloc_id")]
         let res = Bookkeeper {
-            pool: vec,
+            pool: Pool::new(),
             total_bytes: 0,
-            reserving: false,
+            rng: Rng::new(),
+            ops: 0,
             // Increment the ID counter to get a brand new ID.
             id: BOOKKEEPER_ID_COUNTER.fetch_add(1, atomic::Ordering::SeqCst),
+            policy: PhantomData,
         };
         #[cfg(not(feature = "alloc_id"))]
         let res = Bookkeeper {
-            pool: vec,
+            pool: Pool::new(),
             total_bytes: 0,
-            reserving: false,
+            rng: Rng::new(),
+            ops: 0,
+            policy: PhantomData,
         };
 
         bk_log!(res, "Bookkeeper created.");
-        res.check();
+        res.check_all();
 
         res
     }
 
-    /// Perform a binary search to find the appropriate place where the block can be insert or is
-    /// located.
+    /// Find the appropriate place where the block can be insert or is located.
     ///
-    /// It is guaranteed that no block left to the returned value, satisfy the above condition.
+    /// It is guaranteed that no block left to the returned position, satisfy the above condition.
     #[inline]
-    fn find(&mut self, block: &Block) -> usize {
+    fn find(&mut self, block: &Block) -> Position {
         // Logging.
         bk_log!(self, "Searching (exact) for {:?}.", block);
 
-        let ind = match self.pool.binary_search(block) {
-            Ok(x) | Err(x) => x,
-        };
-        let len = self.pool.len();
+        let pos = self.pool.find(block);
 
-        // Move left.
-        ind - self.pool.iter_mut()
-            .rev()
-            .skip(len - ind)
-            .take_while(|x| x.is_empty())
-            .count()
+        // Verify the entries around the position, as the result depends on them.
+        if cfg!(feature = "checksum") {
+            if let Some(bad) = self.corrupted_neighbor(pos, "find") {
+                self.quarantine(bad);
+                return self.find(block);
+            }
+        }
+
+        pos
     }
 
-    /// Perform a binary search to find the appropriate bound where the block can be insert or is
-    /// located.
+    /// Find a neighbor of some position with an invalid checksum.
     ///
-    /// It is guaranteed that no block left to the returned value, satisfy the above condition.
-    #[inline]
-    fn find_bound(&mut self, block: &Block) -> Range<usize> {
-        // Logging.
-        bk_log!(self, "Searching (bounds) for {:?}.", block);
+    /// Violations are reported from `place`, so this only returns `Some` if the violation policy
+    /// is to quarantine.
+    fn corrupted_neighbor(&self, pos: Position, place: &'static str) -> Option<Position> {
+        let left = self.pool.prev(pos);
+        let right = self.pool.next(pos);
 
-        let mut left_ind = match self.pool.binary_search(block) {
-            Ok(x) | Err(x) => x,
-        };
-
-        let len = self.pool.len();
-
-        // Move left.
-        left_ind -= self.pool.iter_mut()
-            .rev()
-            .skip(len - left_ind)
-            .take_while(|x| x.is_empty())
-            .count();
+        for &i in [left, right].iter() {
+            if let Some(i) = i {
+                if !self.pool[i].verify(place) {
+                    return Some(i);
+                }
+            }
+        }
 
-        let mut right_ind = match self.pool.binary_search(&block.empty_right()) {
-            Ok(x) | Err(x) => x,
-        };
+        None
+    }
 
-        // Move right.
-        right_ind += self.pool.iter()
-            .skip(right_ind)
-            .take_while(|x| x.is_empty())
-            .count();
+    /// Quarantine a corrupted entry.
+    ///
+    /// The entry is removed from the pool, leaking the memory it (supposedly) represents. As its
+    /// size cannot be trusted, the byte count is recalculated.
+    #[cold]
+    fn quarantine(&mut self, pos: Position) {
+        // Logging.
+        bk_log!(self;pos, "Quarantining the entry at {:?}.", pos);
 
-        left_ind..right_ind
+        let _ = self.pool.remove(pos);
+        self.total_bytes = self.pool.iter().map(Block::size).sum();
     }
 
     /// Go over every block in the allocator and call some function.
     ///
     /// Technically, this could be done through an iterator, but this, more unidiomatic, way is
     /// slightly faster in some cases.
-    pub fn for_each<F: FnMut(Block)>(mut self, mut f: F) {
+    pub fn for_each<F: FnMut(Block)>(self, f: F) {
         // Logging.
         bk_log!(self, "Iterating over the blocks of the bookkeeper...");
 
-        // Run over all the blocks in the pool.
-        for i in self.pool.pop_iter() {
-            f(i);
-        }
-
-        // Take the block holding the pool.
-        f(Block::from(self.pool));
+        // Run over all the blocks in the pool, and then the blocks holding the pool.
+        self.pool.for_each(f);
     }
 
     /// Pop the top block from the pool.
@@ -186,6 +182,55 @@ impl Bookkeeper {
         })
     }
 
+    /// Is a block free of the pool?
+    ///
+    /// That is, the block does not overlap any free block, as is the case for allocated buffers.
+    pub fn is_allocated(&mut self, block: &Block) -> bool {
+        let pos = self.find(block);
+
+        // The pool is sorted, so only the neighbors of the position can overlap the block.
+        self.pool.prev(pos).map_or(true, |left| self.pool[left].empty_right() <= *block)
+            && self.pool.next(pos).map_or(true, |right| block.empty_right() <= self.pool[right])
+    }
+
+    /// Is a block entirely free?
+    ///
+    /// That is, the block is contained in a single free block of the pool.
+    pub fn covers(&mut self, block: &Block) -> bool {
+        let pos = self.find(block);
+        let end = block.addr() + block.size();
+
+        // The containing block starts at or left to the block, so it is either the entry at the
+        // position, or the one before it.
+        [self.pool.prev(pos), self.pool.next(pos)].iter().any(|&i| i.map_or(false, |i| {
+            let entry = &self.pool[i];
+            entry.addr() <= block.addr() && end <= entry.addr() + entry.size()
+        }))
+    }
+
+    /// Iterate over the free blocks of the pool, in address order.
+    pub fn iter(&self) -> Iter {
+        self.pool.iter()
+    }
+
+    /// Go over the metadata of the pool.
+    ///
+    /// See `Pool::for_each_meta`.
+    pub fn for_each_meta<F: FnMut(usize, usize)>(&self, f: F) {
+        self.pool.for_each_meta(f);
+    }
+
+    /// Empty the pool.
+    ///
+    /// The blocks of the pool are forgotten, and its metadata is given back.
+    pub fn clear(&mut self) {
+        // Logging.
+        bk_log!(self, "Clearing the pool.");
+
+        mem::replace(&mut self.pool, Pool::new()).for_each(|_| ());
+        self.total_bytes = 0;
+    }
+
     /// Get the length of the pool.
     pub fn len(&self) -> usize {
         self.pool.len()
@@ -196,79 +241,224 @@ impl Bookkeeper {
         self.total_bytes
     }
 
-    /// Perform consistency checks.
+    /// Count an operation, and perform a full consistency check every
+    /// `config::FULL_CHECK_INTERVAL` operations.
+    ///
+    /// This is NOOP, unless the checks are enabled (see `conf::checks`).
+    fn check(&mut self) {
+        if conf::checks() {
+            self.ops += 1;
+
+            if self.ops >= config::FULL_CHECK_INTERVAL {
+                self.ops = 0;
+                self.check_all();
+            }
+        }
+    }
+
+    /// Perform consistency checks around some position.
+    ///
+    /// This checks the conditions of `check_all` for the entry before the position and the two
+    /// entries at and after it, i.e. the entries touched by an operation at the position. This
+    /// takes constant time, so it is done after every operation, while the full check is done
+    /// periodically (see `check`).
+    ///
+    /// This is NOOP, unless the checks are enabled (see `conf::checks`).
+    fn check_at(&mut self, pos: Position) {
+        if conf::checks() {
+            // Logging.
+            bk_log!(self;pos, "Checking locally...");
+
+            // The previous block.
+            let mut prev: Option<&Block> = None;
+            // The position of the current block.
+            let mut cur = self.pool.prev(pos).or_else(|| self.pool.next(pos));
+
+            for _ in 0..3 {
+                let n = match cur {
+                    Some(n) => n,
+                    None => break,
+                };
+                let i = &self.pool[n];
+
+                // Make sure there are no empty blocks.
+                invariant!(!i.is_empty(), "check_at", Some(i), "Empty block in the pool");
+
+                if let Some(prev) = prev {
+                    // Check if sorted.
+                    invariant!(i > prev, "check_at", Some(i), "The block pool is not sorted");
+                    // Make sure no blocks are adjacent.
+                    invariant!(!prev.left_to(i), "check_at", Some(i),
+                               "Adjacent blocks in the pool");
+                }
+
+                prev = Some(i);
+                cur = self.pool.next(Position {
+                    seg: n.seg,
+                    ind: n.ind + 1,
+                });
+            }
+        }
+
+        self.check();
+    }
+
+    /// Perform full consistency checks.
     ///
     /// This will check for the following conditions:
     ///
     /// 1. The list is sorted.
     /// 2. No blocks are adjacent.
+    /// 3. No blocks are empty.
+    /// 4. The segments are consistent.
+    ///
+    /// This walks the whole pool, so it is only done periodically (see `check`), or on demand.
     ///
-    /// This is NOOP in release mode.
-    fn check(&self) {
-        if cfg!(debug_assertions) {
+    /// This is NOOP, unless the checks are enabled (see `conf::checks`).
+    pub fn check_all(&self) {
+        if conf::checks() {
             // Logging.
             bk_log!(self, "Checking...");
 
             // The total number of bytes.
             let mut total_bytes = 0;
-            // Reverse iterator over the blocks.
-            let mut it = self.pool.iter().enumerate().rev();
-
-            // Check that the capacity is large enough.
-            assert!(self.reserving || self.pool.len() + EXTRA_ELEMENTS <= self.pool.capacity(),
-                    "The capacity should be at least {} more than the length of the pool.",
-                    EXTRA_ELEMENTS);
+            // The previous block.
+            let mut prev: Option<&Block> = None;
 
-            if let Some((_, x)) = it.next() {
-                // Make sure there are no leading empty blocks.
-                assert!(!x.is_empty(), "The leading block is empty.");
+            for (n, i) in self.pool.iter().enumerate() {
+                total_bytes += i.size();
 
-                total_bytes += x.size();
+                // Logging.
+                bk_log!(self, "Checking {:?} (ordinal {}).", i, n);
 
-                let mut next = x;
-                for (n, i) in it {
-                    total_bytes += i.size();
+                // Make sure there are no empty blocks.
+                self.check_invariant(!i.is_empty(), Some(i), "Empty block in the pool");
 
+                if let Some(prev) = prev {
                     // Check if sorted.
-                    assert!(next >= i, "The block pool is not sorted at index, {} ({:?} < {:?}).",
-                            n, next, i);
+                    self.check_invariant(i > prev, Some(i), "The block pool is not sorted");
                     // Make sure no blocks are adjacent.
-                    assert!(!i.left_to(next) || i.is_empty(), "Adjacent blocks at index, {} ({:?} and \
-                            {:?})", n, i, next);
-                    // Make sure an empty block has the same address as its right neighbor.
-                    assert!(!i.is_empty() || i == next, "Empty block not adjacent to right neighbor \
-                            at index {} ({:?} and {:?})", n, i, next);
-
-                    // Set the variable tracking the previous block.
-                    next = i;
+                    self.check_invariant(!prev.left_to(i), Some(i), "Adjacent blocks in the pool");
                 }
 
-                // Check for trailing empty blocks.
-                assert!(!self.pool.last().unwrap().is_empty(), "Trailing empty blocks.");
+                prev = Some(i);
             }
 
+            // Check the segments.
+            self.pool.check();
+
             // Make sure the sum is maintained properly.
-            assert!(total_bytes == self.total_bytes, "The sum is not equal to the 'total_bytes' \
-                    field: {} ≠ {}.", total_bytes, self.total_bytes);
+            log!(INTERNAL, "The sum is {}, and the 'total_bytes' field is {}.", total_bytes,
+                 self.total_bytes);
+            self.check_invariant(total_bytes == self.total_bytes, None,
+                                 "The sum is not equal to the 'total_bytes' field");
         }
     }
+
+    /// Check an invariant of the pool (see `check_all`).
+    ///
+    /// If it is violated, the pool (see the `Debug` implementation) and the start of the block
+    /// involved are reported (regardless of the logging configuration) before the violation, so
+    /// the corruption can be diagnosed without a debugger.
+    fn check_invariant(&self, holds: bool, block: Option<&Block>, desc: &'static str) -> bool {
+        if !holds {
+            let _ = writeln!(ReportWriter, "{:?}", self);
+            if let Some(block) = block {
+                let _ = writeln!(ReportWriter, "{:?}", unsafe {
+                    // The blocks of the pool are free memory of the allocator.
+                    block.dump(DUMP_BYTES)
+                });
+            }
+        }
+
+        invariant!(holds, "check", block, desc)
+    }
+
+    /// Check that a block fits in between the neighbors of some position.
+    ///
+    /// That is, the block must not overlap its neighbors, which must be sorted around it. This is
+    /// a cheap, local version of `check`, catching corruption (like double frees) early. Unlike
+    /// `check`, this is done in release mode too, when the security policy says so (e.g. with the
+    /// `paranoid` feature).
+    ///
+    /// Violations are reported from `place`. If the violation policy is to quarantine, `false` is
+    /// returned, and the block is to be leaked.
+    fn check_neighbors(&self, pos: Position, block: &Block, place: &'static str) -> bool {
+        if P::Security::CHECK_NEIGHBORS || conf::checks() {
+            if let Some(left) = self.pool.prev(pos) {
+                // Logging.
+                bk_log!(self;left, "Checking {:?} against its left neighbor.", block);
+
+                if !invariant!(self.pool[left].empty_right() <= *block, place, Some(block),
+                               "The block overlaps or precedes its left neighbor") {
+                    return false;
+                }
+            }
+            if let Some(right) = self.pool.next(pos) {
+                // Logging.
+                bk_log!(self;right, "Checking {:?} against its right neighbor.", block);
+
+                if !invariant!(block.empty_right() <= self.pool[right], place, Some(block),
+                               "The block overlaps or follows its right neighbor") {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// The pool is shown as a table of its entries (with their index, block, and size), flagging the
+/// entries breaking the assumptions of the pool: `EMPTY` for empty blocks, `UNSORTED` for blocks
+/// not following the previous entry, and `ADJACENT` for blocks adjacent to it. Only the first
+/// `DEBUG_ENTRIES` entries are listed, so the output stays bounded.
+impl<P: Policy> fmt::Debug for Bookkeeper<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bookkeeper ({} entries, {} bytes):", self.len(), self.total_bytes)?;
+
+        let mut prev: Option<&Block> = None;
+        for (n, block) in self.pool.iter().enumerate().take(DEBUG_ENTRIES) {
+            write!(f, "\n    #{:<5} {:?} ({} bytes)", n, block, block.size())?;
+
+            if block.is_empty() {
+                f.write_str(" EMPTY")?;
+            }
+            if let Some(prev) = prev {
+                if block <= prev {
+                    f.write_str(" UNSORTED")?;
+                }
+                if prev.left_to(block) {
+                    f.write_str(" ADJACENT")?;
+                }
+            }
+
+            prev = Some(block);
+        }
+
+        if self.len() > DEBUG_ENTRIES {
+            write!(f, "\n    ... ({} more entries)", self.len() - DEBUG_ENTRIES)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// An allocator.
 ///
-/// This provides the functionality of the memory bookkeeper, requiring only provision of two
+/// This provides the functionality of the memory bookkeeper, requiring only provision of three
 /// methods, defining the "breaker" (fresh allocator). The core functionality is provided by
 /// default methods, which aren't generally made to be overwritten.
 ///
 /// The reason why these methods aren't implemented directly on the bookkeeper is the distinction
 /// between different forms of allocators (global, local, and so on). Any newtype of
-/// [`Bookkeeper`](./struct.Bookkeeper.html).
+/// [`Bookkeeper`](./struct.Bookkeeper.html), with the policy `P`.
 ///
 /// # Guarantees vs. assumptions
 ///
 /// Please note that whenever a guarantee is mentioned, it relies on that the all the methods
 /// overwritten are upholding the guarantees specified in the documentation.
-pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
+pub trait Allocator<P: Policy = DefaultPolicy>: ops::DerefMut<Target = Bookkeeper<P>> {
     /// Allocate _fresh_ space.
     ///
     /// "Fresh" means that the space is allocated through some breaker (be it SBRK or the global
@@ -283,6 +473,14 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     /// prior to call of this function, it should be too after it.
     fn alloc_fresh(&mut self, size: usize, align: usize) -> Block;
 
+    /// Try to allocate a chunk of fresh space from the upstream allocator.
+    ///
+    /// This is like `alloc_fresh`, but `None` is returned, if no fresh memory is available (e.g.
+    /// due to a limit), instead of calling the OOM handler. By default, this never fails.
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
+        Some(self.alloc_fresh(size, align))
+    }
+
     /// Called right before new memory is added to the pool.
     fn on_new_memory(&mut self) {}
 
@@ -329,56 +527,232 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     ///
     /// A block representing the marked area is then returned.
     fn alloc(&mut self, size: usize, align: usize) -> Block {
+        match self.alloc_free(size, align) {
+            Some(res) => res,
+            // No fitting block found. Allocate a new block.
+            None => self.alloc_external(size, align),
+        }
+    }
+
+    /// Try to allocate a chunk of memory.
+    ///
+    /// This is like `alloc`, but `None` is returned, if no fresh memory is available (see
+    /// `try_alloc_fresh`).
+    fn try_alloc(&mut self, size: usize, align: usize) -> Option<Block> {
+        match self.alloc_free(size, align) {
+            Some(res) => Some(res),
+            None => self.try_alloc_external(size, align),
+        }
+    }
+
+    /// Allocate a chunk of memory, keeping the slack.
+    ///
+    /// This is like `try_alloc`, but if the free block left after the split would be smaller than
+    /// `config::MIN_SPLIT`, it is handed out along with the requested space, so the returned block
+    /// can be larger than `size`.
+    fn alloc_excess(&mut self, size: usize, align: usize) -> Option<Block> {
+        match self.take_free(size, align, config::MIN_SPLIT) {
+            Some(res) => Some(res),
+            None => self.try_alloc_external(size, align),
+        }
+    }
+
+    /// Take a given range out of the pool.
+    ///
+    /// If the block is contained in a single free block, it is split out of it, and returned.
+    /// Otherwise (the range is not entirely free), the block is given back as an error.
+    fn alloc_at(&mut self, block: Block) -> Result<Block, Block> {
+        // Logging.
+        bk_log!(self, "Allocating {:?} at a fixed address.", block);
+
+        let pos = self.find(&block);
+        let end = block.addr() + block.size();
+
+        // The containing block is either the entry at the position, or the one before it (see
+        // `covers`).
+        let found = [self.pool.prev(pos), self.pool.next(pos)].iter().filter_map(|&i| i)
+            .find(|&i| {
+                let entry = &self.pool[i];
+                entry.addr() <= block.addr() && end <= entry.addr() + entry.size()
+            });
+        let entry = match found {
+            Some(i) => self.remove_at(i),
+            None => return Err(block),
+        };
+
+        // Split off the space around the range, and give it back.
+        let (front, rest) = entry.split(block.addr() - entry.addr());
+        let (res, back) = rest.split(block.size());
+        record_split(&[front.size(), back.size()]);
+        self.free(front);
+        self.free(back);
+
+        // Check consistency.
+        self.check();
+
+        Ok(res)
+    }
+
+    /// Allocate a chunk of memory from the free blocks of the pool.
+    ///
+    /// `None` is returned, if no free block fits.
+    fn alloc_free(&mut self, size: usize, align: usize) -> Option<Block> {
+        self.take_free(size, align, 0)
+    }
+
+    /// Take a chunk of memory from the free blocks of the pool.
+    ///
+    /// The excessive space after the chunk is kept in the chunk, if smaller than `keep`, and freed
+    /// otherwise. `None` is returned, if no free block fits.
+    fn take_free(&mut self, size: usize, align: usize, keep: usize) -> Option<Block> {
         // Logging.
         bk_log!(self, "Allocating {} bytes with alignment {}.", size, align);
 
-        if let Some((n, b)) = self.pool.iter_mut().enumerate().filter_map(|(n, i)| {
-            if i.size() >= size {
-                // Try to split at the aligner.
-                i.align(align).and_then(|(mut a, mut b)| {
-                    if b.size() >= size {
-                        // Override the old block.
-                        *i = a;
-                        Some((n, b))
-                    } else {
-                        // Put the split block back together and place it back in its spot.
-                        a.merge_right(&mut b).expect("Unable to merge block right.");
-                        *i = a;
-                        None
+        // With a random fit policy, we skip a random number of fitting blocks, making the heap
+        // layout unpredictable.
+        let mut skip = if P::Fit::CANDIDATES > 1 {
+            self.rng.below(P::Fit::CANDIDATES)
+        } else { 0 };
+        // The last fitting block, which was skipped.
+        let mut skipped = None;
+
+        // Find the first fitting block (which isn't skipped).
+        let mut found = None;
+        let mut cur = self.pool.first();
+        while let Some(pos) = cur {
+            {
+                let i = &mut self.pool[pos];
+                if i.size() >= size {
+                    if skip == 0 && align <= mem::align_of::<usize>() && i.aligned_to(align) {
+                        // Fast path: The block is aligned already (which is the common case for
+                        // word-aligned requests), so there is no aligner to split off.
+                        found = Some((pos, i.pop()));
+                    } else if let Some((mut a, mut b)) = i.align(align) {
+                        // Try to split at the aligner.
+                        if b.size() >= size && skip == 0 {
+                            // Override the old block.
+                            *i = a;
+                            found = Some((pos, b));
+                        } else {
+                            if b.size() >= size {
+                                skip -= 1;
+                                skipped = Some(pos);
+                            }
+
+                            // Put the split block back together and place it back in its spot.
+                            a.merge_right(&mut b).expect("Unable to merge block right.");
+                            *i = a;
+                        }
                     }
-                })
-            } else {
-                None
+                }
+            }
+
+            if found.is_some() {
+                break;
             }
-        }).next() {
+
+            cur = self.pool.next(Position {
+                seg: pos.seg,
+                ind: pos.ind + 1,
+            });
+
+            if cur.is_none() && skip > 0 {
+                // There were fewer fitting blocks than we wanted to skip, so we take the last.
+                cur = skipped.take();
+                skip = 0;
+            }
+        }
+
+        if let Some((pos, b)) = found {
             // Update the pool byte count.
             self.total_bytes -= b.size();
 
-            if self.pool[n].is_empty() {
-                // For empty alignment invariant.
-                let _ = self.remove_at(n);
+            // With a random fit policy, we take the end of the block half of the time.
+            let offset = if P::Fit::RANDOM_END && self.rng.next() & 1 == 1 {
+                // The block is aligned, so stepping by multiples of the alignment keeps it so.
+                (b.size() - size) / align * align
+            } else { 0 };
+
+            // The aligner stays in the entry, unless it is empty.
+            let aligner = self.pool[pos].size();
+
+            if offset == 0 && self.pool[pos].is_empty() {
+                // The chunk is taken from the front of the entry, so the remainder can stay in
+                // its spot, rather than being removed and searched for again.
+                return Some(self.take_front(pos, b, size, keep));
+            }
+
+            if self.pool[pos].is_empty() {
+                // The aligner is empty, so we remove its entry to keep the pool dense.
+                let _ = self.remove_at(pos);
             }
 
             // Split and mark the block uninitialized to the debugger.
-            let (res, excessive) = b.mark_uninitialized().split(size);
+            let (front, rest) = b.mark_uninitialized().split(offset);
+            let (mut res, mut excessive) = rest.split(size);
 
             // There are many corner cases that make knowing where to insert it difficult
             // so we search instead.
-            self.free(excessive);
+            let front_size = front.size();
+            self.free(front);
+            if excessive.size() < keep {
+                // The remainder is too small to be worth keeping, so the caller gets it.
+                record_split(&[aligner, front_size]);
+                res.merge_right(&mut excessive).expect("Unable to merge block right.");
+            } else {
+                record_split(&[aligner, front_size, excessive.size()]);
+                self.free(excessive);
+            }
 
             // Check consistency.
             self.check();
             debug_assert!(res.aligned_to(align), "Alignment failed.");
-            debug_assert!(res.size() == size, "Requested space does not match with the returned \
-                          block.");
+            debug_assert!(res.size() >= size && res.size() < size + cmp::max(keep, 1),
+                          "Requested space does not match with the returned block.");
 
-            res
+            Some(res)
         } else {
-            // No fitting block found. Allocate a new block.
-            self.alloc_external(size, align)
+            None
         }
     }
 
+    /// Take a chunk from the front of a free block, whose entry has been emptied.
+    ///
+    /// The remainder is put back in the entry at `pos`, unless it is smaller than `keep` (in
+    /// which case it is kept in the chunk), empty (in which case the entry is removed), or starts
+    /// in a later segment (in which case the entry is removed, and the remainder freed).
+    fn take_front(&mut self, pos: Position, block: Block, size: usize, keep: usize) -> Block {
+        let seg = segment::segment_of(&block);
+        let (mut res, mut excessive) = block.mark_uninitialized().split(size);
+
+        if excessive.size() < cmp::max(keep, 1) {
+            // The remainder is too small to be worth keeping, so the caller gets it.
+            res.merge_right(&mut excessive).expect("Unable to merge block right.");
+            let _ = self.remove_at(pos);
+        } else if segment::segment_of(&excessive) != seg {
+            record_split(&[excessive.size()]);
+
+            // The remainder belongs to another segment, so it is inserted there.
+            let _ = self.remove_at(pos);
+            self.free(excessive);
+        } else {
+            record_split(&[excessive.size()]);
+
+            // Update the pool byte count.
+            self.total_bytes += excessive.size();
+
+            // The remainder is within the old block and its segment, so it keeps the pool sorted.
+            self.pool[pos] = excessive.mark_free();
+        }
+
+        // Check consistency.
+        self.check();
+        debug_assert!(res.size() >= size && res.size() < size + cmp::max(keep, 1),
+                      "Requested space does not match with the returned block.");
+
+        res
+    }
+
     /// Free a memory block.
     ///
     /// After this have been called, no guarantees are made about the passed pointer. If it want
@@ -426,11 +800,46 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // Just logging for the unlucky people debugging this shit. No problem.
         bk_log!(self, "Freeing {:?}...", block);
 
-        // Binary search for the block.
-        let bound = self.find_bound(&block);
+        // Short circuit in case of empty block.
+        if block.is_empty() { return; }
+
+        // Trigger the new memory event handler.
+        self.on_new_memory();
+
+        // Make room for the block, in case it cannot be merged. This might touch the pool, so it
+        // must precede the search.
+        self.reserve(&block);
+
+        // Search for the block.
+        let pos = self.find(&block);
 
         // Free the given block.
-        self.free_bound(bound, block);
+        self.free_at(pos, block);
+    }
+
+    /// Free a batch of blocks.
+    ///
+    /// The blocks must be sorted by address, and adjacent blocks must be merged beforehand. This
+    /// is like calling `free` on each, but the new memory handler is only triggered once, after
+    /// the whole batch is in the pool.
+    fn free_sorted<I: Iterator<Item = Block>>(&mut self, blocks: I) {
+        // Logging.
+        bk_log!(self, "Freeing a batch of blocks...");
+
+        for block in blocks {
+            // Short circuit in case of empty block.
+            if block.is_empty() { continue; }
+
+            // Make room for the block, in case it cannot be merged.
+            self.reserve(&block);
+
+            // Search for the block, and free it.
+            let pos = self.find(&block);
+            self.free_at(pos, block);
+        }
+
+        // Trigger the new memory event handler.
+        self.on_new_memory();
     }
 
     /// Reallocate memory.
@@ -465,14 +874,14 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     /// deallocate the old one, after which we use memmove to copy the data over to the newly
     /// allocated list.
     fn realloc(&mut self, block: Block, new_size: usize, align: usize) -> Block {
-        // Find the index bound.
-        let ind = self.find_bound(&block);
+        // Find the position.
+        let pos = self.find(&block);
 
         // Logging.
-        bk_log!(self;ind, "Reallocating {:?} to size {} with align {}...", block, new_size, align);
+        bk_log!(self;pos, "Reallocating {:?} to size {} with align {}...", block, new_size, align);
 
         // Try to do an inplace reallocation.
-        match self.realloc_inplace_bound(ind, block, new_size) {
+        match self.realloc_inplace_at(pos, block, new_size) {
             Ok(block) => block,
             Err(block) => {
                 // Reallocation cannot be done inplace.
@@ -505,19 +914,19 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     ///
     /// On failure, return `Err(Block)` with the old _intact_ block. Shrinking cannot fail.
     ///
-    /// This shouldn't be used when the index of insertion is known, since this performs an binary
-    /// search to find the blocks index. When you know the index use
-    /// [`realloc_inplace_bound`](#method.realloc_inplace_bound.html).
+    /// This shouldn't be used when the position of insertion is known, since this performs a
+    /// search to find the blocks position. When you know the position use
+    /// [`realloc_inplace_at`](#method.realloc_inplace_at.html).
     #[inline]
     fn realloc_inplace(&mut self, block: Block, new_size: usize) -> Result<Block, Block> {
         // Logging.
         bk_log!(self, "Reallocating {:?} inplace to {}...", block, new_size);
 
-        // Find the bounds of given block.
-        let bound = self.find_bound(&block);
+        // Find the position of given block.
+        let pos = self.find(&block);
 
         // Go for it!
-        let res = self.realloc_inplace_bound(bound, block, new_size);
+        let res = self.realloc_inplace_at(pos, block, new_size);
 
         // Check consistency.
         debug_assert!(res.as_ref().ok().map_or(true, |x| x.size() == new_size), "Requested space \
@@ -526,25 +935,27 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         res
     }
 
-    /// Reallocate a block on a know index bound inplace.
+    /// Reallocate a block on a know position inplace.
     ///
     /// See [`realloc_inplace`](#method.realloc_inplace.html) for more information.
-    fn realloc_inplace_bound(&mut self, ind: Range<usize>, mut block: Block, new_size: usize) -> Result<Block, Block> {
+    fn realloc_inplace_at(&mut self, pos: Position, mut block: Block, new_size: usize) -> Result<Block, Block> {
         // Logging.
-        bk_log!(self;ind, "Try inplace reallocating {:?} to size {}.", block, new_size);
+        bk_log!(self;pos, "Try inplace reallocating {:?} to size {}.", block, new_size);
 
         /// Assertions...
-        debug_assert!(self.find(&block) == ind.start, "Block is not inserted at the appropriate \
-                      index.");
+        debug_assert!(self.find(&block) == pos, "Block is not inserted at the appropriate \
+                      position.");
 
         if new_size <= block.size() {
             // Shrink the block.
-            bk_log!(self;ind, "Shrinking {:?}.", block);
+            bk_log!(self;pos, "Shrinking {:?}.", block);
 
             // Split the block in two segments, the main segment and the excessive segment.
             let (block, excessive) = block.split(new_size);
-            // Free the excessive segment.
-            self.free_bound(ind, excessive);
+            record_split(&[excessive.size()]);
+            // Free the excessive segment. Note that it might belong to another address segment,
+            // so we search for it again.
+            self.free(excessive);
 
             // Make some assertions to avoid dumb bugs.
             debug_assert!(block.size() == new_size, "Block wasn't shrinked properly.");
@@ -553,33 +964,28 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             self.check();
 
             return Ok(block);
+        }
 
-            // We check if `ind` is the end of the array.
-        } else {
-            let mut mergable = false;
-            if let Some(entry) = self.pool.get_mut(ind.end) {
-                mergable = entry.size() + block.size() >= new_size && block.left_to(entry);
-            }
-            // Note that we are sure that no segments in the array are adjacent (unless they have size
-            // 0). This way we know that we will, at maximum, need one and only one block for extending
-            // the current block.
-            if mergable {
+        // The block to the right, possibly in a later segment.
+        if let Some(right) = self.pool.next(pos) {
+            // Note that we are sure that no blocks in the pool are adjacent. This way we know
+            // that we will, at maximum, need one and only one block for extending the current
+            // block.
+            if self.pool[right].size() + block.size() >= new_size && block.left_to(&self.pool[right]) {
                 // Logging...
-                bk_log!(self;ind, "Merging {:?} to the right.", block);
+                bk_log!(self;right, "Merging {:?} to the right.", block);
 
-                // We'll merge it with the block at the end of the range.
-                block.merge_right(&mut self.remove_at(ind.end))
+                // We'll merge it with the block to the right, taking it out of the pool.
+                block.merge_right(&mut self.remove_at(right))
                     .expect("Unable to merge block right, to the end of the range.");
                 // Merge succeeded.
+                stats::record_pool(PoolOp::Merge);
 
-                // Place the excessive block back.
+                // Free the excessive space. It might start in another segment than the block we
+                // merged, so we search for it again.
                 let (res, excessive) = block.split(new_size);
-                // Remove_at may have shortened the vector.
-                if ind.start == self.pool.len() {
-                    self.push(excessive);
-                } else if !excessive.is_empty() {
-                    self.pool[ind.start] = excessive;
-                }
+                record_split(&[excessive.size()]);
+                self.free(excessive);
                 // Block will still not be adjacent, due to `excessive` being guaranteed to not be
                 // adjacent to the next block.
 
@@ -590,57 +996,110 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             }
         }
 
+        // There is no free block to grow into.
+        stats::record_pool(PoolOp::FailedMerge);
+
         Err(block)
     }
 
-    /// Free a block placed in some index bound.
+    /// Free a block placed at some position.
     ///
-    /// This will at maximum insert one element.
+    /// This will at maximum insert one element. Room for it must have been reserved beforehand.
     ///
     /// See [`free`](#method.free) for more information.
     #[inline]
-    fn free_bound(&mut self, ind: Range<usize>, mut block: Block) {
+    fn free_at(&mut self, pos: Position, mut block: Block) {
         // Logging.
-        bk_log!(self;ind, "Freeing {:?}.", block);
+        bk_log!(self;pos, "Freeing {:?}.", block);
 
         // Short circuit in case of empty block.
         if block.is_empty() { return; }
 
-        // When compiled with `security`, we zero this block.
-        block.sec_zero();
+        // If the security policy says so (e.g. with `security`), we zero this block.
+        if P::Security::ZERO_ON_FREE {
+            block.sec_zero();
+        }
+
+        // Assertions...
+        debug_assert!(self.find(&block) == pos, "Block is not inserted at the appropriate \
+                      position.");
+
+        // Make sure the neighbors are intact, before merging with them.
+        if cfg!(feature = "checksum") {
+            if let Some(bad) = self.corrupted_neighbor(pos, "free_at") {
+                self.quarantine(bad);
 
-        if ind.start == self.pool.len() {
-            self.push(block);
+                let pos = self.find(&block);
+                return self.free_at(pos, block);
+            }
+        }
+        if !self.check_neighbors(pos, &block, "free_at") {
+            // Leak the block.
             return;
         }
 
-        // Assertions...
-        debug_assert!(self.find(&block) == ind.start, "Block is not inserted at the appropriate \
-                      index.");
+        // The neighbors of the block, which might live in other segments.
+        let left = self.pool.prev(pos);
+        let right = self.pool.next(pos);
+
+        let merge_left = left.map_or(false, |left| self.pool[left].left_to(&block));
 
         // Try to merge it with the block to the right.
-        if ind.end < self.pool.len() && block.left_to(&self.pool[ind.end]) {
-            // Merge the block with the rightmost block in the range.
-            block.merge_right(&mut self.remove_at(ind.end))
-                .expect("Unable to merge block right to the block at the end of the range");
+        if let Some(right) = right {
+            if block.left_to(&self.pool[right]) {
+                // Update the pool byte count.
+                self.total_bytes += block.size();
+
+                // Merge the block with the block to the right.
+                block.merge_right(&mut self.pool[right])
+                    .expect("Unable to merge block right to the block at the position");
+                stats::record_pool(PoolOp::Merge);
+
+                // The merging succeeded. We proceed to try to close in the possible gap.
+                if merge_left {
+                    self.pool[left.unwrap()].merge_right(&mut block)
+                        .expect("Unable to merge block left to the block before the position");
+                    stats::record_pool(PoolOp::Merge);
+
+                    // The entry of the right block has been emptied by the merge, so we remove it.
+                    let _ = self.remove_at(right);
+                } else if right == pos {
+                    // Put the merged block back in its spot.
+                    self.pool[pos] = block;
+                } else {
+                    // The block to the right lives in a later segment, but the merged block
+                    // belongs to the segment of `block`, so we move it over.
+                    let _ = self.remove_at(right);
+
+                    self.total_bytes -= block.size();
+                    self.insert(pos, block);
+                }
 
-            // The merging succeeded. We proceed to try to close in the possible gap.
-            if ind.start != 0 && self.pool[ind.start - 1].merge_right(&mut block).is_ok() {
                 // Check consistency.
-                self.check();
+                self.check_at(pos);
 
                 return;
             }
+        }
+
         // Dammit, let's try to merge left.
-        } else if ind.start != 0 && self.pool[ind.start - 1].merge_right(&mut block).is_ok() {
+        if merge_left {
+            // Update the pool byte count.
+            self.total_bytes += block.size();
+
+            self.pool[left.unwrap()].merge_right(&mut block)
+                .expect("Unable to merge block left to the block before the position");
+            stats::record_pool(PoolOp::Merge);
+
             // Check consistency.
-            self.check();
+            self.check_at(pos);
 
             return;
         }
 
         // Well, it failed, so we insert it the old-fashioned way.
-        self.insert(ind.start, block);
+        stats::record_pool(PoolOp::FailedMerge);
+        self.insert(pos, block);
 
         // Check consistency.
         self.check();
@@ -664,115 +1123,55 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         res
     }
 
-    /// Push an element without reserving.
-    // TODO: Make `push` and `free` one.
-    fn push(&mut self, block: Block) {
+    /// Try to allocate external ("fresh") space.
+    ///
+    /// See `alloc_external` and `try_alloc_fresh`.
+    fn try_alloc_external(&mut self, size: usize, align: usize) -> Option<Block> {
         // Logging.
-        bk_log!(self;self.pool.len(), "Pushing {:?}.", block);
-
-        // Mark the block free.
-        let mut block = block.mark_free();
-
-        // Short-circuit in case on empty block.
-        if !block.is_empty() {
-            // Trigger the new memory event handler.
-            self.on_new_memory();
-
-            // Update the pool byte count.
-            self.total_bytes += block.size();
-
-            // Some assertions...
-            debug_assert!(self.pool.is_empty() || &block > self.pool.last().unwrap(), "Pushing will \
-                          make the list unsorted.");
-
-            // We will try to simply merge it with the last block.
-            if let Some(x) = self.pool.last_mut() {
-                if x.merge_right(&mut block).is_ok() {
-                    return;
-                }
-            }
-
-            // Reserve space and free the old buffer.
-            if let Some(x) = unborrow!(self.reserve(self.pool.len() + 1)) {
-                // Note that we do not set the count down because this isn't setting back our
-                // pushed block.
-
-                self.free(x);
-            }
-
-            // Try again to merge with last block on the off chance reserve pushed something we can
-            // merge with. This has actually happened in testing.
-            if let Some(x) = self.pool.last_mut() {
-                if x.merge_right(&mut block).is_ok() {
-                    return;
-                }
-            }
+        bk_log!(self, "Fallible fresh allocation of size {} with alignment {}.", size, align);
 
+        let res = self.try_alloc_fresh(size, align);
 
-            // Merging failed. Note that trailing empty blocks are not allowed, hence the last block is
-            // the only non-empty candidate which may be adjacent to `block`.
+        // Check consistency.
+        self.check();
 
-            // Check again that pushing is correct.
-            if self.pool.is_empty() || &block > self.pool.last().unwrap() {
-                // We push.
-                let res = self.pool.push(block);
+        res
+    }
 
-                // Make some assertions.
-                debug_assert!(res.is_ok(), "Push failed (buffer full).");
-            } else {
-                // `free` handles the count, so we set it back.
-                // TODO: Find a better way to do so.
-                self.total_bytes -= block.size();
+    /// Push a block fresh from the breaker to the pool.
+    ///
+    /// Only the program break is guaranteed to grow upwards, so the block might be placed
+    /// anywhere in the pool.
+    fn push(&mut self, block: Block) {
+        // Logging.
+        bk_log!(self, "Pushing {:?}.", block);
 
-                // Can't push because reserve changed the end of the pool.
-                self.free(block);
-            }
-        }
+        // Mark the block free.
+        let block = block.mark_free();
 
-        // Check consistency.
-        self.check();
+        // The segments make pushing no different from freeing.
+        self.free(block);
     }
 
-    /// Reserve some number of elements, and return the old buffer's block.
+    /// Make room for a block in the pool.
+    ///
+    /// The metadata is taken from the metadata arena, which lives in its own region, apart from
+    /// the blocks of the pool. Hence, this never recurses.
     ///
     /// # Assumptions
     ///
-    /// This is assumed to not modify the order. If some block `b` is associated with index `i`
-    /// prior to call of this function, it should be too after it.
-    fn reserve(&mut self, min_cap: usize) -> Option<Block> {
+    /// This might add segments, thus invalidating positions.
+    fn reserve(&mut self, block: &Block) {
         // Logging.
-        bk_log!(self;min_cap, "Reserving {}.", min_cap);
-
-        if !self.reserving && (self.pool.capacity() < self.pool.len() + EXTRA_ELEMENTS || self.pool.capacity() < min_cap + EXTRA_ELEMENTS) {
-            // Reserve a little extra for performance reasons.
-            // TODO: This should be moved to some new method.
-            let new_cap = min_cap + EXTRA_ELEMENTS + config::extra_fresh(min_cap);
+        bk_log!(self, "Reserving room for {:?}.", block);
 
-            // Catch 'em all.
-            debug_assert!(new_cap > self.pool.capacity(), "Reserve shrinks?!");
-
-            // Make sure no unbounded reallocation happens.
-            self.reserving = true;
-
-            // Break it to me!
-            let new_buf = self.alloc_external(new_cap * mem::size_of::<Block>(), mem::align_of::<Block>());
-
-            // Go back to the original state.
-            self.reserving = false;
-
-            // Check consistency.
-            self.check();
-
-            Some(self.pool.refill(new_buf))
-        } else {
-            None
-        }
+        self.pool.make_room(block);
     }
 
-    /// Insert a block entry at some index.
+    /// Insert a block entry at some position.
     ///
-    /// If the space is non-empty, the elements will be pushed filling out the empty gaps to the
-    /// right.
+    /// The elements at and after `pos` in the same segment will be shifted to the right,
+    /// extending the segment by one entry. Room must have been reserved beforehand.
     ///
     /// # Warning
     ///
@@ -780,12 +1179,12 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     ///
     /// # Panics
     ///
-    /// Panics on when `ind` is greater than the block pool's length.
+    /// Panics if `pos` is not in the segment of `block`.
     ///
     /// # Example
     ///
-    /// We want to insert the block denoted by the tildes into our list. Perform a binary search to
-    /// find where insertion is appropriate.
+    /// We want to insert the block denoted by the tildes into our list. Search to find where
+    /// insertion is appropriate.
     ///
     /// ```notrust
     ///    Address space
@@ -797,31 +1196,20 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     ///                  I~~~~~~~~~~I
     /// ```
     ///
-    /// We keep pushing the blocks to the right to the next entry until a empty entry is reached:
-    ///
-    /// ```notrust
-    ///    Address space
-    ///   I------I
-    /// B < here                      I--------I <~ this one cannot move down, due to being blocked.
-    /// l
-    /// k                                              I------------I <~ thus we have moved this one down.
-    /// s                                                             I---I
-    ///              I~~~~~~~~~~I
-    /// ```
-    ///
-    /// Repeating yields:
+    /// We memmove the blocks to the right of the position (in the same segment) one entry down,
+    /// leaving a gap:
     ///
     /// ```notrust
     ///    Address space
     ///   I------I
     /// B < here
-    /// l                             I--------I <~ this one cannot move down, due to being blocked.
-    /// k                                              I------------I <~ thus we have moved this one down.
+    /// l                             I--------I
+    /// k                                              I------------I
     /// s                                                             I---I
     ///              I~~~~~~~~~~I
     /// ```
     ///
-    /// Now an empty space is left out, meaning that we can insert the block:
+    /// Now we can insert the block:
     ///
     /// ```notrust
     ///    Address space
@@ -834,123 +1222,59 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     ///
     /// The insertion is now completed.
     #[inline]
-    fn insert(&mut self, ind: usize, block: Block) {
+    fn insert(&mut self, pos: Position, block: Block) {
         // Logging.
-        bk_log!(self;ind, "Inserting block {:?}...", block);
-
-        // Bound check.
-        assert!(self.pool.len() >= ind, "Insertion out of bounds.");
+        bk_log!(self;pos, "Inserting block {:?}...", block);
 
         // Some assertions...
-        debug_assert!(self.pool.len() <= ind || block <= self.pool[ind], "Inserting at {} will make \
-                      the list unsorted.", ind);
-        debug_assert!(self.find(&block) == ind, "Block is not inserted at the appropriate index.");
+        if !self.check_neighbors(pos, &block, "insert") {
+            // Leak the block.
+            return;
+        }
+        debug_assert!(self.find(&block) == pos, "Block is not inserted at the appropriate position.");
         debug_assert!(!block.is_empty(), "Inserting an empty block.");
 
-        // Trigger the new memory event handler.
-        self.on_new_memory();
-
-        // Find the next gap, where a used block were.
-        let gap = self.pool
-            .iter()
-            .enumerate()
-            // We only check _after_ the index.
-            .skip(ind)
-            // Until the block is empty.
-            .filter(|&(_, x)| x.is_empty())
-            .next()
-            .map(|(n, _)| n);
-
-        // Log the operation.
-        bk_log!(self;ind, "Moving all blocks right to {} blocks to the right.",
-             gap.unwrap_or_else(|| self.pool.len()));
-
-        // The old vector's buffer.
-        let mut old_buf = None;
-
-        unsafe {
-            // LAST AUDIT: 2016-08-21 (Ticki).
-
-            // Memmove the elements to make a gap to the new block.
-            ptr::copy(self.pool.get_unchecked(ind) as *const Block,
-                      self.pool.get_unchecked_mut(ind + 1) as *mut Block,
-                      // The gap defaults to the end of the pool.
-                      gap.unwrap_or_else(|| {
-                          // We will only extend the length if we were unable to fit it into the current length.
-
-                          // Loooooooging...
-                          bk_log!(self;ind, "Block pool not long enough for shift. Extending.");
-
-                          // Reserve space. This does not break order, due to the assumption that
-                          // `reserve` never breaks order.
-                          old_buf = unborrow!(self.reserve(self.pool.len() + 1));
-
-                          // We will move a block into reserved memory but outside of the vec's bounds. For
-                          // that reason, we push an uninitialized element to extend the length, which will
-                          // be assigned in the memcpy.
-                          let res = self.pool.push(mem::uninitialized());
-
-                          // Just some assertions...
-                          debug_assert!(res.is_ok(), "Push failed (buffer full).");
-
-                          self.pool.len() - 1
-                      }) - ind);
-
+        // Mark it free and insert the element. This memmoves the elements to the right. If it
+        // does not belong at the position, it is leaked.
+        let size = block.size();
+        if self.pool.insert(pos, block.mark_free()) {
             // Update the pool byte count.
-            self.total_bytes += block.size();
-
-            // Mark it free and set the element.
-            ptr::write(self.pool.get_unchecked_mut(ind), block.mark_free());
-        }
-
-        // Free the old buffer, if it exists.
-        if let Some(block) = old_buf {
-            self.free(block);
+            self.total_bytes += size;
         }
 
         // Check consistency.
-        self.check();
+        self.check_at(pos);
     }
 
     /// Remove a block.
-    fn remove_at(&mut self, ind: usize) -> Block {
+    ///
+    /// The elements after `pos` in the same segment will be shifted to the left, keeping the pool
+    /// dense.
+    fn remove_at(&mut self, pos: Position) -> Block {
         // Logging.
-        bk_log!(self;ind, "Removing block at {}.", ind);
-
-        let res = if ind + 1 == self.pool.len() {
-            let block = self.pool[ind].pop();
-            // Make sure there are no trailing empty blocks.
-            let new_len = self.pool.len() - self.pool.iter().rev().take_while(|x| x.is_empty()).count();
-
-            // Truncate the vector.
-            self.pool.truncate(new_len);
-
-            block
-        } else {
-            // Calculate the upper and lower bound
-            let empty = self.pool[ind + 1].empty_left();
-            let empty2 = empty.empty_left();
-
-            // Replace the block at `ind` with the left empty block from `ind + 1`.
-            let block = mem::replace(&mut self.pool[ind], empty);
-
-            // Iterate over the pool from `ind` and down and set it to the  empty of our block.
-            let skip = self.pool.len() - ind;
-            for place in self.pool.iter_mut().rev().skip(skip).take_while(|x| x.is_empty()) {
-                // Empty the blocks.
-                *place = empty2.empty_left();
-            }
+        bk_log!(self;pos, "Removing block at {:?}.", pos);
 
-            block
-        };
+        let res = self.pool.remove(pos);
 
         // Update the pool byte count.
         self.total_bytes -= res.size();
 
         // Check consistency.
-        self.check();
+        self.check_at(pos);
 
         // Mark the block uninitialized to the debugger.
         res.mark_uninitialized()
     }
 }
+
+/// Account for a free block being split, with parts of the given sizes going back to the pool.
+///
+/// Nothing is counted, if all the parts are empty.
+fn record_split(rest: &[usize]) {
+    if rest.iter().any(|&size| size != 0) {
+        stats::record_pool(PoolOp::Split);
+    }
+    for _ in rest.iter().filter(|&&size| size != 0 && size < config::MIN_SPLIT) {
+        stats::record_pool(PoolOp::Stub);
+    }
+}
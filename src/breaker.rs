@@ -0,0 +1,573 @@
+//! Memory sources.
+//!
+//! Whenever the pool of an arena runs dry, fresh memory is acquired from its breaker. The global
+//! allocator uses the program break, but arenas can be fed by any memory source (e.g. hugepage
+//! pools or device memory), by implementing `Breaker`.
+
+use prelude::*;
+
+use core::convert::TryInto;
+
+use shim::{config, syscalls, system};
+#[cfg(target_os = "fuchsia")]
+use shim::zircon;
+
+use brk;
+
+/// A source of fresh memory.
+///
+/// # Safety
+///
+/// The regions given out by `fresh` must be valid for reads and writes, and must not overlap any
+/// other region given out by the breaker (unless released in the meantime).
+pub unsafe trait Breaker {
+    /// Acquire a fresh region of at least `size` bytes.
+    ///
+    /// The start and the size of the region are returned. On failure, `Err` holds the OS error
+    /// number, or zero if the source is merely exhausted (e.g. a fixed buffer is used up). The
+    /// arenas report the former as `Error::BreakerFailed`, and the latter as
+    /// `Error::OutOfMemory`.
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize>;
+
+    /// Give a region back to the source.
+    ///
+    /// The region is (a part of) some region acquired through `fresh`. If the source cannot take
+    /// it back (the default), `Err(())` is returned, and the region stays in the arena.
+    fn release(&mut self, _ptr: *mut u8, _size: usize) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// Get the address, where the next region will start.
+    ///
+    /// This is used for placing buffers at fixed addresses (see `Arena::alloc_at`). If the source
+    /// cannot tell (the default), `None` is returned.
+    fn next(&mut self) -> Option<*mut u8> {
+        None
+    }
+}
+
+/// The program break.
+///
+/// The regions extend the data segment, and are released by shrinking it, so only the region
+/// right below the break can be released.
+pub struct Brk;
+
+unsafe impl Breaker for Brk {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        // Important! The conversion is failable to avoid arithmetic overflow-based attacks.
+        let diff = match size.try_into() {
+            Ok(diff) => diff,
+            Err(_) => return Err(0),
+        };
+
+        let res = unsafe {
+            // The fresh part of the data segment belongs to no one else.
+            brk::lock().sbrk(diff)
+        };
+
+        // The break tells no error number, but only fails for lack of memory.
+        res.map(|ptr| (ptr.get(), size)).map_err(|()| 0)
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        brk::lock().release(unsafe {
+            // The region was given out by `fresh`.
+            Block::from_raw_parts(Pointer::new(ptr), size)
+        }).map_err(|_| ())
+    }
+
+    fn next(&mut self) -> Option<*mut u8> {
+        // The data segment grows upwards from the break.
+        Some(brk::lock().current_brk().get())
+    }
+}
+
+/// Anonymous memory mappings.
+///
+/// Every region is mapped on its own, apart from the heap. Only whole pages can be released.
+pub struct Mmap;
+
+unsafe impl Breaker for Mmap {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        // Round up to whole pages.
+        let size = match size.checked_add(config::PAGE_SIZE - 1) {
+            Some(size) => size & !(config::PAGE_SIZE - 1),
+            None => return Err(0),
+        };
+
+        unsafe {
+            // Fresh mappings belong to no one else.
+            syscalls::mmap(size).map(|ptr| (ptr, size))
+        }
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        if ptr as usize % config::PAGE_SIZE != 0 || size % config::PAGE_SIZE != 0 {
+            return Err(());
+        }
+
+        unsafe {
+            // The pages were mapped by `fresh`, and are no longer used.
+            syscalls::munmap(ptr, size)
+        }
+    }
+}
+
+/// Anonymous memory mappings, concealed from core dumps.
+///
+/// This is `Mmap` for arenas holding secrets (e.g. keys), which must not leak through a crash. See
+/// `syscalls::mmap_concealed` for the platform support.
+pub struct Concealed;
+
+unsafe impl Breaker for Concealed {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        // Round up to whole pages.
+        let size = match size.checked_add(config::PAGE_SIZE - 1) {
+            Some(size) => size & !(config::PAGE_SIZE - 1),
+            None => return Err(0),
+        };
+
+        unsafe {
+            // Fresh mappings belong to no one else.
+            syscalls::mmap_concealed(size).map(|ptr| (ptr, size))
+        }
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        // The mappings are unmapped the same way.
+        Mmap.release(ptr, size)
+    }
+}
+
+/// The program break for small regions, and memory mappings for big ones.
+///
+/// The breaker tracks the extent of the data segment it grew, so released regions are given back
+/// to the source they came from. If one source fails, the other is tried.
+pub struct Hybrid {
+    /// The size above which regions are mapped.
+    threshold: usize,
+    /// The start of the data segment grown by the breaker (zero if never grown).
+    brk_start: usize,
+    /// The end of the data segment grown by the breaker.
+    brk_end: usize,
+}
+
+impl Hybrid {
+    /// Create a breaker mapping regions bigger than `threshold` bytes.
+    ///
+    /// `config::MMAP_THRESHOLD` is a reasonable default.
+    pub fn new(threshold: usize) -> Hybrid {
+        Hybrid {
+            threshold: threshold,
+            brk_start: 0,
+            brk_end: 0,
+        }
+    }
+
+    /// Grow the data segment.
+    fn brk(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        let res = Brk.fresh(size);
+
+        if let Ok((ptr, size)) = res {
+            if self.brk_start == 0 {
+                self.brk_start = ptr as usize;
+            }
+            self.brk_end = ptr as usize + size;
+        }
+
+        res
+    }
+}
+
+unsafe impl Breaker for Hybrid {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        if size > self.threshold {
+            // Logging.
+            log!(DEBUG, "Mapping {} bytes.", size);
+
+            Mmap.fresh(size).or_else(|_| self.brk(size))
+        } else {
+            self.brk(size).or_else(|_| Mmap.fresh(size))
+        }
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        let (start, end) = (ptr as usize, ptr as usize + size);
+
+        if start >= self.brk_start && end <= self.brk_end {
+            // The region is in the data segment.
+            Brk.release(ptr, size)?;
+            self.brk_end = start;
+
+            Ok(())
+        } else if end <= self.brk_start || start >= self.brk_end {
+            // The region is mapped.
+            Mmap.release(ptr, size)
+        } else {
+            // The region straddles the end of the data segment, and some adjacent mapping.
+            Err(())
+        }
+    }
+}
+
+/// The platform allocator (i.e. `aligned_alloc` of the C library).
+///
+/// This is for environments, where neither the program break nor memory mappings are available
+/// (e.g. some sandboxes). The regions cannot be released, and it must not be used, if ralloc
+/// itself provides `malloc`.
+pub struct System;
+
+unsafe impl Breaker for System {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        unsafe {
+            // The buffers of the platform allocator belong to no one else.
+            system::alloc(size, config::SYSTEM_ALIGN).map(|ptr| (ptr, size)).ok_or(0)
+        }
+    }
+}
+
+/// A breaker falling back to another, when the first is unsupported.
+///
+/// The first breaker is taken to be unsupported, if it fails before ever handing out memory (e.g.
+/// the program break in a sandbox forbidding it). From then on, the second breaker is used.
+pub struct Chain<A: Breaker, B: Breaker> {
+    /// The first breaker.
+    first: A,
+    /// The second breaker.
+    second: B,
+    /// Has the first breaker handed out memory?
+    supported: bool,
+    /// Has the chain fallen back to the second breaker?
+    fallen_back: bool,
+}
+
+impl<A: Breaker, B: Breaker> Chain<A, B> {
+    /// Create a breaker using `first`, or `second` if `first` is unsupported.
+    pub fn new(first: A, second: B) -> Chain<A, B> {
+        Chain {
+            first: first,
+            second: second,
+            supported: false,
+            fallen_back: false,
+        }
+    }
+}
+
+unsafe impl<A: Breaker, B: Breaker> Breaker for Chain<A, B> {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        if !self.fallen_back {
+            match self.first.fresh(size) {
+                Ok(res) => {
+                    self.supported = true;
+                    return Ok(res);
+                },
+                // The source is merely exhausted (or failing).
+                Err(err) if self.supported => return Err(err),
+                Err(_) => {
+                    log!(WARNING, "The memory source is unsupported; falling back to another.");
+
+                    self.fallen_back = true;
+                },
+            }
+        }
+
+        self.second.fresh(size)
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        // Only one of the breakers ever hands out memory.
+        if self.fallen_back {
+            self.second.release(ptr, size)
+        } else {
+            self.first.release(ptr, size)
+        }
+    }
+
+    fn next(&mut self) -> Option<*mut u8> {
+        if self.fallen_back {
+            self.second.next()
+        } else {
+            self.first.next()
+        }
+    }
+}
+
+/// A fixed buffer.
+///
+/// The buffer is handed out piece by piece, from the start. The last piece handed out can be
+/// released back.
+pub struct Fixed {
+    /// The start of the buffer.
+    ptr: Pointer<u8>,
+    /// The size of the buffer.
+    size: usize,
+    /// The number of bytes handed out.
+    used: usize,
+}
+
+impl Fixed {
+    /// Create a breaker handing out a static buffer.
+    pub fn new(buf: &'static mut [u8]) -> Fixed {
+        unsafe {
+            // The buffer is borrowed for the rest of the program.
+            Fixed::from_raw_parts(buf.as_mut_ptr(), buf.len())
+        }
+    }
+
+    /// Create a breaker handing out the buffer of some size at some pointer.
+    ///
+    /// This can be used for memory regions not known to Rust, e.g. pre-pinned DMA regions.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must be valid for reads and writes, and not used by anything else, as long as
+    /// the breaker (and the memory it handed out) is used.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, size: usize) -> Fixed {
+        Fixed {
+            ptr: Pointer::new(ptr),
+            size: size,
+            used: 0,
+        }
+    }
+}
+
+unsafe impl Breaker for Fixed {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        if size > self.size - self.used {
+            return Err(0);
+        }
+
+        let res = unsafe {
+            // The offset is within the buffer.
+            self.ptr.clone().offset(self.used as isize).get()
+        };
+        self.used += size;
+
+        Ok((res, size))
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        // Only the end of the used part can be released.
+        if ptr as usize + size == self.ptr.get() as usize + self.used {
+            self.used -= size;
+
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn next(&mut self) -> Option<*mut u8> {
+        Some(unsafe {
+            // The offset is within the buffer (or at its end).
+            self.ptr.clone().offset(self.used as isize).get()
+        })
+    }
+}
+
+/// A reserved range of address space.
+///
+/// The range is reserved up front, without committing any memory to it, and regions are handed
+/// out from its start, committing their pages on demand. Released regions (only the last one
+/// handed out can be released) are decommitted, so their memory goes back to the OS, while the
+/// address space stays reserved. This gives arenas a contiguous, bounded address range, which
+/// can grow without moving.
+///
+/// Pages can also be committed and decommitted by hand (see `commit` and `decommit`).
+///
+/// On Fuchsia, the range is a mapped VMO, whose pages are committed and decommitted through the
+/// VMO (see `shim::zircon`).
+pub struct Reserved {
+    /// The start of the range.
+    ptr: Pointer<u8>,
+    /// The size of the range.
+    size: usize,
+    /// The number of bytes handed out.
+    used: usize,
+    /// The VMO backing the range.
+    #[cfg(target_os = "fuchsia")]
+    vmo: zircon::Handle,
+}
+
+impl Reserved {
+    /// Reserve a range of `size` bytes (rounded up to whole pages).
+    ///
+    /// `None` is returned, if the address space cannot be reserved.
+    pub fn new(size: usize) -> Option<Reserved> {
+        let size = match size.checked_add(config::PAGE_SIZE - 1) {
+            Some(size) => size & !(config::PAGE_SIZE - 1),
+            None => return None,
+        };
+
+        Reserved::map(size).map(|res| {
+            // Logging.
+            log!(NOTE, "Reserved {} bytes at 0x{:x}.", size, res.ptr.get() as usize);
+
+            res
+        })
+    }
+
+    /// Reserve the address space of a range.
+    #[cfg(not(target_os = "fuchsia"))]
+    fn map(size: usize) -> Option<Reserved> {
+        unsafe { syscalls::reserve(size) }.ok().map(|ptr| Reserved {
+            ptr: Pointer::new(ptr),
+            size: size,
+            used: 0,
+        })
+    }
+
+    /// Create and map the VMO of a range.
+    ///
+    /// The VMO commits its pages lazily anyway, so the mapping is readable and writable from the
+    /// start.
+    #[cfg(target_os = "fuchsia")]
+    fn map(size: usize) -> Option<Reserved> {
+        unsafe { zircon::vmo_map(size) }.ok().map(|(vmo, ptr)| Reserved {
+            ptr: Pointer::new(ptr),
+            size: size,
+            used: 0,
+            vmo: vmo,
+        })
+    }
+
+    /// Get the start of the range.
+    pub fn ptr(&self) -> *mut u8 {
+        self.ptr.get()
+    }
+
+    /// Get the size of the range.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Commit the pages spanning `len` bytes at `offset` into the range.
+    ///
+    /// The pages become readable and writable. `Err(())` is returned, if the bytes are not within
+    /// the range, or the pages cannot be committed.
+    #[cfg(not(target_os = "fuchsia"))]
+    pub fn commit(&mut self, offset: usize, len: usize) -> Result<(), ()> {
+        let (start, end) = self.pages(offset, len)?;
+
+        unsafe {
+            // The pages are part of the reservation.
+            syscalls::mprotect(start as *mut u8, end - start,
+                               syscalls::PROT_READ | syscalls::PROT_WRITE)
+        }
+    }
+
+    /// Commit the pages spanning `len` bytes at `offset` into the range.
+    ///
+    /// The pages become readable and writable. `Err(())` is returned, if the bytes are not within
+    /// the range, or the pages cannot be committed.
+    #[cfg(target_os = "fuchsia")]
+    pub fn commit(&mut self, offset: usize, len: usize) -> Result<(), ()> {
+        let (start, end) = self.pages(offset, len)?;
+        let base = self.ptr.get() as usize;
+
+        unsafe {
+            // The pages are part of the VMO.
+            zircon::vmo_commit(self.vmo, start - base, end - start)
+        }
+    }
+
+    /// Decommit the pages spanning `len` bytes at `offset` into the range.
+    ///
+    /// The memory is given back to the OS, and the pages become inaccessible, though they stay
+    /// reserved. `Err(())` is returned, if the bytes are not within the range, or the pages cannot
+    /// be decommitted.
+    ///
+    /// # Safety
+    ///
+    /// The contents are lost, and any later access faults, so the pages must not be in use.
+    #[cfg(not(target_os = "fuchsia"))]
+    pub unsafe fn decommit(&mut self, offset: usize, len: usize) -> Result<(), ()> {
+        let (start, end) = self.pages(offset, len)?;
+
+        syscalls::madvise(start as *mut u8, end - start, syscalls::MADV_DONTNEED)?;
+        syscalls::mprotect(start as *mut u8, end - start, syscalls::PROT_NONE)
+    }
+
+    /// Decommit the pages spanning `len` bytes at `offset` into the range.
+    ///
+    /// The memory is given back to the kernel. The pages stay mapped, and read as zeros
+    /// afterwards. `Err(())` is returned, if the bytes are not within the range, or the pages
+    /// cannot be decommitted.
+    ///
+    /// # Safety
+    ///
+    /// The contents are lost, so the pages must not be in use.
+    #[cfg(target_os = "fuchsia")]
+    pub unsafe fn decommit(&mut self, offset: usize, len: usize) -> Result<(), ()> {
+        let (start, end) = self.pages(offset, len)?;
+        let base = self.ptr.get() as usize;
+
+        zircon::vmo_decommit(self.vmo, start - base, end - start)
+    }
+
+    /// Get the start and end of the pages spanning `len` bytes at `offset` into the range.
+    fn pages(&self, offset: usize, len: usize) -> Result<(usize, usize), ()> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => {
+                let base = self.ptr.get() as usize;
+                let start = base + offset / config::PAGE_SIZE * config::PAGE_SIZE;
+                let end = base + (end + config::PAGE_SIZE - 1) / config::PAGE_SIZE
+                          * config::PAGE_SIZE;
+
+                Ok((start, end))
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+unsafe impl Breaker for Reserved {
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        if size > self.size - self.used {
+            return Err(0);
+        }
+
+        let offset = self.used;
+        if self.commit(offset, size).is_err() {
+            log!(WARNING, "Unable to commit {} bytes of the reserved range.", size);
+
+            return Err(0);
+        }
+        self.used += size;
+
+        let res = unsafe {
+            // The offset is within the range.
+            self.ptr.clone().offset(offset as isize).get()
+        };
+
+        Ok((res, size))
+    }
+
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        // Only the end of the used part can be released.
+        if ptr as usize + size != self.ptr.get() as usize + self.used {
+            return Err(());
+        }
+        self.used -= size;
+
+        // Only the pages entirely released are decommitted, as the first might still be in use.
+        let start = (self.used + config::PAGE_SIZE - 1) / config::PAGE_SIZE * config::PAGE_SIZE;
+        if start < self.used + size {
+            unsafe {
+                // The pages lie in the released region.
+                if self.decommit(start, self.used + size - start).is_err() {
+                    log!(WARNING, "Unable to decommit the released pages.");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Option<*mut u8> {
+        Some(unsafe {
+            // The offset is within the buffer (or at its end).
+            self.ptr.clone().offset(self.used as isize).get()
+        })
+    }
+}
@@ -1,6 +1,12 @@
 //! BRK abstractions.
 //!
 //! This module provides safe abstractions over BRK.
+//!
+//! The program break is moved through a `Memory`, so its users don't care about the mechanism
+//! behind it: The break of the OS (`Native`), where there is one, or a break emulated over a range
+//! of address space reserved up front (`Emulated`), where there isn't (or with the
+//! `brk_emulation` feature). The mechanism is chosen on first use, unless a region is provided
+//! up front (`Region`, see `set_heap_region`), which is the only source of an enclave.
 
 use prelude::*;
 
@@ -9,21 +15,217 @@ use core::convert::TryInto;
 
 use shim::{syscalls, config};
 
-use {sync, fail};
+use breaker::Reserved;
+use fence::page_up;
+use sync;
 
 /// The BRK mutex.
 ///
 /// This is used for avoiding data races in multiple allocator.
 static BRK_MUTEX: Mutex<BrkState> = Mutex::new(BrkState {
     current_brk: None,
+    start_brk: None,
+    memory: None,
 });
 
+/// A mechanism moving a program break.
+///
+/// # Safety
+///
+/// The memory between the start of the break and the break must be valid for reads and writes.
+pub unsafe trait Memory {
+    /// Move the break to `ptr`, or get it, if `ptr` is null.
+    ///
+    /// The new break is returned. If the break cannot be moved, the old break is returned (like
+    /// the BRK syscall).
+    unsafe fn brk(&mut self, ptr: *const u8) -> *const u8;
+}
+
+/// The program break of the OS.
+///
+/// The platform specifics are left to the shim (see `syscalls::brk`).
+pub struct Native;
+
+unsafe impl Memory for Native {
+    #[inline]
+    unsafe fn brk(&mut self, ptr: *const u8) -> *const u8 {
+        syscalls::brk(ptr)
+    }
+}
+
+/// A program break emulated over a reserved range of address space.
+///
+/// The pages are committed as the break grows, and decommitted as it shrinks, so the break behaves
+/// like the one of the OS, within the range.
+pub struct Emulated {
+    /// The range.
+    range: Reserved,
+    /// The offset of the break into the range.
+    len: usize,
+}
+
+impl Emulated {
+    /// Reserve the range of an emulated break of (at most) `size` bytes.
+    ///
+    /// `None` is returned, if the address space cannot be reserved.
+    pub fn new(size: usize) -> Option<Emulated> {
+        Reserved::new(size).map(|range| Emulated {
+            range: range,
+            len: 0,
+        })
+    }
+}
+
+unsafe impl Memory for Emulated {
+    unsafe fn brk(&mut self, ptr: *const u8) -> *const u8 {
+        let start = self.range.ptr() as usize;
+        let cur = (start + self.len) as *const u8;
+        if ptr.is_null() {
+            return cur;
+        }
+
+        let new = ptr as usize;
+        if new < start || new - start > self.range.size() {
+            // Out of the range.
+            return cur;
+        }
+
+        let len = new - start;
+        let res = if len > self.len {
+            self.range.commit(self.len, len - self.len)
+        } else {
+            // Only the pages entirely above the new break are decommitted.
+            let keep = page_up(len);
+            if keep < self.len {
+                self.range.decommit(keep, self.len - keep)
+            } else {
+                Ok(())
+            }
+        };
+
+        if res.is_ok() {
+            self.len = len;
+            ptr
+        } else {
+            cur
+        }
+    }
+}
+
+/// A program break within a region provided up front (e.g. the heap of an SGX enclave).
+///
+/// The break just moves within the region, so no syscalls are made.
+pub struct Region {
+    /// The start of the region.
+    start: usize,
+    /// The size of the region.
+    size: usize,
+    /// The offset of the break into the region.
+    len: usize,
+}
+
+impl Region {
+    /// Create a break within the `size` bytes at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, and unused otherwise.
+    pub unsafe fn new(ptr: *mut u8, size: usize) -> Region {
+        Region {
+            start: ptr as usize,
+            size: size,
+            len: 0,
+        }
+    }
+
+    /// Create a break without any room.
+    fn empty() -> Region {
+        Region {
+            // Like `Pointer::empty`, this is non-null.
+            start: 1,
+            size: 0,
+            len: 0,
+        }
+    }
+}
+
+unsafe impl Memory for Region {
+    unsafe fn brk(&mut self, ptr: *const u8) -> *const u8 {
+        let new = ptr as usize;
+        if !ptr.is_null() && new >= self.start && new - self.start <= self.size {
+            self.len = new - self.start;
+        }
+
+        (self.start + self.len) as *const u8
+    }
+}
+
+/// The mechanism behind the program break.
+enum Backend {
+    /// The program break of the OS.
+    Native(Native),
+    /// An emulated program break.
+    Emulated(Emulated),
+    /// A program break within a provided region.
+    Region(Region),
+}
+
+impl Backend {
+    /// Choose the mechanism.
+    ///
+    /// The break of the OS is used, unless it is missing, or the `brk_emulation` feature is
+    /// enabled. In an enclave, there is no memory, but the provided region (see
+    /// `set_heap_region`).
+    fn choose() -> Backend {
+        if cfg!(feature = "enclave") {
+            log!(WARNING, "No heap region was provided to the enclave.");
+
+            return Backend::Region(Region::empty());
+        }
+
+        if !cfg!(feature = "brk_emulation") && !unsafe { Native.brk(ptr::null()) }.is_null() {
+            return Backend::Native(Native);
+        }
+
+        match Emulated::new(config::EMULATED_BRK_SIZE) {
+            Some(emulated) => {
+                // Logging.
+                log!(NOTE, "Emulating the program break.");
+
+                Backend::Emulated(emulated)
+            },
+            None => {
+                log!(WARNING, "Unable to reserve the range of the emulated program break.");
+
+                Backend::Native(Native)
+            },
+        }
+    }
+}
+
+unsafe impl Memory for Backend {
+    #[inline]
+    unsafe fn brk(&mut self, ptr: *const u8) -> *const u8 {
+        match *self {
+            Backend::Native(ref mut native) => native.brk(ptr),
+            Backend::Emulated(ref mut emulated) => emulated.brk(ptr),
+            Backend::Region(ref mut region) => region.brk(ptr),
+        }
+    }
+}
+
 /// A cache of the BRK state.
 ///
 /// To avoid keeping asking the OS for information whenever needed, we cache it.
 struct BrkState {
     /// The program break's end
     current_brk: Option<Pointer<u8>>,
+    /// The program break, when it was first requested.
+    ///
+    /// This is the start of the heap of the allocator.
+    start_brk: Option<Pointer<u8>>,
+    /// The mechanism behind the program break (`None` if not chosen yet).
+    memory: Option<Backend>,
 }
 
 /// A BRK lock.
@@ -38,7 +240,7 @@ impl BrkLock {
     /// # Safety
     ///
     /// Due to being able shrink the program break, this method is unsafe.
-    unsafe fn sbrk(&mut self, size: isize) -> Result<Pointer<u8>, ()> {
+    pub unsafe fn sbrk(&mut self, size: isize) -> Result<Pointer<u8>, ()> {
         log!(NOTE, "Incrementing the program break by {} bytes.", size);
 
         // Calculate the new program break. To avoid making multiple syscalls, we make use of the
@@ -46,7 +248,7 @@ impl BrkLock {
         let expected_brk = self.current_brk().offset(size);
 
         // Break it to me, babe!
-        let old_brk = Pointer::new(syscalls::brk(expected_brk.get() as *const u8) as *mut u8);
+        let old_brk = Pointer::new(self.memory().brk(expected_brk.get() as *const u8) as *mut u8);
 
         /// AAAARGH WAY TOO MUCH LOGGING
         ///
@@ -59,6 +261,14 @@ impl BrkLock {
             // Update the program break cache.
             self.state.current_brk = Some(expected_brk.clone());
 
+            // Enable tag checks for the fresh memory, which ends at the new break.
+            #[cfg(feature = "mte")]
+            {
+                if size > 0 {
+                    ::mte::protect(expected_brk.get().offset(-size), size as usize);
+                }
+            }
+
             // Return the old break.
             Ok(old_brk)
         } else {
@@ -100,28 +310,59 @@ impl BrkLock {
         }
     }
 
+    /// Get the mechanism behind the program break, choosing it on first use.
+    fn memory(&mut self) -> &mut Backend {
+        if self.state.memory.is_none() {
+            self.state.memory = Some(Backend::choose());
+        }
+
+        self.state.memory.as_mut().unwrap()
+    }
+
+    /// Get the program break from the mechanism behind it.
+    fn query(&mut self) -> Pointer<u8> {
+        unsafe {
+            // LAST AUDIT: 2016-08-21 (Ticki).
+
+            Pointer::new(self.memory().brk(ptr::null()) as *mut u8)
+        }
+    }
+
     /// Get the current program break.
     ///
     /// If not available in the cache, requested it from the OS.
-    fn current_brk(&mut self) -> Pointer<u8> {
-        if let Some(ref cur) = self.state.current_brk {
-            let res = cur.clone();
+    pub fn current_brk(&mut self) -> Pointer<u8> {
+        if let Some(cur) = self.state.current_brk.clone() {
             // Make sure that the break is set properly (i.e. there is no libc interference).
-            debug_assert!(res == current_brk(), "The cached program break is out of sync with the \
-                          actual program break. Are you interfering with BRK? If so, prefer the \
-                          provided 'sbrk' instead, then.");
+            debug_assert!(cur == self.query(), "The cached program break is out of sync with \
+                          the actual program break. Are you interfering with BRK? If so, prefer \
+                          the provided 'sbrk' instead, then.");
 
-            return res;
+            return cur;
         }
 
-        // TODO: Damn it, borrowck.
         // Get the current break.
-        let cur = current_brk();
+        let cur = self.query();
         self.state.current_brk = Some(cur.clone());
+        if self.state.start_brk.is_none() {
+            self.state.start_brk = Some(cur.clone());
+        }
 
         cur
     }
 
+    /// Get the start of the heap.
+    ///
+    /// This is the program break, when it was first requested, so the memory between it and the
+    /// current program break was taken by the allocator (or through `sbrk`).
+    pub fn start_brk(&mut self) -> Pointer<u8> {
+        if self.state.start_brk.is_none() {
+            self.current_brk();
+        }
+
+        self.state.start_brk.clone().unwrap()
+    }
+
     /// BRK new space.
     ///
     /// The first block represents the aligner segment (that is the precursor aligning the middle
@@ -130,9 +371,9 @@ impl BrkLock {
     ///
     /// # Failure
     ///
-    /// This method calls the OOM handler if it is unable to acquire the needed space.
+    /// If it is unable to acquire the needed space, `Err(())` is returned.
     // TODO: This method is possibly unsafe.
-    pub fn canonical_brk(&mut self, size: usize, align: usize) -> (Block, Block, Block) {
+    pub fn canonical_brk(&mut self, size: usize, align: usize) -> Result<(Block, Block, Block), ()> {
         // Calculate the canonical size (extra space is allocated to limit the number of system calls).
         let brk_size = size + config::extra_brk(size) + align;
 
@@ -145,7 +386,7 @@ impl BrkLock {
             Block::from_raw_parts(
                 // Important! The conversion is failable to avoid arithmetic overflow-based
                 // attacks.
-                self.sbrk(brk_size.try_into().unwrap()).unwrap_or_else(|()| fail::oom()),
+                self.sbrk(brk_size.try_into().unwrap())?,
                 brk_size,
             )
         }.align(align).unwrap();
@@ -157,7 +398,7 @@ impl BrkLock {
         debug_assert!(res.aligned_to(align), "Alignment failed.");
         debug_assert!(res.size() + alignment_block.size() + excessive.size() == brk_size, "BRK memory leak.");
 
-        (alignment_block, res, excessive)
+        Ok((alignment_block, res, excessive))
     }
 }
 
@@ -168,6 +409,15 @@ pub fn lock() -> BrkLock {
     }
 }
 
+/// Lock the BRK lock, if it is not locked.
+///
+/// See `Mutex::try_lock`.
+pub fn try_lock() -> Option<BrkLock> {
+    BRK_MUTEX.try_lock().map(|state| BrkLock {
+        state: state,
+    })
+}
+
 /// `SBRK` symbol which can coexist with the allocator.
 ///
 /// `SBRK`-ing directly (from the `BRK` syscall or libc) might make the state inconsistent. This
@@ -183,13 +433,27 @@ pub unsafe extern fn sbrk(size: isize) -> *mut u8 {
     lock().sbrk(size).unwrap_or_else(|()| Pointer::new(!0 as *mut u8)).get()
 }
 
-/// Get the current program break.
-fn current_brk() -> Pointer<u8> {
-    unsafe {
-        // LAST AUDIT: 2016-08-21 (Ticki).
-
-        Pointer::new(syscalls::brk(ptr::null()) as *mut u8)
+/// Provide the region of `size` bytes at `ptr`, which the program break moves within.
+///
+/// This is how an enclave (see the `enclave` feature) gets its heap, but it works anywhere. It
+/// must be called before the first allocation. If the mechanism behind the break was chosen
+/// already, `Err(())` is returned.
+///
+/// # Safety
+///
+/// The region must be valid for reads and writes, and unused otherwise.
+pub unsafe fn set_heap_region(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    let mut state = BRK_MUTEX.lock();
+    if state.memory.is_some() {
+        return Err(());
     }
+
+    // Logging.
+    log!(NOTE, "Using the heap region 0x{:x}[{}].", ptr as usize, size);
+
+    state.memory = Some(Backend::Region(Region::new(ptr, size)));
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -213,4 +477,41 @@ mod test {
             assert!(brk1.get() < brk2.get());
         }
     }
+
+    #[test]
+    fn test_emulated() {
+        let mut memory = Emulated::new(1 << 20).unwrap();
+
+        unsafe {
+            let start = memory.brk(ptr::null());
+            let end = start.offset(10000);
+            assert_eq!(memory.brk(end), end);
+            *(end.offset(-1) as *mut u8) = 1;
+
+            // The break stays within the range.
+            assert_eq!(memory.brk(start.offset(1 << 21)), end);
+
+            let mid = start.offset(5000);
+            assert_eq!(memory.brk(mid), mid);
+            *(mid.offset(-1) as *mut u8) = 2;
+            assert_eq!(memory.brk(start), start);
+        }
+    }
+
+    #[test]
+    fn test_region() {
+        let mut buf = [0u8; 256];
+        let start = &mut buf[0] as *mut u8;
+
+        unsafe {
+            let mut memory = Region::new(start, 256);
+            assert_eq!(memory.brk(ptr::null()), start as *const u8);
+            assert_eq!(memory.brk(start.offset(100)), start.offset(100) as *const u8);
+
+            // The break stays within the region.
+            assert_eq!(memory.brk(start.offset(257)), start.offset(100) as *const u8);
+            assert_eq!(memory.brk(start.offset(256)), start.offset(256) as *const u8);
+            assert_eq!(memory.brk(start), start as *const u8);
+        }
+    }
 }
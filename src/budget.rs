@@ -0,0 +1,170 @@
+//! Scoped allocation budgets.
+//!
+//! `budget` runs a closure (e.g. the entry point of an untrusted plugin), while accounting for
+//! the memory allocated and freed by the current thread through the entry points of the crate.
+//! If the net allocations ever exceed the budget, the closure is reported as having exceeded it,
+//! and the handler (if any) is called right away.
+//!
+//! The accounting is per thread, so memory allocated by other threads (e.g. threads spawned by
+//! the closure) is not counted, and freeing memory allocated before the scope lowers the net
+//! allocations.
+
+use core::{cmp, mem};
+use core::sync::atomic::{self, AtomicPtr, AtomicUsize};
+
+use hook::Event;
+
+/// The net bytes allocated by the current thread, while in a scope.
+///
+/// This wraps around, as frees can outweigh allocations. Only differences are meaningful.
+#[thread_local]
+static NET: AtomicUsize = AtomicUsize::new(0);
+/// The value of `NET` when the innermost scope was entered.
+#[thread_local]
+static START: AtomicUsize = AtomicUsize::new(0);
+/// The budget of the innermost scope (`!0` if not in a scope).
+#[thread_local]
+static LIMIT: AtomicUsize = AtomicUsize::new(!0);
+/// The peak net allocations of the innermost scope.
+#[thread_local]
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+/// The handler of the innermost scope (null if none, or while it is running).
+#[thread_local]
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
+
+/// A closure, which exceeded its budget.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Exceeded<T> {
+    /// The value returned by the closure.
+    pub value: T,
+    /// The peak net allocations of the closure, in bytes.
+    pub peak: usize,
+}
+
+/// Run a closure with an allocation budget.
+///
+/// If the net allocations of the current thread during the closure ever exceed `bytes`, the
+/// result is returned as an error, along with the peak. The closure is not interrupted; see
+/// `budget_with` to act as soon as the budget is exceeded.
+///
+/// Budgets nest, and the allocations of an inner scope count towards the outer ones.
+pub fn budget<T, F: FnOnce() -> T>(bytes: usize, f: F) -> Result<T, Exceeded<T>> {
+    scope(bytes, None, f)
+}
+
+/// Run a closure with an allocation budget, calling a handler when it is exceeded.
+///
+/// This is like `budget`, but as soon as an allocation makes the net allocations exceed the
+/// budget, `handler` is called with the net allocations (after the allocation is done, with no
+/// locks held), e.g. to log the offending stack, or to tell the plugin to stop. It is called once
+/// per scope.
+pub fn budget_with<T, F: FnOnce() -> T>(bytes: usize, handler: fn(usize), f: F)
+                                        -> Result<T, Exceeded<T>> {
+    scope(bytes, Some(handler), f)
+}
+
+/// The state of the outer scope, which is restored when dropped.
+struct Outer {
+    /// The value of `NET` when the inner scope was entered.
+    net: usize,
+    /// The start of the outer scope.
+    start: usize,
+    /// The budget of the outer scope.
+    limit: usize,
+    /// The peak of the outer scope.
+    peak: usize,
+    /// The handler of the outer scope.
+    handler: *mut (),
+}
+
+impl Drop for Outer {
+    fn drop(&mut self) {
+        let peak = PEAK.load(atomic::Ordering::Relaxed);
+
+        START.store(self.start, atomic::Ordering::Relaxed);
+        LIMIT.store(self.limit, atomic::Ordering::Relaxed);
+        PEAK.store(self.peak, atomic::Ordering::Relaxed);
+        HANDLER.store(self.handler, atomic::Ordering::Relaxed);
+
+        if self.limit != !0 {
+            // The allocations of the inner scope count towards the outer one, which is checked
+            // against the peak of the inner one.
+            raise((self.net.wrapping_sub(self.start) as isize).saturating_add(peak as isize));
+        }
+    }
+}
+
+/// Run a closure in a scope.
+fn scope<T, F: FnOnce() -> T>(bytes: usize, handler: Option<fn(usize)>, f: F)
+                              -> Result<T, Exceeded<T>> {
+    // Logging.
+    log!(DEBUG, "Entering a scope with a budget of {} bytes.", bytes);
+
+    // The outer scope is restored on the way out, even if the closure unwinds.
+    let net = NET.load(atomic::Ordering::Relaxed);
+    let outer = Outer {
+        net: net,
+        start: START.swap(net, atomic::Ordering::Relaxed),
+        // A budget of `!0` would mean no scope, but is unlimited anyway.
+        limit: LIMIT.swap(cmp::min(bytes, !0 - 1), atomic::Ordering::Relaxed),
+        peak: PEAK.swap(0, atomic::Ordering::Relaxed),
+        handler: HANDLER.swap(handler.map_or(0 as *mut (), |h| h as *mut ()),
+                              atomic::Ordering::Relaxed),
+    };
+
+    let value = f();
+
+    let peak = PEAK.load(atomic::Ordering::Relaxed);
+    drop(outer);
+
+    if peak > bytes {
+        // Logging.
+        log!(NOTE, "The budget of {} bytes was exceeded (peak of {} bytes).", bytes, peak);
+
+        Err(Exceeded {
+            value: value,
+            peak: peak,
+        })
+    } else {
+        Ok(value)
+    }
+}
+
+/// Account for an operation performed through the entry points.
+#[inline]
+pub fn record(event: &Event) {
+    // Short circuit, if not in a scope.
+    if LIMIT.load(atomic::Ordering::Relaxed) == !0 {
+        return;
+    }
+
+    let delta = match *event {
+        Event::Alloc { size, .. } => size,
+        Event::Free { size, .. } => 0usize.wrapping_sub(size),
+        Event::Realloc { old_size, size, .. } => size.wrapping_sub(old_size),
+    };
+    let net = NET.load(atomic::Ordering::Relaxed).wrapping_add(delta);
+    NET.store(net, atomic::Ordering::Relaxed);
+
+    raise(net.wrapping_sub(START.load(atomic::Ordering::Relaxed)) as isize);
+}
+
+/// Raise the peak of the innermost scope to some net allocations, calling the handler if the
+/// budget is exceeded.
+fn raise(net: isize) {
+    if net <= PEAK.load(atomic::Ordering::Relaxed) as isize {
+        return;
+    }
+    PEAK.store(net as usize, atomic::Ordering::Relaxed);
+
+    if net as usize > LIMIT.load(atomic::Ordering::Relaxed) {
+        // The handler is taken, so it is called once, and not for its own allocations.
+        let handler = HANDLER.swap(0 as *mut (), atomic::Ordering::Relaxed);
+        if !handler.is_null() {
+            unsafe {
+                // The pointer was stored from a function pointer by `budget_with`.
+                mem::transmute::<_, fn(usize)>(handler)(net as usize);
+            }
+        }
+    }
+}
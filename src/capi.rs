@@ -0,0 +1,205 @@
+//! The C interface.
+//!
+//! With the `c_api` feature, jemalloc-style `mallocx` and `sdallocx` are exported, so C and C++
+//! code can use the allocator. As the size is handed back on free (e.g. by a C++14 sized
+//! `operator delete`), no header lookup is needed; the size is trusted as is.
+//!
+//! glibc's `malloc_trim` and `malloc_stats` are exported as well, as applications sometimes call
+//! them directly, and so are OpenBSD's overflow-checked `recallocarray` and (with the
+//! `interior_pointers` feature, as it needs to look the old size up) `reallocarray`. The
+//! statistics can be read through a read-only `mallctl` (see the `ctl` module).
+
+use core::{cmp, mem, ptr, slice, str};
+use core::fmt::Write;
+
+use {allocator, ctl, stats};
+use fail::ReportWriter;
+#[cfg(feature = "interior_pointers")]
+use interior;
+
+/// The alignment of buffers, which do not specify one.
+///
+/// This is the alignment of `max_align_t` on the common platforms, and of the capabilities of
+/// 128-bit CHERI, so buffers returned to C can hold pointers on either.
+const DEFAULT_ALIGN: usize = 16;
+/// The flag bits holding the base 2 logarithm of the alignment (`MALLOCX_LG_ALIGN`).
+const LG_ALIGN_MASK: i32 = 0x3f;
+/// The flag asking for zeroed memory (`MALLOCX_ZERO`).
+const ZERO: i32 = 0x40;
+/// The error number of writes to read-only names.
+const EPERM: i32 = 1;
+/// The error number of unknown names.
+const ENOENT: i32 = 2;
+/// The error number of invalid arguments.
+const EINVAL: i32 = 22;
+
+/// Get the alignment encoded in some flags.
+#[inline]
+fn align(flags: i32) -> usize {
+    match flags & LG_ALIGN_MASK {
+        0 => DEFAULT_ALIGN,
+        lg => 1 << lg,
+    }
+}
+
+/// Allocate a buffer of `size` bytes.
+///
+/// The low bits of `flags` might hold the base 2 logarithm of the alignment, and `0x40` asks for
+/// zeroed memory. A null pointer is returned on failure.
+#[no_mangle]
+pub unsafe extern fn mallocx(size: usize, flags: i32) -> *mut u8 {
+    match allocator::try_alloc(size, align(flags)) {
+        Ok(res) => {
+            if flags & ZERO != 0 {
+                // The buffer was just allocated.
+                ptr::write_bytes(res, 0, size);
+            }
+
+            res
+        },
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Free a buffer allocated through `mallocx`, with the size and the flags it was allocated with.
+#[no_mangle]
+pub unsafe extern fn sdallocx(ptr: *mut u8, size: usize, flags: i32) {
+    if !ptr.is_null() {
+        allocator::free_sized(ptr, size, align(flags));
+    }
+}
+
+/// Give the free memory at the end of the heap back to the OS, but `pad` bytes.
+///
+/// `1` is returned, if any memory was given back, and `0` otherwise.
+#[no_mangle]
+pub extern fn malloc_trim(pad: usize) -> i32 {
+    allocator::trim(pad) as i32
+}
+
+/// Print a summary of the memory usage to the standard error.
+#[no_mangle]
+pub extern fn malloc_stats() {
+    let usage = stats::peak();
+    let ops = stats::operations();
+    let free = allocator::with_global(|pool| pool.total_bytes());
+
+    let _ = writeln!(ReportWriter, "system bytes      = {:10}", usage.extent);
+    let _ = writeln!(ReportWriter, "in use bytes      = {:10}", usage.in_use);
+    let _ = writeln!(ReportWriter, "free bytes        = {:10}", free);
+    let _ = writeln!(ReportWriter, "max system bytes  = {:10}", usage.peak_extent);
+    let _ = writeln!(ReportWriter, "max in use bytes  = {:10}", usage.peak_in_use);
+    let _ = writeln!(ReportWriter, "allocations       = {:10}", ops.allocs);
+    let _ = writeln!(ReportWriter, "frees             = {:10}", ops.frees);
+    let _ = writeln!(ReportWriter, "reallocations     = {:10}", ops.reallocs);
+}
+
+/// Resize a buffer of `old_size` bytes to `size` bytes.
+///
+/// On failure, null is returned, and the buffer is left as is. If `clear` is set, and the buffer
+/// is moved, the old buffer is zeroed, before it is freed.
+unsafe fn resize(ptr: *mut u8, old_size: usize, size: usize, clear: bool) -> *mut u8 {
+    if allocator::realloc_inplace(ptr, old_size, size).is_ok() {
+        return ptr;
+    }
+
+    match allocator::try_alloc(size, DEFAULT_ALIGN) {
+        Ok(res) => {
+            // The buffers are distinct, and both hold at least the copied bytes.
+            ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+            if clear {
+                ptr::write_bytes(ptr, 0, old_size);
+            }
+            allocator::free(ptr, old_size);
+
+            res
+        },
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Resize a buffer to an array of `nmemb` elements of `size` bytes.
+///
+/// The old size is looked up in the live allocations (see `resolve_interior`). A null pointer
+/// allocates. On failure, including an overflowing size, or a pointer not allocated by ralloc,
+/// null is returned, and the buffer is left as is.
+#[cfg(feature = "interior_pointers")]
+#[no_mangle]
+pub unsafe extern fn reallocarray(ptr: *mut u8, nmemb: usize, size: usize) -> *mut u8 {
+    let size = match nmemb.checked_mul(size) {
+        Some(size) => size,
+        None => return ptr::null_mut(),
+    };
+
+    if ptr.is_null() {
+        return mallocx(size, 0);
+    }
+
+    match interior::resolve_interior(ptr) {
+        Some((start, old_size)) if start == ptr => resize(ptr, old_size, size, false),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Resize an array of `oldnmemb` elements of `size` bytes to `nmemb` elements.
+///
+/// The elements added are zeroed. Memory given up (the elements removed, or the old buffer, if
+/// it is moved) is cleared, so the contents do not linger in free memory. A null pointer
+/// allocates a zeroed array. On failure, including an overflowing size, null is returned, and the
+/// buffer is left as is.
+#[no_mangle]
+pub unsafe extern fn recallocarray(ptr: *mut u8, oldnmemb: usize, nmemb: usize, size: usize)
+                                   -> *mut u8 {
+    let (old_size, size) = match (oldnmemb.checked_mul(size), nmemb.checked_mul(size)) {
+        (Some(old_size), Some(size)) => (old_size, size),
+        _ => return ptr::null_mut(),
+    };
+
+    if ptr.is_null() {
+        return mallocx(size, ZERO);
+    }
+
+    if size < old_size {
+        ptr::write_bytes(ptr.offset(size as isize), 0, old_size - size);
+    }
+
+    let res = resize(ptr, old_size, size, true);
+    if !res.is_null() && size > old_size {
+        ptr::write_bytes(res.offset(old_size as isize), 0, size - old_size);
+    }
+
+    res
+}
+
+/// Read a statistic by name (see the `ctl` module).
+///
+/// The value is stored in `oldp` (if not null), which must hold `*oldlenp` bytes, the size of a
+/// `size_t`. Nothing can be written, so `newp` must be null. Like jemalloc, zero is returned on
+/// success, `ENOENT` for unknown names, `EPERM` for writes, and `EINVAL` for a wrong length.
+#[no_mangle]
+pub unsafe extern fn mallctl(name: *const u8, oldp: *mut usize, oldlenp: *mut usize,
+                             newp: *const u8, _: usize) -> i32 {
+    if !newp.is_null() {
+        return EPERM;
+    }
+
+    let mut len = 0;
+    while *name.offset(len as isize) != 0 {
+        len += 1;
+    }
+
+    let value = match str::from_utf8(slice::from_raw_parts(name, len)).ok().and_then(ctl::ctl) {
+        Some(value) => value,
+        None => return ENOENT,
+    };
+
+    if !oldp.is_null() {
+        if oldlenp.is_null() || *oldlenp != mem::size_of::<usize>() {
+            return EINVAL;
+        }
+
+        *oldp = value;
+    }
+
+    0
+}
@@ -0,0 +1,316 @@
+//! Runtime configuration.
+//!
+//! Some behavior can be changed without recompiling, either through the API, or through the
+//! `RALLOC_CONF` environment variable. The latter holds a comma-separated list of options, each
+//! of which is either a name (enabling the option) or `name=value`, e.g. `RALLOC_CONF=check`.
+//!
+//! The environment is read on first use. The API overrides it. On OpenBSD, the checks also
+//! follow the `S` and `C` flags of the native `MALLOC_OPTIONS`, unless `RALLOC_CONF` decides.
+
+use core::cmp;
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::{config, env};
+
+/// The state of an option, which has not been decided yet.
+const UNSET: usize = 0;
+/// The state of a disabled option.
+const OFF: usize = 1;
+/// The state of an enabled option.
+const ON: usize = 2;
+
+/// Are the consistency checks enabled at runtime?
+static CHECKS: AtomicUsize = AtomicUsize::new(UNSET);
+/// The maximum number of free bytes kept in a thread cache.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static CACHE_BYTES: AtomicUsize = AtomicUsize::new(UNSET);
+/// The maximum number of free blocks kept in a thread cache.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static CACHE_BLOCKS: AtomicUsize = AtomicUsize::new(UNSET);
+/// The maximum number of bytes the global allocator takes from the OS.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static LIMIT: AtomicUsize = AtomicUsize::new(UNSET);
+/// The decay interval of free pages, in ticks of the decay clock.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static DECAY: AtomicUsize = AtomicUsize::new(UNSET);
+/// The maximum number of bytes the heap grows by in a window.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static GROWTH_BYTES: AtomicUsize = AtomicUsize::new(UNSET);
+/// The length of the growth windows, in milliseconds.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static GROWTH_WINDOW: AtomicUsize = AtomicUsize::new(UNSET);
+/// The share of the heap (in percent) in use, below which the heap doesn't grow.
+///
+/// Numeric options are stored plus one, so they don't collide with `UNSET`.
+static GROWTH_UTILIZATION: AtomicUsize = AtomicUsize::new(UNSET);
+
+/// Get the value of an option in `RALLOC_CONF`.
+///
+/// Options given by name only have the value `1`. `None` is returned, if the option is not given.
+fn option(name: &str) -> Option<&'static [u8]> {
+    env::var("RALLOC_CONF").and_then(|conf| {
+        conf.split(|&x| x == b',').filter_map(|opt| {
+            if opt == name.as_bytes() {
+                Some(&b"1"[..])
+            } else if opt.len() > name.len() && opt.starts_with(name.as_bytes())
+                      && opt[name.len()] == b'=' {
+                Some(&opt[name.len() + 1..])
+            } else {
+                None
+            }
+        }).last()
+    })
+}
+
+/// Is some boolean option enabled?
+///
+/// The environment is read the first time.
+fn flag(state: &AtomicUsize, name: &str) -> bool {
+    match state.load(atomic::Ordering::Relaxed) {
+        ON => true,
+        OFF => false,
+        _ => {
+            let on = option(name).map_or_else(|| native(name), |x| x != b"0");
+
+            // Logging.
+            log!(NOTE, "The option '{}' is {}.", name, if on { "on" } else { "off" });
+
+            // The API might have decided in the meantime, in which case it wins.
+            let _ = state.compare_and_swap(UNSET, if on { ON } else { OFF },
+                                           atomic::Ordering::Relaxed);
+
+            state.load(atomic::Ordering::Relaxed) == ON
+        },
+    }
+}
+
+/// Is some boolean option enabled by the configuration of the native allocator?
+///
+/// The flags of `MALLOC_OPTIONS` are toggled by their case, and the last one wins, so `CSc`
+/// enables the canaries and the security checks, and then disables the canaries again. Either of
+/// those turns on the consistency checks.
+#[cfg(target_os = "openbsd")]
+fn native(name: &str) -> bool {
+    name == "check" && env::var("MALLOC_OPTIONS").map_or(false, |opts| {
+        opts.iter().fold((false, false), |(c, s), &x| match x {
+            b'C' | b'c' => (x == b'C', s),
+            b'S' | b's' => (c, x == b'S'),
+            _ => (c, s),
+        }) != (false, false)
+    })
+}
+
+/// Is some boolean option enabled by the configuration of the native allocator?
+///
+/// Only OpenBSD is honored.
+#[cfg(not(target_os = "openbsd"))]
+fn native(_: &str) -> bool {
+    false
+}
+
+/// Get the value of some numeric option.
+///
+/// The environment is read the first time. If the option is not given (or is not a decimal
+/// number), it is `default`.
+fn number(state: &AtomicUsize, name: &str, default: usize) -> usize {
+    match state.load(atomic::Ordering::Relaxed) {
+        UNSET => {
+            let value = option(name).and_then(parse).unwrap_or(default);
+
+            // Logging.
+            log!(NOTE, "The option '{}' is {}.", name, value);
+
+            // The API might have decided in the meantime, in which case it wins.
+            let _ = state.compare_and_swap(UNSET, encode(value), atomic::Ordering::Relaxed);
+
+            state.load(atomic::Ordering::Relaxed) - 1
+        },
+        x => x - 1,
+    }
+}
+
+/// Encode the value of a numeric option.
+///
+/// The greatest value is clamped, so it has room for the offset.
+#[inline]
+fn encode(value: usize) -> usize {
+    cmp::min(value, !0 - 1) + 1
+}
+
+/// Parse a decimal number.
+fn parse(s: &[u8]) -> Option<usize> {
+    if s.is_empty() {
+        return None;
+    }
+
+    s.iter().fold(Some(0usize), |acc, &x| {
+        acc.and_then(|acc| {
+            if x >= b'0' && x <= b'9' {
+                acc.checked_mul(10).and_then(|acc| acc.checked_add((x - b'0') as usize))
+            } else {
+                None
+            }
+        })
+    })
+}
+
+/// Are the consistency checks enabled?
+///
+/// The checks are always enabled in debug mode. In release mode, they are enabled by the `check`
+/// option or `set_checks`.
+#[inline]
+pub fn checks() -> bool {
+    cfg!(debug_assertions) || flag(&CHECKS, "check")
+}
+
+/// Enable or disable the consistency checks in release mode.
+///
+/// The checks (see `ralloc::check`) walk the heap, so they are slow, but they are useful when
+/// chasing memory corruption in a release binary. This overrides the `check` option of
+/// `RALLOC_CONF`.
+pub fn set_checks(enabled: bool) {
+    // Logging.
+    log!(NOTE, "Turning the consistency checks {}.", if enabled { "on" } else { "off" });
+
+    CHECKS.store(if enabled { ON } else { OFF }, atomic::Ordering::Relaxed);
+}
+
+/// Get the maximum number of free bytes kept in a thread cache.
+///
+/// This is given by the `cache_bytes` option or `set_thread_cache_limits`, and defaults to
+/// `config::LOCAL_MEMTRIM_LIMIT`.
+#[inline]
+pub fn cache_bytes() -> usize {
+    number(&CACHE_BYTES, "cache_bytes", config::LOCAL_MEMTRIM_LIMIT)
+}
+
+/// Get the maximum number of free blocks kept in a thread cache.
+///
+/// This is given by the `cache_blocks` option or `set_thread_cache_limits`, and defaults to
+/// `config::LOCAL_MEMTRIM_BLOCKS`.
+#[inline]
+pub fn cache_blocks() -> usize {
+    number(&CACHE_BLOCKS, "cache_blocks", config::LOCAL_MEMTRIM_BLOCKS)
+}
+
+/// Set the maximum number of free bytes and blocks kept in each thread cache.
+///
+/// When a thread cache exceeds either, its memory is given back to the global allocator. Low
+/// limits keep the memory overhead of many threads predictable, at the cost of more contention
+/// on the global allocator. This overrides the `cache_bytes` and `cache_blocks` options of
+/// `RALLOC_CONF`.
+pub fn set_thread_cache_limits(bytes: usize, blocks: usize) {
+    // Logging.
+    log!(NOTE, "Limiting the thread caches to {} bytes and {} blocks.", bytes, blocks);
+
+    CACHE_BYTES.store(encode(bytes), atomic::Ordering::Relaxed);
+    CACHE_BLOCKS.store(encode(blocks), atomic::Ordering::Relaxed);
+}
+
+/// Get the heap size limit.
+///
+/// This is given by the `limit` option or `set_limit`, and defaults to no limit.
+#[inline]
+pub fn limit() -> usize {
+    number(&LIMIT, "limit", !0)
+}
+
+/// Limit the number of bytes the global allocator takes from the OS.
+///
+/// The limit is checked before the heap is extended. When it would be exceeded, the allocator
+/// first gives unused memory back to the OS and flushes the thread cache, and only then fails the
+/// allocation: `try_alloc` returns an error, and the other entry points call the OOM handler.
+/// Memory already taken is kept, if the limit is lowered below it. This overrides the `limit`
+/// option of `RALLOC_CONF`.
+pub fn set_limit(bytes: usize) {
+    // Logging.
+    log!(NOTE, "Limiting the heap to {} bytes.", bytes);
+
+    LIMIT.store(encode(bytes), atomic::Ordering::Relaxed);
+}
+
+/// Get the decay interval of free pages.
+///
+/// This is given by the `decay` option or `set_decay`, and defaults to `config::DECAY_TICKS`.
+#[inline]
+pub fn decay() -> usize {
+    number(&DECAY, "decay", config::DECAY_TICKS)
+}
+
+/// Set the decay interval of the free pages of the global allocator, in ticks.
+///
+/// Free pages, which are not reused for `ticks` ticks of the decay clock, are made muzzy (the OS
+/// may reclaim them), and purged after another `ticks` ticks. Every housekeeping is a tick (so
+/// with the housekeeping thread, the interval is `ticks` times its interval), and so is every
+/// `config::DECAY_EVENTS`th allocation. Zero disables the decay. This overrides the `decay`
+/// option of `RALLOC_CONF`.
+pub fn set_decay(ticks: usize) {
+    // Logging.
+    log!(NOTE, "Setting the decay interval to {} ticks.", ticks);
+
+    DECAY.store(encode(ticks), atomic::Ordering::Relaxed);
+}
+
+/// Get the maximum number of bytes the heap grows by in a window.
+///
+/// This is given by the `growth_bytes` option or `set_growth_governor`, and defaults to no limit.
+#[inline]
+pub fn growth_bytes() -> usize {
+    number(&GROWTH_BYTES, "growth_bytes", !0)
+}
+
+/// Get the length of the growth windows, in milliseconds.
+///
+/// This is given by the `growth_window` option or `set_growth_governor`, and defaults to
+/// `config::GROWTH_WINDOW`.
+#[inline]
+pub fn growth_window() -> usize {
+    number(&GROWTH_WINDOW, "growth_window", config::GROWTH_WINDOW)
+}
+
+/// Get the share of the heap (in percent), which must be in use for the heap to grow.
+///
+/// This is given by the `growth_utilization` option or `set_growth_governor`, and defaults to
+/// zero. Values above 100 are clamped.
+#[inline]
+pub fn growth_utilization() -> usize {
+    cmp::min(number(&GROWTH_UTILIZATION, "growth_utilization", 0), 100)
+}
+
+/// Govern the growth of the heap.
+///
+/// The heap grows by no more than `bytes` bytes per `window` milliseconds, and only while at
+/// least `utilization` percent of it are in use. Growth beyond that is refused like growth past
+/// the heap size limit (see `set_limit`), making the allocation fail unless memory is given back.
+/// This keeps pathological workloads from ballooning the heap with mostly free memory. `!0`
+/// bytes and zero percent turn the respective brake off. This overrides the `growth_bytes`,
+/// `growth_window`, and `growth_utilization` options of `RALLOC_CONF`.
+pub fn set_growth_governor(bytes: usize, window: usize, utilization: usize) {
+    // Logging.
+    log!(NOTE, "Governing the heap growth to {} bytes per {} ms, at {}% utilization.", bytes,
+         window, utilization);
+
+    GROWTH_BYTES.store(encode(bytes), atomic::Ordering::Relaxed);
+    GROWTH_WINDOW.store(encode(window), atomic::Ordering::Relaxed);
+    GROWTH_UTILIZATION.store(encode(utilization), atomic::Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(b"16384"), Some(16384));
+        assert_eq!(parse(b"0"), Some(0));
+        assert_eq!(parse(b""), None);
+        assert_eq!(parse(b"12k"), None);
+        assert_eq!(parse(b"99999999999999999999999"), None);
+    }
+}
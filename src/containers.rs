@@ -0,0 +1,220 @@
+//! Containers bound to an arena.
+//!
+//! `RBox` and `RVec` are minimal counterparts of `Box` and `Vec`, which place their contents in
+//! an arena instead of the global allocator. A subsystem can thus keep its data in an isolated
+//! arena. The arena is borrowed through a `RefCell`, so the containers cannot outlive it, and
+//! its memory can be dropped wholesale after them.
+
+use core::{cmp, fmt, mem, ops, ptr, slice};
+use core::cell::RefCell;
+use core::ptr::Unique;
+
+use arena::Arena;
+use breaker::Breaker;
+use fail;
+
+/// A box in an arena.
+pub struct RBox<'a, T, B: Breaker + 'a> {
+    /// The value.
+    ptr: Unique<T>,
+    /// The arena holding the value.
+    arena: &'a RefCell<Arena<B>>,
+}
+
+impl<'a, T, B: Breaker> RBox<'a, T, B> {
+    /// Place a value in an arena.
+    ///
+    /// # Panics
+    ///
+    /// This panics, if the arena is borrowed.
+    pub fn new_in(value: T, arena: &'a RefCell<Arena<B>>) -> RBox<'a, T, B> {
+        let ptr = arena.borrow_mut().alloc(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+
+        unsafe {
+            // The buffer was just allocated, and fits a `T`.
+            ptr::write(ptr, value);
+
+            RBox {
+                // The arena never returns null.
+                ptr: Unique::new_unchecked(ptr),
+                arena: arena,
+            }
+        }
+    }
+
+    /// Move the value out of the box.
+    pub fn into_inner(self) -> T {
+        let res = unsafe {
+            // The value is moved out, and the box is forgotten below, so it is not dropped.
+            ptr::read(self.ptr.as_ptr())
+        };
+
+        unsafe {
+            // The buffer was allocated from the arena, and the value was moved out of it.
+            self.arena.borrow_mut().free(self.ptr.as_ptr() as *mut u8, mem::size_of::<T>());
+        }
+        mem::forget(self);
+
+        res
+    }
+}
+
+impl<'a, T, B: Breaker> ops::Deref for RBox<'a, T, B> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {
+            // The value lives as long as the box.
+            &*self.ptr.as_ptr()
+        }
+    }
+}
+
+impl<'a, T, B: Breaker> ops::DerefMut for RBox<'a, T, B> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {
+            // The value lives as long as the box, which is borrowed mutably.
+            &mut *self.ptr.as_ptr()
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug, B: Breaker> fmt::Debug for RBox<'a, T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T, B: Breaker> Drop for RBox<'a, T, B> {
+    fn drop(&mut self) {
+        unsafe {
+            // The value is owned by the box. It is dropped before the arena is borrowed, as it
+            // might use the arena itself.
+            ptr::drop_in_place(self.ptr.as_ptr());
+            self.arena.borrow_mut().free(self.ptr.as_ptr() as *mut u8, mem::size_of::<T>());
+        }
+    }
+}
+
+/// A growable array in an arena.
+pub struct RVec<'a, T, B: Breaker + 'a> {
+    /// The buffer.
+    ptr: Unique<T>,
+    /// The number of elements the buffer fits.
+    cap: usize,
+    /// The number of elements.
+    len: usize,
+    /// The arena holding the buffer.
+    arena: &'a RefCell<Arena<B>>,
+}
+
+impl<'a, T, B: Breaker> RVec<'a, T, B> {
+    /// Create an empty array in an arena.
+    ///
+    /// Nothing is allocated, until elements are pushed.
+    pub fn new_in(arena: &'a RefCell<Arena<B>>) -> RVec<'a, T, B> {
+        RVec {
+            ptr: unsafe {
+                // The alignment is nonzero.
+                Unique::new_unchecked(mem::align_of::<T>() as *mut T)
+            },
+            cap: 0,
+            len: 0,
+            arena: arena,
+        }
+    }
+
+    /// Get the number of elements the array can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Push an element.
+    ///
+    /// # Panics
+    ///
+    /// This panics, if the array needs to grow, while the arena is borrowed.
+    pub fn push(&mut self, elem: T) {
+        if self.len == self.cap {
+            let cap = cmp::max(2 * self.cap, 4);
+            self.grow(cap);
+        }
+
+        unsafe {
+            // There is room for the element.
+            ptr::write(self.ptr.as_ptr().offset(self.len as isize), elem);
+        }
+        self.len += 1;
+    }
+
+    /// Pop the last element.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+
+            unsafe {
+                // The element is initialized, and no longer part of the array.
+                Some(ptr::read(self.ptr.as_ptr().offset(self.len as isize)))
+            }
+        }
+    }
+
+    /// Reallocate the buffer to fit `cap` elements.
+    fn grow(&mut self, cap: usize) {
+        let size = match mem::size_of::<T>().checked_mul(cap) {
+            Some(size) => size,
+            None => fail::oom(fail::Error::LimitExceeded),
+        };
+
+        unsafe {
+            // The buffer was allocated from the arena, and holds `self.cap` elements.
+            let ptr = self.arena.borrow_mut().realloc(self.ptr.as_ptr() as *mut u8,
+                                                      mem::size_of::<T>() * self.cap, size,
+                                                      mem::align_of::<T>());
+
+            // The arena never returns null.
+            self.ptr = Unique::new_unchecked(ptr as *mut T);
+        }
+        self.cap = cap;
+    }
+}
+
+impl<'a, T, B: Breaker> ops::Deref for RVec<'a, T, B> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe {
+            // The first `len` elements are initialized.
+            slice::from_raw_parts(self.ptr.as_ptr(), self.len)
+        }
+    }
+}
+
+impl<'a, T, B: Breaker> ops::DerefMut for RVec<'a, T, B> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe {
+            // The first `len` elements are initialized, and the array is borrowed mutably.
+            slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len)
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug, B: Breaker> fmt::Debug for RVec<'a, T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T, B: Breaker> Drop for RVec<'a, T, B> {
+    fn drop(&mut self) {
+        unsafe {
+            // The elements are owned by the array, and dropped before the arena is borrowed, as
+            // they might use it themselves.
+            ptr::drop_in_place(&mut **self as *mut [T]);
+            self.arena.borrow_mut().free(self.ptr.as_ptr() as *mut u8,
+                                         mem::size_of::<T>() * self.cap);
+        }
+    }
+}
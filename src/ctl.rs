@@ -0,0 +1,71 @@
+//! Statistics by name.
+//!
+//! Like jemalloc's `mallctl`, the statistics are read by dotted names, so tools and scripts can
+//! query them without an entry point for each. The names are:
+//!
+//! - `stats.allocated` and `stats.peak_allocated`: The bytes in use, and their peak.
+//! - `stats.mapped` and `stats.peak_mapped`: The bytes taken from the OS, and their peak.
+//! - `stats.nallocs`, `stats.nfrees` and `stats.nreallocs`: The number of operations.
+//! - `pool.nmerges` and `pool.nfailed_merges`: The number of free blocks merged with a neighbor,
+//!   and of those having none to merge with.
+//! - `pool.nsplits` and `pool.nstubs`: The number of free blocks split, and of the stubs (free
+//!   blocks smaller than `config::MIN_SPLIT`) left in the pool by splits.
+//! - `bins`: The number of size classes (see `Stats::live`).
+//! - `bin.<i>.size`: The smallest size of class `i` (the classes are powers of two).
+//! - `bin.<i>.nallocs` and `bin.<i>.nfrees`: The number of allocations and frees of class `i`.
+//!   Reallocations moving a buffer to another class count as a free and an allocation.
+//! - `bin.<i>.curblocks`: The number of live allocations of class `i`.
+//!
+//! These expose how the allocations of a workload spread over the sizes, to tune the size
+//! classes (e.g. of the fast bins) from real data.
+
+use stats;
+
+/// Read a statistic by name.
+///
+/// `None` is returned, if there is no statistic of that name. See the module documentation for
+/// the names.
+pub fn ctl(name: &str) -> Option<usize> {
+    let usage = stats::peak();
+    let ops = stats::operations();
+    let pool = stats::pool_operations();
+
+    Some(match name {
+        "stats.allocated" => usage.in_use,
+        "stats.peak_allocated" => usage.peak_in_use,
+        "stats.mapped" => usage.extent,
+        "stats.peak_mapped" => usage.peak_extent,
+        "stats.nallocs" => ops.allocs,
+        "stats.nfrees" => ops.frees,
+        "stats.nreallocs" => ops.reallocs,
+        "pool.nmerges" => pool.merges,
+        "pool.nfailed_merges" => pool.failed_merges,
+        "pool.nsplits" => pool.splits,
+        "pool.nstubs" => pool.stubs,
+        "bins" => stats::CLASSES,
+        _ => return bin(name),
+    })
+}
+
+/// Read a statistic of a size class (`bin.<i>.<key>`).
+fn bin(name: &str) -> Option<usize> {
+    let mut parts = name.splitn(3, '.');
+
+    if parts.next() != Some("bin") {
+        return None;
+    }
+    let class = match parts.next().and_then(|class| class.parse::<usize>().ok()) {
+        Some(class) if class < stats::CLASSES => class,
+        _ => return None,
+    };
+
+    let (allocs, live) = stats::class_stats(class);
+
+    match parts.next() {
+        Some("size") => Some(if class == 0 { 0 } else { 1 << class }),
+        Some("nallocs") => Some(allocs),
+        Some("nfrees") => Some(allocs.wrapping_sub(live)),
+        Some("curblocks") => Some(live),
+        _ => None,
+    }
+}
@@ -0,0 +1,75 @@
+//! Graphviz export.
+//!
+//! The structures of the global allocator are written as a DOT graph: The free blocks of the
+//! pool, clustered by address segment and chained in address order (the edges are labeled with
+//! the allocated space between the blocks), and the size-class bins of the metadata arena. Render
+//! it with e.g. `dot -Tsvg`, to look at the state before and after a failing operation.
+
+use prelude::*;
+
+use core::fmt;
+
+use shim::config;
+
+use {allocator, meta};
+
+/// Write the structures of the global allocator as a DOT graph.
+pub fn write_dot<W: fmt::Write>(out: &mut W) -> fmt::Result {
+    // The blocks are copied into metadata, as the sink might allocate, which it cannot do while
+    // the global allocator is locked.
+    let (blocks, free) = allocator::with_global(|pool| {
+        (super::copy_blocks(pool), pool.total_bytes())
+    });
+    let bins = meta::bins();
+
+    let res = write_graph(out, &blocks, free, &bins);
+
+    meta::free(Block::from(blocks));
+
+    res
+}
+
+/// Write the graph.
+fn write_graph<W: fmt::Write>(out: &mut W, blocks: &[(usize, usize)], free: usize,
+                              bins: &[usize]) -> fmt::Result {
+    writeln!(out, "digraph ralloc {{")?;
+    writeln!(out, "    node [shape=box, fontname=monospace];")?;
+
+    // The pool.
+    writeln!(out, "    pool [label=\"global pool\\n{} blocks\\n{} bytes free\"];", blocks.len(),
+             free)?;
+    if !blocks.is_empty() {
+        writeln!(out, "    pool -> b0;")?;
+    }
+
+    // The blocks, clustered by segment.
+    let mut i = 0;
+    while i < blocks.len() {
+        let segment = blocks[i].0 >> config::SEGMENT_SHIFT;
+
+        writeln!(out, "    subgraph cluster_{} {{", segment)?;
+        writeln!(out, "        label=\"segment 0x{:x}\";", segment << config::SEGMENT_SHIFT)?;
+        while i < blocks.len() && blocks[i].0 >> config::SEGMENT_SHIFT == segment {
+            writeln!(out, "        b{} [label=\"0x{:x}\\n{} bytes\"];", i, blocks[i].0,
+                     blocks[i].1)?;
+            i += 1;
+        }
+        writeln!(out, "    }}")?;
+    }
+
+    // The order of the blocks, and the space between them.
+    for (i, w) in blocks.windows(2).enumerate() {
+        let gap = w[1].0 - (w[0].0 + w[0].1);
+        writeln!(out, "    b{} -> b{} [label=\"{} allocated\"];", i, i + 1, gap)?;
+    }
+
+    // The bins of the metadata arena.
+    write!(out, "    meta [shape=record, label=\"{{metadata arena")?;
+    for (class, &count) in bins.iter().enumerate().filter(|&(_, &count)| count != 0) {
+        write!(out, "|{} bytes: {} free", 1usize << class, count)?;
+    }
+    writeln!(out, "}}\"];")?;
+    writeln!(out, "    pool -> meta [style=dashed, label=\"metadata\"];")?;
+
+    writeln!(out, "}}")
+}
@@ -0,0 +1,80 @@
+//! JSON export.
+//!
+//! The state of the global allocator is written as a single JSON object, for external tools, and
+//! for tests asserting on the state of the allocator:
+//!
+//! ```json
+//! {"heap":{"start":94203456,"end":94465600},
+//!  "pool":{"free":151040,"blocks":[{"addr":94204416,"size":4096}]},
+//!  "stats":{"in_use":1024,"peak_in_use":8192,"extent":262144,"peak_extent":262144,
+//!           "allocs":12,"frees":9,"reallocs":2},
+//!  "config":{"checks":false,"limit":null,"cache_bytes":16384,"cache_blocks":256,
+//!            "min_split":64,"segment_shift":20,"page_size":4096}}
+//! ```
+//!
+//! (Broken into lines here for readability; the output is a single line.) Addresses and sizes are
+//! in bytes. A `limit` of `null` means there is none.
+
+use prelude::*;
+
+use core::fmt;
+
+use shim::config;
+
+use {allocator, brk, conf, meta, stats};
+
+/// Write the state of the global allocator as JSON.
+///
+/// This emits the free blocks of the pool, the extents of the heap, the statistics, and the
+/// configuration, followed by a newline. See the module documentation for the format.
+pub fn to_json<W: fmt::Write>(out: &mut W) -> fmt::Result {
+    // The blocks are copied into metadata, as the sink might allocate, which it cannot do while
+    // the global allocator is locked.
+    let (blocks, free, start, end) = allocator::with_global(|pool| {
+        let mut brk = brk::lock();
+
+        (super::copy_blocks(pool), pool.total_bytes(), brk.start_brk().get() as usize,
+         brk.current_brk().get() as usize)
+    });
+
+    let res = write_object(out, &blocks, free, start, end);
+
+    meta::free(Block::from(blocks));
+
+    res
+}
+
+/// Write the object.
+fn write_object<W: fmt::Write>(out: &mut W, blocks: &[(usize, usize)], free: usize, start: usize,
+                               end: usize) -> fmt::Result {
+    // The heap.
+    write!(out, "{{\"heap\":{{\"start\":{},\"end\":{}}},", start, end)?;
+
+    // The pool.
+    write!(out, "\"pool\":{{\"free\":{},\"blocks\":[", free)?;
+    for (n, &(addr, size)) in blocks.iter().enumerate() {
+        if n != 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{{\"addr\":{},\"size\":{}}}", addr, size)?;
+    }
+    write!(out, "]}},")?;
+
+    // The statistics.
+    let usage = stats::peak();
+    let ops = stats::operations();
+    write!(out, "\"stats\":{{\"in_use\":{},\"peak_in_use\":{},\"extent\":{},\"peak_extent\":{},\
+                 \"allocs\":{},\"frees\":{},\"reallocs\":{}}},", usage.in_use, usage.peak_in_use,
+           usage.extent, usage.peak_extent, ops.allocs, ops.frees, ops.reallocs)?;
+
+    // The configuration.
+    write!(out, "\"config\":{{\"checks\":{},\"limit\":", conf::checks())?;
+    if conf::limit() == !0 {
+        write!(out, "null")?;
+    } else {
+        write!(out, "{}", conf::limit())?;
+    }
+    writeln!(out, ",\"cache_bytes\":{},\"cache_blocks\":{},\"min_split\":{},\"segment_shift\":{},\
+                   \"page_size\":{}}}}}", conf::cache_bytes(), conf::cache_blocks(),
+             config::MIN_SPLIT, config::SEGMENT_SHIFT, config::PAGE_SIZE)
+}
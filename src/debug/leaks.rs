@@ -0,0 +1,448 @@
+//! Leak hunting.
+//!
+//! With the `leak_tracking` feature, the stack trace of every `config::LEAK_SAMPLE_RATE`th
+//! allocation through the entry points is recorded in a side table in metadata, until the buffer
+//! is freed. `live_allocations` groups the recorded buffers by stack, attributing the memory in
+//! use (and hence, leaks) to the code allocating it.
+//!
+//! The stack traces are raw return addresses (see the `backtrace` module), which are to be
+//! symbolized by the application, e.g. with `addr2line`.
+//!
+//! On platforms without Valgrind (e.g. Redox), `find_leaks` offers a first pass at finding the
+//! leaks themselves, by conservatively scanning memory for pointers to the recorded buffers.
+
+use prelude::*;
+
+use core::mem;
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::{config, globals};
+
+use backtrace::{EMPTY_TRACE, Trace};
+use hook::Event;
+use {arena, meta};
+use vec::Vec;
+
+/// The number of allocations left before the next sample.
+static COUNTDOWN: AtomicUsize = AtomicUsize::new(1);
+/// The recorded buffers (`None` if nothing was recorded yet).
+static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+/// An entry of the table.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The address of the buffer (`EMPTY` or `REMOVED` if unused).
+    key: usize,
+    /// The size of the buffer.
+    size: usize,
+    /// The stack trace of the allocation.
+    trace: Trace,
+}
+
+/// The key of an entry, which was never used.
+const EMPTY: usize = 0;
+/// The key of an entry, which was removed.
+const REMOVED: usize = !0;
+
+/// A table of the recorded buffers.
+///
+/// This is an open addressing hash table in metadata, so recording never goes through the entry
+/// points of the allocator.
+struct Table {
+    /// The entries.
+    ///
+    /// The length is a power of two.
+    entries: Vec<Entry>,
+    /// The number of entries in use (including the removed ones).
+    used: usize,
+    /// The number of buffers in the table.
+    live: usize,
+}
+
+impl Table {
+    /// Create a table with some capacity.
+    fn with_capacity(cap: usize) -> Table {
+        let mut entries = unsafe {
+            // The block is fresh metadata, and the vector is empty.
+            Vec::from_raw_parts(meta::alloc(cap * mem::size_of::<Entry>(),
+                                            mem::align_of::<Entry>()), 0)
+        };
+        for _ in 0..cap {
+            entries.push(Entry {
+                key: EMPTY,
+                size: 0,
+                trace: EMPTY_TRACE,
+            }).expect("The table was allocated too small.");
+        }
+
+        Table {
+            entries: entries,
+            used: 0,
+            live: 0,
+        }
+    }
+
+    /// Get the index, where the search for some key starts.
+    #[inline]
+    fn home(&self, key: usize) -> usize {
+        ((key as u64).wrapping_mul(0x9E3779B97F4A7C15) >> 32) as usize & (self.entries.len() - 1)
+    }
+
+    /// Insert a buffer.
+    ///
+    /// The key must not be in the table.
+    fn insert(&mut self, key: usize, size: usize, trace: Trace) {
+        // Keep the table at most half full, so searches stay short and always terminate.
+        if 2 * (self.used + 1) > self.entries.len() {
+            self.grow();
+        }
+
+        let mut i = self.home(key);
+        while self.entries[i].key != EMPTY && self.entries[i].key != REMOVED {
+            i = (i + 1) & (self.entries.len() - 1);
+        }
+
+        if self.entries[i].key == EMPTY {
+            self.used += 1;
+        }
+        self.live += 1;
+        self.entries[i] = Entry {
+            key: key,
+            size: size,
+            trace: trace,
+        };
+    }
+
+    /// Remove a buffer, returning its entry.
+    fn remove(&mut self, key: usize) -> Option<Entry> {
+        let mut i = self.home(key);
+
+        loop {
+            match self.entries[i].key {
+                EMPTY => return None,
+                k if k == key => {
+                    self.entries[i].key = REMOVED;
+                    self.live -= 1;
+
+                    return Some(self.entries[i]);
+                },
+                _ => i = (i + 1) & (self.entries.len() - 1),
+            }
+        }
+    }
+
+    /// Double the capacity of the table, dropping the removed entries.
+    fn grow(&mut self) {
+        let old = mem::replace(self, Table::with_capacity(2 * self.entries.len()));
+
+        for e in old.entries.iter().filter(|e| e.key != EMPTY && e.key != REMOVED) {
+            self.insert(e.key, e.size, e.trace);
+        }
+
+        meta::free(Block::from(old.entries));
+    }
+}
+
+/// Record an operation performed through the entry points.
+pub fn record(event: &Event) {
+    match *event {
+        Event::Alloc { ptr, size, .. } => {
+            if size != 0 && COUNTDOWN.fetch_sub(1, atomic::Ordering::Relaxed) == 1 {
+                COUNTDOWN.store(config::LEAK_SAMPLE_RATE, atomic::Ordering::Relaxed);
+
+                let trace = Trace::capture();
+
+                let mut table = TABLE.lock();
+                if table.is_none() {
+                    *table = Some(Table::with_capacity(64));
+                }
+                table.as_mut().unwrap().insert(ptr as usize, size, trace);
+            }
+        },
+        Event::Free { ptr, size } => if size != 0 {
+            if let Some(ref mut table) = *TABLE.lock() {
+                table.remove(ptr as usize);
+            }
+        },
+        Event::Realloc { old_ptr, old_size, ptr, size, .. } => if old_size != 0 {
+            // The buffer stays attributed to its original allocation.
+            if let Some(ref mut table) = *TABLE.lock() {
+                if let Some(entry) = table.remove(old_ptr as usize) {
+                    if size != 0 {
+                        table.insert(ptr as usize, size, entry.trace);
+                    }
+                }
+            }
+        },
+    }
+}
+
+/// The live allocations from some stack.
+#[derive(Clone, Copy)]
+pub struct Site {
+    /// The stack trace of the allocations.
+    trace: Trace,
+    /// The number of live allocations.
+    pub count: usize,
+    /// The number of bytes in the live allocations.
+    pub bytes: usize,
+}
+
+impl Site {
+    /// Get the stack trace of the allocations.
+    ///
+    /// These are the return addresses, innermost first. The innermost few are in the allocator
+    /// itself.
+    pub fn frames(&self) -> &[usize] {
+        self.trace.frames()
+    }
+}
+
+/// An iterator over the live allocations, grouped by stack.
+///
+/// See `live_allocations`.
+pub struct LiveAllocations {
+    /// The sites.
+    sites: Vec<Site>,
+    /// The index of the next site.
+    next: usize,
+}
+
+impl Iterator for LiveAllocations {
+    type Item = Site;
+
+    fn next(&mut self) -> Option<Site> {
+        let res = self.sites.get(self.next).cloned();
+        self.next += 1;
+
+        res
+    }
+}
+
+impl Drop for LiveAllocations {
+    fn drop(&mut self) {
+        meta::free(Block::from(mem::replace(&mut self.sites, Vec::default())));
+    }
+}
+
+/// Get the recorded live allocations, grouped by stack.
+///
+/// The sites are ordered by the number of bytes, greatest first. Only the sampled allocations
+/// (see `config::LEAK_SAMPLE_RATE`) are counted.
+pub fn live_allocations() -> LiveAllocations {
+    // Logging.
+    log!(NOTE, "Grouping the live allocations by stack.");
+
+    let mut sites = {
+        let table = TABLE.lock();
+        let live = table.as_ref().map_or(0, |table| table.live);
+
+        let mut sites: Vec<Site> = unsafe {
+            // The block is fresh metadata, and the vector is empty.
+            Vec::from_raw_parts(meta::alloc(live * mem::size_of::<Site>(),
+                                            mem::align_of::<Site>()), 0)
+        };
+
+        if let Some(ref table) = *table {
+            for e in table.entries.iter().filter(|e| e.key != EMPTY && e.key != REMOVED) {
+                if let Some(site) = sites.iter_mut().find(|site| site.trace == e.trace) {
+                    site.count += 1;
+                    site.bytes += e.size;
+                    continue;
+                }
+
+                sites.push(Site {
+                    trace: e.trace,
+                    count: 1,
+                    bytes: e.size,
+                }).expect("The sites were allocated too small.");
+            }
+        }
+
+        sites
+    };
+
+    sites.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes));
+
+    LiveAllocations {
+        sites: sites,
+        next: 0,
+    }
+}
+
+/// A recorded buffer, which nothing seems to point to.
+#[derive(Clone, Copy)]
+pub struct Leaked {
+    /// The stack trace of the allocation.
+    trace: Trace,
+    /// The address of the buffer.
+    pub ptr: usize,
+    /// The size of the buffer.
+    pub size: usize,
+}
+
+impl Leaked {
+    /// Get the stack trace of the allocation.
+    ///
+    /// See `Site::frames`.
+    pub fn frames(&self) -> &[usize] {
+        self.trace.frames()
+    }
+}
+
+/// An iterator over the leaked buffers.
+///
+/// See `find_leaks`.
+pub struct Leaks {
+    /// The leaked buffers.
+    leaked: Vec<Leaked>,
+    /// The index of the next buffer.
+    next: usize,
+}
+
+impl Iterator for Leaks {
+    type Item = Leaked;
+
+    fn next(&mut self) -> Option<Leaked> {
+        let res = self.leaked.get(self.next).cloned();
+        self.next += 1;
+
+        res
+    }
+}
+
+impl Drop for Leaks {
+    fn drop(&mut self) {
+        meta::free(Block::from(mem::replace(&mut self.leaked, Vec::default())));
+    }
+}
+
+/// A recorded buffer, as seen by the leak scanner.
+#[derive(Clone, Copy)]
+struct Candidate {
+    /// The entry of the buffer.
+    entry: Entry,
+    /// Has a pointer into the buffer been found?
+    reached: bool,
+}
+
+/// The state of a leak scan.
+struct Scan {
+    /// The recorded buffers, sorted by address.
+    candidates: Vec<Candidate>,
+    /// The reached buffers, which are yet to be scanned.
+    pending: Vec<usize>,
+}
+
+impl Scan {
+    /// Scan a region for words pointing into the buffers.
+    ///
+    /// # Safety
+    ///
+    /// The region must be readable.
+    unsafe fn scan(&mut self, ptr: *const u8, size: usize) {
+        let word = mem::size_of::<usize>();
+        // Only whole, aligned words are scanned.
+        let mut addr = (ptr as usize + word - 1) & !(word - 1);
+        let end = ptr as usize + size;
+
+        while addr + word <= end {
+            let value = *(addr as *const usize);
+
+            // Find the last buffer starting at or before the value. Pointers into the middle of
+            // a buffer count as well.
+            let i = match self.candidates.binary_search_by(|c| c.entry.key.cmp(&value)) {
+                Ok(i) => Some(i),
+                Err(0) => None,
+                Err(i) => Some(i - 1),
+            };
+            if let Some(i) = i {
+                let c = &mut self.candidates[i];
+                if !c.reached && value < c.entry.key + c.entry.size {
+                    c.reached = true;
+                    arena::push(&mut self.pending, i);
+                }
+            }
+
+            addr += word;
+        }
+    }
+}
+
+/// Find the recorded buffers, which nothing seems to point to.
+///
+/// This is a conservative scan, in the style of a mark-and-sweep collector: Every aligned word
+/// of the roots, the global variables (where their region is known, see `shim::globals`), and the
+/// reached buffers, which looks like a pointer into a recorded buffer, marks that buffer as
+/// reached. The buffers never reached are returned, biggest first.
+///
+/// The allocator cannot find the stacks of the threads on its own, so they are to be passed as
+/// roots, along with any other memory holding pointers into the heap (e.g. memory mapped by the
+/// program). Integers looking like pointers can hide leaks, but never cause false reports. Only
+/// the sampled allocations (see `config::LEAK_SAMPLE_RATE`) are scanned and reported, so the
+/// scan is only complete with a sample rate of 1.
+///
+/// Allocations and frees wait for the scan to finish, so the heap does not change under it.
+///
+/// # Safety
+///
+/// The roots must be readable.
+pub unsafe fn find_leaks(roots: &[(*const u8, usize)]) -> Leaks {
+    // Logging.
+    log!(NOTE, "Scanning for leaks.");
+
+    let table = TABLE.lock();
+    let live = table.as_ref().map_or(0, |table| table.live);
+
+    let mut scan = Scan {
+        // The block is fresh metadata, and the vector is empty.
+        candidates: Vec::from_raw_parts(meta::alloc(live * mem::size_of::<Candidate>(),
+                                                    mem::align_of::<Candidate>()), 0),
+        pending: Vec::default(),
+    };
+
+    if let Some(ref table) = *table {
+        for e in table.entries.iter().filter(|e| e.key != EMPTY && e.key != REMOVED) {
+            scan.candidates.push(Candidate {
+                entry: *e,
+                reached: false,
+            }).expect("The candidates were allocated too small.");
+        }
+    }
+    scan.candidates.sort_unstable_by(|a, b| a.entry.key.cmp(&b.entry.key));
+
+    // Mark the buffers reached from the roots.
+    if let Some((ptr, size)) = globals::region() {
+        scan.scan(ptr, size);
+    }
+    for &(ptr, size) in roots {
+        scan.scan(ptr, size);
+    }
+
+    // Mark the buffers reached from the reached buffers.
+    while let Some(i) = scan.pending.pop() {
+        let entry = scan.candidates[i].entry;
+        scan.scan(entry.key as *const u8, entry.size);
+    }
+
+    // Collect the buffers, which were never reached.
+    let mut leaked: Vec<Leaked> = Vec::default();
+    for c in scan.candidates.iter().filter(|c| !c.reached) {
+        log!(NOTE, "0x{:x}[{}] seems to be leaked.", c.entry.key, c.entry.size);
+
+        arena::push(&mut leaked, Leaked {
+            trace: c.entry.trace,
+            ptr: c.entry.key,
+            size: c.entry.size,
+        });
+    }
+    leaked.sort_unstable_by(|a, b| b.size.cmp(&a.size));
+
+    meta::free(Block::from(scan.candidates));
+    meta::free(Block::from(scan.pending));
+
+    Leaks {
+        leaked: leaked,
+        next: 0,
+    }
+}
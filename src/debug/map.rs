@@ -0,0 +1,177 @@
+//! Heap maps.
+//!
+//! The heap (the memory between the start of the heap and the program break) is rendered as a
+//! bar of characters, in the spirit of the diagrams of the `bookkeeper` module:
+//!
+//! ```notrust
+//! 0x55d0e4a4f000 [m###..##+.....#####+...........] 0x55d0e4a8f000
+//! ```
+//!
+//! Every character stands for an equal share of the heap, which is
+//!
+//! - `.` free,
+//! - `#` allocated,
+//! - `+` partly free, and partly allocated, or
+//! - `m` (partly) holding the metadata of the pool.
+//!
+//! The heap is seen from the global allocator, so blocks held in thread caches count as
+//! allocated. This is meant for eyeballing fragmentation, e.g. between the phases of a program.
+
+use prelude::*;
+
+use core::{cmp, fmt};
+use core::fmt::Write;
+use core::iter::Peekable;
+
+use bookkeeper::Bookkeeper;
+use fail::ReportWriter;
+use segment::Iter;
+use vec::Vec;
+use {allocator, brk, meta};
+
+/// Write a map of the heap, `width` characters wide, to some sink.
+///
+/// The bar is followed by a line summing up the heap.
+pub fn write_map<W: fmt::Write>(out: &mut W, width: usize) -> fmt::Result {
+    let width = cmp::max(width, 1);
+
+    // The bar is drawn into metadata, as the sink might allocate, which it cannot do while the
+    // global allocator is locked.
+    let mut bar: Vec<u8> = unsafe {
+        // The block is fresh metadata, and the vector is empty.
+        Vec::from_raw_parts(meta::alloc(width, 1), 0)
+    };
+
+    let (start, end, free, blocks) = allocator::with_global(|pool| {
+        let (start, end) = {
+            let mut brk = brk::lock();
+            (brk.start_brk().get() as usize, brk.current_brk().get() as usize)
+        };
+
+        draw(pool, start, end, width, |c| bar.push(c).expect("The bar was allocated too small."));
+
+        (start, end, pool.total_bytes(), pool.iter().count())
+    });
+
+    let res = write_bar(out, start, end, &bar).and_then(|_| {
+        writeln!(out, "{} bytes, {} free in {} blocks.", end - start, free, blocks)
+    });
+
+    meta::free(Block::from(bar));
+
+    res
+}
+
+/// Print a map of the heap, `width` characters wide.
+///
+/// The map is written to the log of the shim, like the reports of the allocator, even if logging
+/// is disabled. See `write_map`.
+pub fn print_map(width: usize) {
+    let _ = write_map(&mut ReportWriter, width);
+}
+
+/// Print a map of the heap, `width` characters wide, without blocking.
+///
+/// This is `print_map` for dying processes (e.g. in the OOM handler), where the allocator might
+/// be locked, or be out of metadata: The bar is written as it is drawn, and if the global
+/// allocator or the program break is locked, a note is printed instead.
+pub fn report_map(width: usize) {
+    let width = cmp::max(width, 1);
+    let mut out = ReportWriter;
+
+    let res = allocator::try_with_global(|pool| {
+        let (start, end) = match brk::try_lock() {
+            Some(mut brk) => (brk.start_brk().get() as usize, brk.current_brk().get() as usize),
+            None => return Ok(false),
+        };
+
+        let mut res = write!(out, "0x{:x} [", start);
+        draw(pool, start, end, width, |c| {
+            res = res.and_then(|()| out.write_char(c as char));
+        });
+
+        res.and_then(|()| {
+            writeln!(out, "] 0x{:x}", end)
+        }).and_then(|()| {
+            writeln!(out, "{} bytes, {} free in {} blocks.", end - start, pool.total_bytes(),
+                     pool.iter().count())
+        }).map(|()| true)
+    });
+
+    if res != Some(Ok(true)) {
+        let _ = writeln!(out, "(The heap map is unavailable, as the allocator is locked.)");
+    }
+}
+
+/// Write the bar.
+fn write_bar<W: fmt::Write>(out: &mut W, start: usize, end: usize, bar: &[u8]) -> fmt::Result {
+    write!(out, "0x{:x} [", start)?;
+    for &c in bar {
+        out.write_char(c as char)?;
+    }
+    writeln!(out, "] 0x{:x}", end)
+}
+
+/// Draw the bar of the range from `start` to `end`, passing its characters to `emit`.
+fn draw<F: FnMut(u8)>(pool: &Bookkeeper, start: usize, end: usize, width: usize, mut emit: F) {
+    if end <= start {
+        return;
+    }
+
+    // The number of bytes of each character, rounded up, so the bar fits.
+    let cell = (end - start + width - 1) / width;
+    let mut free = pool.iter().peekable();
+
+    let mut lo = start;
+    while lo < end {
+        let hi = cmp::min(lo + cell, end);
+
+        let free_bytes = free_in(&mut free, lo, hi);
+        let mut meta_bytes = 0;
+        pool.for_each_meta(|addr, size| meta_bytes += overlap(addr, addr + size, lo, hi));
+
+        let c = if meta_bytes != 0 {
+            b'm'
+        } else if free_bytes == hi - lo {
+            b'.'
+        } else if free_bytes == 0 {
+            b'#'
+        } else {
+            b'+'
+        };
+        emit(c);
+
+        lo = hi;
+    }
+}
+
+/// Count the free bytes in the range from `lo` to `hi`.
+///
+/// The blocks before the range are skipped, and the blocks ending in the range are consumed, so
+/// the next range carries on from there.
+fn free_in(blocks: &mut Peekable<Iter>, lo: usize, hi: usize) -> usize {
+    let mut res = 0;
+
+    loop {
+        let (block, block_end) = match blocks.peek() {
+            Some(block) if block.addr() < hi => (block.addr(), block.addr() + block.size()),
+            _ => break,
+        };
+
+        res += overlap(block, block_end, lo, hi);
+
+        if block_end > hi {
+            // The block goes on in the next range.
+            break;
+        }
+        blocks.next();
+    }
+
+    res
+}
+
+/// Get the size of the overlap of two ranges.
+#[inline]
+fn overlap(a: usize, a_end: usize, b: usize, b_end: usize) -> usize {
+    cmp::min(a_end, b_end).saturating_sub(cmp::max(a, b))
+}
@@ -0,0 +1,44 @@
+//! Debugging tools.
+//!
+//! These inspect the state of the allocator, to hunt leaks (with the `leak_tracking` feature),
+//! or to look at the layout of the heap.
+
+use core::mem;
+
+use bookkeeper::Bookkeeper;
+use vec::Vec;
+use meta;
+
+mod dot;
+mod json;
+#[cfg(feature = "leak_tracking")]
+mod leaks;
+mod map;
+mod snapshot;
+
+pub use self::dot::write_dot;
+pub use self::json::to_json;
+#[cfg(feature = "leak_tracking")]
+pub use self::leaks::{Leaked, Leaks, LiveAllocations, Site, find_leaks, live_allocations, record};
+pub use self::map::{print_map, report_map, write_map};
+pub use self::snapshot::write_snapshot;
+
+/// Copy the free blocks of a pool into metadata, as `(address, size)` pairs.
+///
+/// The tools copy the state of the global allocator before writing it out, as their sinks might
+/// allocate, which they cannot do while the global allocator is locked. The vector is to be given
+/// back through `meta::free`.
+fn copy_blocks(pool: &Bookkeeper) -> Vec<(usize, usize)> {
+    let len = pool.iter().count();
+    let mut blocks: Vec<(usize, usize)> = unsafe {
+        // The block is fresh metadata, and the vector is empty.
+        Vec::from_raw_parts(meta::alloc(len * mem::size_of::<(usize, usize)>(),
+                                        mem::align_of::<(usize, usize)>()), 0)
+    };
+
+    for block in pool.iter() {
+        blocks.push((block.addr(), block.size())).expect("The list was allocated too small.");
+    }
+
+    blocks
+}
@@ -0,0 +1,84 @@
+//! Heap snapshots.
+//!
+//! The state of the global allocator is encoded in the binary format of the `analysis` module,
+//! which also loads and analyzes the snapshots.
+
+use analysis::{MAGIC, VERSION};
+use {allocator, brk, stats};
+
+/// An encoder of a snapshot.
+///
+/// This keeps counting past the end of the buffer, so the length needed is known, even if the
+/// snapshot does not fit.
+struct Encoder<'a> {
+    /// The buffer.
+    buf: &'a mut [u8],
+    /// The length of the snapshot.
+    len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    /// Push a byte.
+    #[inline]
+    fn push(&mut self, byte: u8) {
+        if let Some(b) = self.buf.get_mut(self.len) {
+            *b = byte;
+        }
+        self.len += 1;
+    }
+
+    /// Push an LEB128 integer.
+    fn int(&mut self, mut x: usize) {
+        while x >= 0x80 {
+            self.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.push(x as u8);
+    }
+}
+
+/// Write a snapshot of the global allocator to a buffer.
+///
+/// The length of the snapshot is returned. If it does not fit in the buffer, the length needed is
+/// returned as an error, so the snapshot can be taken again with a larger buffer (the state might
+/// have changed in between, so leave some room). The snapshot is loaded with
+/// `analysis::Snapshot::parse`.
+pub fn write_snapshot(buf: &mut [u8]) -> Result<usize, usize> {
+    let mut enc = Encoder {
+        buf: buf,
+        len: 0,
+    };
+
+    // The buffer is owned by the caller, so it can be written without allocating, while the
+    // global allocator is locked.
+    allocator::with_global(|pool| {
+        let (start, end) = {
+            let mut brk = brk::lock();
+            (brk.start_brk().get() as usize, brk.current_brk().get() as usize)
+        };
+        let usage = stats::peak();
+
+        for &byte in MAGIC {
+            enc.push(byte);
+        }
+        enc.push(VERSION);
+        enc.int(start);
+        enc.int(end);
+        enc.int(usage.in_use);
+        enc.int(usage.extent);
+        enc.int(pool.iter().count());
+
+        let mut prev = 0;
+        for block in pool.iter() {
+            enc.int(block.addr() - prev);
+            enc.int(block.size());
+            prev = block.addr() + block.size();
+        }
+    });
+
+    if enc.len <= enc.buf.len() {
+        Ok(enc.len)
+    } else {
+        Err(enc.len)
+    }
+}
@@ -0,0 +1,359 @@
+//! Decay of free memory.
+//!
+//! Free pages kept in the pool of the global allocator are given back to the OS, when they have
+//! not been reused for a while. This is done in two stages: A page, which stays free for the
+//! decay interval, is first made muzzy (`MADV_FREE`), so the OS may reclaim it lazily, and one
+//! staying free for another interval is purged (`MADV_DONTNEED`). Reusing a page is free at
+//! either stage, as the OS hands out zeroed pages on the next touch.
+//!
+//! There is no clock in `no_std`, so time is counted in ticks. Every housekeeping (see
+//! `housekeeping::maintain`) is a tick, and so is every `config::DECAY_EVENTS`th operation
+//! through the entry points (counted per thread with the `tls` feature), so the memory decays
+//! even without a housekeeping thread. The interval is given by `conf::decay`.
+//!
+//! The stages are tracked per run of pages, which the free blocks are broken into: A block
+//! merged with a freed neighbor keeps the stages of its old pages, and only its new pages start
+//! over as dirty. Allocations overlapping muzzy or purged pages are logged, so reused pages start
+//! over as dirty at the next tick, even if they were freed again meanwhile. The reuse of dirty
+//! pages is not logged (to keep the log off the hot path), which only lets them decay early.
+
+use prelude::*;
+
+use core::{cmp, mem};
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::{config, syscalls};
+
+use {allocator, conf, meta};
+use fence::{page_down, page_up};
+use hook::Event;
+use vec::Vec;
+
+#[cfg(feature = "tls")]
+use tls;
+
+/// The number of reused ranges logged between two ticks.
+///
+/// Beyond this, the last range is widened to cover the new ones.
+const REUSE_LOG: usize = 16;
+
+/// The decay clock.
+static CLOCK: AtomicUsize = AtomicUsize::new(0);
+/// The number of operations since the last tick driven by them.
+#[cfg(not(feature = "tls"))]
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "tls")]
+tls! {
+    /// The number of operations of the thread since the last tick driven by them.
+    static EVENTS: MoveCell<usize> = MoveCell::new(0);
+}
+/// The runs of pages of the free blocks, sorted by address (`None` if never scanned).
+static PAGES: Mutex<Option<Vec<Pages>>> = Mutex::new(None);
+/// The start of the muzzy and purged pages (`!0` if none).
+static DECAYED_START: AtomicUsize = AtomicUsize::new(!0);
+/// The end of the muzzy and purged pages.
+static DECAYED_END: AtomicUsize = AtomicUsize::new(0);
+/// The ranges of decayed pages reused since the last tick.
+static REUSED: Mutex<Reused> = Mutex::new(Reused {
+    ranges: [(0, 0); REUSE_LOG],
+    len: 0,
+});
+
+/// The decay stage of some pages.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The pages are resident.
+    Dirty,
+    /// The pages may be reclaimed by the OS.
+    Muzzy,
+    /// The pages are given back to the OS.
+    Purged,
+}
+
+/// A run of whole pages of a free block in the same stage.
+#[derive(Clone, Copy)]
+struct Pages {
+    /// The start of the pages.
+    start: usize,
+    /// The end of the pages.
+    end: usize,
+    /// The tick at which the pages entered their stage.
+    since: usize,
+    /// The stage of the pages.
+    stage: Stage,
+}
+
+impl Pages {
+    /// Advance the pages to the next stage.
+    fn decay(&mut self, now: usize) {
+        let res = unsafe {
+            // The pages are whole pages of a free block, so their content is unused.
+            match self.stage {
+                Stage::Dirty => {
+                    syscalls::madvise(self.start as *mut u8, self.end - self.start,
+                                      syscalls::MADV_FREE)
+                },
+                _ => {
+                    syscalls::madvise(self.start as *mut u8, self.end - self.start,
+                                      syscalls::MADV_DONTNEED)
+                },
+            }
+        };
+
+        // Logging.
+        log!(DEBUG, "Decaying the free pages 0x{:x}[{}].", self.start, self.end - self.start);
+
+        self.since = now;
+        self.stage = match (self.stage, res) {
+            (Stage::Dirty, Ok(())) => Stage::Muzzy,
+            // Lazy freeing is not supported (e.g. on older kernels), so we purge right away.
+            (Stage::Dirty, Err(())) => {
+                let res = unsafe {
+                    // See above.
+                    syscalls::madvise(self.start as *mut u8, self.end - self.start,
+                                      syscalls::MADV_DONTNEED)
+                };
+                if res.is_ok() { Stage::Purged } else { Stage::Dirty }
+            },
+            (_, Ok(())) => Stage::Purged,
+            (stage, Err(())) => stage,
+        };
+    }
+}
+
+/// The log of the reused ranges of decayed pages.
+struct Reused {
+    /// The ranges, as `(start, end)` pairs of page boundaries.
+    ranges: [(usize, usize); REUSE_LOG],
+    /// The number of ranges.
+    len: usize,
+}
+
+impl Reused {
+    /// Log a range.
+    fn push(&mut self, start: usize, end: usize) {
+        if self.len < REUSE_LOG {
+            self.ranges[self.len] = (start, end);
+            self.len += 1;
+        } else {
+            // The log is full, so the last range is widened to cover this one.
+            let last = &mut self.ranges[REUSE_LOG - 1];
+            *last = (cmp::min(last.0, start), cmp::max(last.1, end));
+        }
+    }
+
+    /// Take the logged ranges, sorted by their start.
+    fn take(&mut self) -> ([(usize, usize); REUSE_LOG], usize) {
+        let len = mem::replace(&mut self.len, 0);
+        let mut ranges = self.ranges;
+        ranges[..len].sort_unstable();
+
+        (ranges, len)
+    }
+}
+
+/// Get the decay clock.
+#[inline]
+pub fn now() -> usize {
+    CLOCK.load(atomic::Ordering::Relaxed)
+}
+
+/// Account for an operation through the entry points.
+///
+/// Allocations reusing decayed pages are logged, and every `config::DECAY_EVENTS`th operation
+/// ticks the clock. No locks of the allocator may be held.
+#[inline]
+pub fn record(event: &Event) {
+    match *event {
+        Event::Alloc { ptr, size, .. } | Event::Realloc { ptr, size, .. } => reuse(ptr, size),
+        Event::Free { .. } => (),
+    }
+
+    if count() {
+        tick();
+    }
+}
+
+/// Log an allocation of `size` bytes at `ptr`, if it overlaps decayed pages.
+///
+/// Only the bounds of the decayed pages are checked on the way, so this is cheap, unless the
+/// allocation falls within them.
+#[inline]
+fn reuse(ptr: *mut u8, size: usize) {
+    let start = ptr as usize;
+    let end = start.saturating_add(size);
+
+    if size != 0 && end > DECAYED_START.load(atomic::Ordering::Relaxed)
+        && start < DECAYED_END.load(atomic::Ordering::Relaxed) {
+        REUSED.lock().push(page_down(start), page_up(end));
+    }
+}
+
+/// Count an operation, and tell if the clock is to tick.
+#[cfg(feature = "tls")]
+#[inline]
+fn count() -> bool {
+    EVENTS.with(|events| {
+        let n = events.replace(0) + 1;
+        if n < config::DECAY_EVENTS {
+            events.replace(n);
+        }
+
+        n >= config::DECAY_EVENTS
+    })
+}
+
+/// Count an operation, and tell if the clock is to tick.
+///
+/// Without the `tls` feature, the operations of all threads are counted together.
+#[cfg(not(feature = "tls"))]
+#[inline]
+fn count() -> bool {
+    if EVENTS.fetch_add(1, atomic::Ordering::Relaxed) + 1 >= config::DECAY_EVENTS {
+        EVENTS.store(0, atomic::Ordering::Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
+/// Add a run of pages to the table, merging it into the last run if they are alike.
+fn push(table: &mut Vec<Pages>, pages: Pages) {
+    if let Some(last) = table.last_mut() {
+        if last.end == pages.start && last.stage == pages.stage && last.since == pages.since {
+            last.end = pages.end;
+            return;
+        }
+    }
+
+    table.push(pages).expect("The table was allocated too small.");
+}
+
+/// Add a run of pages to the table, starting the parts reused since the last tick over as dirty.
+fn push_reused(table: &mut Vec<Pages>, mut pages: Pages, reused: &[(usize, usize)], now: usize) {
+    let overlapping = reused.iter().filter(|&&(start, end)| start < pages.end && end > pages.start);
+    for &(start, end) in overlapping {
+        if pages.start < start {
+            push(table, Pages {
+                end: start,
+                ..pages
+            });
+        }
+
+        push(table, Pages {
+            start: cmp::max(start, pages.start),
+            end: cmp::min(end, pages.end),
+            since: now,
+            stage: Stage::Dirty,
+        });
+
+        pages.start = cmp::min(end, pages.end);
+        if pages.start == pages.end {
+            return;
+        }
+    }
+
+    push(table, pages);
+}
+
+/// Tick the decay clock.
+///
+/// The free blocks of the global allocator are scanned, and the pages having stayed in their
+/// stage for the decay interval decay to the next. No locks of the allocator may be held.
+pub fn tick() {
+    // Decaying takes `madvise`, which neither an enclave nor a seccomp filter allows.
+    if cfg!(feature = "enclave") || cfg!(feature = "seccomp") {
+        return;
+    }
+
+    let now = CLOCK.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+    let interval = conf::decay();
+
+    let mut table = PAGES.lock();
+    let old = table.take().unwrap_or_else(Vec::default);
+
+    let new = allocator::with_global(|pool| {
+        // The allocations from the pool are done under its lock, so pages reused before this
+        // scan are either logged by now, or not free (and thus dropped from the table).
+        let (reused, reused_len) = REUSED.lock().take();
+        let reused = &reused[..reused_len];
+
+        // Every run is split at most by the bounds of the blocks, of the old runs and of the
+        // reused ranges.
+        let cap = pool.len() + 2 * old.len() + 2 * reused.len();
+        let mut new: Vec<Pages> = unsafe {
+            // The block is fresh metadata, and the vector is empty.
+            Vec::from_raw_parts(meta::alloc(cap * mem::size_of::<Pages>(),
+                                            mem::align_of::<Pages>()), 0)
+        };
+
+        // Both the pool and the old table are sorted, so they are walked side by side.
+        let mut ind = 0;
+        for block in pool.iter() {
+            let start = page_up(block.addr());
+            let end = page_down(block.addr() + block.size());
+
+            // The pages are covered by the old runs overlapping them, and dirty elsewhere.
+            let mut at = start;
+            while at < end {
+                while ind < old.len() && old[ind].end <= at {
+                    ind += 1;
+                }
+
+                let pages = match old.get(ind) {
+                    Some(run) if run.start <= at => Pages {
+                        start: at,
+                        end: cmp::min(run.end, end),
+                        ..*run
+                    },
+                    next => Pages {
+                        start: at,
+                        end: next.map_or(end, |run| cmp::min(run.start, end)),
+                        since: now,
+                        stage: Stage::Dirty,
+                    },
+                };
+
+                at = pages.end;
+                push_reused(&mut new, pages, reused, now);
+            }
+        }
+
+        // Decay the runs, and bound the decayed ones.
+        let mut decayed = (!0, 0);
+        for pages in new.iter_mut() {
+            if interval != 0 && pages.stage != Stage::Purged && now - pages.since >= interval {
+                pages.decay(now);
+            }
+            if pages.stage != Stage::Dirty {
+                decayed = (cmp::min(decayed.0, pages.start), cmp::max(decayed.1, pages.end));
+            }
+        }
+        DECAYED_START.store(decayed.0, atomic::Ordering::Relaxed);
+        DECAYED_END.store(decayed.1, atomic::Ordering::Relaxed);
+
+        new
+    });
+
+    meta::free(Block::from(old));
+    *table = Some(new);
+}
+
+/// Count the free bytes of the global allocator in each stage, as `(dirty, muzzy, purged)`.
+///
+/// This is as of the last tick. Bytes of free blocks, which are not whole pages, are not
+/// counted.
+pub fn decay_stages() -> (usize, usize, usize) {
+    let table = PAGES.lock();
+
+    table.as_ref().map_or((0, 0, 0), |table| {
+        table.iter().fold((0, 0, 0), |(dirty, muzzy, purged), pages| {
+            let size = pages.end - pages.start;
+            match pages.stage {
+                Stage::Dirty => (dirty + size, muzzy, purged),
+                Stage::Muzzy => (dirty, muzzy + size, purged),
+                Stage::Purged => (dirty, muzzy, purged + size),
+            }
+        })
+    })
+}
@@ -0,0 +1,96 @@
+//! Detached buffers.
+//!
+//! A buffer can be detached from the allocator, handing it to the caller for good (e.g. when it
+//! is mapped into another process, which never gives it back). The detached ranges are recorded,
+//! so freeing or reallocating them by mistake is caught, rather than letting the memory be
+//! reused.
+
+use prelude::*;
+
+use core::{cmp, mem, usize};
+use core::sync::atomic::{self, AtomicUsize};
+
+use meta;
+use vec::Vec;
+
+/// The start of the first detached range (`usize::MAX` if nothing was detached yet).
+///
+/// Together with `END`, this bounds the detached ranges, so frees outside of them need not take
+/// the lock of the table.
+static START: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// The end of the last detached range (zero if nothing was detached yet).
+static END: AtomicUsize = AtomicUsize::new(0);
+/// The start and end of the detached ranges, sorted and merged (`None` if nothing was detached
+/// yet).
+///
+/// As the ranges are disjoint, their ends are sorted as well.
+static RANGES: Mutex<Option<Vec<(usize, usize)>>> = Mutex::new(None);
+
+/// Get the number of ranges starting before `end`.
+#[inline]
+fn before(ranges: &[(usize, usize)], end: usize) -> usize {
+    // The comparison never reports equality, so the search ends at the partition point.
+    let res = ranges.binary_search_by(|&(a, _)| {
+        if a < end { cmp::Ordering::Less } else { cmp::Ordering::Greater }
+    });
+
+    match res {
+        Ok(ind) | Err(ind) => ind,
+    }
+}
+
+/// Record a detached buffer.
+pub fn detach(ptr: *mut u8, size: usize) {
+    // Logging.
+    log!(DEBUG, "Detaching 0x{:x}[{}].", ptr as usize, size);
+
+    let mut ranges = RANGES.lock();
+    if ranges.is_none() {
+        *ranges = Some(Vec::default());
+    }
+
+    let ranges = ranges.as_mut().unwrap();
+    let (mut start, mut end) = (ptr as usize, ptr as usize + size);
+
+    // Swallow the ranges overlapping or adjacent to the buffer, keeping the table disjoint.
+    let mut ind = before(ranges, start);
+    if ind > 0 && ranges[ind - 1].1 >= start {
+        ind -= 1;
+    }
+    while ind < ranges.len() && ranges[ind].0 <= end {
+        let (a, b) = ranges.remove(ind);
+        start = cmp::min(start, a);
+        end = cmp::max(end, b);
+    }
+
+    if ranges.len() == ranges.capacity() {
+        // Grow the table.
+        let cap = cmp::max(2 * ranges.capacity(), 16);
+        let block = meta::alloc(cap * mem::size_of::<(usize, usize)>(),
+                                mem::align_of::<(usize, usize)>());
+        meta::free(ranges.refill(block));
+    }
+    ranges.insert(ind, (start, end)).expect("The table was grown too little.");
+
+    START.store(ranges[0].0, atomic::Ordering::SeqCst);
+    END.store(ranges[ranges.len() - 1].1, atomic::Ordering::SeqCst);
+}
+
+/// Does a buffer overlap a detached range?
+#[inline]
+pub fn overlaps(ptr: *mut u8, size: usize) -> bool {
+    let (start, end) = (ptr as usize, ptr as usize + size);
+
+    start < END.load(atomic::Ordering::SeqCst) && START.load(atomic::Ordering::SeqCst) < end
+        && overlaps_slow(start, end)
+}
+
+/// Does `start..end` overlap a detached range?
+#[cold]
+fn overlaps_slow(start: usize, end: usize) -> bool {
+    RANGES.lock().as_ref().map_or(false, |ranges| {
+        // Of the ranges starting before the end, the last one reaches the furthest.
+        let ind = before(ranges, end);
+        ind > 0 && ranges[ind - 1].1 > start
+    })
+}
@@ -0,0 +1,96 @@
+//! Arenas running destructors.
+//!
+//! A `DroppingArena` hands out objects of a single type, which live as long as the arena. Every
+//! object is recorded, and when the arena is dropped, the destructors of all the objects are run,
+//! so unlike a raw `Arena`, it can safely hold types with non-trivial `Drop` implementations
+//! (e.g. the nodes of a graph holding strings or reference counts).
+
+use prelude::*;
+
+use core::{mem, ptr};
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+use arena::{self, Arena};
+use breaker::Breaker;
+use meta;
+use vec::Vec;
+
+/// An arena of objects of type `T`, which are dropped along with the arena.
+pub struct DroppingArena<T, B: Breaker> {
+    /// The inner state.
+    inner: RefCell<Inner<B>>,
+    /// The arena owns the objects.
+    _marker: PhantomData<T>,
+}
+
+/// The inner state of a dropping arena.
+struct Inner<B: Breaker> {
+    /// The arena holding the objects.
+    arena: Arena<B>,
+    /// The addresses of the objects, in allocation order.
+    objects: Vec<usize>,
+}
+
+impl<T, B: Breaker> DroppingArena<T, B> {
+    /// Create a new dropping arena fed by some breaker.
+    pub fn new(breaker: B) -> DroppingArena<T, B> {
+        DroppingArena {
+            inner: RefCell::new(Inner {
+                arena: Arena::new(breaker),
+                objects: Vec::default(),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Place a value in the arena.
+    ///
+    /// The value lives as long as the arena, and is dropped along with it.
+    #[allow(mut_from_ref)]
+    pub fn alloc(&self, value: T) -> &mut T {
+        let mut inner = self.inner.borrow_mut();
+        let ptr = inner.arena.alloc(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+
+        unsafe {
+            // The buffer was just allocated, and fits a `T`.
+            ptr::write(ptr, value);
+        }
+        arena::push(&mut inner.objects, ptr as usize);
+
+        unsafe {
+            // The object is never moved or freed before the arena is dropped, and every call
+            // hands out a distinct object, so the reference is unique.
+            &mut *ptr
+        }
+    }
+
+    /// Get the number of objects in the arena.
+    pub fn len(&self) -> usize {
+        self.inner.borrow().objects.len()
+    }
+
+    /// Is the arena empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, B: Breaker> Drop for DroppingArena<T, B> {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+
+        // Logging.
+        log!(DEBUG, "Dropping the {} objects of an arena.", inner.objects.len());
+
+        for &addr in inner.objects.iter() {
+            unsafe {
+                // The objects are initialized, and owned by the arena. They are dropped in
+                // allocation order.
+                ptr::drop_in_place(addr as *mut T);
+            }
+        }
+
+        meta::free(Block::from(mem::replace(&mut inner.objects, Vec::default())));
+    }
+}
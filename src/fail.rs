@@ -3,41 +3,173 @@
 use prelude::*;
 
 use core::sync::atomic::{self, AtomicPtr};
-use core::mem;
+use core::{fmt, mem};
+
+use alloc::heap::Layout;
 
 use shim::config;
 
+use {debug, stats};
+
 #[cfg(feature = "tls")]
 use tls;
 
 /// The global OOM handler.
-static OOM_HANDLER: AtomicPtr<()> = AtomicPtr::new(config::default_oom_handler as *mut ());
+static OOM_HANDLER: AtomicPtr<()> = AtomicPtr::new(default_oom_handler as *mut ());
 #[cfg(feature = "tls")]
 tls! {
     /// The thread-local OOM handler.
-    static THREAD_OOM_HANDLER: MoveCell<Option<fn() -> !>> = MoveCell::new(None);
+    static THREAD_OOM_HANDLER: MoveCell<Option<fn(Error) -> !>> = MoveCell::new(None);
+}
+
+/// The cause of an allocation failure.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The system is unable to provide more memory.
+    OutOfMemory {
+        /// The number of bytes requested from the system.
+        requested: usize,
+        /// The number of free bytes held by the allocator, which were unable to serve the
+        /// request (e.g. due to fragmentation).
+        available: usize,
+    },
+    /// The memory source failed with some OS error number.
+    BreakerFailed(usize),
+    /// The request exceeds the limits of the allocator (e.g. the size overflows `isize`).
+    LimitExceeded,
+    /// The allocator state is corrupted (e.g. the metadata has been overwritten).
+    Poisoned,
+}
+
+impl Error {
+    /// Get a short description of the error.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Error::OutOfMemory { .. } => "Out of memory",
+            Error::BreakerFailed(_) => "The memory source failed",
+            Error::LimitExceeded => "The request exceeds the limits of the allocator",
+            Error::Poisoned => "The allocator state is corrupted",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::OutOfMemory { requested, available } => {
+                write!(f, "{} ({} bytes requested, {} bytes available)", self.description(),
+                       requested, available)
+            },
+            Error::BreakerFailed(errno) => write!(f, "{} (error {})", self.description(), errno),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+/// Get the error of a failure to acquire `requested` bytes from a breaker.
+///
+/// The breaker fails with an OS error number, which is reported as `BreakerFailed`, or zero, if it
+/// is merely exhausted (see `Breaker::fresh`), which is reported as `OutOfMemory`.
+pub fn acquire_error(errno: usize, requested: usize, available: usize) -> Error {
+    if errno == 0 {
+        Error::OutOfMemory {
+            requested: requested,
+            available: available,
+        }
+    } else {
+        Error::BreakerFailed(errno)
+    }
+}
+
+/// A writer to the log, which is used for reports even if logging is disabled.
+pub struct ReportWriter;
+
+impl fmt::Write for ReportWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if config::log(s) == !0 { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
+/// The default OOM handler.
+///
+/// This reports the error, and then calls the default handler of the shim.
+#[cold]
+fn default_oom_handler(err: Error) -> ! {
+    use core::fmt::Write;
+
+    let _ = writeln!(ReportWriter, "\x1b[31;1m{}.\x1b[m", err);
+
+    config::default_oom_handler()
+}
+
+/// The width of the heap map of `report_oom`.
+const REPORT_MAP_WIDTH: usize = 64;
+
+/// An OOM handler reporting the state of the allocator.
+///
+/// Besides the error, the memory usage, the operation counts, and a map of the heap (see
+/// `debug::report_map`) are written to the log of the shim (even if logging is disabled), before
+/// the default handler of the shim is called (aborting by default). Nothing is allocated, and no
+/// lock is waited for, so this works on `no_std` targets, and when the allocator failed with its
+/// locks held. Install it with `install_oom_report`.
+#[cold]
+pub fn report_oom(err: Error) -> ! {
+    use core::fmt::Write;
+
+    let usage = stats::peak();
+    let ops = stats::operations();
+
+    let _ = writeln!(ReportWriter, "\x1b[31;1m{}.\x1b[m", err);
+    let _ = writeln!(ReportWriter, "{} bytes in use (at most {}), {} bytes taken from the OS (at \
+                                    most {}).", usage.in_use, usage.peak_in_use, usage.extent,
+                     usage.peak_extent);
+    let _ = writeln!(ReportWriter, "{} allocations, {} frees, {} reallocations.", ops.allocs,
+                     ops.frees, ops.reallocs);
+    debug::report_map(REPORT_MAP_WIDTH);
+
+    config::default_oom_handler()
+}
+
+/// Install `report_oom` as the OOM handler.
+///
+/// This way, OOM crashes come with the diagnostics needed to tell a leak from fragmentation or a
+/// runaway request.
+pub fn install_oom_report() {
+    set_oom_handler(report_oom);
+}
+
+/// Handle an allocation error.
+///
+/// This calls the OOM handler (see `oom`) with the size of the failed request, so the allocation
+/// error handler of a program (`Alloc::oom`, or the handler of its `no_std` runtime) can hand the
+/// failure over to ralloc's handler, e.g. `report_oom`.
+pub fn handle_alloc_error(layout: Layout) -> ! {
+    oom(Error::OutOfMemory {
+        requested: layout.size(),
+        available: 0,
+    })
 }
 
 /// Call the OOM handler.
 ///
-/// This is used one out-of-memory errors, and will never return. Usually, it simply consists
-/// of aborting the process.
+/// This is used on allocation failures, and will never return. Usually, it simply consists of
+/// aborting the process. The handler is given the cause of the failure.
 ///
 /// # An important note
 ///
-/// This is for OOM-conditions, not malformed or too big allocations, but when the system is unable
-/// to gather memory for the allocation (SBRK fails).
+/// This is for conditions where the allocation cannot be served (e.g. SBRK fails, or the request
+/// is impossible), not for recoverable errors of fallible APIs.
 ///
 /// The rule of thumb is that this should be called, if and only if unwinding (which allocates)
 /// will hit the same error.
-pub fn oom() -> ! {
+pub fn oom(err: Error) -> ! {
     // If TLS is enabled, we will use the thread-local OOM.
     #[cfg(feature = "tls")]
     {
         if let Some(handler) = THREAD_OOM_HANDLER.with(|x| x.replace(None)) {
             log!(DEBUG, "Calling the local OOM handler.");
 
-            handler();
+            handler(err);
         }
     }
 
@@ -47,7 +179,7 @@ pub fn oom() -> ! {
         // LAST AUDIT: 2016-08-21 (Ticki).
 
         // Transmute the atomic pointer to a function pointer and call it.
-        (mem::transmute::<_, fn() -> !>(OOM_HANDLER.load(atomic::Ordering::SeqCst)))()
+        (mem::transmute::<_, fn(Error) -> !>(OOM_HANDLER.load(atomic::Ordering::SeqCst)))(err)
     }
 }
 
@@ -55,7 +187,7 @@ pub fn oom() -> ! {
 ///
 /// This is called when the process is out-of-memory.
 #[inline]
-pub fn set_oom_handler(handler: fn() -> !) {
+pub fn set_oom_handler(handler: fn(Error) -> !) {
     // Logging...
     log!(NOTE, "Setting the global OOM handler.");
 
@@ -69,7 +201,7 @@ pub fn set_oom_handler(handler: fn() -> !) {
 /// This might panic if a thread OOM handler already exists.
 #[inline]
 #[cfg(feature = "tls")]
-pub fn set_thread_oom_handler(handler: fn() -> !) {
+pub fn set_thread_oom_handler(handler: fn(Error) -> !) {
     // Logging...
     log!(NOTE, "Setting the thread OOM handler.");
 
@@ -84,6 +216,83 @@ pub fn set_thread_oom_handler(handler: fn() -> !) {
     });
 }
 
+/// An invariant violation.
+///
+/// This is the diagnostic passed to the violation callback.
+#[derive(Clone, Copy, Debug)]
+pub struct Violation {
+    /// The violated invariant.
+    pub description: &'static str,
+    /// The operation, which found the violation.
+    pub place: &'static str,
+    /// The address of the block involved (zero if none).
+    pub addr: usize,
+    /// The size of the block involved (zero if none).
+    pub size: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (in {}, at 0x{:x}[{}])", self.description, self.place, self.addr, self.size)
+    }
+}
+
+/// What to do, when an invariant of the allocator is violated.
+#[derive(Clone, Copy)]
+pub enum ViolationPolicy {
+    /// Call the OOM handler with `Error::Poisoned` (aborting by default).
+    Abort,
+    /// Call a callback with the diagnostic.
+    Callback(fn(&Violation) -> !),
+    /// Quarantine the corrupted region (leaking it) as far as possible, and continue.
+    ///
+    /// This is best-effort: The allocator state might be corrupted in ways, which aren't
+    /// detected.
+    Quarantine,
+}
+
+/// The violation policy.
+static VIOLATION_POLICY: Mutex<ViolationPolicy> = Mutex::new(ViolationPolicy::Abort);
+
+/// Set the violation policy.
+///
+/// This decides what happens, when the consistency checks (in debug mode) or the hardening
+/// checks (e.g. with the `checksum` or `paranoid` features) fail.
+#[inline]
+pub fn set_violation_policy(policy: ViolationPolicy) {
+    // Logging...
+    log!(NOTE, "Setting the violation policy.");
+
+    *VIOLATION_POLICY.lock() = policy;
+}
+
+/// Handle an invariant violation.
+///
+/// The violation is reported (regardless of the logging configuration), and the violation policy
+/// is carried out. This only returns, if the caller is to quarantine the corrupted region and
+/// continue.
+#[cold]
+pub fn violation(violation: &Violation) {
+    use core::fmt::Write;
+
+    let _ = writeln!(ReportWriter, "\x1b[31;1mInvariant violated: {}.\x1b[m", violation);
+
+    // Copy the policy out, so the lock isn't held while calling the callback.
+    let policy = *VIOLATION_POLICY.lock();
+    enforce(policy, violation);
+}
+
+/// Carry out a violation policy.
+///
+/// This only returns, if the policy is to quarantine.
+fn enforce(policy: ViolationPolicy, violation: &Violation) {
+    match policy {
+        ViolationPolicy::Abort => oom(Error::Poisoned),
+        ViolationPolicy::Callback(callback) => callback(violation),
+        ViolationPolicy::Quarantine => log!(WARNING, "Quarantining and continuing."),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -91,28 +300,39 @@ mod test {
     #[test]
     #[should_panic]
     fn test_panic_oom() {
-        fn panic() -> ! {
+        fn panic(_: Error) -> ! {
             panic!("cats are not cute.");
         }
 
         set_oom_handler(panic);
-        oom();
+        oom(Error::LimitExceeded);
     }
 
     #[test]
     #[should_panic]
     #[cfg(feature = "tls")]
     fn test_panic_thread_oom() {
-        fn infinite() -> ! {
+        fn infinite(_: Error) -> ! {
             #[allow(empty_loop)]
             loop {}
         }
-        fn panic() -> ! {
+        fn panic(_: Error) -> ! {
             panic!("cats are not cute.");
         }
 
         set_oom_handler(infinite);
         set_thread_oom_handler(panic);
-        oom();
+        oom(Error::OutOfMemory { requested: 42, available: 0 });
+    }
+
+    #[test]
+    fn test_quarantine() {
+        // The global policy is left be, as the tests running in parallel rely on it.
+        enforce(ViolationPolicy::Quarantine, &Violation {
+            description: "Cats are not cute",
+            place: "test",
+            addr: 0,
+            size: 0,
+        });
     }
 }
@@ -0,0 +1,75 @@
+//! Electric fence.
+//!
+//! With the `electric_fence` feature, the bookkeepers are bypassed entirely: Every allocation
+//! gets its own pages, placed right before a guard page, and freeing protects the pages instead
+//! of recycling them. Hence, overflows and use-after-frees fault deterministically.
+//!
+//! This is very wasteful (every allocation takes at least two pages of address space, which is
+//! never reused), and only meant for chasing memory bugs.
+
+use shim::{config, syscalls};
+
+use fail;
+
+/// Round an address up to a page boundary.
+#[inline]
+pub fn page_up(addr: usize) -> usize {
+    (addr + config::PAGE_SIZE - 1) & !(config::PAGE_SIZE - 1)
+}
+
+/// Round an address down to a page boundary.
+#[inline]
+pub fn page_down(addr: usize) -> usize {
+    addr & !(config::PAGE_SIZE - 1)
+}
+
+/// Allocate a fenced buffer.
+///
+/// The buffer ends as close to the guard page as the alignment allows.
+///
+/// # Failure
+///
+/// The OOM handler is called, if the pages cannot be mapped.
+pub fn alloc(size: usize, align: usize) -> *mut u8 {
+    // The pages holding the buffer (with room for aligning it), and the guard page.
+    let len = page_up(size + align) + config::PAGE_SIZE;
+
+    unsafe {
+        let base = syscalls::mmap(len)
+            .unwrap_or_else(|err| fail::oom(fail::Error::BreakerFailed(err))) as usize;
+        let guard = base + len - config::PAGE_SIZE;
+
+        if syscalls::mprotect(guard as *mut u8, config::PAGE_SIZE, syscalls::PROT_NONE).is_err() {
+            log!(WARNING, "Unable to protect the guard page at 0x{:x}.", guard);
+        }
+
+        // Logging.
+        log!(DEBUG, "Fenced {} bytes at 0x{:x}.", size, (guard - size) / align * align);
+
+        // The room left for aligning makes sure this stays in the mapping.
+        ((guard - size) / align * align) as *mut u8
+    }
+}
+
+/// Free a fenced buffer.
+///
+/// The pages of the buffer are protected, and never reused, so any later access faults.
+pub fn free(ptr: *mut u8, size: usize) {
+    // Logging.
+    log!(DEBUG, "Unfencing {} bytes at 0x{:x}.", size, ptr as usize);
+
+    // Pages might be shared with other parts of the mapping, but these are unused.
+    let start = page_down(ptr as usize);
+    let end = page_up(ptr as usize + size);
+
+    if start != end {
+        let res = unsafe {
+            // The pages belong to the mapping of this buffer.
+            syscalls::mprotect(start as *mut u8, end - start, syscalls::PROT_NONE)
+        };
+
+        if res.is_err() {
+            log!(WARNING, "Unable to protect the freed pages at 0x{:x}.", start);
+        }
+    }
+}
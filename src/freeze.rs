@@ -0,0 +1,117 @@
+//! Frozen allocations.
+//!
+//! An allocation can be frozen, making the whole pages it spans read-only, so data that must not
+//! change after startup (e.g. configuration) cannot be modified by accident or by an attacker.
+//! The frozen ranges are recorded, and made writable again before the allocation is freed or
+//! reallocated, so the allocator never trips over them.
+
+use prelude::*;
+
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::{config, syscalls};
+
+use fence;
+
+/// The number of frozen ranges.
+///
+/// This mirrors `Table::len`, so frees can skip the table, when nothing is frozen.
+static LEN: AtomicUsize = AtomicUsize::new(0);
+/// The frozen ranges.
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    ranges: [(0, 0); config::FROZEN_RANGES],
+    len: 0,
+});
+
+/// The table of frozen ranges.
+struct Table {
+    /// The page-aligned start and end of the frozen ranges.
+    ///
+    /// Only the first `len` are in use.
+    ranges: [(usize, usize); config::FROZEN_RANGES],
+    /// The number of frozen ranges.
+    len: usize,
+}
+
+/// Freeze the whole pages of a buffer.
+///
+/// The pages only partially covered by the buffer are shared with other allocations, and thus
+/// left writable. `Err(())` is returned, if the pages cannot be protected, or there are too many
+/// frozen ranges (see `config::FROZEN_RANGES`).
+pub fn freeze(ptr: *mut u8, size: usize) -> Result<(), ()> {
+    let start = fence::page_up(ptr as usize);
+    let end = fence::page_down(ptr as usize + size);
+
+    if start >= end {
+        // Logging.
+        log!(NOTE, "0x{:x}[{}] spans no whole page, so nothing is frozen.", ptr as usize, size);
+
+        return Ok(());
+    }
+
+    let mut table = TABLE.lock();
+    if table.len == config::FROZEN_RANGES {
+        log!(WARNING, "Too many frozen ranges to freeze 0x{:x}[{}].", ptr as usize, size);
+
+        return Err(());
+    }
+
+    unsafe {
+        // The pages lie within the buffer, which is owned by the caller.
+        syscalls::mprotect(start as *mut u8, end - start, syscalls::PROT_READ)?;
+    }
+
+    // Logging.
+    log!(DEBUG, "Froze the pages 0x{:x}-0x{:x}.", start, end);
+
+    let len = table.len;
+    table.ranges[len] = (start, end);
+    table.len += 1;
+    LEN.store(table.len, atomic::Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Thaw the frozen ranges overlapping a buffer.
+///
+/// This is called before a buffer is given back, so the pages are writable, when they are reused.
+#[inline]
+pub fn thaw(ptr: *mut u8, size: usize) {
+    if LEN.load(atomic::Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    thaw_overlapping(ptr as usize, ptr as usize + size);
+}
+
+/// Thaw the frozen ranges overlapping `start..end`.
+#[cold]
+fn thaw_overlapping(start: usize, end: usize) {
+    let mut table = TABLE.lock();
+
+    let mut i = 0;
+    while i < table.len {
+        let (a, b) = table.ranges[i];
+        if a < end && start < b {
+            // Logging.
+            log!(DEBUG, "Thawing the pages 0x{:x}-0x{:x}.", a, b);
+
+            let res = unsafe {
+                // The pages belong to the buffer being given back.
+                syscalls::mprotect(a as *mut u8, b - a, syscalls::PROT_READ | syscalls::PROT_WRITE)
+            };
+            if res.is_err() {
+                log!(WARNING, "Unable to thaw the pages 0x{:x}-0x{:x}.", a, b);
+            }
+
+            // Swap in the last range.
+            table.len -= 1;
+            let last = table.ranges[table.len];
+            table.ranges[i] = last;
+        } else {
+            i += 1;
+        }
+    }
+
+    LEN.store(table.len, atomic::Ordering::SeqCst);
+}
@@ -0,0 +1,76 @@
+//! Heap growth governor.
+//!
+//! Some workloads make the heap balloon, even though most of it is free (e.g. when buffers grow
+//! one after another, and the old ones are never reused). The governor puts two brakes on the
+//! growth of the global heap:
+//!
+//! - The heap grows by no more than `growth_bytes` bytes per window of `growth_window`
+//!   milliseconds of the monotonic clock. Where the clock is unavailable, the window never ends,
+//!   so the bytes bound the total growth.
+//! - Before growing, a share of `growth_utilization` percent of the heap must be in use.
+//!
+//! Growth the governor refuses is handled like growth past the heap size limit: Unused memory is
+//! given back, the memory pressure handler is called, and if that doesn't help, the allocation
+//! fails. By default, the governor admits everything.
+
+use shim::syscalls;
+
+use {conf, stats};
+
+/// The growth of the heap in the current window.
+pub struct Governor {
+    /// The start of the window, in nanoseconds of the monotonic clock.
+    start: u64,
+    /// The number of bytes the heap grew by in the window.
+    grown: usize,
+}
+
+impl Governor {
+    /// Create a governor, which has not seen any growth.
+    pub const fn new() -> Governor {
+        Governor {
+            start: 0,
+            grown: 0,
+        }
+    }
+
+    /// May the heap, holding `acquired` bytes, grow by `size` bytes?
+    pub fn admit(&mut self, size: usize, acquired: usize) -> bool {
+        // The utilization only matters once there is a heap.
+        let utilization = conf::growth_utilization();
+        if utilization > 0 && acquired > 0
+           && stats::peak().in_use < acquired / 100 * utilization {
+            // Logging.
+            log!(WARNING, "Refusing to grow the heap, as less than {}% of it is in use.",
+                 utilization);
+
+            return false;
+        }
+
+        let max = conf::growth_bytes();
+        if max != !0 {
+            let now = syscalls::clock_monotonic();
+            if now.wrapping_sub(self.start) >= conf::growth_window() as u64 * 1_000_000 {
+                // Start a new window.
+                self.start = now;
+                self.grown = 0;
+            }
+
+            if size > max.saturating_sub(self.grown) {
+                // Logging.
+                log!(WARNING, "Refusing to grow the heap by {} bytes, beyond {} bytes in the \
+                     window.", size, max);
+
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Count `size` bytes of growth against the window.
+    #[inline]
+    pub fn grew(&mut self, size: usize) {
+        self.grown = self.grown.saturating_add(size);
+    }
+}
@@ -0,0 +1,161 @@
+//! Allocation hooks.
+//!
+//! A hook is a function called after every allocation, free, and reallocation through the entry
+//! points of the crate (not the arenas), e.g. to gather statistics or record traces (see the
+//! `trace` module).
+//!
+//! The hook is called on the thread performing the operation, with no locks held, so it is free
+//! to allocate. Operations performed by the hook itself are not reported, to avoid recursion.
+//!
+//! Apart from the hook, a callback can be set to watch the heap growing (see `on_heap_growth`).
+
+use core::mem;
+use core::sync::atomic::{self, AtomicPtr};
+#[cfg(feature = "tls")]
+use core::sync::atomic::AtomicBool;
+
+#[cfg(feature = "heaptrack")]
+use shim::heaptrack;
+
+/// The hook (null if none).
+static HOOK: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
+/// The heap growth callback (null if none).
+static GROWTH_CALLBACK: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
+
+/// Is the current thread running the hook?
+#[cfg(feature = "tls")]
+#[thread_local]
+static IN_HOOK: AtomicBool = AtomicBool::new(false);
+
+/// An operation of the allocator.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// A buffer was allocated.
+    Alloc {
+        /// The buffer.
+        ptr: *mut u8,
+        /// The size of the buffer.
+        size: usize,
+        /// The alignment of the buffer.
+        align: usize,
+    },
+    /// A buffer was freed.
+    Free {
+        /// The buffer.
+        ptr: *mut u8,
+        /// The size of the buffer.
+        size: usize,
+    },
+    /// A buffer was reallocated.
+    ///
+    /// Inplace reallocations are reported with `ptr` equal to `old_ptr`, and an alignment of 1.
+    Realloc {
+        /// The old buffer.
+        old_ptr: *mut u8,
+        /// The size of the old buffer.
+        old_size: usize,
+        /// The new buffer.
+        ptr: *mut u8,
+        /// The size of the new buffer.
+        size: usize,
+        /// The alignment of the new buffer.
+        align: usize,
+    },
+}
+
+/// The cause of the heap growing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Growth {
+    /// The global allocator ran dry, and moved the program break.
+    Break,
+    /// The global allocator ran dry, and took a region from the platform allocator.
+    ///
+    /// This only happens with the `system_fallback` feature, when the program break is
+    /// unsupported. The region is not adjacent to the rest of the heap.
+    Fallback,
+    /// The metadata arena ran dry, and fell back to the program break (see the `meta` module).
+    Metadata,
+}
+
+/// Set the hook, or remove it (with `None`).
+pub fn set_hook(hook: Option<fn(Event)>) {
+    // Logging.
+    log!(NOTE, "Setting the allocation hook.");
+
+    HOOK.store(hook.map_or(0 as *mut (), |hook| hook as *mut ()), atomic::Ordering::SeqCst);
+}
+
+/// Set the heap growth callback, or remove it (with `None`).
+///
+/// The callback is called with the old and the new end of the heap, and the cause, whenever the
+/// heap grows, e.g. to log unexpected growth or to pre-touch the new memory. For regions which
+/// are not adjacent to the heap (`Growth::Fallback`), the start and the end of the region are
+/// given.
+///
+/// Unlike the hook, the callback is called right away, before any buffer is carved from the new
+/// memory, with the lock of the global allocator held. Hence, it must not allocate or free.
+pub fn on_heap_growth(callback: Option<fn(*mut u8, *mut u8, Growth)>) {
+    // Logging.
+    log!(NOTE, "Setting the heap growth callback.");
+
+    GROWTH_CALLBACK.store(callback.map_or(0 as *mut (), |callback| callback as *mut ()),
+                          atomic::Ordering::SeqCst);
+}
+
+/// Report the heap growing from `old_end` to `new_end` to the heap growth callback.
+#[inline]
+pub fn grow(old_end: *mut u8, new_end: *mut u8, reason: Growth) {
+    let callback = GROWTH_CALLBACK.load(atomic::Ordering::Relaxed);
+    if callback.is_null() {
+        return;
+    }
+
+    unsafe {
+        // The pointer was stored from a function pointer by `on_heap_growth`.
+        mem::transmute::<_, fn(*mut u8, *mut u8, Growth)>(callback)(old_end, new_end, reason);
+    }
+}
+
+/// Report an event to heaptrack, if it is attached (see `shim::heaptrack`).
+///
+/// Empty buffers are dangling, and thus not reported.
+#[cfg(feature = "heaptrack")]
+#[inline]
+pub fn profile(event: &Event) {
+    match *event {
+        Event::Alloc { ptr, size, .. } if size != 0 => heaptrack::report_alloc(ptr, size),
+        Event::Free { ptr, size } if size != 0 => heaptrack::report_free(ptr),
+        Event::Realloc { old_ptr, old_size, ptr, size, .. } => match (old_size, size) {
+            (0, 0) => (),
+            (0, _) => heaptrack::report_alloc(ptr, size),
+            (_, 0) => heaptrack::report_free(old_ptr),
+            _ => heaptrack::report_realloc(old_ptr, size, ptr),
+        },
+        _ => (),
+    }
+}
+
+/// Report an event to the hook.
+#[inline]
+pub fn emit(event: Event) {
+    let hook = HOOK.load(atomic::Ordering::Relaxed);
+    if hook.is_null() {
+        return;
+    }
+
+    // Don't report the operations of the hook itself.
+    #[cfg(feature = "tls")]
+    {
+        if IN_HOOK.swap(true, atomic::Ordering::Relaxed) {
+            return;
+        }
+    }
+
+    unsafe {
+        // The pointer was stored from a function pointer by `set_hook`.
+        mem::transmute::<_, fn(Event)>(hook)(event);
+    }
+
+    #[cfg(feature = "tls")]
+    IN_HOOK.store(false, atomic::Ordering::Relaxed);
+}
@@ -0,0 +1,150 @@
+//! Housekeeping.
+//!
+//! Some work is better done away from the allocations, to keep their tail latencies low: Free
+//! memory at the end of the heap is given back to the OS, and the thread caches are decayed, so
+//! memory freed by a thread in a burst doesn't stay stranded in its cache.
+//!
+//! The thread caches belong to their threads, so they cannot be touched from elsewhere. Instead,
+//! housekeeping advances the decay epoch, and every thread cache seeing a new epoch (when memory
+//! is next freed to it) is memtrimmed, giving its memory back to the global allocator. The free
+//! blocks are always coalesced, and frees are never queued. Lastly, every housekeeping ticks the
+//! decay clock of the free pages (see the `decay` module).
+//!
+//! The work can be bounded (see `maintain`), so an event loop can schedule it in its idle time.
+//! With the `std` feature, the housekeeping can be done periodically by a background thread (see
+//! `start_housekeeping`).
+
+use core::sync::atomic::{self, AtomicUsize};
+#[cfg(feature = "std")]
+use core::sync::atomic::AtomicBool;
+
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::Duration;
+
+use {allocator, decay};
+
+/// The decay epoch of the thread caches.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+/// Is the housekeeping thread running (or asked to keep running)?
+#[cfg(feature = "std")]
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// The generation of the housekeeping thread.
+///
+/// This is bumped by `stop_housekeeping`, and a thread exits as soon as it sees another
+/// generation than the one it was started in, so a thread being stopped never lingers next to
+/// its successor.
+#[cfg(feature = "std")]
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the decay epoch of the thread caches.
+#[inline]
+pub fn epoch() -> usize {
+    EPOCH.load(atomic::Ordering::Relaxed)
+}
+
+/// A bound on the housekeeping work done by `maintain`.
+#[derive(Clone, Copy)]
+pub enum Deadline {
+    /// Do at most some number of steps.
+    ///
+    /// A step gives a single block back to the OS.
+    Steps(usize),
+    /// Keep going until some function (e.g. reading a clock) returns `true`.
+    ///
+    /// The function is called before every step, with no locks held.
+    Until(fn() -> bool),
+}
+
+impl Deadline {
+    /// Has the deadline passed, after some number of steps?
+    #[inline]
+    fn passed(&self, steps: usize) -> bool {
+        match *self {
+            Deadline::Steps(max) => steps >= max,
+            Deadline::Until(f) => f(),
+        }
+    }
+}
+
+/// Do a bounded amount of housekeeping.
+///
+/// The thread caches are asked to decay, and the decay clock ticks. Then, the free memory at the
+/// end of the heap is given back to the OS, until the deadline passes. `true` is returned, if all
+/// the work is done, and `false`, if some is left for the next call.
+pub fn maintain(deadline: Deadline) -> bool {
+    // Logging.
+    log!(NOTE, "Housekeeping.");
+
+    EPOCH.fetch_add(1, atomic::Ordering::Relaxed);
+    decay::tick();
+
+    let mut steps = 0;
+    while !deadline.passed(steps) {
+        if !allocator::trim_one() {
+            return true;
+        }
+
+        steps += 1;
+    }
+
+    false
+}
+
+/// Do the housekeeping.
+///
+/// This is `maintain` without a deadline.
+pub fn housekeep() {
+    maintain(Deadline::Steps(!0));
+}
+
+/// Start a background thread doing the housekeeping every `interval`.
+///
+/// The thread sleeps in between, so it stays off the processor (and out of the way of the
+/// allocating threads) most of the time. `Err(())` is returned, if the thread is already running,
+/// or cannot be spawned.
+#[cfg(feature = "std")]
+pub fn start_housekeeping(interval: Duration) -> Result<(), ()> {
+    // This is read first, so a concurrent stop ends the thread.
+    let generation = GENERATION.load(atomic::Ordering::SeqCst);
+    if RUNNING.swap(true, atomic::Ordering::SeqCst) {
+        return Err(());
+    }
+
+    // Logging.
+    log!(NOTE, "Starting the housekeeping thread.");
+
+    let res = thread::Builder::new().name("ralloc-housekeeping".into()).spawn(move || {
+        loop {
+            thread::sleep(interval);
+
+            if GENERATION.load(atomic::Ordering::SeqCst) != generation {
+                break;
+            }
+
+            housekeep();
+        }
+    });
+
+    if res.is_err() {
+        RUNNING.store(false, atomic::Ordering::SeqCst);
+        log!(WARNING, "Unable to spawn the housekeeping thread.");
+
+        return Err(());
+    }
+
+    Ok(())
+}
+
+/// Stop the housekeeping thread.
+///
+/// The thread exits when it next wakes up, even if a new one is started meanwhile.
+#[cfg(feature = "std")]
+pub fn stop_housekeeping() {
+    // Logging.
+    log!(NOTE, "Stopping the housekeeping thread.");
+
+    GENERATION.fetch_add(1, atomic::Ordering::SeqCst);
+    RUNNING.store(false, atomic::Ordering::SeqCst);
+}
@@ -0,0 +1,84 @@
+//! Failure injection.
+//!
+//! With the `failure_injection` feature, allocations can be made to fail (or to take the
+//! fresh-allocation path) deterministically, e.g. every Nth allocation of some sizes. This is
+//! meant for testing the out-of-memory paths of applications (the OOM handler, and fallible
+//! allocation APIs), as well as of the allocator itself.
+
+use prelude::*;
+
+use core::sync::atomic::{self, AtomicBool, AtomicUsize};
+
+/// Is any failure injected?
+///
+/// This avoids taking the lock on the fast path.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The injection.
+static INJECTION: Mutex<Option<Injection>> = Mutex::new(None);
+/// The number of allocations matching the injection so far.
+static MATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// What to inject into an allocation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Injected {
+    /// Fail the allocation, as if the system was out of memory.
+    ///
+    /// Infallible allocations call the OOM handler, and fallible ones return an error.
+    Fail,
+    /// Serve the allocation from fresh memory, bypassing the free blocks.
+    Fresh,
+}
+
+/// A failure injection.
+#[derive(Clone, Copy, Debug)]
+pub struct Injection {
+    /// Inject into every this many matching allocations (e.g. 1 for all, and 3 for every third).
+    ///
+    /// If zero, nothing is injected.
+    pub every: usize,
+    /// Only allocations with sizes matching this are affected (all, if `None`).
+    pub predicate: Option<fn(usize) -> bool>,
+    /// What to inject.
+    pub action: Injected,
+}
+
+/// Set the failure injection, or stop injecting (with `None`).
+///
+/// This resets the count of matching allocations.
+pub fn set_injection(injection: Option<Injection>) {
+    // Logging.
+    log!(NOTE, "Setting the failure injection to {:?}.", injection);
+
+    let mut guard = INJECTION.lock();
+    *guard = injection;
+    MATCHED.store(0, atomic::Ordering::SeqCst);
+    ENABLED.store(injection.is_some(), atomic::Ordering::SeqCst);
+}
+
+/// Decide what to inject into an allocation of some size.
+///
+/// Zero-sized allocations take no memory, and are never affected.
+#[inline]
+pub fn check(size: usize) -> Option<Injected> {
+    if !ENABLED.load(atomic::Ordering::Relaxed) || size == 0 {
+        return None;
+    }
+
+    let injection = match *INJECTION.lock() {
+        Some(injection) => injection,
+        None => return None,
+    };
+
+    if injection.every == 0 || !injection.predicate.map_or(true, |f| f(size)) {
+        return None;
+    }
+
+    if (MATCHED.fetch_add(1, atomic::Ordering::SeqCst) + 1) % injection.every == 0 {
+        // Logging.
+        log!(DEBUG, "Injecting {:?} into an allocation of {} bytes.", injection.action, size);
+
+        Some(injection.action)
+    } else {
+        None
+    }
+}
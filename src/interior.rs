@@ -0,0 +1,177 @@
+//! Interior pointers.
+//!
+//! With the `interior_pointers` feature, the live allocations are recorded in a segmented pool
+//! in metadata (like the free blocks of the bookkeepers), so a pointer anywhere inside an
+//! allocation can be resolved to the allocation, as some runtimes (e.g. of garbage collected
+//! languages) and C libraries expect of `free`.
+//!
+//! Partial frees (see `free_part`) trim the recorded allocations. Splitting and merging buffers
+//! (see `split_alloc` and `merge_allocs`) is not recorded, so an interior pointer resolves to the
+//! buffer as it was allocated (or to what is left of it).
+
+use prelude::*;
+
+use core::mem;
+
+use hook::Event;
+use segment::{Pool, Position};
+
+/// The live allocations (`None` if nothing was allocated yet).
+static LIVE: Mutex<Option<Pool>> = Mutex::new(None);
+
+/// Record an allocation.
+fn insert(pool: &mut Pool, block: Block) {
+    if block.is_empty() {
+        return;
+    }
+
+    pool.make_room(&block);
+    let pos = pool.find(&block);
+    pool.insert(pos, block);
+}
+
+/// Find the position of the allocation containing `ptr` in a pool.
+fn locate(pool: &mut Pool, ptr: *mut u8) -> Option<Position> {
+    let key = Block::empty(unsafe {
+        // Only the address of the key is used.
+        Pointer::new(ptr)
+    });
+    let pos = pool.find(&key);
+
+    // The allocation either starts at the pointer, or is the last one before it.
+    match pool.next(pos) {
+        Some(next) if pool[next].addr() == ptr as usize => Some(next),
+        _ => match pool.prev(pos) {
+            Some(prev) if ptr as usize - pool[prev].addr() < pool[prev].size() => Some(prev),
+            _ => None,
+        },
+    }
+}
+
+/// Call a closure with the pool of the live allocations.
+fn with_live<F: FnOnce(&mut Pool)>(f: F) {
+    let mut live = LIVE.lock();
+    if live.is_none() {
+        *live = Some(Pool::new());
+    }
+
+    f(live.as_mut().unwrap());
+}
+
+/// Account for an operation performed through the entry points.
+///
+/// Only the new allocations are recorded here. The freed ones (and the old buffers of
+/// reallocations) are forgotten by the entry points (see `forget`), before their memory can be
+/// reused.
+pub fn record(event: &Event) {
+    match *event {
+        Event::Alloc { ptr, size, .. } | Event::Realloc { ptr, size, .. } => remember(ptr, size),
+        Event::Free { .. } => (),
+    }
+}
+
+/// Record `size` bytes at `ptr` as a live allocation.
+pub fn remember(ptr: *mut u8, size: usize) {
+    with_live(|pool| insert(pool, unsafe {
+        // The allocation is only recorded, never accessed.
+        Block::from_raw_parts(Pointer::new(ptr), size)
+    }));
+}
+
+/// Forget `size` bytes at `ptr`, which are being freed (or reallocated).
+///
+/// This must be done before the memory is given back: Otherwise, another thread might allocate
+/// it, and record the allocation, before the stale entry is gone, which would then shadow it.
+/// The parts of the allocation before and after the range stay recorded.
+pub fn forget(ptr: *mut u8, size: usize) {
+    with_live(|pool| free(pool, ptr, size));
+}
+
+/// Account for freeing `size` bytes at `ptr`.
+///
+/// The parts of the allocation before and after the freed range stay recorded.
+fn free(pool: &mut Pool, ptr: *mut u8, size: usize) {
+    if size == 0 {
+        return;
+    }
+
+    let block = match locate(pool, ptr) {
+        Some(pos) => pool.remove(pos),
+        None => return,
+    };
+
+    let offset = ptr as usize - block.addr();
+    let (left, rest) = block.split(offset);
+    insert(pool, left);
+    if size < rest.size() {
+        insert(pool, rest.split(size).1);
+    }
+}
+
+/// Find the allocation containing some pointer.
+///
+/// The start and size of the allocation are returned, or `None`, if the pointer is not inside a
+/// live allocation made through the entry points.
+pub fn resolve_interior(ptr: *const u8) -> Option<(*mut u8, usize)> {
+    let mut live = LIVE.lock();
+    let pool = match live.as_mut() {
+        Some(pool) => pool,
+        None => return None,
+    };
+
+    locate(pool, ptr as *mut u8).map(|pos| {
+        (Pointer::from(pool[pos].empty_left()).get(), pool[pos].size())
+    })
+}
+
+/// Block the recording of allocations, until `enable` is called.
+///
+/// As every entry point records its operations, this blocks them all (including the ones served
+/// by thread caches), so the live allocations stay as they are.
+pub fn disable() {
+    mem::forget(LIVE.lock());
+}
+
+/// Unblock the recording of allocations, after `disable`.
+///
+/// # Safety
+///
+/// This must be paired with a call to `disable`.
+pub unsafe fn enable() {
+    LIVE.force_unlock();
+}
+
+/// Call a closure with the start and size of each live allocation starting in `start..end`.
+///
+/// # Safety
+///
+/// This must be called between `disable` and `enable`, by the thread disabling the recording.
+pub unsafe fn for_each_live<F: FnMut(*mut u8, usize)>(start: usize, end: usize, mut f: F) {
+    // The lock is held by the caller.
+    if let Some(pool) = LIVE.peek().as_ref() {
+        for block in pool.iter().filter(|block| block.addr() >= start && block.addr() < end) {
+            f(Pointer::from(block.empty_left()).get(), block.size());
+        }
+    }
+}
+
+/// Free an allocation through a pointer anywhere inside it.
+///
+/// `Err(())` is returned (and nothing is freed), if the pointer is not inside a live allocation
+/// made through the entry points.
+///
+/// # Safety
+///
+/// See `free`.
+pub unsafe fn free_interior(ptr: *mut u8) -> Result<(), ()> {
+    log!(CALL, "Freeing the buffer containing 0x{:x}.", ptr as usize);
+
+    match resolve_interior(ptr) {
+        Some((start, size)) => {
+            ::allocator::free(start, size);
+
+            Ok(())
+        },
+        None => Err(()),
+    }
+}
@@ -0,0 +1,118 @@
+//! Pools for interrupt handlers.
+//!
+//! An interrupt handler cannot take the locks of the allocator: If it interrupted the holder on
+//! the same CPU, it would wait forever. An `IrqPool` is a reservation of objects of a fixed size,
+//! which are handed out and taken back by a compare-and-swap on a lock-free stack (see the `bins`
+//! module), so it is safe in any context, including interrupt handlers preempting a thread using
+//! the same pool.
+//!
+//! The pool is filled explicitly, in process context (where the allocator may be locked), e.g.
+//! after an interrupt handler found it empty or running low. A kernel typically keeps a pool per
+//! CPU (in a static array indexed by the CPU number, as `IrqPool::new` is a `const fn`), so the
+//! CPUs don't fight over the cache line of a shared head.
+//!
+//! The objects are never given back to the allocator, as a racing pop might still read them.
+
+use core::{cmp, mem};
+
+use allocator;
+use bins::Bin;
+
+/// A pool of objects for interrupt handlers.
+///
+/// See the `irq` module.
+pub struct IrqPool {
+    /// The free objects.
+    bin: Bin,
+    /// The size of the objects.
+    size: usize,
+    /// The alignment of the objects.
+    align: usize,
+}
+
+impl IrqPool {
+    /// Create an empty pool of objects of `size` bytes, aligned to `align`.
+    ///
+    /// The objects are padded to hold at least a pointer.
+    pub const fn new(size: usize, align: usize) -> IrqPool {
+        IrqPool {
+            bin: Bin::new(),
+            size: size,
+            align: align,
+        }
+    }
+
+    /// Take an object.
+    ///
+    /// This never blocks, so it is safe in interrupt handlers. If the pool is empty, `None` is
+    /// returned.
+    #[inline]
+    pub fn alloc(&self) -> Option<*mut u8> {
+        self.bin.pop()
+    }
+
+    /// Give an object back to the pool.
+    ///
+    /// This never blocks, so it is safe in interrupt handlers.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been taken from this pool, and must not be used afterwards.
+    #[inline]
+    pub unsafe fn free(&self, ptr: *mut u8) {
+        debug_assert!(ptr as usize % self.align() == 0, "Giving 0x{:x} back to a pool, which it \
+                      is not an object of.", ptr as usize);
+
+        self.bin.push(ptr);
+    }
+
+    /// Add `n` objects to the pool.
+    ///
+    /// The objects are allocated at once from the global allocator, so this must be called in
+    /// process context, never from an interrupt handler.
+    ///
+    /// # Errors
+    ///
+    /// The OOM handler is called, if the objects cannot be allocated.
+    pub fn refill(&self, n: usize) {
+        // Logging.
+        log!(NOTE, "Refilling an interrupt pool with {} objects of {} bytes.", n, self.stride());
+
+        let size = n.checked_mul(self.stride()).expect("Refilling an interrupt pool with more \
+                                                        objects than the address space holds.");
+        let chunk = allocator::alloc(size, self.align());
+
+        for i in 0..n {
+            unsafe {
+                // The objects are within the chunk, aligned, and given to the pool for good.
+                self.bin.push(chunk.offset((i * self.stride()) as isize));
+            }
+        }
+    }
+
+    /// Count the objects in the pool.
+    ///
+    /// This walks the pool, and is only exact, if the pool is not used meanwhile.
+    pub fn len(&self) -> usize {
+        self.bin.len()
+    }
+
+    /// Is the pool empty?
+    pub fn is_empty(&self) -> bool {
+        self.bin.is_empty()
+    }
+
+    /// Get the alignment of the objects, which fits a pointer.
+    #[inline]
+    fn align(&self) -> usize {
+        cmp::max(self.align, mem::align_of::<usize>())
+    }
+
+    /// Get the distance between neighboring objects.
+    #[inline]
+    fn stride(&self) -> usize {
+        let size = cmp::max(self.size, mem::size_of::<usize>());
+
+        (size + self.align() - 1) / self.align() * self.align()
+    }
+}
@@ -0,0 +1,434 @@
+//! Allocator layers.
+//!
+//! A layer wraps an inner allocator, adding some behavior to its allocations, frees, and
+//! reallocations. Layers stack, so a custom allocator can be built from stock parts, e.g.
+//! `Stats<Quarantine<Allocator>>`, without forking the crate. The bottom of the stack is the
+//! global allocator (`Allocator`), or an arena.
+
+use core::{cmp, ptr};
+
+use shim::config;
+
+use arena::Arena;
+use breaker::Breaker;
+use fail::{self, Violation};
+use hook::Event;
+use policy::Policy;
+use {allocator, Allocator};
+
+/// An allocator, which can be wrapped by layers.
+///
+/// The methods have the semantics of the entry points of the crate (see `ralloc::alloc`,
+/// `ralloc::free`, and `ralloc::realloc`).
+///
+/// # Safety
+///
+/// The buffers returned must be valid for reads and writes, and not overlap any other live
+/// buffer.
+pub unsafe trait Layer {
+    /// Allocate a buffer.
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8;
+
+    /// Free a buffer allocated through this allocator.
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize);
+
+    /// Reallocate a buffer allocated through this allocator.
+    ///
+    /// By default, a new buffer is allocated, and the contents are copied.
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        let res = self.alloc(size, align);
+        ptr::copy_nonoverlapping(ptr, res, cmp::min(old_size, size));
+        self.free(ptr, old_size);
+
+        res
+    }
+}
+
+unsafe impl Layer for Allocator {
+    #[inline]
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        allocator::alloc(size, align)
+    }
+
+    #[inline]
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        allocator::free(ptr, size);
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        allocator::realloc(ptr, old_size, size, align)
+    }
+}
+
+unsafe impl<B: Breaker, P: Policy> Layer for Arena<B, P> {
+    #[inline]
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        Arena::alloc(self, size, align)
+    }
+
+    #[inline]
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        Arena::free(self, ptr, size);
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        Arena::realloc(self, ptr, old_size, size, align)
+    }
+}
+
+/// A layer counting the operations and the bytes in use.
+pub struct Stats<L: Layer> {
+    /// The inner allocator.
+    inner: L,
+    /// The number of allocations.
+    allocs: usize,
+    /// The number of frees.
+    frees: usize,
+    /// The number of bytes in use.
+    in_use: usize,
+    /// The peak number of bytes in use.
+    peak: usize,
+}
+
+impl<L: Layer> Stats<L> {
+    /// Wrap an allocator.
+    pub fn new(inner: L) -> Stats<L> {
+        Stats {
+            inner: inner,
+            allocs: 0,
+            frees: 0,
+            in_use: 0,
+            peak: 0,
+        }
+    }
+
+    /// Get the number of allocations.
+    pub fn allocs(&self) -> usize {
+        self.allocs
+    }
+
+    /// Get the number of frees.
+    pub fn frees(&self) -> usize {
+        self.frees
+    }
+
+    /// Get the number of bytes in use.
+    pub fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// Get the peak number of bytes in use.
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Get the inner allocator.
+    pub fn inner(&mut self) -> &mut L {
+        &mut self.inner
+    }
+
+    /// Account for a buffer growing by `size` bytes.
+    fn grow(&mut self, size: usize) {
+        self.in_use += size;
+        self.peak = cmp::max(self.peak, self.in_use);
+    }
+}
+
+unsafe impl<L: Layer> Layer for Stats<L> {
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let res = self.inner.alloc(size, align);
+
+        self.allocs += 1;
+        self.grow(size);
+
+        res
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        self.inner.free(ptr, size);
+
+        self.frees += 1;
+        self.in_use -= size;
+    }
+
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        let res = self.inner.realloc(ptr, old_size, size, align);
+
+        if size > old_size {
+            self.grow(size - old_size);
+        } else {
+            self.in_use -= old_size - size;
+        }
+
+        res
+    }
+}
+
+/// A layer poisoning memory.
+///
+/// Fresh buffers are filled with a byte (`config::POISON_ALLOC` by default), and freed ones with
+/// another (`config::POISON_FREE` by default), so uses of uninitialized or freed memory stand
+/// out.
+pub struct Poison<L: Layer> {
+    /// The inner allocator.
+    inner: L,
+    /// The byte filling fresh buffers.
+    alloc_byte: u8,
+    /// The byte filling freed buffers.
+    free_byte: u8,
+}
+
+impl<L: Layer> Poison<L> {
+    /// Wrap an allocator.
+    pub fn new(inner: L) -> Poison<L> {
+        Poison::with_bytes(inner, config::POISON_ALLOC, config::POISON_FREE)
+    }
+
+    /// Wrap an allocator, filling fresh buffers with `alloc_byte`, and freed ones with
+    /// `free_byte`.
+    ///
+    /// Bytes unlikely to be valid data (or valid pointers) of the program make the misuse stand
+    /// out the most.
+    pub fn with_bytes(inner: L, alloc_byte: u8, free_byte: u8) -> Poison<L> {
+        Poison {
+            inner: inner,
+            alloc_byte: alloc_byte,
+            free_byte: free_byte,
+        }
+    }
+}
+
+unsafe impl<L: Layer> Layer for Poison<L> {
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let res = self.inner.alloc(size, align);
+
+        unsafe {
+            // The buffer was just allocated.
+            ptr::write_bytes(res, self.alloc_byte, size);
+        }
+
+        res
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        ptr::write_bytes(ptr, self.free_byte, size);
+        self.inner.free(ptr, size);
+    }
+
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        // The part cut off is freed.
+        if size < old_size {
+            ptr::write_bytes(ptr.offset(size as isize), self.free_byte, old_size - size);
+        }
+
+        let res = self.inner.realloc(ptr, old_size, size, align);
+
+        // The part added is fresh.
+        if size > old_size {
+            ptr::write_bytes(res.offset(old_size as isize), self.alloc_byte, size - old_size);
+        }
+
+        res
+    }
+}
+
+/// A layer delaying frees.
+///
+/// Freed buffers are kept in a quarantine of `config::QUARANTINE_LEN` buffers, and only given to
+/// the inner allocator, when they are evicted by later frees. Hence, a use-after-free doesn't
+/// hit a reused buffer right away, which (combined with `Poison`) makes it easier to detect.
+///
+/// Stacked below `Poison`, the quarantine can verify the poison of the buffers on eviction (see
+/// `verifying`), catching writes to freed memory before it is reused.
+///
+/// The quarantined buffers are freed, when the layer is dropped.
+pub struct Quarantine<L: Layer> {
+    /// The inner allocator.
+    inner: L,
+    /// The quarantined buffers (address and size), starting at `next`, and wrapping around.
+    ///
+    /// Unused entries have address zero.
+    buffers: [(usize, usize); config::QUARANTINE_LEN],
+    /// The index of the entry to evict next.
+    next: usize,
+    /// The byte the quarantined buffers must be filled with, if verified.
+    poison: Option<u8>,
+}
+
+impl<L: Layer> Quarantine<L> {
+    /// Wrap an allocator.
+    pub fn new(inner: L) -> Quarantine<L> {
+        Quarantine {
+            inner: inner,
+            buffers: [(0, 0); config::QUARANTINE_LEN],
+            next: 0,
+            poison: None,
+        }
+    }
+
+    /// Wrap an allocator, verifying that the quarantined buffers are still filled with `poison`.
+    ///
+    /// This is meant for a quarantine below a `Poison` layer filling freed buffers with `poison`.
+    /// When a buffer is evicted, it is checked (see `verify_poison`), and if it was written to,
+    /// an invariant violation is reported (see `ralloc::set_violation_policy`) at the first
+    /// modified byte. If the policy is to quarantine, the buffer is leaked.
+    pub fn verifying(inner: L, poison: u8) -> Quarantine<L> {
+        Quarantine {
+            poison: Some(poison),
+            ..Quarantine::new(inner)
+        }
+    }
+
+    /// Free every quarantined buffer.
+    pub fn flush(&mut self) {
+        for i in 0..config::QUARANTINE_LEN {
+            self.evict(i);
+        }
+    }
+
+    /// Free a quarantined buffer, if any.
+    fn evict(&mut self, i: usize) {
+        let (ptr, size) = self.buffers[i];
+        if ptr != 0 {
+            self.buffers[i] = (0, 0);
+
+            let verified = self.poison.map_or(Ok(()), |poison| unsafe {
+                // The buffer is quarantined, and thus still ours.
+                verify_poison(ptr as *const u8, size, poison)
+            });
+            if let Err(offset) = verified {
+                log!(ERROR, "Freed buffer 0x{:x}[{}] was written to at offset {}.", ptr, size,
+                     offset);
+
+                // Leak the buffer, if the policy is to quarantine.
+                fail::violation(&Violation {
+                    description: "Freed memory was written to",
+                    place: "Quarantine::evict",
+                    addr: ptr + offset,
+                    size: size - offset,
+                });
+                return;
+            }
+
+            unsafe {
+                // The buffer was freed by the user, and kept in quarantine since.
+                self.inner.free(ptr as *mut u8, size);
+            }
+        }
+    }
+}
+
+unsafe impl<L: Layer> Layer for Quarantine<L> {
+    #[inline]
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        self.inner.alloc(size, align)
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        // Zero-sized buffers take no memory.
+        if size == 0 {
+            return self.inner.free(ptr, size);
+        }
+
+        let next = self.next;
+        self.evict(next);
+
+        self.buffers[next] = (ptr as usize, size);
+        self.next = (next + 1) % config::QUARANTINE_LEN;
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        self.inner.realloc(ptr, old_size, size, align)
+    }
+}
+
+impl<L: Layer> Drop for Quarantine<L> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Verify that a buffer is filled with a poison byte.
+///
+/// If the buffer was written to, the offset of the first byte differing from `poison` is
+/// returned.
+///
+/// # Safety
+///
+/// The buffer must be valid for reads.
+pub unsafe fn verify_poison(ptr: *const u8, size: usize, poison: u8) -> Result<(), usize> {
+    for offset in 0..size {
+        if *ptr.offset(offset as isize) != poison {
+            return Err(offset);
+        }
+    }
+
+    Ok(())
+}
+
+/// A layer reporting every operation to a function.
+///
+/// This is like the hook (see `ralloc::set_hook`), but for a single allocator stack.
+pub struct Tracing<L: Layer> {
+    /// The inner allocator.
+    inner: L,
+    /// The function, which the operations are reported to.
+    sink: fn(Event),
+}
+
+impl<L: Layer> Tracing<L> {
+    /// Wrap an allocator, reporting its operations to `sink`.
+    pub fn new(inner: L, sink: fn(Event)) -> Tracing<L> {
+        Tracing {
+            inner: inner,
+            sink: sink,
+        }
+    }
+}
+
+unsafe impl<L: Layer> Layer for Tracing<L> {
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let res = self.inner.alloc(size, align);
+
+        (self.sink)(Event::Alloc {
+            ptr: res,
+            size: size,
+            align: align,
+        });
+
+        res
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        self.inner.free(ptr, size);
+
+        (self.sink)(Event::Free {
+            ptr: ptr,
+            size: size,
+        });
+    }
+
+    unsafe fn realloc(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: usize)
+                      -> *mut u8 {
+        let res = self.inner.realloc(ptr, old_size, size, align);
+
+        (self.sink)(Event::Realloc {
+            old_ptr: ptr,
+            old_size: old_size,
+            ptr: res,
+            size: size,
+            align: align,
+        });
+
+        res
+    }
+}
@@ -17,13 +17,36 @@
 #![feature(alloc, allocator_api, const_fn, core_intrinsics, stmt_expr_attributes, drop_types_in_const,
            nonzero, optin_builtin_traits, type_ascription, thread_local, linkage,
            try_from, const_unsafe_cell_new, const_atomic_bool_new, const_nonzero_new,
-           const_atomic_ptr_new)]
+           const_atomic_ptr_new, unique)]
+#![cfg_attr(any(feature = "mte", feature = "sampling", feature = "leak_tracking", feature = "usdt"),
+            feature(asm))]
+#![cfg_attr(any(feature = "futex_lock", not(target_pointer_width = "64")),
+            feature(integer_atomics))]
 #![warn(missing_docs, cast_precision_loss, cast_sign_loss, cast_possible_wrap,
         cast_possible_truncation, filter_map, if_not_else, items_after_statements,
         invalid_upcast_comparisons, mutex_integer, nonminimal_bool, shadow_same, shadow_unrelated,
         single_match_else, string_add, string_add_assign, wrong_pub_self_convention)]
 
+#[cfg(all(feature = "fast_bins", feature = "mte"))]
+compile_error!("The fast bins (the `fast_bins` feature) hand out untagged memory.");
+#[cfg(all(feature = "futex_lock", any(feature = "enclave", feature = "seccomp")))]
+compile_error!("The futex lock (the `futex_lock` feature) needs the futex syscall.");
+#[cfg(all(feature = "usdt", not(all(target_os = "linux", any(target_arch = "x86_64",
+                                                              target_arch = "aarch64")))))]
+compile_error!("Static tracepoints (the `usdt` feature) are only supported on x86-64 and AArch64 \
+                Linux.");
+#[cfg(all(feature = "mte", not(target_arch = "aarch64")))]
+compile_error!("Memory tagging (the `mte` feature) is only supported on AArch64.");
+#[cfg(all(feature = "enclave", any(feature = "brk_emulation", feature = "system_fallback")))]
+compile_error!("An enclave (the `enclave` feature) has no memory but its heap region.");
+#[cfg(all(feature = "seccomp", any(feature = "brk_emulation", feature = "electric_fence",
+                                   feature = "mte", feature = "sampling")))]
+compile_error!("The seccomp mode (the `seccomp` feature) only allows BRK, mmap and munmap, which \
+                the emulated BRK, the fences, memory tagging, and sampling cannot do with.");
+
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 extern crate ralloc_shim as shim;
 
 #[macro_use]
@@ -35,32 +58,128 @@ mod tls;
 #[macro_use]
 mod unborrow;
 
+mod advice;
 mod allocator;
+#[cfg(feature = "android")]
+mod android;
+pub mod analysis;
+mod arena;
+#[cfg(any(feature = "sampling", feature = "leak_tracking"))]
+mod backtrace;
+mod bins;
 mod block;
 mod bookkeeper;
+#[cfg(feature = "tls")]
+mod budget;
+mod breaker;
 mod brk;
+#[cfg(feature = "c_api")]
+mod capi;
 mod cell;
+mod conf;
+mod containers;
+mod ctl;
+mod decay;
+pub mod debug;
+mod detach;
+mod dropping;
 mod fail;
+mod fence;
+mod freeze;
+mod governor;
+mod hook;
+mod housekeeping;
+#[cfg(feature = "failure_injection")]
+mod inject;
+#[cfg(feature = "interior_pointers")]
+mod interior;
+mod irq;
+pub mod layer;
 mod lazy_init;
 mod leak;
+mod meta;
+mod metrics;
+mod options;
+#[cfg(feature = "mte")]
+mod mte;
+mod pages;
+pub mod policy;
 mod prelude;
+mod pressure;
 mod ptr;
+mod rand;
+mod realtime;
+mod regions;
+#[cfg(feature = "sampling")]
+mod sample;
+mod segment;
+#[cfg(feature = "selftest")]
+mod selftest;
+mod shared;
+#[cfg(test)]
+mod sim;
+mod stats;
+mod storage;
 mod sync;
+mod timeline;
+mod trace;
+mod typed;
+#[cfg(feature = "usdt")]
+mod usdt;
 mod vec;
 
 use alloc::heap::{Alloc, AllocErr, Layout, CannotReallocInPlace};
 
-pub use allocator::{alloc, free, realloc, realloc_inplace};
-pub use brk::sbrk;
-pub use fail::set_oom_handler;
+pub use advice::Advice;
+pub use allocator::{advise, alloc, alloc_at, alloc_batch, alloc_cacheline, alloc_excess,
+                    alloc_with, check, detach, disable_thread_cache, donate, flush_thread_cache,
+                    free, free_batch, free_part, free_sized, freeze, merge_allocs, realloc,
+                    realloc_inplace, split_alloc, trim, try_alloc};
+pub use arena::{Arena, Snapshot};
+pub use bins::fast_bins;
+pub use breaker::{Breaker, Brk, Chain, Concealed, Fixed, Hybrid, Mmap, Reserved, System};
+pub use brk::{sbrk, set_heap_region};
+#[cfg(feature = "tls")]
+pub use budget::{Exceeded, budget, budget_with};
+pub use conf::{set_checks, set_decay, set_growth_governor, set_limit, set_thread_cache_limits};
+pub use containers::{RBox, RVec};
+pub use ctl::ctl;
+pub use decay::decay_stages;
+pub use dropping::DroppingArena;
+pub use fail::{Error, Violation, ViolationPolicy, handle_alloc_error, install_oom_report,
+               report_oom, set_oom_handler, set_violation_policy};
 #[cfg(feature = "tls")]
 pub use fail::set_thread_oom_handler;
+pub use hook::{Event, Growth, on_heap_growth, set_hook};
+pub use housekeeping::{Deadline, housekeep, maintain};
+#[cfg(feature = "std")]
+pub use housekeeping::{start_housekeeping, stop_housekeeping};
+#[cfg(feature = "failure_injection")]
+pub use inject::{Injected, Injection, set_injection};
+#[cfg(feature = "interior_pointers")]
+pub use interior::{free_interior, resolve_interior};
+pub use irq::IrqPool;
+pub use metrics::metrics_prometheus;
+pub use options::{AllocOptions, CACHE_LINE};
+pub use pages::{alloc_pages, free_pages, pages_in_use};
+pub use pressure::set_pressure_handler;
+pub use realtime::RealtimeArena;
+#[cfg(feature = "selftest")]
+pub use selftest::{SelfTestConfig, selftest};
+pub use shared::SharedArena;
+pub use stats::{Delta, PoolOperations, Stats, Usage, peak, pool_operations, reset_peak};
+pub use timeline::{record_timeline, stop_timeline};
+pub use trace::{record_trace, replay_trace};
+pub use typed::{alloc_array, alloc_one, dealloc_array, dealloc_one};
 
 pub struct Allocator;
 
 unsafe impl<'a> Alloc for &'a Allocator {
     unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
-        Ok(allocator::alloc(layout.size(), layout.align()))
+        allocator::try_alloc(layout.size(), layout.align()).map_err(|err| match err {
+            Error::LimitExceeded => AllocErr::Unsupported { details: err.description() },
+            _ => AllocErr::Exhausted { request: layout },
+        })
     }
 
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
@@ -68,6 +187,10 @@ unsafe impl<'a> Alloc for &'a Allocator {
     }
 
     unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_layout: Layout) -> Result<*mut u8, AllocErr> {
+        if !allocator::is_possible(new_layout.size(), new_layout.align()) {
+            return Err(AllocErr::Unsupported { details: Error::LimitExceeded.description() });
+        }
+
         Ok(allocator::realloc(ptr, layout.size(), new_layout.size(), new_layout.align()))
     }
 
@@ -91,4 +214,11 @@ unsafe impl<'a> Alloc for &'a Allocator {
         // Yay! It matches exactly.
         (layout.size(), layout.size())
     }
+
+    fn oom(&mut self, err: AllocErr) -> ! {
+        match err {
+            AllocErr::Exhausted { request } => fail::handle_alloc_error(request),
+            AllocErr::Unsupported { .. } => fail::oom(Error::LimitExceeded),
+        }
+    }
 }
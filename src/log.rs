@@ -1,6 +1,16 @@
 //! Allocator logging.
 //!
 //! This allows for detailed logging for `ralloc`.
+//!
+//! Logging happens from inside the allocator, so it must never allocate, nor re-enter itself.
+//! Messages are formatted into a fixed buffer on the stack, and written in one go. A message
+//! logged while the thread is already formatting one (e.g. from a formatting implementation,
+//! which calls the allocator) is dropped, and the number of dropped messages is reported later.
+//!
+//! With the `std` feature, the messages are routed through the `log` crate facade, instead of
+//! the shim logger. The application's logger might allocate, so it cannot be called from inside
+//! the allocator. Instead, messages are queued, and forwarded on the way out of the entry points,
+//! when no locks are held (see `internal::forward`).
 
 /// Log to the appropriate source.
 ///
@@ -35,12 +45,13 @@ macro_rules! log {
 
             // Set the level.
             if level($lv) {
-                // Print the pool state.
-                let mut log = LogWriter::new();
-                // Print the log message.
-                let _ = write!(log, $kind);
-                let _ = write!(log, $( $arg ),*);
-                let _ = writeln!(log, " (at {}:{})", file!(), line!());
+                // Messages logged while formatting another are dropped.
+                if let Some(mut log) = LogWriter::new() {
+                    // Print the log message.
+                    let _ = write!(log, $kind);
+                    let _ = write!(log, $( $arg ),*);
+                    let _ = writeln!(log, " (at {}:{})", file!(), line!());
+                }
             }
         }
     };
@@ -68,7 +79,7 @@ macro_rules! bk_log {
             use log::internal::{IntoCursor, BlockLogger};
 
             log!(INTERNAL, "({:2}) {:10?} : {}", $bk.id, BlockLogger {
-                cur: $cur.clone().into_cursor(),
+                cur: $cur.clone().into_cursor(&$bk.pool),
                 blocks: &$bk.pool,
             }, format_args!($( $arg ),*));
         }
@@ -103,6 +114,33 @@ macro_rules! assert {
     }}
 }
 
+/// Check an invariant of the allocator.
+///
+/// Unlike `assert!`, violations are handled according to the violation policy (see
+/// `fail::set_violation_policy`), which might let the allocator continue. The arguments are the
+/// condition, the operation checking it, the block involved (`Option<&Block>`), and a description
+/// of the invariant.
+///
+/// This evaluates to whether the invariant holds.
+#[macro_export]
+macro_rules! invariant {
+    ($e:expr, $place:expr, $block:expr, $desc:expr) => {{
+        let holds = $e;
+
+        if !holds {
+            let block: Option<&Block> = $block;
+            ::fail::violation(&::fail::Violation {
+                description: $desc,
+                place: $place,
+                addr: block.map_or(0, |x| x.addr()),
+                size: block.map_or(0, |x| x.size()),
+            });
+        }
+
+        holds
+    }};
+}
+
 /// Make a runtime assertion in debug mode.
 ///
 /// The only way it differs from the one provided by `libcore` is the panicking strategy, which
@@ -139,47 +177,316 @@ macro_rules! assert_eq {
 pub mod internal {
     use prelude::*;
 
-    use core::fmt;
+    use core::{cmp, fmt, str};
+    #[cfg(feature = "std")]
+    use core::mem;
     use core::cell::Cell;
+    use core::fmt::Write;
     use core::ops::Range;
+    use core::sync::atomic::{self, AtomicUsize};
+    #[cfg(any(feature = "tls", feature = "std"))]
+    use core::sync::atomic::AtomicBool;
 
     use shim::config;
+    #[cfg(feature = "std")]
+    use shim::log as facade;
+
+    use segment::{Pool, Position};
+    use storage::PoolStorage;
 
-    use sync;
+    /// The size of the message buffer.
+    ///
+    /// Longer messages are truncated.
+    const BUFFER_SIZE: usize = 512;
 
     /// The log lock.
     ///
-    /// This lock is used to avoid bungling and intertwining the log.
+    /// This lock is used to avoid bungling and intertwining the log. It is only held while
+    /// writing a formatted message, which cannot re-enter the allocator.
     #[cfg(not(feature = "no_log_lock"))]
     pub static LOG_LOCK: Mutex<()> = Mutex::new(());
 
+    /// Is the current thread formatting a message?
+    #[cfg(feature = "tls")]
+    #[thread_local]
+    static LOGGING: AtomicBool = AtomicBool::new(false);
+
+    /// The number of messages dropped due to re-entrance.
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    /// The number of messages queued for the facade.
+    #[cfg(feature = "std")]
+    const QUEUE_LEN: usize = 16;
+
+    /// The messages queued for the facade.
+    #[cfg(feature = "std")]
+    static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+        msgs: [EMPTY_BUFFER; QUEUE_LEN],
+        start: 0,
+        len: 0,
+        overflowed: 0,
+    });
+    /// The number of messages in the queue.
+    ///
+    /// This mirrors `Queue::len`, so the queue can be checked without locking.
+    #[cfg(feature = "std")]
+    static QUEUED: AtomicUsize = AtomicUsize::new(0);
+    /// Is some thread forwarding the queue to the facade?
+    #[cfg(feature = "std")]
+    static FORWARDING: AtomicBool = AtomicBool::new(false);
+
+    /// An empty buffer.
+    #[cfg(feature = "std")]
+    const EMPTY_BUFFER: Buffer = Buffer {
+        buf: [0; BUFFER_SIZE],
+        len: 0,
+        truncated: false,
+    };
+
+    /// A message buffer.
+    #[derive(Clone, Copy)]
+    struct Buffer {
+        /// The buffer.
+        buf: [u8; BUFFER_SIZE],
+        /// The number of bytes written to the buffer.
+        len: usize,
+        /// Was the message truncated?
+        truncated: bool,
+    }
+
+    impl Buffer {
+        /// Create a new, empty buffer.
+        fn new() -> Buffer {
+            Buffer {
+                buf: [0; BUFFER_SIZE],
+                len: 0,
+                truncated: false,
+            }
+        }
+
+        /// Get the message.
+        fn as_str(&self) -> &str {
+            // The buffer might end in the middle of a character.
+            match str::from_utf8(&self.buf[..self.len]) {
+                Ok(s) => s,
+                Err(err) => unsafe {
+                    // The prefix is valid UTF-8.
+                    str::from_utf8_unchecked(&self.buf[..err.valid_up_to()])
+                },
+            }
+        }
+
+        /// Write the buffer to the shim logger.
+        #[cfg(not(feature = "std"))]
+        fn flush(&self) {
+            config::log(self.as_str());
+
+            if self.truncated {
+                config::log("…\n");
+            }
+        }
+
+        /// Queue the buffer for the facade.
+        #[cfg(feature = "std")]
+        fn flush(&self) {
+            QUEUE.lock().push(self);
+        }
+    }
+
+    /// A queue of messages.
+    #[cfg(feature = "std")]
+    struct Queue {
+        /// The messages, starting at `start`, and wrapping around.
+        msgs: [Buffer; QUEUE_LEN],
+        /// The index of the oldest message.
+        start: usize,
+        /// The number of messages.
+        len: usize,
+        /// The number of messages dropped, as the queue was full.
+        overflowed: usize,
+    }
+
+    #[cfg(feature = "std")]
+    impl Queue {
+        /// Push a message.
+        ///
+        /// If the queue is full, the message is dropped (and counted).
+        fn push(&mut self, msg: &Buffer) {
+            if self.len == QUEUE_LEN {
+                self.overflowed += 1;
+                return;
+            }
+
+            self.msgs[(self.start + self.len) % QUEUE_LEN] = *msg;
+            self.len += 1;
+            QUEUED.store(self.len, atomic::Ordering::Relaxed);
+        }
+
+        /// Pop the oldest message.
+        fn pop(&mut self) -> Option<Buffer> {
+            if self.len == 0 {
+                return None;
+            }
+
+            let res = self.msgs[self.start];
+            self.start = (self.start + 1) % QUEUE_LEN;
+            self.len -= 1;
+            QUEUED.store(self.len, atomic::Ordering::Relaxed);
+
+            Some(res)
+        }
+    }
+
+    /// Forward the queued messages to the `log` crate facade.
+    ///
+    /// This must be called with no locks of the allocator held, as the logger might allocate.
+    /// Messages logged by the logger itself are dropped, and only one thread forwards at a time.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn forward() {
+        if QUEUED.load(atomic::Ordering::Relaxed) == 0
+           || FORWARDING.swap(true, atomic::Ordering::Acquire) {
+            return;
+        }
+
+        // Drop the messages logged while forwarding, rather than forwarding them in turn.
+        #[cfg(feature = "tls")]
+        let logging = LOGGING.swap(true, atomic::Ordering::Relaxed);
+
+        loop {
+            let (msg, overflowed) = {
+                let mut queue = QUEUE.lock();
+                (queue.pop(), mem::replace(&mut queue.overflowed, 0))
+            };
+
+            if overflowed != 0 {
+                emit(facade::Level::Info, format_args!("{} log messages dropped, as the queue was \
+                                                        full.", overflowed));
+            }
+
+            match msg {
+                Some(msg) => {
+                    let (level, s) = split_level(msg.as_str());
+                    emit(level, format_args!("{}{}", s.trim_right(),
+                                             if msg.truncated { "…" } else { "" }));
+                },
+                None => break,
+            }
+        }
+
+        #[cfg(feature = "tls")]
+        LOGGING.store(logging, atomic::Ordering::Relaxed);
+
+        FORWARDING.store(false, atomic::Ordering::Release);
+    }
+
+    /// Split the level prefix of a message (e.g. `WARNING:  `) off.
+    #[cfg(feature = "std")]
+    fn split_level(msg: &str) -> (facade::Level, &str) {
+        let level = match msg.split(':').next() {
+            Some("ERROR") => facade::Level::Error,
+            Some("WARNING") => facade::Level::Warn,
+            Some("NOTE") => facade::Level::Info,
+            Some("CALL") | Some("DEBUG") => facade::Level::Debug,
+            _ => facade::Level::Trace,
+        };
+
+        (level, msg.find(':').map_or(msg, |i| msg[i + 1..].trim_left()))
+    }
+
+    /// Log a message through the facade.
+    #[cfg(feature = "std")]
+    fn emit(level: facade::Level, args: fmt::Arguments) {
+        if level <= facade::max_level() {
+            facade::logger().log(&facade::Record::builder()
+                .args(args)
+                .level(level)
+                .target("ralloc")
+                .build());
+        }
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let len = cmp::min(s.len(), BUFFER_SIZE - self.len);
+            self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+
+            if len < s.len() {
+                // Stop formatting, as the rest is lost anyway.
+                self.truncated = true;
+                Err(fmt::Error)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
     /// A log writer.
     ///
-    /// This writes to the shim logger.
+    /// This formats into a buffer on the stack, which is written to the shim logger when the
+    /// writer is dropped.
     pub struct LogWriter {
-        /// The inner lock.
-        #[cfg(not(feature = "no_log_lock"))]
-        _lock: sync::MutexGuard<'static, ()>,
+        /// The message.
+        msg: Buffer,
     }
 
     impl LogWriter {
-        /// Standard error output.
-        pub fn new() -> LogWriter {
-            #[cfg(feature = "no_log_lock")]
+        /// Start a message to the standard error output.
+        ///
+        /// If the current thread is already formatting a message, this one is dropped, and
+        /// `None` is returned.
+        pub fn new() -> Option<LogWriter> {
+            #[cfg(feature = "tls")]
             {
-                LogWriter {}
+                if LOGGING.swap(true, atomic::Ordering::Relaxed) {
+                    DROPPED.fetch_add(1, atomic::Ordering::Relaxed);
+                    return None;
+                }
             }
 
-            #[cfg(not(feature = "no_log_lock"))]
-            LogWriter {
-                _lock: LOG_LOCK.lock(),
+            // Without TLS, the messages of the logger cannot be told apart, so every message
+            // logged while forwarding is dropped.
+            #[cfg(all(feature = "std", not(feature = "tls")))]
+            {
+                if FORWARDING.load(atomic::Ordering::Relaxed) {
+                    DROPPED.fetch_add(1, atomic::Ordering::Relaxed);
+                    return None;
+                }
             }
+
+            Some(LogWriter {
+                msg: Buffer::new(),
+            })
         }
     }
 
     impl fmt::Write for LogWriter {
+        #[inline]
         fn write_str(&mut self, s: &str) -> fmt::Result {
-            if config::log(s) == !0 { Err(fmt::Error) } else { Ok(()) }
+            self.msg.write_str(s)
+        }
+    }
+
+    impl Drop for LogWriter {
+        fn drop(&mut self) {
+            {
+                #[cfg(not(feature = "no_log_lock"))]
+                let _lock = LOG_LOCK.lock();
+
+                let dropped = DROPPED.swap(0, atomic::Ordering::Relaxed);
+                if dropped != 0 {
+                    let mut notice = Buffer::new();
+                    let _ = writeln!(notice, "NOTE:     {} re-entrant log messages dropped.",
+                                     dropped);
+                    notice.flush();
+                }
+
+                self.msg.flush();
+            }
+
+            #[cfg(feature = "tls")]
+            LOGGING.store(false, atomic::Ordering::Relaxed);
         }
     }
 
@@ -210,7 +517,9 @@ pub mod internal {
         type Cursor: Cursor;
 
         /// Convert this value into its equivalent cursor.
-        fn into_cursor(self) -> Self::Cursor;
+        ///
+        /// The pool is the one the cursor is going to be printed along with.
+        fn into_cursor<S: PoolStorage>(self, pool: &Pool<S>) -> Self::Cursor;
     }
 
     /// A single-point cursor.
@@ -245,7 +554,7 @@ pub mod internal {
     impl IntoCursor for usize {
         type Cursor = UniCursor;
 
-        fn into_cursor(self) -> UniCursor {
+        fn into_cursor<S: PoolStorage>(self, _: &Pool<S>) -> UniCursor {
             UniCursor {
                 pos: self,
                 is_printed: Cell::new(false),
@@ -253,6 +562,14 @@ pub mod internal {
         }
     }
 
+    impl IntoCursor for Position {
+        type Cursor = UniCursor;
+
+        fn into_cursor<S: PoolStorage>(self, pool: &Pool<S>) -> UniCursor {
+            pool.ordinal(self).into_cursor(pool)
+        }
+    }
+
     impl Cursor for () {
         fn at(&self, _: &mut fmt::Formatter, _: usize) -> fmt::Result { Ok(()) }
 
@@ -262,7 +579,7 @@ pub mod internal {
     impl IntoCursor for () {
         type Cursor = ();
 
-        fn into_cursor(self) -> () {
+        fn into_cursor<S: PoolStorage>(self, _: &Pool<S>) -> () {
             ()
         }
     }
@@ -292,7 +609,7 @@ pub mod internal {
     impl IntoCursor for Range<usize> {
         type Cursor = RangeCursor;
 
-        fn into_cursor(self) -> RangeCursor {
+        fn into_cursor<S: PoolStorage>(self, _: &Pool<S>) -> RangeCursor {
             RangeCursor {
                 range: self,
             }
@@ -309,16 +626,16 @@ pub mod internal {
     ///
     /// where `x` denotes an non-empty block. `_` denotes an empty block, with `|` representing the
     /// cursor.
-    pub struct BlockLogger<'a, T> {
+    pub struct BlockLogger<'a, T, S: 'a + PoolStorage> {
         /// The cursor.
         ///
         /// This is where the `|` will be printed.
         pub cur: T,
         /// The blocks.
-        pub blocks: &'a [Block],
+        pub blocks: &'a Pool<S>,
     }
 
-    impl<'a, T: Cursor> fmt::Debug for BlockLogger<'a, T> {
+    impl<'a, T: Cursor, S: PoolStorage> fmt::Debug for BlockLogger<'a, T, S> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             // TODO: Handle alignment etc.
 
@@ -0,0 +1,310 @@
+//! Metadata allocation.
+//!
+//! The structures of the bookkeepers themselves (the segment lists and the radix maps) live in a
+//! dedicated metadata arena. Memory never flows between the arena and the block pools: The arena
+//! takes fresh chunks from its own region, and buffers replaced through growth are kept in the
+//! arena for later reuse. This way, growing a pool never mutates any pool, and hence cannot
+//! recurse.
+//!
+//! The chunks are mapped apart from the heap, and fenced by guard pages. This way, overflowing a
+//! user buffer cannot silently corrupt the bookkeeping; it either misses the metadata entirely,
+//! or faults on a guard page. Where the platform allows it, the chunks are also concealed from
+//! core dumps, so a crash doesn't leak the layout of the heap.
+
+use prelude::*;
+
+use core::{cmp, mem};
+
+use shim::config;
+#[cfg(not(test))]
+use shim::syscalls;
+
+#[cfg(not(test))]
+use {brk, fail};
+#[cfg(not(test))]
+use hook::{self, Growth};
+
+/// The metadata arena.
+///
+/// This is shared by all the bookkeepers, and thus, metadata given back by a bookkeeper (e.g.
+/// when a thread exits) is reused by the others.
+static META_ARENA: Mutex<MetaArena> = Mutex::new(MetaArena::new());
+
+/// The number of size classes.
+pub const CLASSES: usize = 48;
+/// The size of the smallest piece handed out by the arena.
+const MIN_PIECE: usize = 64;
+
+/// A metadata arena.
+///
+/// Pieces are handed out in power-of-two sizes, and freed pieces are kept in a free list per size
+/// class. Pieces are carved from the current chunk, which is refilled with a fresh mapping when
+/// it runs dry.
+pub struct MetaArena {
+    /// The unused part of the current chunk.
+    chunk: Block,
+    /// The free lists.
+    ///
+    /// Each entry points to the first free piece of the class (null if none). A free piece
+    /// stores the pointer to the next in its first word. These are pointers rather than
+    /// addresses, so they stay valid on targets where pointers are capabilities (e.g. CHERI).
+    free: [*mut Piece; CLASSES],
+}
+
+/// The pieces are owned by the arena, and only accessed through it.
+unsafe impl Send for MetaArena {}
+
+/// A free piece of metadata.
+struct Piece {
+    /// The next free piece of the class (null if none).
+    next: *mut Piece,
+}
+
+impl MetaArena {
+    /// Create a new, empty metadata arena.
+    pub const fn new() -> MetaArena {
+        MetaArena {
+            chunk: Block::empty(Pointer::empty()),
+            free: [0 as *mut Piece; CLASSES],
+        }
+    }
+
+    /// Get the size of the pieces holding `size` bytes.
+    #[inline]
+    pub fn piece_size(size: usize) -> usize {
+        cmp::max(size, MIN_PIECE).next_power_of_two()
+    }
+
+    /// Get the size of the chunk to map, when `size` bytes are needed.
+    #[inline]
+    pub fn chunk_size(size: usize) -> usize {
+        cmp::max(MetaArena::piece_size(size), config::META_CHUNK_SIZE)
+    }
+
+    /// Allocate a piece holding `size` bytes.
+    ///
+    /// The returned piece is `piece_size(size)` bytes long and aligned to the alignment of the
+    /// chunks. If the arena has no room for it, `None` is returned, and it needs a new chunk.
+    pub fn alloc(&mut self, size: usize) -> Option<Block> {
+        let size = MetaArena::piece_size(size);
+
+        if let Some(piece) = self.pop_free(size.trailing_zeros() as usize) {
+            // Reuse a freed piece.
+            Some(piece)
+        } else if self.chunk.size() >= size {
+            // Carve it from the chunk.
+            let (res, rest) = self.chunk.pop().split(size);
+            self.chunk = rest;
+
+            Some(res)
+        } else {
+            None
+        }
+    }
+
+    /// Pop a piece from the free list of some class.
+    fn pop_free(&mut self, class: usize) -> Option<Block> {
+        if self.free[class].is_null() {
+            None
+        } else {
+            let ptr = self.free[class];
+            unsafe {
+                // The free piece points to the next one in its first word.
+                self.free[class] = (*ptr).next;
+
+                Some(Block::from_raw_parts(Pointer::new(ptr as *mut u8), 1 << class))
+            }
+        }
+    }
+
+    /// Count the free pieces of each size class.
+    ///
+    /// The pieces of class `n` are `1 << n` bytes long.
+    pub fn bins(&self) -> [usize; CLASSES] {
+        let mut res = [0; CLASSES];
+
+        for (class, &first) in self.free.iter().enumerate() {
+            let mut ptr = first;
+            while !ptr.is_null() {
+                res[class] += 1;
+                ptr = unsafe {
+                    // The free piece points to the next one in its first word.
+                    (*ptr).next
+                };
+            }
+        }
+
+        res
+    }
+
+    /// Free a piece allocated through `alloc`.
+    pub fn free(&mut self, block: Block) {
+        // Short circuit in case of empty block.
+        if block.is_empty() { return; }
+
+        debug_assert!(block.size().is_power_of_two() && block.size() >= MIN_PIECE, "Freeing {:?}, \
+                      which is not a piece of the metadata arena.", block);
+
+        let class = block.size().trailing_zeros() as usize;
+        let ptr = Pointer::from(block).cast::<Piece>().get();
+        unsafe {
+            // The piece is at least one pointer long and aligned, as it is a piece of a chunk.
+            (*ptr).next = self.free[class];
+        }
+        self.free[class] = ptr;
+    }
+
+    /// Replace the chunk by a fresh one.
+    ///
+    /// The chunk is aligned to `align`, and the rest of the old chunk is broken into pieces,
+    /// which go to the free lists.
+    pub fn refill(&mut self, mut chunk: Block, align: usize) {
+        let chunk = match chunk.align(align) {
+            Some((_, chunk)) => chunk,
+            // The chunk is too small to be aligned.
+            None => return,
+        };
+
+        let mut old = mem::replace(&mut self.chunk, chunk);
+        while old.size() >= MIN_PIECE {
+            // Take the largest piece possible.
+            let size = 1 << (mem::size_of::<usize>() * 8 - 1 - old.size().leading_zeros() as usize);
+            let (piece, rest) = old.split(size);
+
+            self.free(piece);
+            old = rest;
+        }
+    }
+}
+
+/// Allocate a piece of metadata holding `size` bytes.
+///
+/// The returned piece is aligned to `align`, which must not exceed the page size.
+pub fn alloc(size: usize, align: usize) -> Block {
+    // Logging.
+    log!(INTERNAL, "Allocating {} bytes of metadata.", size);
+
+    let mut arena = META_ARENA.lock();
+
+    loop {
+        if let Some(piece) = arena.alloc(size) {
+            return piece;
+        }
+
+        // The arena ran dry, so we give it a fresh chunk.
+        arena.refill(map(MetaArena::chunk_size(size)), align);
+    }
+}
+
+/// Give a piece of metadata back to the arena.
+///
+/// The piece must have been allocated through `alloc`.
+pub fn free(block: Block) {
+    META_ARENA.lock().free(block);
+}
+
+/// Count the free pieces of each size class of the metadata arena.
+///
+/// See `MetaArena::bins`.
+pub fn bins() -> [usize; CLASSES] {
+    META_ARENA.lock().bins()
+}
+
+/// Map a chunk of metadata.
+///
+/// The chunk is placed in its own mapping, with a guard page on each side. If mapping fails
+/// (e.g. the platform has no `mmap`), we fall back to the program break, without the guards. In
+/// an enclave, the program break is used right away.
+#[cfg(not(test))]
+fn map(size: usize) -> Block {
+    // Round up to whole pages.
+    let size = (size + config::PAGE_SIZE - 1) / config::PAGE_SIZE * config::PAGE_SIZE;
+
+    if !cfg!(feature = "enclave") {
+        if let Some(chunk) = map_apart(size) {
+            return chunk;
+        }
+
+        // Logging.
+        log!(WARNING, "Unable to map the metadata apart; falling back to BRK.");
+    }
+
+    // The three blocks are adjacent, as they come from a single BRK.
+    let (mut aligner, mut res, mut excessive) = brk::lock().canonical_brk(size, mem::align_of::<*mut u8>())
+        .unwrap_or_else(|()| fail::oom(fail::Error::OutOfMemory {
+            requested: size,
+            available: 0,
+        }));
+    res.merge_right(&mut excessive).expect("BRK'd blocks are not adjacent.");
+    aligner.merge_right(&mut res).expect("BRK'd blocks are not adjacent.");
+
+    hook::grow(Pointer::from(aligner.empty_left()).get(),
+               Pointer::from(aligner.empty_right()).get(), Growth::Metadata);
+
+    aligner
+}
+
+/// Map a chunk of `size` bytes (a multiple of the page size) with a guard page on each side.
+#[cfg(not(test))]
+fn map_apart(size: usize) -> Option<Block> {
+    unsafe {
+        let ptr = match syscalls::mmap_concealed(size + 2 * config::PAGE_SIZE) {
+            Ok(ptr) => ptr,
+            Err(_) => return None,
+        };
+
+        // The mapping is at least three pages, hence the offsets are in bounds.
+        let res = ptr.offset(config::PAGE_SIZE as isize);
+        let end = res.offset(size as isize);
+
+        // Put up the guards. If this fails, the chunk is still usable, just unguarded.
+        if syscalls::mprotect(ptr, config::PAGE_SIZE, syscalls::PROT_NONE).is_err()
+           || syscalls::mprotect(end, config::PAGE_SIZE, syscalls::PROT_NONE).is_err() {
+            log!(WARNING, "Unable to protect the guard pages of the metadata.");
+        }
+
+        Some(Block::from_raw_parts(Pointer::new(res), size))
+    }
+}
+
+/// Take a chunk of metadata from the simulated memory.
+///
+/// Unit tests do not perform syscalls (see the `sim` module).
+#[cfg(test)]
+fn map(size: usize) -> Block {
+    ::sim::region(size, config::PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    use core::mem;
+
+    use super::MetaArena;
+
+    #[test]
+    fn test_meta_arena() {
+        let mut buf = [0usize; 64];
+        let chunk = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut buf[0] as *mut usize as *mut u8),
+                                  64 * mem::size_of::<usize>())
+        };
+
+        let mut arena = MetaArena::new();
+        assert!(arena.alloc(1).is_none());
+
+        arena.refill(chunk, mem::align_of::<usize>());
+        let a = arena.alloc(100).unwrap();
+        assert_eq!(a.size(), 128);
+        let b = arena.alloc(64).unwrap();
+        assert_eq!(b.size(), 64);
+        assert!(a < b);
+
+        // Freed pieces are reused.
+        let addr = a.addr();
+        arena.free(a);
+        assert_eq!(arena.alloc(128).unwrap().addr(), addr);
+        assert!(arena.alloc(512).is_none());
+    }
+}
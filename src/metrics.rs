@@ -0,0 +1,45 @@
+//! Metrics export.
+//!
+//! The statistics (see the `stats` module) can be written in the text exposition format of
+//! Prometheus, so services using ralloc can serve them to the scraper as is. As the crate has no
+//! strings of its own, the metrics are written to any `fmt::Write` sink (e.g. a `String`).
+//!
+//! Rates (e.g. allocations per second) are to be derived from the counters by the scraper, e.g.
+//! with `rate(ralloc_allocations_total[1m])`.
+
+use core::fmt;
+
+use {pages, stats};
+
+/// Write the metrics in the text exposition format of Prometheus.
+///
+/// The counters and gauges are prefixed with `ralloc_`.
+pub fn metrics_prometheus<W: fmt::Write>(out: &mut W) -> fmt::Result {
+    let usage = stats::peak();
+    let ops = stats::operations();
+
+    metric(out, "ralloc_in_use_bytes", "gauge", "The bytes allocated, and not freed.",
+           usage.in_use)?;
+    metric(out, "ralloc_in_use_peak_bytes", "gauge",
+           "The peak bytes allocated, since the start or the last reset.", usage.peak_in_use)?;
+    metric(out, "ralloc_heap_bytes", "gauge", "The bytes taken from the OS, and not given back.",
+           usage.extent)?;
+    metric(out, "ralloc_heap_peak_bytes", "gauge",
+           "The peak bytes taken from the OS, since the start or the last reset.",
+           usage.peak_extent)?;
+    metric(out, "ralloc_pages_in_use", "gauge", "The pages allocated through alloc_pages.",
+           pages::pages_in_use())?;
+    metric(out, "ralloc_allocations_total", "counter", "The number of allocations.",
+           ops.allocs)?;
+    metric(out, "ralloc_frees_total", "counter", "The number of frees.", ops.frees)?;
+    metric(out, "ralloc_reallocations_total", "counter", "The number of reallocations.",
+           ops.reallocs)
+}
+
+/// Write a metric.
+fn metric<W: fmt::Write>(out: &mut W, name: &str, kind: &str, help: &str, value: usize)
+                         -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)?;
+    writeln!(out, "{} {}", name, value)
+}
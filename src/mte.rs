@@ -0,0 +1,119 @@
+//! Memory tagging (AArch64 MTE).
+//!
+//! With the `mte` feature, every allocation gets a random, nonzero tag, which is written to the
+//! tag memory of its granules and to the top byte of the returned pointer. Freed memory is
+//! retagged to zero, which is never handed out, so use-after-frees and overflows into neighboring
+//! allocations trap in hardware.
+//!
+//! The bookkeepers only deal in untagged pointers, and only access free (zero-tagged) memory.
+//! Tags are added and stripped at the entry points (see the `allocator` module).
+
+use core::sync::atomic::{self, AtomicBool};
+
+use shim::{config, syscalls};
+
+/// The size of a tag granule.
+///
+/// Allocations are rounded to this, so no two allocations share a granule.
+pub const GRANULE: usize = 16;
+
+/// The bits of a pointer holding the tag.
+const TAG_MASK: usize = 0xf << 56;
+
+/// Has memory tagging been enabled?
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Round a size up to whole granules.
+#[inline]
+pub fn round(size: usize) -> usize {
+    (size + GRANULE - 1) & !(GRANULE - 1)
+}
+
+/// Strip the tag of a pointer.
+#[inline]
+pub fn untag(ptr: *mut u8) -> *mut u8 {
+    (ptr as usize & !TAG_MASK) as *mut u8
+}
+
+/// Enable tag checks for some fresh region of the heap.
+///
+/// The first call also enables tag checking for the process. It should thus happen before any
+/// threads are spawned, which is the case when the region comes from the first BRK.
+pub fn protect(ptr: *mut u8, size: usize) {
+    if !ENABLED.swap(true, atomic::Ordering::SeqCst) {
+        // Logging.
+        log!(NOTE, "Enabling memory tagging.");
+
+        if unsafe { syscalls::enable_mte() }.is_err() {
+            log!(WARNING, "Unable to enable memory tagging.");
+        }
+    }
+
+    // The region is rounded to whole pages. The page holding the start is already part of the
+    // heap (or the start is page aligned), and the page holding the end is mapped by BRK.
+    let start = ptr as usize & !(config::PAGE_SIZE - 1);
+    let end = (ptr as usize + size + config::PAGE_SIZE - 1) & !(config::PAGE_SIZE - 1);
+
+    let res = unsafe {
+        syscalls::mprotect(start as *mut u8, end - start,
+                           syscalls::PROT_READ | syscalls::PROT_WRITE | syscalls::PROT_MTE)
+    };
+    if res.is_err() {
+        log!(WARNING, "Unable to enable tag checks for 0x{:x}[{}].", ptr as usize, size);
+    }
+}
+
+/// Give a buffer a random tag.
+///
+/// The buffer must be untagged, granule aligned, and `size` must be a whole number of granules.
+/// The tagged pointer is returned.
+///
+/// # Safety
+///
+/// The buffer must be valid and owned by the caller.
+#[inline]
+pub unsafe fn tag(ptr: *mut u8, size: usize) -> *mut u8 {
+    let tagged: *mut u8;
+    // Generate a random tag, excluding zero (the tag of free memory).
+    asm!(".arch_extension memtag
+          irg $0, $1, $2"
+         : "=r"(tagged)
+         : "r"(ptr), "r"(1usize)
+         :
+         : "volatile");
+
+    set(tagged, size);
+
+    tagged
+}
+
+/// Set the tag of a buffer to the tag of the pointer.
+///
+/// # Safety
+///
+/// The buffer must be valid, owned by the caller, granule aligned, and `size` must be a whole
+/// number of granules.
+#[inline]
+pub unsafe fn set(ptr: *mut u8, size: usize) {
+    let mut granule = ptr;
+    for _ in 0..size / GRANULE {
+        asm!(".arch_extension memtag
+              stg $0, [$0]"
+             :
+             : "r"(granule)
+             : "memory"
+             : "volatile");
+
+        granule = granule.offset(GRANULE as isize);
+    }
+}
+
+/// Retag a buffer to zero, which is the tag of free memory.
+///
+/// # Safety
+///
+/// See `set`.
+#[inline]
+pub unsafe fn clear(ptr: *mut u8, size: usize) {
+    set(untag(ptr), size);
+}
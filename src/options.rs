@@ -0,0 +1,113 @@
+//! Allocation options.
+//!
+//! Some behavior can be chosen per allocation (see `ralloc::alloc_with`), or per arena (see
+//! `Arena::set_options`). The options are gathered in a single struct, rather than multiplying
+//! the variants of the entry points.
+
+use core::{cmp, ptr};
+
+use shim::config;
+
+/// The size of a cache line.
+///
+/// Buffers allocated with `AllocOptions::cacheline` occupy whole cache lines.
+pub const CACHE_LINE: usize = 64;
+
+/// Options for an allocation.
+///
+/// Start from `AllocOptions::new()`, and set the options needed, e.g.
+/// `AllocOptions { zeroed: true, ..AllocOptions::new() }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocOptions {
+    /// Zero the buffer.
+    pub zeroed: bool,
+    /// Pre-fault the pages of the allocation.
+    ///
+    /// Fresh memory is usually mapped lazily by the OS, i.e. on the first access to each page.
+    /// With this, every page of the buffer is touched before it is returned, so latency-critical
+    /// code doesn't take page faults on first access.
+    pub prefault: bool,
+    /// The minimum alignment of the allocation.
+    ///
+    /// The buffer is aligned to the greater of this and the alignment requested (if any).
+    pub min_align: usize,
+    /// Give the buffer cache lines of its own.
+    ///
+    /// The buffer is aligned to `CACHE_LINE`, and its size is rounded up to a multiple of it, so
+    /// no other buffer shares its cache lines. This avoids false sharing between small buffers
+    /// used by different threads, without padding them manually.
+    ///
+    /// As the padding is part of the buffer, it must be freed and reallocated with the rounded
+    /// size (see `size`). Arenas with this option do so on their own.
+    pub cacheline: bool,
+}
+
+impl AllocOptions {
+    /// The default options.
+    pub const fn new() -> AllocOptions {
+        AllocOptions {
+            zeroed: false,
+            prefault: false,
+            min_align: 1,
+            cacheline: false,
+        }
+    }
+
+    /// Get the alignment of an allocation requesting `align`.
+    #[inline]
+    pub fn align(&self, align: usize) -> usize {
+        if self.cacheline {
+            cmp::max(cmp::max(align, self.min_align), CACHE_LINE)
+        } else {
+            cmp::max(align, self.min_align)
+        }
+    }
+
+    /// Get the size of the buffer of an allocation requesting `size` bytes.
+    #[inline]
+    pub fn size(&self, size: usize) -> usize {
+        if self.cacheline {
+            // Overflowing sizes are impossible anyway, so saturating is fine.
+            size.saturating_add(CACHE_LINE - 1) & !(CACHE_LINE - 1)
+        } else {
+            size
+        }
+    }
+
+    /// Apply the options to a freshly allocated buffer.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must be valid, and not in use.
+    pub unsafe fn apply(&self, ptr: *mut u8, size: usize) {
+        if self.zeroed {
+            // Zeroing touches every page as well.
+            ptr::write_bytes(ptr, 0, size);
+        } else if self.prefault {
+            prefault(ptr, size);
+        }
+    }
+}
+
+/// Pre-fault the pages of a buffer.
+///
+/// Every page is written (with its current content), so the OS maps it. Only the buffer itself is
+/// touched.
+///
+/// # Safety
+///
+/// The buffer must be valid, and not in use.
+pub unsafe fn prefault(ptr: *mut u8, size: usize) {
+    // Logging.
+    log!(DEBUG, "Pre-faulting 0x{:x}[{}].", ptr as usize, size);
+
+    let mut addr = ptr as usize;
+    let end = addr + size;
+    while addr < end {
+        let byte = addr as *mut u8;
+        ptr::write_volatile(byte, ptr::read_volatile(byte));
+
+        // Skip to the next page.
+        addr = (addr & !(config::PAGE_SIZE - 1)) + config::PAGE_SIZE;
+    }
+}
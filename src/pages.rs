@@ -0,0 +1,73 @@
+//! Page-granular allocation.
+//!
+//! Whole pages are handed out straight from the breaker, bypassing the bookkeepers. This is
+//! convenient for buffer pools, I/O rings and `mprotect` tricks, which need page-aligned regions,
+//! and would only fragment the pools. The pages are tracked apart from the heap, and are given
+//! back to the OS when freed.
+
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::config;
+
+use breaker::{Breaker, Mmap};
+use fail;
+
+/// The number of pages in use.
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate `n` pages.
+///
+/// The returned region is page-aligned and `n` pages long. Zero pages take no memory, and give a
+/// dangling, page-aligned pointer.
+///
+/// # Errors
+///
+/// The OOM handler is called, if the pages cannot be mapped (or `n` pages overflow the address
+/// space).
+pub fn alloc_pages(n: usize) -> *mut u8 {
+    // Logging.
+    log!(CALL, "Allocating {} pages.", n);
+
+    if n == 0 {
+        return config::PAGE_SIZE as *mut u8;
+    }
+
+    let size = match n.checked_mul(config::PAGE_SIZE) {
+        Some(size) => size,
+        None => fail::oom(fail::Error::LimitExceeded),
+    };
+
+    let (ptr, _) = Mmap.fresh(size)
+        .unwrap_or_else(|err| fail::oom(fail::acquire_error(err, size, 0)));
+    IN_USE.fetch_add(n, atomic::Ordering::Relaxed);
+
+    ptr
+}
+
+/// Free `n` pages allocated through `alloc_pages`.
+///
+/// A run of pages from a single allocation can be freed on its own, as long as it is freed only
+/// once.
+///
+/// # Safety
+///
+/// The pages must have been allocated through `alloc_pages`, and must not be used afterwards.
+pub unsafe fn free_pages(ptr: *mut u8, n: usize) {
+    // Logging.
+    log!(CALL, "Freeing {} pages at 0x{:x}.", n, ptr as usize);
+
+    if n == 0 {
+        return;
+    }
+
+    if Mmap.release(ptr, n * config::PAGE_SIZE).is_err() {
+        // The pages are still ours, so they are merely leaked.
+        log!(WARNING, "Unable to unmap the pages at 0x{:x}.", ptr as usize);
+    }
+    IN_USE.fetch_sub(n, atomic::Ordering::Relaxed);
+}
+
+/// Get the number of pages allocated through `alloc_pages`, and not freed.
+pub fn pages_in_use() -> usize {
+    IN_USE.load(atomic::Ordering::Relaxed)
+}
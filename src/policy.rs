@@ -0,0 +1,139 @@
+//! Bookkeeping policies.
+//!
+//! The tuning of a bookkeeper is chosen at compile time, through a `Policy` bundling four
+//! aspects: Which free block serves an allocation (`FitPolicy`), how much memory is acquired when
+//! the pool runs dry (`GrowthPolicy`), which hardening is applied (`SecurityPolicy`), and where
+//! the metadata of the pool is kept (`PoolStorage`). The policies are types, and their decisions
+//! are constants or inlined functions, so they cost nothing at runtime.
+//!
+//! The global allocator uses `DefaultPolicy`, which follows the features of the crate. Arenas can
+//! be tuned independently (see `Arena::with_policy`), e.g. an exactly growing arena for a kernel,
+//! and a hardened one for parsing untrusted input, in the same program.
+
+use shim::config;
+
+pub use storage::{MetaStorage, PoolStorage, StaticStorage};
+
+/// How a free block is chosen for an allocation.
+pub trait FitPolicy {
+    /// The number of fitting blocks to choose from at random.
+    ///
+    /// With `1`, the first (lowest) fitting block is taken.
+    const CANDIDATES: usize;
+    /// Take the allocation from the end of the chosen block half of the time?
+    const RANDOM_END: bool;
+}
+
+/// How much fresh memory is acquired, when the pool runs dry.
+pub trait GrowthPolicy {
+    /// Get the number of bytes to acquire in excess of an allocation of `size` bytes.
+    ///
+    /// More excess means fewer calls to the breaker, but more memory held.
+    fn extra(size: usize) -> usize;
+}
+
+/// Which hardening is applied.
+pub trait SecurityPolicy {
+    /// Zero the blocks when they are freed?
+    const ZERO_ON_FREE: bool;
+    /// Check the freed blocks against their neighbors, even with the checks disabled?
+    ///
+    /// See `conf::checks`.
+    const CHECK_NEIGHBORS: bool;
+}
+
+/// A bookkeeping policy.
+pub trait Policy {
+    /// The fit policy.
+    type Fit: FitPolicy;
+    /// The growth policy.
+    type Growth: GrowthPolicy;
+    /// The security policy.
+    type Security: SecurityPolicy;
+    /// The storage of the metadata of the pool.
+    type Storage: PoolStorage;
+}
+
+/// Take the first fitting block.
+pub struct FirstFit;
+
+impl FitPolicy for FirstFit {
+    const CANDIDATES: usize = 1;
+    const RANDOM_END: bool = false;
+}
+
+/// Take a random one of the first `config::RANDOM_CANDIDATES` fitting blocks, and a random end
+/// of it, making the heap layout unpredictable.
+pub struct RandomFit;
+
+impl FitPolicy for RandomFit {
+    const CANDIDATES: usize = config::RANDOM_CANDIDATES;
+    const RANDOM_END: bool = true;
+}
+
+/// The fit policy of the features of the crate (`RandomFit` with `randomize`).
+#[cfg(feature = "randomize")]
+pub type DefaultFit = RandomFit;
+/// The fit policy of the features of the crate (`RandomFit` with `randomize`).
+#[cfg(not(feature = "randomize"))]
+pub type DefaultFit = FirstFit;
+
+/// Acquire some excess memory, limiting the number of calls to the breaker.
+///
+/// See `config::extra_brk`.
+pub struct EagerGrowth;
+
+impl GrowthPolicy for EagerGrowth {
+    #[inline]
+    fn extra(size: usize) -> usize {
+        config::extra_brk(size)
+    }
+}
+
+/// Acquire no more than needed.
+///
+/// This suits breakers, where memory is scarce (e.g. a kernel heap).
+pub struct ExactGrowth;
+
+impl GrowthPolicy for ExactGrowth {
+    #[inline]
+    fn extra(_: usize) -> usize {
+        0
+    }
+}
+
+/// The hardening of the features of the crate (`security` and `paranoid`).
+pub struct DefaultSecurity;
+
+impl SecurityPolicy for DefaultSecurity {
+    const ZERO_ON_FREE: bool = cfg!(feature = "security");
+    const CHECK_NEIGHBORS: bool = cfg!(feature = "paranoid");
+}
+
+/// All the hardening.
+pub struct Hardened;
+
+impl SecurityPolicy for Hardened {
+    const ZERO_ON_FREE: bool = true;
+    const CHECK_NEIGHBORS: bool = true;
+}
+
+/// The policy of the features of the crate.
+pub struct DefaultPolicy;
+
+impl Policy for DefaultPolicy {
+    type Fit = DefaultFit;
+    type Growth = EagerGrowth;
+    type Security = DefaultSecurity;
+    type Storage = MetaStorage;
+}
+
+/// A policy for handling untrusted input: Random fits, and all the hardening.
+pub struct HardenedPolicy;
+
+impl Policy for HardenedPolicy {
+    type Fit = RandomFit;
+    type Growth = EagerGrowth;
+    type Security = Hardened;
+    type Storage = MetaStorage;
+}
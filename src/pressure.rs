@@ -0,0 +1,86 @@
+//! Memory pressure.
+//!
+//! The application can register a handler, which is called when memory runs low: When the heap
+//! cannot be extended (the OS refuses, or the heap size limit is hit), and when the heap grows
+//! close to the limit. The handler can drop caches of the application, after which a failed
+//! allocation is retried once, before it is reported as failed.
+//!
+//! The pressure is detected with the global allocator locked, so the handler is called later, on
+//! the way out of the entry points, with no locks held. Hence, it is free to use the allocator.
+//! Operations performed by the handler itself don't call it again.
+
+use core::mem;
+use core::sync::atomic::{self, AtomicBool, AtomicPtr};
+
+/// The handler (null if none).
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
+/// Has pressure been detected since the handler was last called?
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Is the current thread running the handler?
+#[cfg(feature = "tls")]
+#[thread_local]
+static IN_HANDLER: AtomicBool = AtomicBool::new(false);
+
+/// Set the memory pressure handler, or remove it (with `None`).
+///
+/// See the `pressure` module.
+pub fn set_pressure_handler(handler: Option<fn()>) {
+    // Logging.
+    log!(NOTE, "Setting the memory pressure handler.");
+
+    HANDLER.store(handler.map_or(0 as *mut (), |handler| handler as *mut ()),
+                  atomic::Ordering::SeqCst);
+}
+
+/// Note that memory is running low.
+///
+/// The handler is called by the next `poll` or `relieve`.
+#[inline]
+pub fn signal() {
+    PENDING.store(true, atomic::Ordering::Relaxed);
+}
+
+/// Call the handler, if pressure has been signaled.
+///
+/// No locks of the allocator may be held.
+#[inline]
+pub fn poll() {
+    if PENDING.load(atomic::Ordering::Relaxed) {
+        relieve();
+    }
+}
+
+/// Call the handler.
+///
+/// `true` is returned, if the handler was called, in which case it might have freed memory. No
+/// locks of the allocator may be held.
+pub fn relieve() -> bool {
+    PENDING.store(false, atomic::Ordering::Relaxed);
+
+    let handler = HANDLER.load(atomic::Ordering::Relaxed);
+    if handler.is_null() {
+        return false;
+    }
+
+    // Don't relieve the pressure caused by the handler itself.
+    #[cfg(feature = "tls")]
+    {
+        if IN_HANDLER.swap(true, atomic::Ordering::Relaxed) {
+            return false;
+        }
+    }
+
+    // Logging.
+    log!(NOTE, "Calling the memory pressure handler.");
+
+    unsafe {
+        // The pointer was stored from a function pointer by `set_pressure_handler`.
+        mem::transmute::<_, fn()>(handler)();
+    }
+
+    #[cfg(feature = "tls")]
+    IN_HANDLER.store(false, atomic::Ordering::Relaxed);
+
+    true
+}
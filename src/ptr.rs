@@ -82,6 +82,31 @@ impl<T> Pointer<T> {
     pub fn get(&self) -> *mut T {
         self.ptr.get()
     }
+
+    /// Get the address of this pointer.
+    ///
+    /// The address is only meant for arithmetic and comparisons. Pointers are never made from
+    /// addresses again, as that would lose their provenance; use `with_addr` instead.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.ptr.get() as usize
+    }
+
+    /// Make a pointer to another address, derived from this one.
+    ///
+    /// The result has the provenance of this pointer, unlike casting the address to a pointer,
+    /// so the allocator stays analyzable under strict provenance (e.g. in Miri).
+    ///
+    /// # Safety
+    ///
+    /// The address must be in the same allocated object as this pointer (see `offset`).
+    #[inline]
+    #[allow(cast_possible_wrap)]
+    pub unsafe fn with_addr(self, addr: usize) -> Pointer<T> {
+        let diff = addr.wrapping_sub(self.addr()) as isize;
+
+        self.cast::<u8>().offset(diff).cast()
+    }
 }
 
 impl<T> Default for Pointer<T> {
@@ -117,6 +142,18 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_with_addr() {
+        let mut x = [0u32; 4];
+
+        unsafe {
+            let ptr = Pointer::new(&mut x[0] as *mut u32);
+            let addr = ptr.addr();
+            assert_eq!(ptr.clone().with_addr(addr + 8).get(), &mut x[2] as *mut u32);
+            assert_eq!(ptr.clone().with_addr(addr + 8).with_addr(addr).get(), ptr.get());
+        }
+    }
+
     #[test]
     fn test_empty() {
         assert_eq!(Pointer::<u8>::empty().get() as usize, 1);
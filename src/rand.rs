@@ -0,0 +1,88 @@
+//! Pseudorandom number generation.
+//!
+//! This is used for hardening (e.g. randomizing the heap layout), not cryptography. The
+//! generator is xorshift64*, seeded from address space layout randomization and a counter.
+
+use core::sync::atomic::{self, AtomicUsize};
+
+/// The seed counter.
+///
+/// This is incremented whenever a new generator is created, so two generators seeded at the same
+/// address still differ.
+static SEED_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A pseudorandom number generator.
+pub struct Rng {
+    /// The state of the generator.
+    ///
+    /// This is never zero.
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new, freshly seeded generator.
+    pub fn new() -> Rng {
+        // The addresses of the stack and the code are randomized by the OS (if supported).
+        let stack = 0u8;
+        let seed = (&stack as *const u8 as u64)
+            ^ (Rng::new as usize as u64).rotate_left(32)
+            ^ (SEED_COUNTER.fetch_add(1, atomic::Ordering::Relaxed) as u64)
+                .wrapping_mul(0x9E3779B97F4A7C15);
+
+        Rng::with_seed(seed)
+    }
+
+    /// Create a new generator with a given seed.
+    ///
+    /// Generators with the same seed yield the same numbers.
+    pub fn with_seed(seed: u64) -> Rng {
+        Rng {
+            // Mix the seed (splitmix64 finalizer), and make sure the state is nonzero.
+            state: {
+                let mut x = seed;
+                x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+                x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+                (x ^ (x >> 31)) | 1
+            },
+        }
+    }
+
+    /// Get the next number.
+    #[inline]
+    pub fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Get a number below `n`.
+    ///
+    /// `n` must be nonzero.
+    #[inline]
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::new();
+        let mut b = Rng::new();
+
+        // Differently seeded.
+        assert!(a.next() != b.next());
+
+        for _ in 0..1000 {
+            assert!(a.below(7) < 7);
+        }
+
+        // Equally seeded.
+        assert_eq!(Rng::with_seed(42).next(), Rng::with_seed(42).next());
+    }
+}
@@ -0,0 +1,319 @@
+//! Real-time arenas.
+//!
+//! Threads with deadlines (e.g. audio callbacks or control loops) cannot afford the worst case of
+//! the general-purpose allocator: Taking a lock, searching the pool, or entering the kernel to
+//! extend the heap. A `RealtimeArena` bounds all of it:
+//!
+//! - The memory is preallocated (and pre-faulted) up front, so no syscall (nor page fault) ever
+//!   happens on the deadline.
+//! - The objects are segregated in power-of-two size classes, each with a free list, and a bitmap
+//!   of the non-empty lists. Allocation pops from its list, splits the smallest larger object, or
+//!   carves from the unused memory; freeing pushes to the list. Each takes a bounded number of
+//!   steps, independent of the state of the arena.
+//! - There are no locks (the arena is owned by a thread), no consistency scans, and no hooks or
+//!   statistics.
+//!
+//! When the arena runs dry, allocation fails, rather than waiting or falling back to the heap.
+//! Freed objects are never coalesced, so the arena suits workloads with a stable mix of sizes.
+
+use core::{cmp, ptr};
+
+use shim::config;
+
+use {options, pages};
+
+/// The number of size classes.
+const CLASSES: usize = 48;
+/// The class of the smallest objects.
+///
+/// Free objects hold the pointer to the next in their first word, so they are at least that.
+const MIN_CLASS: usize = 4;
+
+/// A free object.
+struct Object {
+    /// The next free object of the class (null if none).
+    next: *mut Object,
+}
+
+/// An arena for real-time threads.
+///
+/// See the `realtime` module.
+pub struct RealtimeArena {
+    /// The start of the memory, which was never handed out.
+    next: *mut u8,
+    /// The number of bytes never handed out.
+    rest: usize,
+    /// The free lists, one per size class.
+    ///
+    /// The objects of class `n` are `1 << n` bytes long, and aligned to that.
+    free: [*mut Object; CLASSES],
+    /// The bitmap of the classes with free objects.
+    nonempty: u64,
+    /// The number of bytes in use.
+    in_use: usize,
+    /// The number of pages reserved by `reserve` (zero if the memory was given).
+    pages: usize,
+    /// The start of the memory.
+    start: *mut u8,
+}
+
+/// The arena is owned by a single thread at a time.
+unsafe impl Send for RealtimeArena {}
+
+impl RealtimeArena {
+    /// Create an arena handing out a region.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, and must not be used by anything else, as
+    /// long as the arena (or any of its objects) lives.
+    pub unsafe fn new(ptr: *mut u8, size: usize) -> RealtimeArena {
+        // Align the start to the smallest objects.
+        let skip = cmp::min((ptr as usize).wrapping_neg() % (1 << MIN_CLASS), size);
+
+        RealtimeArena {
+            next: ptr.offset(skip as isize),
+            rest: size - skip,
+            free: [ptr::null_mut(); CLASSES],
+            nonempty: 0,
+            in_use: 0,
+            pages: 0,
+            start: ptr,
+        }
+    }
+
+    /// Create an arena of `size` bytes, reserved from the OS right away.
+    ///
+    /// The pages are mapped and pre-faulted here, so this should be done before the deadlines
+    /// start. They are given back when the arena is dropped.
+    ///
+    /// # Errors
+    ///
+    /// The OOM handler is called, if the pages cannot be mapped.
+    pub fn reserve(size: usize) -> RealtimeArena {
+        // Logging.
+        log!(NOTE, "Reserving {} bytes for a real-time arena.", size);
+
+        let pages = (size + config::PAGE_SIZE - 1) / config::PAGE_SIZE;
+        let ptr = pages::alloc_pages(pages);
+
+        unsafe {
+            // The pages were just mapped, and belong to the arena alone.
+            options::prefault(ptr, pages * config::PAGE_SIZE);
+
+            let mut res = RealtimeArena::new(ptr, pages * config::PAGE_SIZE);
+            res.pages = pages;
+
+            res
+        }
+    }
+
+    /// Allocate a buffer of `size` bytes, aligned to `align`.
+    ///
+    /// The buffer is rounded up to a power of two, which is at least `align`. If the arena has no
+    /// room for it, `None` is returned. This never blocks, and never calls the OOM handler.
+    pub fn alloc(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let class = match class(size, align) {
+            Some(class) => class,
+            None => return None,
+        };
+
+        // Free objects are used before the memory never handed out.
+        let res = match self.pop(class) {
+            Some(ptr) => Some(ptr),
+            None => match self.split(class) {
+                Some(ptr) => Some(ptr),
+                None => self.carve(class),
+            },
+        };
+
+        if res.is_some() {
+            self.in_use += 1 << class;
+        }
+
+        res
+    }
+
+    /// Free a buffer allocated through `alloc` with the same size and alignment.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated by this arena, and must not be used afterwards.
+    pub unsafe fn free(&mut self, ptr: *mut u8, size: usize, align: usize) {
+        let class = class(size, align).expect("Freeing a buffer, which the arena cannot hold.");
+        debug_assert!(ptr as usize % (1 << class) == 0, "Freeing 0x{:x}, which is not an object \
+                      of the arena.", ptr as usize);
+
+        self.in_use -= 1 << class;
+        self.push(ptr, class);
+    }
+
+    /// Get the number of bytes in use (with the buffers rounded up to their classes).
+    pub fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// Pop a free object of a class.
+    #[inline]
+    fn pop(&mut self, class: usize) -> Option<*mut u8> {
+        let ptr = self.free[class];
+        if ptr.is_null() {
+            return None;
+        }
+
+        unsafe {
+            // The free object points to the next one in its first word.
+            self.free[class] = (*ptr).next;
+        }
+        if self.free[class].is_null() {
+            self.nonempty &= !(1 << class);
+        }
+
+        Some(ptr as *mut u8)
+    }
+
+    /// Push a free object of a class.
+    #[inline]
+    fn push(&mut self, ptr: *mut u8, class: usize) {
+        let ptr = ptr as *mut Object;
+        unsafe {
+            // The object is at least a pointer long and aligned, and unused.
+            (*ptr).next = self.free[class];
+        }
+
+        self.free[class] = ptr;
+        self.nonempty |= 1 << class;
+    }
+
+    /// Carve an object of a class from the memory, which was never handed out.
+    ///
+    /// The memory skipped to align the object is broken into objects of smaller classes, which go
+    /// to the free lists. This takes at most a step per class.
+    fn carve(&mut self, class: usize) -> Option<*mut u8> {
+        let size = 1 << class;
+        let addr = self.next as usize;
+        let pad = match addr.checked_add(size - 1) {
+            Some(end) => (end & !(size - 1)) - addr,
+            None => return None,
+        };
+        if pad.saturating_add(size) > self.rest {
+            return None;
+        }
+
+        // Break the padding into the largest objects aligned to their size. The start is aligned
+        // to the smallest objects, so each is at least that, and the object is aligned to all of
+        // them, so each fits.
+        let mut offset = 0;
+        while offset < pad {
+            let class = (addr + offset).trailing_zeros() as usize;
+            let ptr = unsafe {
+                // The offset is within the memory never handed out.
+                self.next.offset(offset as isize)
+            };
+
+            self.push(ptr, class);
+            offset += 1 << class;
+        }
+
+        let res = unsafe {
+            // The object is within the memory never handed out.
+            self.next.offset(pad as isize)
+        };
+        self.next = unsafe {
+            // The end of the object is at most the end of the memory.
+            res.offset(size as isize)
+        };
+        self.rest -= pad + size;
+
+        Some(res)
+    }
+
+    /// Split the smallest free object of a larger class into an object of `class`.
+    ///
+    /// The halves not taken go to the free lists. This takes at most a step per class.
+    fn split(&mut self, class: usize) -> Option<*mut u8> {
+        let larger = self.nonempty & !((1 << (class + 1)) - 1);
+        if larger == 0 {
+            return None;
+        }
+
+        let mut larger = larger.trailing_zeros() as usize;
+        let res = self.pop(larger).expect("The bitmap of the free lists is out of sync.");
+
+        while larger > class {
+            larger -= 1;
+            self.push(unsafe {
+                // The upper half is within the object.
+                res.offset(1 << larger)
+            }, larger);
+        }
+
+        Some(res)
+    }
+}
+
+impl Drop for RealtimeArena {
+    fn drop(&mut self) {
+        if self.pages != 0 {
+            unsafe {
+                // The pages were reserved by `reserve`, and the objects die with the arena.
+                pages::free_pages(self.start, self.pages);
+            }
+        }
+    }
+}
+
+/// Get the size class of buffers of `size` bytes, aligned to `align`.
+///
+/// `None` is returned, if no class holds such buffers.
+#[inline]
+fn class(size: usize, align: usize) -> Option<usize> {
+    match cmp::max(cmp::max(size, align), 1 << MIN_CLASS).checked_next_power_of_two() {
+        Some(size) if (size.trailing_zeros() as usize) < CLASSES => {
+            Some(size.trailing_zeros() as usize)
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_realtime_arena() {
+        let mut buf = [0u64; 600];
+        let mut arena = unsafe {
+            // Start 16 bytes past a multiple of 256.
+            let ptr = &mut buf[0] as *mut u64 as *mut u8;
+            RealtimeArena::new(ptr.offset(((ptr as usize).wrapping_neg() % 256 + 16) as isize),
+                               4096)
+        };
+
+        // The padding before an aligned object is split into objects, and reused.
+        let a = arena.alloc(1, 1).unwrap();
+        let b = arena.alloc(100, 128).unwrap();
+        assert_eq!(b as usize % 128, 0);
+        assert_eq!(b as usize - a as usize, 112);
+        let c = arena.alloc(16, 1).unwrap();
+        assert_eq!(c as usize - a as usize, 16);
+        assert_eq!(arena.in_use(), 16 + 128 + 16);
+
+        // Freed objects are reused.
+        unsafe {
+            arena.free(b, 100, 128);
+        }
+        let d = arena.alloc(128, 1).unwrap();
+        assert_eq!(d, b);
+
+        // Running dry fails.
+        assert!(arena.alloc(8192, 1).is_none());
+
+        unsafe {
+            arena.free(a, 1, 1);
+            arena.free(c, 16, 1);
+            arena.free(d, 128, 1);
+        }
+        assert_eq!(arena.in_use(), 0);
+    }
+}
@@ -0,0 +1,135 @@
+//! The regions of the global allocator.
+//!
+//! The regions the global allocator acquires from its breaker (or is donated) are recorded, so
+//! frees can be checked against them. Freeing a pointer, which was never handed out by the
+//! allocator (a common failure when mixing allocators over FFI), would otherwise insert a bogus
+//! block into the pool, silently corrupting it. With the checks enabled (see `conf::checks`) or
+//! the `paranoid` feature, such frees are reported as violations instead.
+
+use prelude::*;
+
+use core::{cmp, mem};
+
+use {conf, fail, meta};
+use vec::Vec;
+
+/// The start and end of the regions, sorted and merged (`None` if none were acquired yet).
+static RANGES: Mutex<Option<Vec<(usize, usize)>>> = Mutex::new(None);
+
+/// Make room for another range.
+fn reserve(ranges: &mut Vec<(usize, usize)>) {
+    if ranges.len() == ranges.capacity() {
+        let cap = cmp::max(2 * ranges.capacity(), 16);
+        let block = meta::alloc(cap * mem::size_of::<(usize, usize)>(),
+                                mem::align_of::<(usize, usize)>());
+        meta::free(ranges.refill(block));
+    }
+}
+
+/// Record a region acquired by the global allocator.
+pub fn acquire(ptr: *mut u8, size: usize) {
+    if size == 0 {
+        return;
+    }
+
+    let mut ranges = RANGES.lock();
+    if ranges.is_none() {
+        *ranges = Some(Vec::default());
+    }
+
+    let ranges = ranges.as_mut().unwrap();
+    let (start, end) = (ptr as usize, ptr as usize + size);
+
+    // The index of the first range after the region.
+    let ind = ranges.iter().position(|&(a, _)| a > start).unwrap_or(ranges.len());
+
+    // Merge with the neighbors, if adjacent (the program break grows contiguously, so this keeps
+    // the table tiny).
+    let left = ind > 0 && ranges[ind - 1].1 >= start;
+    let right = ind < ranges.len() && ranges[ind].0 <= end;
+    match (left, right) {
+        (true, true) => {
+            ranges[ind - 1].1 = cmp::max(end, ranges[ind].1);
+            ranges.remove(ind);
+        },
+        (true, false) => ranges[ind - 1].1 = cmp::max(ranges[ind - 1].1, end),
+        (false, true) => {
+            ranges[ind].0 = start;
+            ranges[ind].1 = cmp::max(ranges[ind].1, end);
+        },
+        (false, false) => {
+            reserve(ranges);
+            ranges.insert(ind, (start, end)).expect("The table was grown too little.");
+        },
+    }
+}
+
+/// Forget a region released by the global allocator.
+pub fn release(ptr: *mut u8, size: usize) {
+    if size == 0 {
+        return;
+    }
+
+    let mut ranges = RANGES.lock();
+    let ranges = match ranges.as_mut() {
+        Some(ranges) => ranges,
+        None => return,
+    };
+    let (start, end) = (ptr as usize, ptr as usize + size);
+
+    let ind = match ranges.iter().position(|&(a, b)| a <= start && end <= b) {
+        Some(ind) => ind,
+        None => {
+            log!(WARNING, "Releasing 0x{:x}[{}], which was never acquired.", start, size);
+            return;
+        },
+    };
+
+    let (a, b) = ranges[ind];
+    if a == start && b == end {
+        ranges.remove(ind);
+    } else if a == start {
+        ranges[ind].0 = end;
+    } else if b == end {
+        ranges[ind].1 = start;
+    } else {
+        // The region is in the middle, so the range is split.
+        ranges[ind].1 = start;
+        reserve(ranges);
+        ranges.insert(ind + 1, (end, b)).expect("The table was grown too little.");
+    }
+}
+
+/// Does some buffer lie in the regions of the global allocator?
+pub fn owns(ptr: *mut u8, size: usize) -> bool {
+    let start = ptr as usize;
+    let end = match start.checked_add(size) {
+        Some(end) => end,
+        None => return false,
+    };
+
+    RANGES.lock().as_ref().map_or(false, |ranges| {
+        ranges.iter().any(|&(a, b)| a <= start && end <= b)
+    })
+}
+
+/// Check that a buffer lies in the regions of the global allocator, before it is given back.
+///
+/// This is NOOP, unless the checks are enabled (see `conf::checks`) or the `paranoid` feature is
+/// enabled. Foreign buffers are reported as violations from `place`. If the violation policy is
+/// to quarantine, `false` is returned, and the buffer is to be left be.
+#[inline]
+pub fn check(ptr: *mut u8, size: usize, place: &'static str) -> bool {
+    if !(cfg!(feature = "paranoid") || conf::checks()) || size == 0 || owns(ptr, size) {
+        return true;
+    }
+
+    fail::violation(&fail::Violation {
+        description: "The buffer was not allocated by ralloc",
+        place: place,
+        addr: ptr as usize,
+        size: size,
+    });
+
+    false
+}
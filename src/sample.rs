@@ -0,0 +1,297 @@
+//! Sampled guarded allocations.
+//!
+//! This is a low-overhead bug detector for production use, in the style of GWP-ASan. A random
+//! one in `config::SAMPLE_RATE` allocations is served from a small pool of slots, each a single
+//! page fenced by guard pages. The allocation is placed at the end of its page, so overflows
+//! fault right away, and freed slots are protected, so use-after-frees fault too. The stacks of
+//! the allocation and the free are recorded, and printed when such a fault happens.
+//!
+//! All other allocations are untouched, apart from a counter decrement.
+
+use prelude::*;
+
+use core::intrinsics;
+use core::fmt::Write;
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::{config, syscalls};
+
+use backtrace::{EMPTY_TRACE, Trace};
+use fail::ReportWriter;
+use rand::Rng;
+
+/// The start of the mapping holding the slots (zero if not mapped yet).
+///
+/// This mirrors `State::base`, so frees can be checked without locking.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+/// The number of allocations left before the next sample.
+static COUNTDOWN: AtomicUsize = AtomicUsize::new(config::SAMPLE_RATE);
+/// The state of the slots.
+static STATE: Mutex<State> = Mutex::new(State {
+    base: 0,
+    next: 0,
+    slots: [EMPTY_SLOT; config::SAMPLE_SLOTS],
+    rng: None,
+});
+
+/// An unused slot.
+const EMPTY_SLOT: Slot = Slot {
+    ptr: 0,
+    size: 0,
+    freed: false,
+    alloc_trace: EMPTY_TRACE,
+    free_trace: EMPTY_TRACE,
+};
+
+/// A guarded slot.
+#[derive(Clone, Copy)]
+struct Slot {
+    /// The address of the allocation (zero if the slot was never used).
+    ptr: usize,
+    /// The size of the allocation.
+    size: usize,
+    /// Has the allocation been freed?
+    freed: bool,
+    /// The stack trace of the allocation.
+    alloc_trace: Trace,
+    /// The stack trace of the free.
+    free_trace: Trace,
+}
+
+/// The state of the slots.
+struct State {
+    /// The start of the mapping holding the slots (zero if not mapped yet).
+    ///
+    /// The mapping consists of alternating guard pages and slot pages, starting and ending with a
+    /// guard page.
+    base: usize,
+    /// The slot to try next.
+    next: usize,
+    /// The slots.
+    slots: [Slot; config::SAMPLE_SLOTS],
+    /// The random number generator, deciding the distance between samples.
+    rng: Option<Rng>,
+}
+
+impl State {
+    /// Get the address of the page of some slot.
+    #[inline]
+    fn page(&self, slot: usize) -> usize {
+        self.base + (2 * slot + 1) * config::PAGE_SIZE
+    }
+
+    /// Get the size of the mapping.
+    #[inline]
+    fn mapping_size() -> usize {
+        (2 * config::SAMPLE_SLOTS + 1) * config::PAGE_SIZE
+    }
+
+    /// Does the mapping contain some address?
+    #[inline]
+    fn contains(&self, addr: usize) -> bool {
+        self.base != 0 && addr >= self.base && addr < self.base + State::mapping_size()
+    }
+
+    /// Map the slots and install the fault handler.
+    fn init(&mut self) -> Result<(), ()> {
+        unsafe {
+            let base = syscalls::mmap(State::mapping_size()).map_err(|_| ())?;
+            // Everything is a guard, until it is taken.
+            syscalls::mprotect(base, State::mapping_size(), syscalls::PROT_NONE)?;
+
+            #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+            {
+                if syscalls::set_fault_handler(Some(on_fault)).is_err() {
+                    log!(WARNING, "Unable to install the fault handler of the sampled allocations.");
+                }
+            }
+
+            self.base = base as usize;
+            BASE.store(self.base, atomic::Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+}
+
+/// Maybe serve an allocation from the guarded slots.
+///
+/// `None` is returned, if the allocation is not sampled (or cannot be served from a slot), in
+/// which case it is to be served as usual.
+#[inline]
+pub fn alloc(size: usize, align: usize) -> Option<*mut u8> {
+    if COUNTDOWN.fetch_sub(1, atomic::Ordering::Relaxed) != 1 {
+        return None;
+    }
+
+    alloc_sampled(size, align)
+}
+
+/// Serve a sampled allocation.
+#[cold]
+fn alloc_sampled(size: usize, align: usize) -> Option<*mut u8> {
+    let mut state = STATE.lock();
+
+    // Decide the distance to the next sample.
+    if state.rng.is_none() {
+        state.rng = Some(Rng::new());
+    }
+    let distance = 1 + state.rng.as_mut().unwrap().below(2 * config::SAMPLE_RATE);
+    COUNTDOWN.store(distance, atomic::Ordering::Relaxed);
+
+    if size == 0 || size > config::PAGE_SIZE || align > config::PAGE_SIZE {
+        return None;
+    }
+
+    if state.base == 0 && state.init().is_err() {
+        log!(WARNING, "Unable to map the slots of the sampled allocations.");
+
+        // Don't try again.
+        COUNTDOWN.store(!0, atomic::Ordering::Relaxed);
+        return None;
+    }
+
+    // Find a slot, which isn't in use.
+    let slot = match (0..config::SAMPLE_SLOTS)
+        .map(|n| (state.next + n) % config::SAMPLE_SLOTS)
+        .find(|&n| state.slots[n].ptr == 0 || state.slots[n].freed) {
+        Some(slot) => slot,
+        None => return None,
+    };
+    state.next = (slot + 1) % config::SAMPLE_SLOTS;
+
+    let page = state.page(slot);
+    let res = unsafe {
+        // The page is part of our mapping, and not in use.
+        syscalls::mprotect(page as *mut u8, config::PAGE_SIZE,
+                           syscalls::PROT_READ | syscalls::PROT_WRITE)
+    };
+    if res.is_err() {
+        return None;
+    }
+
+    // Place the allocation at the end of the page, so overflows hit the guard page.
+    let ptr = (page + config::PAGE_SIZE - size) / align * align;
+
+    // Logging.
+    log!(DEBUG, "Sampling the allocation 0x{:x}[{}].", ptr, size);
+
+    state.slots[slot] = Slot {
+        ptr: ptr,
+        size: size,
+        freed: false,
+        alloc_trace: Trace::capture(),
+        free_trace: EMPTY_TRACE,
+    };
+
+    Some(ptr as *mut u8)
+}
+
+/// Is some buffer a sampled allocation?
+#[inline]
+pub fn owns(ptr: *mut u8) -> bool {
+    let base = BASE.load(atomic::Ordering::SeqCst);
+
+    base != 0 && ptr as usize >= base && (ptr as usize) < base + State::mapping_size()
+}
+
+/// Free a buffer, if it is a sampled allocation.
+///
+/// If the buffer is not sampled, `false` is returned, and it is to be freed as usual. Invalid and
+/// double frees of sampled allocations are reported, and abort.
+pub fn free(ptr: *mut u8) -> bool {
+    if !owns(ptr) {
+        return false;
+    }
+
+    let mut state = STATE.lock();
+
+    let slot = (ptr as usize - state.base) / config::PAGE_SIZE / 2;
+    match state.slots.get(slot) {
+        Some(s) if s.ptr == ptr as usize && s.freed => {
+            report(&state, "Double free", ptr as usize, slot)
+        },
+        Some(s) if s.ptr == ptr as usize => (),
+        _ => report(&state, "Invalid free", ptr as usize, slot),
+    }
+
+    // Logging.
+    log!(DEBUG, "Freeing the sampled allocation 0x{:x}.", ptr as usize);
+
+    state.slots[slot].freed = true;
+    state.slots[slot].free_trace = Trace::capture();
+
+    let page = state.page(slot);
+    unsafe {
+        // The page is ours, and protecting it makes any use-after-free fault.
+        let _ = syscalls::mprotect(page as *mut u8, config::PAGE_SIZE, syscalls::PROT_NONE);
+    }
+
+    true
+}
+
+/// Print a stack trace.
+fn print(trace: &Trace) {
+    for (n, &frame) in trace.frames().iter().enumerate() {
+        let _ = writeln!(ReportWriter, "    #{} 0x{:x}", n, frame);
+    }
+}
+
+/// Report a bug involving some slot, and abort.
+#[cold]
+fn report(state: &State, what: &str, addr: usize, slot: usize) -> ! {
+    let _ = writeln!(ReportWriter, "\x1b[31;1m{} at 0x{:x}.\x1b[m", what, addr);
+
+    if let Some(slot) = state.slots.get(slot) {
+        if slot.ptr == 0 {
+            // The slot was never used.
+            return abort();
+        }
+
+        let _ = writeln!(ReportWriter, "The sampled allocation 0x{:x}[{}] was allocated at:",
+                         slot.ptr, slot.size);
+        print(&slot.alloc_trace);
+
+        if slot.freed {
+            let _ = writeln!(ReportWriter, "And freed at:");
+            print(&slot.free_trace);
+        }
+    }
+
+    abort()
+}
+
+/// Abort the process.
+fn abort() -> ! {
+    unsafe {
+        // Aborting is safe no matter what.
+        intrinsics::abort();
+    }
+}
+
+/// Handle a segmentation fault.
+///
+/// If the fault is in the guarded slots, the bug is reported. Otherwise, the default action is
+/// restored, and the fault is raised again.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+unsafe extern fn on_fault(_: i32, info: *const syscalls::SigInfo, _: *mut u8) {
+    // The lock might be held by the faulting thread, so we peek at the state without it.
+    let state = STATE.peek();
+    let addr = (*info).addr;
+
+    if state.contains(addr) {
+        let page = (addr - state.base) / config::PAGE_SIZE;
+        let offset = (addr - state.base) % config::PAGE_SIZE;
+
+        if page % 2 == 1 {
+            report(state, "Use after free", addr, page / 2);
+        } else if page > 0 && (offset < config::PAGE_SIZE / 2 || page / 2 == config::SAMPLE_SLOTS) {
+            // The first half of a guard page is attributed to the slot before it.
+            report(state, "Buffer overflow", addr, page / 2 - 1);
+        } else {
+            report(state, "Buffer underflow", addr, page / 2);
+        }
+    }
+
+    let _ = syscalls::set_fault_handler(None);
+}
@@ -0,0 +1,885 @@
+//! Address-segmented block pools.
+//!
+//! The block pool is partitioned into fixed-size segments of the address space, each holding a
+//! small sorted list of the free blocks starting in it. A radix map takes an address to its
+//! segment in constant time, so an operation only ever touches (and memmoves) a tiny list, no
+//! matter how big the heap grows.
+//!
+//! Blocks belong to the segment of their start address, but are free to extend into the
+//! following segments. For this reason, the neighbors of a block might live in other segments.
+
+use prelude::*;
+
+use core::{mem, ops, ptr, slice};
+use core::marker::PhantomData;
+
+use leak::Leak;
+use storage::{self, MetaStorage, PoolStorage};
+
+use shim::config;
+
+/// The number of leaves in the radix map.
+const RADIX_ROOT_LEN: usize = 64;
+/// The number of segments covered by a leaf of the radix map.
+const RADIX_LEAF_LEN: usize = 1024;
+
+/// Get the number of the segment, a block belongs to.
+#[inline]
+pub fn segment_of(block: &Block) -> usize {
+    block.addr() >> config::SEGMENT_SHIFT
+}
+
+/// The alignment of the metadata of the pool.
+#[inline]
+pub fn meta_align() -> usize {
+    mem::align_of::<Segment>()
+}
+
+/// The size of a leaf of the radix map, in bytes.
+#[inline]
+fn leaf_size() -> usize {
+    RADIX_LEAF_LEN * mem::size_of::<u32>()
+}
+
+/// The number of bits in a word of the occupancy index.
+#[inline]
+fn word_bits() -> usize {
+    mem::size_of::<usize>() * 8
+}
+
+/// The size of a list holding `len` elements plus some extra space, in bytes.
+#[inline]
+fn grown_size<T>(len: usize) -> usize {
+    (len + config::extra_fresh(len)) * mem::size_of::<T>()
+}
+
+/// A position in the block pool.
+///
+/// This refers to the `ind`'th entry of the `seg`'th segment. If the segment of the position is
+/// missing, `seg` is where it would be inserted, and `ind` is zero.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    /// The index of the segment in the segment list.
+    pub seg: usize,
+    /// The index of the entry in the segment.
+    pub ind: usize,
+}
+
+/// An address segment.
+struct Segment {
+    /// The number of the segment.
+    ///
+    /// This is the start address of the segment shifted by `SEGMENT_SHIFT`.
+    number: usize,
+    /// The free blocks starting in this segment.
+    ///
+    /// This list is sorted, and none of the blocks are adjacent.
+    blocks: Vec<Block>,
+}
+
+unsafe impl Leak for Segment {}
+
+/// Metadata needed by the pool.
+enum Need {
+    /// The segment list is full.
+    List,
+    /// The radix map lacks a leaf.
+    Leaf,
+    /// The occupancy index cannot cover another segment.
+    Index,
+    /// The segment is missing, and should be inserted at some index.
+    Segment(usize),
+    /// The segment at some index is full.
+    Grow(usize),
+}
+
+/// A two-level radix map from segment numbers to indices in the segment list.
+///
+/// The leaves are allocated as needed. The map covers a window of `RADIX_ROOT_LEN *
+/// RADIX_LEAF_LEN` segments starting at the first segment ever inserted. Since the heap grows
+/// upwards, this covers the bulk of the segments in practice. Segments outside the window are
+/// found through binary search of the segment list.
+struct RadixMap {
+    /// The first segment number covered by the map.
+    base: Option<usize>,
+    /// The leaves.
+    ///
+    /// Missing leaves are represented by null. The entries of the leaves are the index of the
+    /// segment plus one, zero being used for segments not in the list. The leaves are kept as
+    /// pointers (rather than addresses), so they keep the provenance of their metadata.
+    leaves: [*mut u32; RADIX_ROOT_LEN],
+}
+
+/// The leaves are owned by the map, and only accessed through it.
+unsafe impl Send for RadixMap {}
+
+impl RadixMap {
+    /// Get the leaf and the index into it of some segment number.
+    ///
+    /// `None` is returned if the number lies outside the window.
+    #[inline]
+    fn locate(&self, number: usize) -> Option<(usize, usize)> {
+        let base = match self.base {
+            Some(base) => base,
+            None => return None,
+        };
+
+        if number < base || number - base >= RADIX_ROOT_LEN * RADIX_LEAF_LEN {
+            None
+        } else {
+            Some(((number - base) / RADIX_LEAF_LEN, (number - base) % RADIX_LEAF_LEN))
+        }
+    }
+
+    /// Get the entry of some segment number.
+    #[inline]
+    #[allow(cast_possible_wrap)]
+    fn get(&self, number: usize) -> Option<usize> {
+        match self.locate(number) {
+            Some((leaf, ind)) if !self.leaves[leaf].is_null() => {
+                let entry = unsafe {
+                    // The leaf is allocated and holds `RADIX_LEAF_LEN` entries, hence the index is
+                    // in bounds.
+                    *self.leaves[leaf].offset(ind as isize)
+                };
+
+                if entry == 0 { None } else { Some(entry as usize - 1) }
+            },
+            _ => None,
+        }
+    }
+
+    /// Set the entry of some segment number.
+    ///
+    /// This is a NOOP if the number lies outside the window.
+    #[inline]
+    #[allow(cast_possible_wrap, cast_possible_truncation)]
+    fn set(&mut self, number: usize, seg: usize) {
+        if let Some((leaf, ind)) = self.locate(number) {
+            if !self.leaves[leaf].is_null() {
+                unsafe {
+                    // See `get`.
+                    *self.leaves[leaf].offset(ind as isize) = (seg + 1) as u32;
+                }
+            }
+        }
+    }
+
+    /// Does the map need a new leaf to cover some segment number?
+    #[inline]
+    fn needs_leaf(&self, number: usize) -> bool {
+        match self.base {
+            // The first leaf sets the window.
+            None => true,
+            Some(_) => self.locate(number).map_or(false, |(leaf, _)| self.leaves[leaf].is_null()),
+        }
+    }
+
+    /// Install the leaf covering some segment number.
+    fn install(&mut self, number: usize, leaf: Block) {
+        debug_assert!(leaf.size() >= leaf_size(), "Radix leaf too small.");
+
+        if self.base.is_none() {
+            // Let the window start at the leaf of the first segment.
+            self.base = Some(number - number % RADIX_LEAF_LEN);
+        }
+
+        let (ind, _) = self.locate(number).expect("Installing a leaf outside the window.");
+
+        let ptr = Pointer::from(leaf).cast::<u32>().get();
+        unsafe {
+            // The leaf is at least `leaf_size()` bytes, and aligned to `u32`.
+            ptr::write_bytes(ptr, 0, RADIX_LEAF_LEN);
+        }
+
+        self.leaves[ind] = ptr;
+    }
+}
+
+/// An index of the non-empty segments.
+///
+/// Bit `n` is set if and only if the `n`'th segment of the list holds blocks. Segments are never
+/// removed, so emptied segments pile up when the heap shrinks, and the neighbor lookups use the
+/// index to step over them a word at a time rather than one by one.
+struct Occupancy {
+    /// The bits, least significant first.
+    ///
+    /// The words cover every segment of the list.
+    words: Vec<usize>,
+}
+
+impl Occupancy {
+    /// Is the `n`'th segment non-empty?
+    #[inline]
+    fn get(&self, n: usize) -> bool {
+        self.words[n / word_bits()] & (1 << (n % word_bits())) != 0
+    }
+
+    /// Mark the `n`'th segment as non-empty or empty.
+    #[inline]
+    fn set(&mut self, n: usize, occupied: bool) {
+        let bit = 1 << (n % word_bits());
+        let word = &mut self.words[n / word_bits()];
+
+        if occupied {
+            *word |= bit;
+        } else {
+            *word &= !bit;
+        }
+    }
+
+    /// Make room for an empty segment inserted at index `n`.
+    ///
+    /// The bits from `n` and up are moved one up, and bit `n` is cleared. The words must cover
+    /// the list with the new segment.
+    fn shift(&mut self, n: usize) {
+        let (ind, bit) = (n / word_bits(), n % word_bits());
+
+        // Move the words after the one holding `n`, carrying in the top bit of their predecessor.
+        for i in (ind + 1..self.words.len()).rev() {
+            self.words[i] = (self.words[i] << 1) | (self.words[i - 1] >> (word_bits() - 1));
+        }
+
+        // Move the bits from `n` and up in its own word, leaving the ones below be.
+        let low = (1 << bit) - 1;
+        let word = self.words[ind];
+        self.words[ind] = (word & low) | (word & !low) << 1;
+    }
+
+    /// Find the first non-empty segment at or after index `n`.
+    #[inline]
+    fn next(&self, n: usize) -> Option<usize> {
+        let mut ind = n / word_bits();
+        // Mask out the segments before `n`.
+        let mut word = match self.words.get(ind) {
+            Some(&word) => word & !((1 << (n % word_bits())) - 1),
+            None => return None,
+        };
+
+        loop {
+            if word != 0 {
+                return Some(ind * word_bits() + word.trailing_zeros() as usize);
+            }
+
+            ind += 1;
+            word = match self.words.get(ind) {
+                Some(&word) => word,
+                None => return None,
+            };
+        }
+    }
+
+    /// Find the last non-empty segment before index `n`.
+    #[inline]
+    fn prev(&self, n: usize) -> Option<usize> {
+        let mut ind = n / word_bits();
+        // Mask out the segments from `n` and up.
+        let mut word = self.words.get(ind).map_or(0, |&word| word & ((1 << (n % word_bits())) - 1));
+
+        loop {
+            if word != 0 {
+                return Some(ind * word_bits() + word_bits() - 1 - word.leading_zeros() as usize);
+            }
+
+            if ind == 0 {
+                return None;
+            }
+
+            ind -= 1;
+            word = self.words[ind];
+        }
+    }
+}
+
+/// A segmented block pool.
+///
+/// The metadata of the pool is taken from the storage, `S` (see the `storage` module).
+pub struct Pool<S: PoolStorage = MetaStorage> {
+    /// The segments, sorted by their number.
+    segments: Vec<Segment>,
+    /// The radix map from segment numbers to indices into `segments`.
+    map: RadixMap,
+    /// The index of the non-empty segments.
+    occupied: Occupancy,
+    /// The number of blocks in the pool.
+    len: usize,
+    /// The position found by the last search.
+    ///
+    /// Frees and allocations tend to hit addresses near the previous operation (e.g. LIFO or
+    /// streaming patterns), making the last position a good guess. The guess is verified before
+    /// use, hence it needn't be updated when the pool is modified.
+    last_found: Position,
+    /// The storage.
+    storage: PhantomData<S>,
+}
+
+#[allow(len_without_is_empty)]
+impl<S: PoolStorage> Pool<S> {
+    /// Create a new, empty pool.
+    ///
+    /// No metadata is allocated before the first block is added.
+    pub fn new() -> Pool<S> {
+        Pool {
+            segments: Vec::default(),
+            map: RadixMap {
+                base: None,
+                leaves: [ptr::null_mut(); RADIX_ROOT_LEN],
+            },
+            occupied: Occupancy {
+                words: Vec::default(),
+            },
+            len: 0,
+            last_found: Position { seg: 0, ind: 0 },
+            storage: PhantomData,
+        }
+    }
+
+    /// Get the number of blocks in the pool.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Find the segment with some number.
+    ///
+    /// Returns `Ok` with the index of the segment, or `Err` with the index where it would be
+    /// inserted.
+    #[inline]
+    fn segment(&self, number: usize) -> Result<usize, usize> {
+        if let Some(seg) = self.map.get(number) {
+            // Verify the hit, just in case.
+            if self.segments.get(seg).map_or(false, |s| s.number == number) {
+                return Ok(seg);
+            }
+        }
+
+        self.segments.binary_search_by(|s| s.number.cmp(&number))
+    }
+
+    /// Find the position of the lower bound of a block.
+    ///
+    /// This is the place where the block can be inserted or is located. It is guaranteed that no
+    /// block before the returned position is placed right to `block`.
+    #[inline]
+    pub fn find(&mut self, block: &Block) -> Position {
+        let number = segment_of(block);
+
+        let last = self.last_found;
+        let pos = if self.segments.get(last.seg).map_or(false, |s| {
+            s.number == number && is_lower_bound(&s.blocks, last.ind, block)
+        }) {
+            // Cache hit.
+            last
+        } else {
+            match self.segment(number) {
+                Ok(seg) => Position {
+                    seg: seg,
+                    ind: lower_bound(&self.segments[seg].blocks, block),
+                },
+                Err(seg) => Position {
+                    seg: seg,
+                    ind: 0,
+                },
+            }
+        };
+
+        // Update the cache.
+        self.last_found = pos;
+
+        pos
+    }
+
+    /// Get the position of the first block at or after some position.
+    #[inline]
+    pub fn next(&self, pos: Position) -> Option<Position> {
+        match self.segments.get(pos.seg) {
+            Some(s) if pos.ind < s.blocks.len() => return Some(pos),
+            Some(_) => {},
+            None => return None,
+        }
+
+        // Skip to the start of the next non-empty segment.
+        self.occupied.next(pos.seg + 1).map(|seg| Position {
+            seg: seg,
+            ind: 0,
+        })
+    }
+
+    /// Get the position of the last block before some position.
+    #[inline]
+    pub fn prev(&self, pos: Position) -> Option<Position> {
+        if pos.ind > 0 {
+            return Some(Position {
+                seg: pos.seg,
+                ind: pos.ind - 1,
+            });
+        }
+
+        // Find the last non-empty segment before the position.
+        self.occupied.prev(pos.seg).map(|seg| Position {
+            seg: seg,
+            ind: self.segments[seg].blocks.len() - 1,
+        })
+    }
+
+    /// Get the position of the first block of the pool.
+    #[inline]
+    pub fn first(&self) -> Option<Position> {
+        self.next(Position { seg: 0, ind: 0 })
+    }
+
+    /// Get the position of the last block of the pool.
+    #[inline]
+    pub fn last(&self) -> Option<Position> {
+        self.prev(Position {
+            seg: self.segments.len(),
+            ind: 0,
+        })
+    }
+
+    /// Get the ordinal of a position, i.e. the number of blocks before it.
+    #[cfg(feature = "log")]
+    pub fn ordinal(&self, pos: Position) -> usize {
+        self.segments.iter().take(pos.seg).map(|s| s.blocks.len()).sum::<usize>() + pos.ind
+    }
+
+    /// Iterate over the blocks of the pool, in order.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            segments: self.segments.iter(),
+            blocks: None,
+        }
+    }
+
+    /// Insert a block at some position.
+    ///
+    /// This memmoves the blocks after it in the same segment, but no others. Room must have been
+    /// made beforehand, through `make_room`.
+    ///
+    /// If the position is not in the segment of the block, or the segment is full, an invariant
+    /// violation is reported (see `fail::violation`), and if the policy lets the allocator
+    /// continue, the block is leaked and `false` is returned.
+    pub fn insert(&mut self, pos: Position, block: Block) -> bool {
+        let placed = self.segments.get(pos.seg).map_or(false, |s| s.number == segment_of(&block));
+        if !invariant!(placed, "Pool::insert", Some(&block),
+                       "Inserting a block outside its segment") {
+            return false;
+        }
+
+        let room = self.segments[pos.seg].blocks.len() < self.segments[pos.seg].blocks.capacity();
+        if !invariant!(room, "Pool::insert", Some(&block),
+                       "Inserting a block into a full segment") {
+            return false;
+        }
+
+        let res = self.segments[pos.seg].blocks.insert(pos.ind, block);
+        debug_assert!(res.is_ok(), "Insertion failed (segment full).");
+
+        self.occupied.set(pos.seg, true);
+        self.len += 1;
+
+        true
+    }
+
+    /// Remove the block at some position.
+    pub fn remove(&mut self, pos: Position) -> Block {
+        self.len -= 1;
+
+        let block = self.segments[pos.seg].blocks.remove(pos.ind);
+        if self.segments[pos.seg].blocks.is_empty() {
+            self.occupied.set(pos.seg, false);
+        }
+
+        block
+    }
+
+    /// Pop the last block from the pool.
+    pub fn pop(&mut self) -> Option<Block> {
+        self.last().map(|pos| self.remove(pos))
+    }
+
+    /// Find out which metadata is needed to take some block.
+    fn need(&self, block: &Block) -> Option<Need> {
+        let number = segment_of(block);
+
+        match self.segment(number) {
+            Ok(seg) => {
+                let blocks = &self.segments[seg].blocks;
+
+                if blocks.len() == blocks.capacity() {
+                    Some(Need::Grow(seg))
+                } else {
+                    None
+                }
+            },
+            Err(_) if self.segments.len() == self.segments.capacity() => Some(Need::List),
+            Err(_) if self.map.needs_leaf(number) => Some(Need::Leaf),
+            Err(_) if self.occupied.words.capacity() * word_bits() <= self.segments.len() => {
+                Some(Need::Index)
+            },
+            Err(seg) => Some(Need::Segment(seg)),
+        }
+    }
+
+    /// Make room for a block in the pool.
+    ///
+    /// The metadata is taken from the storage (see the `storage` module), which is kept apart
+    /// from the pools, so the pool itself is never touched by this.
+    ///
+    /// Taking a block needs at most four pieces of metadata (a grown segment list, a leaf of the
+    /// radix map, a grown occupancy index, and the list of a new segment).
+    pub fn make_room(&mut self, block: &Block) {
+        while let Some(need) = self.need(block) {
+            let size = match need {
+                Need::List => grown_size::<Segment>(self.segments.len() + 1),
+                Need::Leaf => leaf_size(),
+                Need::Index => grown_size::<usize>(self.segments.len() / word_bits() + 1),
+                Need::Segment(_) => grown_size::<Block>(1),
+                Need::Grow(seg) => grown_size::<Block>(self.segments[seg].blocks.len() + 1),
+            };
+
+            let piece = storage::alloc::<S>(size, meta_align());
+            self.apply(block, need, piece);
+        }
+    }
+
+    /// Apply a piece of metadata to the need, it was allocated for.
+    fn apply(&mut self, block: &Block, need: Need, piece: Block) {
+        let number = segment_of(block);
+
+        match need {
+            Need::List => {
+                let old = self.segments.refill(piece);
+                storage::free::<S>(old);
+            },
+            Need::Leaf => {
+                self.map.install(number, piece);
+
+                // Enter the segments covered by the new leaf.
+                for (n, s) in self.segments.iter().enumerate() {
+                    self.map.set(s.number, n);
+                }
+            },
+            Need::Index => {
+                let old = self.occupied.words.refill(piece);
+                storage::free::<S>(old);
+            },
+            Need::Segment(seg) => {
+                let res = self.segments.insert(seg, Segment {
+                    number: number,
+                    blocks: unsafe {
+                        // The piece is unused and aligned, and the vector starts out empty.
+                        Vec::from_raw_parts(piece, 0)
+                    },
+                });
+                debug_assert!(res.is_ok(), "Segment insertion failed (list full).");
+
+                // Cover the new segment by the occupancy index, and mark it empty.
+                if self.occupied.words.len() * word_bits() < self.segments.len() {
+                    let res = self.occupied.words.push(0);
+                    debug_assert!(res.is_ok(), "Occupancy index full.");
+                }
+                self.occupied.shift(seg);
+
+                // The segments after it were shifted, so we update their entries.
+                for n in seg..self.segments.len() {
+                    self.map.set(self.segments[n].number, n);
+                }
+            },
+            Need::Grow(seg) => {
+                let old = self.segments[seg].blocks.refill(piece);
+                storage::free::<S>(old);
+            },
+        }
+    }
+
+    /// Go over the metadata of the pool, calling some function with the address and size of each
+    /// piece (the segment list, the lists of the segments, the occupancy index, and the leaves of
+    /// the radix map).
+    pub fn for_each_meta<F: FnMut(usize, usize)>(&self, mut f: F) {
+        if self.segments.capacity() != 0 {
+            f(self.segments.as_ptr() as usize,
+              self.segments.capacity() * mem::size_of::<Segment>());
+        }
+
+        for seg in self.segments.iter().filter(|seg| seg.blocks.capacity() != 0) {
+            f(seg.blocks.as_ptr() as usize, seg.blocks.capacity() * mem::size_of::<Block>());
+        }
+
+        if self.occupied.words.capacity() != 0 {
+            f(self.occupied.words.as_ptr() as usize,
+              self.occupied.words.capacity() * mem::size_of::<usize>());
+        }
+
+        for &leaf in self.map.leaves.iter().filter(|leaf| !leaf.is_null()) {
+            f(leaf as usize, leaf_size());
+        }
+    }
+
+    /// Go over every block in the pool and call some function.
+    ///
+    /// The metadata of the pool is given back to the storage.
+    pub fn for_each<F: FnMut(Block)>(mut self, mut f: F) {
+        // Run over all the segments.
+        while let Some(mut seg) = self.segments.pop() {
+            for i in seg.blocks.pop_iter() {
+                f(i);
+            }
+
+            // Give back the segment's list.
+            storage::free::<S>(Block::from(seg.blocks));
+        }
+
+        // Give back the segment list and the occupancy index.
+        storage::free::<S>(Block::from(self.segments));
+        storage::free::<S>(Block::from(self.occupied.words));
+
+        // Give back the leaves of the radix map.
+        for &leaf in self.map.leaves.iter().filter(|leaf| !leaf.is_null()) {
+            storage::free::<S>(unsafe {
+                // The leaf was allocated with this size by `make_room`.
+                Block::from_raw_parts(Pointer::new(leaf as *mut u8), leaf_size())
+            });
+        }
+    }
+
+    /// Perform consistency checks of the segments.
+    ///
+    /// This will check for the following conditions:
+    ///
+    /// 1. The segments are sorted.
+    /// 2. Every block is placed in its own segment.
+    /// 3. The radix map agrees with the segment list.
+    /// 4. The occupancy index agrees with the segment list.
+    /// 5. The length is maintained properly.
+    /// 6. The checksums of the blocks are valid (with the `checksum` feature).
+    ///
+    /// Violations are reported through `fail::violation`, like the checks of the bookkeeper.
+    pub fn check(&self) {
+        // The number of blocks.
+        let mut len = 0;
+
+        for (n, s) in self.segments.iter().enumerate() {
+            len += s.blocks.len();
+
+            if let Some(next) = self.segments.get(n + 1) {
+                invariant!(next.number > s.number, "Pool::check", s.blocks.first(),
+                           "The segment list is not sorted");
+            }
+
+            for i in s.blocks.iter() {
+                i.verify("check");
+                invariant!(segment_of(i) == s.number, "Pool::check", Some(i),
+                           "A block is placed in the wrong segment");
+            }
+
+            invariant!(self.map.get(s.number).map_or(true, |x| x == n), "Pool::check",
+                       s.blocks.first(), "The radix map disagrees with the segment list");
+            invariant!(self.occupied.get(n) == !s.blocks.is_empty(), "Pool::check",
+                       s.blocks.first(), "The occupancy index disagrees with the segment list");
+        }
+
+        invariant!(len == self.len, "Pool::check", None,
+                   "The length of the pool is not equal to the 'len' field");
+    }
+}
+
+impl<S: PoolStorage> ops::Index<Position> for Pool<S> {
+    type Output = Block;
+
+    #[inline]
+    fn index(&self, pos: Position) -> &Block {
+        &self.segments[pos.seg].blocks[pos.ind]
+    }
+}
+
+impl<S: PoolStorage> ops::IndexMut<Position> for Pool<S> {
+    #[inline]
+    fn index_mut(&mut self, pos: Position) -> &mut Block {
+        &mut self.segments[pos.seg].blocks[pos.ind]
+    }
+}
+
+/// An iterator over the blocks of a pool.
+pub struct Iter<'a> {
+    /// The segments yet to be visited.
+    segments: slice::Iter<'a, Segment>,
+    /// The blocks of the current segment.
+    blocks: Option<slice::Iter<'a, Block>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Block;
+
+    fn next(&mut self) -> Option<&'a Block> {
+        loop {
+            if let Some(block) = self.blocks.as_mut().and_then(|blocks| blocks.next()) {
+                return Some(block);
+            }
+
+            // Move on to the next segment.
+            self.blocks = match self.segments.next() {
+                Some(s) => Some(s.blocks.iter()),
+                None => return None,
+            };
+        }
+    }
+}
+
+/// Find the lower bound of a block in a sorted block list.
+///
+/// This returns the index of the first entry, which is not placed left to `block`.
+///
+/// The search is branchless: Instead of bailing out on a match, it always performs `log2(len)`
+/// halvings of the interval, and the halving itself is a conditional move rather than a branch.
+/// This avoids the branch mispredictions, which dominate searching in the hot paths.
+#[inline]
+fn lower_bound(pool: &[Block], block: &Block) -> usize {
+    if pool.is_empty() {
+        return 0;
+    }
+
+    // The start of the interval we are searching.
+    let mut base = 0;
+    // The length of the interval we are searching.
+    let mut len = pool.len();
+
+    while len > 1 {
+        let half = len / 2;
+        let mid = base + half;
+
+        // This compiles to a conditional move.
+        base = if &pool[mid] < block { mid } else { base };
+        len -= half;
+    }
+
+    // Finally, we check the last remaining entry.
+    base + (&pool[base] < block) as usize
+}
+
+/// Is `ind` the lower bound of `block` in `pool`?
+///
+/// This takes no more than two comparisons, making it a cheap way to verify a guess before
+/// falling back to `lower_bound`.
+#[inline]
+fn is_lower_bound(pool: &[Block], ind: usize, block: &Block) -> bool {
+    ind <= pool.len()
+        && (ind == 0 || &pool[ind - 1] < block)
+        && (ind == pool.len() || &pool[ind] >= block)
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    use core::mem;
+
+    use super::{lower_bound, is_lower_bound, segment_of, word_bits, Occupancy, Pool, Position};
+
+    #[test]
+    fn test_lower_bound() {
+        let mut arr = [0u8; 32];
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 32)
+        };
+
+        // Build a pool of non-adjacent blocks.
+        let (a, rest) = block.split(4);
+        let (_, rest) = rest.split(4);
+        let (b, rest) = rest.split(8);
+        let (_, c) = rest.split(8);
+        let pool = [a, b, c];
+
+        assert_eq!(lower_bound(&pool, &pool[0]), 0);
+        assert_eq!(lower_bound(&pool, &pool[1]), 1);
+        assert_eq!(lower_bound(&pool, &pool[0].empty_right()), 1);
+        assert_eq!(lower_bound(&pool, &pool[2]), 2);
+        assert_eq!(lower_bound(&pool, &pool[2].empty_right()), 3);
+        assert_eq!(lower_bound(&[], &pool[2]), 0);
+
+        assert!(is_lower_bound(&pool, 1, &pool[1]));
+        assert!(is_lower_bound(&pool, 3, &pool[2].empty_right()));
+        assert!(!is_lower_bound(&pool, 2, &pool[1]));
+        assert!(!is_lower_bound(&pool, 4, &pool[2]));
+    }
+
+    #[test]
+    fn test_pool() {
+        // The blocks to insert.
+        let mut arr = [0u8; 64];
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 64)
+        };
+        let (a, rest) = block.split(16);
+        let (_, rest) = rest.split(16);
+        let (b, _) = rest.split(16);
+
+        let mut pool: Pool = Pool::new();
+        for i in [&a, &b].iter() {
+            pool.make_room(i);
+        }
+
+        let pos = pool.find(&b);
+        pool.insert(pos, b);
+        let pos = pool.find(&a);
+        assert_eq!(pos.ind, 0);
+        pool.insert(pos, a);
+        pool.check();
+
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.first(), Some(Position { seg: pos.seg, ind: 0 }));
+        assert_eq!(pool.last(), Some(Position { seg: pos.seg, ind: 1 }));
+        assert_eq!(pool.prev(pool.last().unwrap()), pool.first());
+        assert_eq!(segment_of(&pool[pos]), pool.iter().map(segment_of).next().unwrap());
+        assert_eq!(pool.iter().count(), 2);
+
+        assert_eq!(pool.pop().unwrap().size(), 16);
+        assert_eq!(pool.pop().unwrap().size(), 16);
+        assert!(pool.pop().is_none());
+        pool.check();
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let mut buf = [0usize; 4];
+        let mut occupied = Occupancy {
+            words: unsafe {
+                // The buffer outlives the index, and the vector starts out empty.
+                let ptr = Pointer::new(&mut buf[0] as *mut usize as *mut u8);
+                Vec::from_raw_parts(Block::from_raw_parts(ptr, mem::size_of_val(&buf)), 0)
+            },
+        };
+
+        // A long list of segments, most of them empty.
+        let len = 3 * word_bits();
+        for n in 0..len {
+            if occupied.words.len() * word_bits() <= n {
+                occupied.words.push(0).unwrap();
+            }
+            occupied.shift(n);
+        }
+        occupied.set(3, true);
+        occupied.set(2 * word_bits() + 5, true);
+
+        assert_eq!(occupied.next(0), Some(3));
+        assert_eq!(occupied.next(3), Some(3));
+        assert_eq!(occupied.next(4), Some(2 * word_bits() + 5));
+        assert_eq!(occupied.next(2 * word_bits() + 6), None);
+        assert_eq!(occupied.prev(len), Some(2 * word_bits() + 5));
+        assert_eq!(occupied.prev(2 * word_bits() + 5), Some(3));
+        assert_eq!(occupied.prev(3), None);
+
+        // Insert empty segments in front, moving the bits across word boundaries.
+        occupied.words.push(0).unwrap();
+        occupied.shift(0);
+        occupied.set(word_bits() - 1, true);
+        occupied.shift(0);
+        assert!(occupied.get(5) && occupied.get(word_bits()) && occupied.get(2 * word_bits() + 7));
+        assert!(!occupied.get(4) && !occupied.get(0));
+        assert_eq!(occupied.next(6), Some(word_bits()));
+        assert_eq!(occupied.prev(2 * word_bits() + 7), Some(word_bits()));
+
+        occupied.set(word_bits(), false);
+        assert_eq!(occupied.prev(2 * word_bits() + 7), Some(5));
+    }
+}
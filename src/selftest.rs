@@ -0,0 +1,228 @@
+//! Self-testing.
+//!
+//! With the `selftest` feature, `ralloc::selftest` runs randomized allocation patterns against
+//! the live allocator, filling every buffer with a pattern, which is verified before the buffer
+//! is reallocated or freed, and checking the consistency of the allocator as it goes. This is
+//! meant for validating the allocator on new platforms.
+
+use prelude::*;
+
+use core::{cmp, mem, ptr};
+
+use rand::Rng;
+use vec::Vec;
+use {allocator, conf, meta};
+
+/// The configuration of a self-test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTestConfig {
+    /// The number of operations to perform.
+    pub rounds: usize,
+    /// The maximum number of buffers live at once.
+    pub slots: usize,
+    /// The maximum size of the buffers.
+    ///
+    /// Most buffers are much smaller, as sizes are skewed towards small ones.
+    pub max_size: usize,
+    /// The maximum alignment of the buffers (a power of two).
+    pub max_align: usize,
+    /// Perform full consistency checks every this many operations (never, if zero).
+    pub check_interval: usize,
+    /// The seed of the random patterns (random, if zero).
+    ///
+    /// A failing self-test can be reproduced by reusing the seed, at least in a single thread.
+    pub seed: u64,
+}
+
+impl SelfTestConfig {
+    /// The default configuration.
+    pub const fn new() -> SelfTestConfig {
+        SelfTestConfig {
+            rounds: 100000,
+            slots: 256,
+            max_size: 1 << 16,
+            max_align: 64,
+            check_interval: 1000,
+            seed: 0,
+        }
+    }
+}
+
+/// A buffer of the self-test.
+#[derive(Clone, Copy)]
+struct Slot {
+    /// The buffer (null if the slot is unused).
+    ptr: *mut u8,
+    /// The size of the buffer.
+    size: usize,
+    /// The byte, which the buffer is filled with.
+    fill: u8,
+}
+
+impl Slot {
+    /// Fill the buffer with its pattern.
+    unsafe fn fill(&self) {
+        ptr::write_bytes(self.ptr, self.fill, self.size);
+    }
+
+    /// Does the buffer still hold its pattern?
+    ///
+    /// Only the first `len` bytes are verified.
+    unsafe fn verify(&self, len: usize) -> bool {
+        (0..cmp::min(len, self.size)).all(|i| *self.ptr.offset(i as isize) == self.fill)
+    }
+}
+
+/// Run a self-test.
+///
+/// Random buffers are allocated, reallocated (including "realloc storms", where a buffer is grown
+/// step by step), and freed, through the entry points of the crate. The consistency checks are
+/// enabled while the test runs, so violated invariants are handled according to the violation
+/// policy.
+///
+/// To test concurrency, run the self-test from several threads at once.
+///
+/// `Err(())` is returned, if a buffer was found corrupted (i.e. its content changed), which
+/// implies that the allocator handed out overlapping buffers.
+pub fn selftest(config: &SelfTestConfig) -> Result<(), ()> {
+    // Logging.
+    log!(NOTE, "Running a self-test of {} rounds.", config.rounds);
+
+    assert!(config.max_align.is_power_of_two(), "The maximum alignment is not a power of two.");
+
+    let checks = conf::checks();
+    conf::set_checks(true);
+
+    let mut rng = if config.seed == 0 { Rng::new() } else { Rng::with_seed(config.seed) };
+    let mut slots = unsafe {
+        // The block is fresh metadata, and the vector is empty.
+        Vec::from_raw_parts(meta::alloc(config.slots * mem::size_of::<Slot>(),
+                                        mem::align_of::<Slot>()), 0)
+    };
+    for _ in 0..config.slots {
+        slots.push(Slot {
+            ptr: ptr::null_mut(),
+            size: 0,
+            fill: 0,
+        }).expect("The slots were allocated too small.");
+    }
+
+    let res = run(config, &mut rng, &mut slots);
+
+    // Free the buffers left.
+    for slot in slots.iter().filter(|slot| !slot.ptr.is_null()) {
+        unsafe {
+            allocator::free(slot.ptr, slot.size);
+        }
+    }
+    meta::free(Block::from(slots));
+
+    allocator::check();
+    conf::set_checks(checks);
+
+    if res.is_err() {
+        log!(ERROR, "The self-test found a corrupted buffer.");
+    }
+
+    res
+}
+
+/// Get a random size, skewed towards small ones.
+fn size(rng: &mut Rng, max: usize) -> usize {
+    // Pick the order of magnitude first.
+    let bits = mem::size_of::<usize>() * 8 - max.leading_zeros() as usize;
+    let limit = cmp::min(1 << rng.below(bits), max);
+
+    1 + rng.below(limit)
+}
+
+/// Perform the operations of a self-test.
+fn run(config: &SelfTestConfig, rng: &mut Rng, slots: &mut [Slot]) -> Result<(), ()> {
+    if slots.is_empty() || config.max_size == 0 {
+        return Ok(());
+    }
+
+    let align_bits = config.max_align.trailing_zeros() as usize + 1;
+
+    for round in 0..config.rounds {
+        let n = rng.below(slots.len());
+        let mut slot = slots[n];
+
+        let intact = unsafe { step(config, rng, &mut slot, align_bits) };
+        slots[n] = slot;
+
+        if !intact {
+            return Err(());
+        }
+
+        if config.check_interval != 0 && round % config.check_interval == 0 {
+            allocator::check();
+        }
+    }
+
+    Ok(())
+}
+
+/// Perform a random operation on a slot.
+///
+/// `false` is returned, if the buffer was found corrupted.
+unsafe fn step(config: &SelfTestConfig, rng: &mut Rng, slot: &mut Slot, align_bits: usize)
+               -> bool {
+    if slot.ptr.is_null() {
+        // Allocate.
+        let align = 1 << rng.below(align_bits);
+        slot.size = size(rng, config.max_size);
+        slot.ptr = allocator::alloc(slot.size, align);
+        slot.fill = rng.next() as u8;
+        slot.fill();
+
+        return true;
+    }
+
+    if !slot.verify(slot.size) {
+        return false;
+    }
+
+    match rng.below(8) {
+        // Reallocate.
+        0 | 1 => {
+            let align = 1 << rng.below(align_bits);
+            let old_size = slot.size;
+            slot.size = size(rng, config.max_size);
+            slot.ptr = allocator::realloc(slot.ptr, old_size, slot.size, align);
+
+            if !slot.verify(old_size) {
+                return false;
+            }
+            slot.fill();
+        },
+        // Reallocate inplace.
+        2 => {
+            let size = size(rng, config.max_size);
+            if allocator::realloc_inplace(slot.ptr, slot.size, size).is_ok() {
+                slot.size = size;
+                slot.fill();
+            }
+        },
+        // Realloc storm: Grow the buffer step by step.
+        3 => {
+            for _ in 0..16 {
+                let old_size = slot.size;
+                slot.size = cmp::min(old_size + 1 + rng.below(64), config.max_size);
+                slot.ptr = allocator::realloc(slot.ptr, old_size, slot.size, 1);
+
+                if !slot.verify(old_size) {
+                    return false;
+                }
+                slot.fill();
+            }
+        },
+        // Free.
+        _ => {
+            allocator::free(slot.ptr, slot.size);
+            slot.ptr = ptr::null_mut();
+        },
+    }
+
+    true
+}
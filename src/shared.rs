@@ -0,0 +1,604 @@
+//! Shared-memory arenas.
+//!
+//! A shared arena lives in a memory file, which cooperating processes map, each at its own
+//! address. Hence, all of its state lives in the file, and refers to memory by offsets rather
+//! than pointers: The file starts with a header, followed by the free list, followed by the heap.
+//!
+//! The free list is a sorted, coalesced list of free ranges, like the pool of a bookkeeper. The
+//! ranges are turned into blocks of the local mapping to be aligned, split and merged.
+//!
+//! As the state lives in the file, an arena in a regular file persists across restarts. The
+//! state is sealed by a checksum after every operation, so an arena left inconsistent (e.g. by a
+//! crash in the middle of an operation) is detected when it is reopened.
+
+use prelude::*;
+
+use core::{mem, slice};
+use core::sync::atomic::{self, AtomicBool};
+
+use shim::{config, syscalls};
+
+use fail::Error;
+use fence::page_up;
+
+/// The magic number identifying an initialized shared arena.
+const MAGIC: u64 = 0x7261_6c6c_6f63_5348;
+
+/// The header of a shared arena.
+#[repr(C)]
+struct Header {
+    /// The magic number.
+    ///
+    /// This is written last, when the arena has been initialized.
+    magic: u64,
+    /// The lock of the arena.
+    ///
+    /// This is taken by any process operating on the arena.
+    lock: AtomicBool,
+    /// The size of the file.
+    size: usize,
+    /// The number of entries in the free list.
+    len: usize,
+    /// The capacity of the free list.
+    cap: usize,
+    /// The offset of the root allocation (zero if none).
+    root: usize,
+    /// The checksum of the state (see `SharedArena::digest`).
+    checksum: u64,
+}
+
+/// A free range of a shared arena.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Range {
+    /// The offset of the range.
+    offset: usize,
+    /// The size of the range.
+    size: usize,
+}
+
+/// An arena, which can be shared between processes.
+///
+/// Allocations are identified by their offset in the arena, which is the same in every process.
+/// Use `ptr` to get the local address of an allocation.
+pub struct SharedArena {
+    /// The start of the local mapping.
+    base: Pointer<u8>,
+    /// The size of the mapping.
+    size: usize,
+    /// The file descriptor of the memory file.
+    fd: usize,
+}
+
+impl SharedArena {
+    /// Create a shared arena of some size, in a new memory file.
+    ///
+    /// Pass the file descriptor (see `fd`) to the other processes (e.g. through inheritance or a
+    /// UNIX socket), so they can open the arena.
+    ///
+    /// # Errors
+    ///
+    /// `Error::LimitExceeded` is returned, if the size cannot even hold the header, and
+    /// `Error::BreakerFailed` is returned, if the file cannot be created or mapped.
+    pub fn create(size: usize) -> Result<SharedArena, Error> {
+        let size = page_up(size);
+        let heap = heap_offset(config::SHARED_RANGES);
+        if size <= heap {
+            return Err(Error::LimitExceeded);
+        }
+
+        // Logging.
+        log!(NOTE, "Creating a shared arena of {} bytes.", size);
+
+        unsafe {
+            // The file is ours, until it is shared.
+            let fd = syscalls::memfd_create(b"ralloc\0").map_err(Error::BreakerFailed)?;
+
+            SharedArena::init(fd, size, heap)
+        }
+    }
+
+    /// Open the shared arena of some memory file.
+    ///
+    /// The arena takes ownership of the file descriptor (which is closed on failure).
+    ///
+    /// # Errors
+    ///
+    /// `Error::Poisoned` is returned, if the file holds no (consistent) arena, and
+    /// `Error::BreakerFailed` is returned, if it cannot be mapped.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified, other than through shared arenas.
+    pub unsafe fn open(fd: usize) -> Result<SharedArena, Error> {
+        let res = SharedArena::map(fd)?;
+
+        {
+            let _guard = res.lock();
+            if !res.verify() {
+                return Err(Error::Poisoned);
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Open the persistent arena in some file, or create it there, with some size.
+    ///
+    /// `path` must be null-terminated. The allocations (and the root, see `set_root`) survive,
+    /// until the file is deleted.
+    ///
+    /// The arena must not be opened by other processes at the same time, as a lock left behind by
+    /// a crashed process is broken.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Poisoned` is returned, if the file holds no (consistent) arena, but isn't empty.
+    /// `Error::LimitExceeded` is returned, if the file is created, but the size cannot even hold
+    /// the header. `Error::BreakerFailed` is returned, if the file cannot be opened or mapped.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified, other than through shared arenas.
+    pub unsafe fn open_file(path: &[u8], size: usize) -> Result<SharedArena, Error> {
+        debug_assert!(path.last() == Some(&0), "The path is not null-terminated.");
+
+        let fd = syscalls::open(path).map_err(Error::BreakerFailed)?;
+        match syscalls::file_size(fd) {
+            Ok(0) => {
+                let size = page_up(size);
+                let heap = heap_offset(config::SHARED_RANGES);
+                if size <= heap {
+                    syscalls::close(fd);
+                    return Err(Error::LimitExceeded);
+                }
+
+                // Logging.
+                log!(NOTE, "Creating a persistent arena of {} bytes.", size);
+
+                SharedArena::init(fd, size, heap)
+            },
+            Ok(_) => {
+                let res = SharedArena::map(fd)?;
+
+                // Break the lock, which might have been held by a crashed process.
+                (*res.header()).lock.store(false, atomic::Ordering::SeqCst);
+
+                {
+                    let _guard = res.lock();
+                    if !res.verify() {
+                        log!(ERROR, "The persistent arena is inconsistent.");
+                        return Err(Error::Poisoned);
+                    }
+                }
+
+                Ok(res)
+            },
+            Err(err) => {
+                syscalls::close(fd);
+                Err(Error::BreakerFailed(err))
+            },
+        }
+    }
+
+    /// Initialize a new arena in some (empty) file.
+    ///
+    /// The file descriptor is closed on failure.
+    unsafe fn init(fd: usize, size: usize, heap: usize) -> Result<SharedArena, Error> {
+        let base = match syscalls::ftruncate(fd, size)
+            .and_then(|()| syscalls::mmap_shared(fd, size)) {
+            Ok(base) => base,
+            Err(err) => {
+                syscalls::close(fd);
+                return Err(Error::BreakerFailed(err));
+            },
+        };
+
+        let res = SharedArena {
+            base: Pointer::new(base),
+            size: size,
+            fd: fd,
+        };
+
+        // The file is zero-filled, so the lock is released.
+        let header = res.header();
+        (*header).size = size;
+        (*header).len = 1;
+        (*header).cap = config::SHARED_RANGES;
+        res.ranges()[0] = Range {
+            offset: heap,
+            size: size - heap,
+        };
+        res.seal();
+
+        // Publish the arena.
+        atomic::fence(atomic::Ordering::SeqCst);
+        (*header).magic = MAGIC;
+
+        Ok(res)
+    }
+
+    /// Map the arena of some file.
+    ///
+    /// The file descriptor is closed on failure.
+    unsafe fn map(fd: usize) -> Result<SharedArena, Error> {
+        let size = match syscalls::file_size(fd) {
+            Ok(size) => size,
+            Err(err) => {
+                syscalls::close(fd);
+                return Err(Error::BreakerFailed(err));
+            },
+        };
+        if size < mem::size_of::<Header>() {
+            syscalls::close(fd);
+            return Err(Error::Poisoned);
+        }
+
+        // Logging.
+        log!(NOTE, "Opening a shared arena of {} bytes.", size);
+
+        match syscalls::mmap_shared(fd, size) {
+            // From here on, the arena closes the file descriptor.
+            Ok(base) => Ok(SharedArena {
+                base: Pointer::new(base),
+                size: size,
+                fd: fd,
+            }),
+            Err(err) => {
+                syscalls::close(fd);
+                Err(Error::BreakerFailed(err))
+            },
+        }
+    }
+
+    /// Is the state of the arena consistent?
+    ///
+    /// This checks that the header matches the file, the free list is sorted, coalesced, and
+    /// within the heap, and the checksum matches.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held.
+    unsafe fn verify(&self) -> bool {
+        let header = self.header();
+
+        if (*header).magic != MAGIC || (*header).size != self.size
+           || (*header).cap > self.size / mem::size_of::<Range>()
+           || heap_offset((*header).cap) > self.size
+           || (*header).len > (*header).cap || (*header).root >= self.size {
+            return false;
+        }
+
+        let heap = heap_offset((*header).cap);
+        let mut end = heap;
+        for range in self.ranges()[..(*header).len].iter() {
+            // The first range may start at the heap, the others must be apart.
+            if range.size == 0 || range.offset < end || (range.offset == end && end != heap)
+               || range.size > self.size - range.offset {
+                return false;
+            }
+
+            end = range.offset + range.size;
+        }
+
+        (*header).checksum == self.digest()
+    }
+
+    /// Calculate the checksum of the state.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held.
+    unsafe fn digest(&self) -> u64 {
+        let header = self.header();
+
+        // FNV-1a over the words of the state.
+        let mut res = 0xcbf29ce484222325u64;
+        {
+            let mut mix = |x: usize| {
+                res ^= x as u64;
+                res = res.wrapping_mul(0x100000001b3);
+            };
+
+            mix((*header).size);
+            mix((*header).len);
+            mix((*header).cap);
+            mix((*header).root);
+            for range in self.ranges()[..(*header).len].iter() {
+                mix(range.offset);
+                mix(range.size);
+            }
+        }
+
+        res
+    }
+
+    /// Seal the state by updating the checksum.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held.
+    unsafe fn seal(&self) {
+        (*self.header()).checksum = self.digest();
+    }
+
+    /// Get the root allocation.
+    ///
+    /// The root is the entry point to the data of a persistent arena, i.e. the allocation from
+    /// which the others can be found. `None` is returned, if it has not been set.
+    pub fn root(&self) -> Option<usize> {
+        let _guard = self.lock();
+
+        match unsafe { (*self.header()).root } {
+            0 => None,
+            root => Some(root),
+        }
+    }
+
+    /// Set the root allocation.
+    pub fn set_root(&self, offset: usize) {
+        let _guard = self.lock();
+
+        unsafe {
+            // The lock is held.
+            (*self.header()).root = offset;
+            self.seal();
+        }
+    }
+
+    /// Flush the arena to its file.
+    ///
+    /// This makes sure a persistent arena survives e.g. power failures, not only process exits.
+    pub fn sync(&self) -> Result<(), ()> {
+        let _guard = self.lock();
+
+        unsafe {
+            // The mapping is owned by the arena.
+            syscalls::msync(self.base.get(), self.size)
+        }
+    }
+
+    /// Get the file descriptor of the memory file.
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Get the local address of some offset in the arena.
+    pub fn ptr(&self, offset: usize) -> *mut u8 {
+        debug_assert!(offset <= self.size, "The offset, {}, is out of the arena.", offset);
+
+        (self.base.get() as usize + offset) as *mut u8
+    }
+
+    /// Get the offset of some local address in the arena.
+    pub fn offset(&self, ptr: *mut u8) -> usize {
+        debug_assert!(ptr as usize >= self.base.get() as usize
+                      && ptr as usize - (self.base.get() as usize) <= self.size, "The address, \
+                      {:?}, is out of the arena.", ptr);
+
+        ptr as usize - self.base.get() as usize
+    }
+
+    /// Allocate some bytes, aligned to `align`.
+    ///
+    /// The offset of the allocation is returned, or `None`, if the arena has no room for it.
+    pub fn alloc(&self, size: usize, align: usize) -> Option<usize> {
+        // Logging.
+        log!(CALL, "Allocating {} shared bytes (align {}).", size, align);
+
+        if size == 0 || align == 0 {
+            return None;
+        }
+
+        let _guard = self.lock();
+
+        unsafe {
+            // The lock is held.
+            let header = self.header();
+            let ranges = self.ranges();
+
+            for i in 0..(*header).len {
+                let mut block = self.block(ranges[i]);
+                let (aligner, rest) = match block.align(align) {
+                    Some((aligner, rest)) if rest.size() >= size => (aligner, rest),
+                    _ => continue,
+                };
+                let (res, excessive) = rest.split(size);
+
+                // Put the rest of the range back.
+                match (aligner.is_empty(), excessive.is_empty()) {
+                    (true, true) => self.remove(ranges, i),
+                    (false, true) => ranges[i] = self.range(&aligner),
+                    (true, false) => ranges[i] = self.range(&excessive),
+                    (false, false) => {
+                        ranges[i] = self.range(&aligner);
+                        self.insert(ranges, i + 1, &excessive);
+                    },
+                }
+                self.seal();
+
+                return Some(self.offset(Pointer::from(res).get()));
+            }
+        }
+
+        None
+    }
+
+    /// Free an allocation.
+    ///
+    /// The freed range is merged with its neighbors, if adjacent.
+    ///
+    /// # Safety
+    ///
+    /// The allocation must have been made from this arena (by any process), and must not be used
+    /// afterwards.
+    pub unsafe fn free(&self, offset: usize, size: usize) {
+        // Logging.
+        log!(CALL, "Freeing {} shared bytes at offset {}.", size, offset);
+
+        if size == 0 {
+            return;
+        }
+
+        let _guard = self.lock();
+
+        let header = self.header();
+        let ranges = self.ranges();
+        let mut block = self.block(Range {
+            offset: offset,
+            size: size,
+        });
+
+        // Find the position of the block.
+        let len = (*header).len;
+        let pos = ranges[..len].iter().position(|x| x.offset > offset).unwrap_or(len);
+
+        // Check the neighbors before touching anything, so a violation leaves the state intact.
+        if pos < len && !invariant!(block.empty_right() <= self.block(ranges[pos]), "shared free",
+                                    Some(&block), "The block overlaps its right neighbor") {
+            return;
+        }
+        if pos > 0 && !invariant!(self.block(ranges[pos - 1]).empty_right() <= block,
+                                  "shared free", Some(&block),
+                                  "The block overlaps its left neighbor") {
+            return;
+        }
+
+        // Merge it with its neighbors.
+        if pos < len {
+            let mut right = self.block(ranges[pos]);
+            if block.merge_right(&mut right).is_ok() {
+                self.remove(ranges, pos);
+            }
+        }
+        let mut merged = false;
+        if pos > 0 {
+            let mut left = self.block(ranges[pos - 1]);
+            if left.merge_right(&mut block).is_ok() {
+                ranges[pos - 1] = self.range(&left);
+                merged = true;
+            }
+        }
+        if !merged {
+            self.insert(ranges, pos, &block);
+        }
+
+        self.seal();
+    }
+
+    /// Lock the arena.
+    fn lock(&self) -> Guard {
+        let lock = unsafe {
+            // The header is mapped as long as the arena lives.
+            &(*self.header()).lock
+        };
+
+        while lock.compare_and_swap(false, true, atomic::Ordering::SeqCst) {
+            syscalls::sched_yield();
+        }
+
+        Guard {
+            lock: lock,
+        }
+    }
+
+    /// Get the header.
+    fn header(&self) -> *mut Header {
+        self.base.get() as *mut Header
+    }
+
+    /// Get the free list.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held, and the list must not be borrowed elsewhere.
+    unsafe fn ranges(&self) -> &mut [Range] {
+        slice::from_raw_parts_mut(self.header().offset(1) as *mut Range, (*self.header()).cap)
+    }
+
+    /// Get the local block of a range.
+    ///
+    /// # Safety
+    ///
+    /// The range must be in the arena.
+    unsafe fn block(&self, range: Range) -> Block {
+        Block::from_raw_parts(Pointer::new(self.ptr(range.offset)), range.size)
+    }
+
+    /// Get the range of a local block.
+    fn range(&self, block: &Block) -> Range {
+        Range {
+            offset: self.offset(block.addr() as *mut u8),
+            size: block.size(),
+        }
+    }
+
+    /// Insert a range into the free list.
+    ///
+    /// If the free list is full, the range is leaked.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held.
+    unsafe fn insert(&self, ranges: &mut [Range], pos: usize, block: &Block) {
+        let header = self.header();
+        let len = (*header).len;
+
+        if len == ranges.len() {
+            log!(WARNING, "The free list of the shared arena is full, leaking {:?}.", block);
+            return;
+        }
+
+        for i in (pos..len).rev() {
+            ranges[i + 1] = ranges[i];
+        }
+        ranges[pos] = self.range(block);
+        (*header).len = len + 1;
+    }
+
+    /// Remove a range from the free list.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held.
+    unsafe fn remove(&self, ranges: &mut [Range], pos: usize) {
+        let header = self.header();
+        let len = (*header).len;
+
+        for i in pos..len - 1 {
+            ranges[i] = ranges[i + 1];
+        }
+        (*header).len = len - 1;
+    }
+}
+
+impl Drop for SharedArena {
+    fn drop(&mut self) {
+        unsafe {
+            // The mapping and the file descriptor are owned by the arena.
+            let _ = syscalls::munmap(self.base.get(), self.size);
+            syscalls::close(self.fd);
+        }
+    }
+}
+
+unsafe impl Sync for SharedArena {}
+
+/// Get the offset of the heap of an arena, whose free list has some capacity.
+///
+/// The header and the free list precede the heap.
+fn heap_offset(cap: usize) -> usize {
+    page_up(mem::size_of::<Header>() + cap * mem::size_of::<Range>())
+}
+
+/// A lock on a shared arena.
+struct Guard<'a> {
+    /// The lock.
+    lock: &'a AtomicBool,
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        self.lock.store(false, atomic::Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,95 @@
+//! Simulated memory.
+//!
+//! Under `cargo test`, memory is taken from a big static array rather than the OS. Both the
+//! metadata and the `Simulated` breaker are served from it, so the bookkeeping logic can be
+//! exercised without performing syscalls (e.g. under Miri).
+
+use prelude::*;
+
+use core::sync::atomic::{self, AtomicUsize};
+
+use breaker::{Breaker, Fixed};
+
+/// The size of the simulated memory.
+const SIZE: usize = 1 << 26;
+
+/// The simulated memory.
+static mut MEMORY: [u8; SIZE] = [0; SIZE];
+/// The number of bytes of the simulated memory handed out.
+static USED: AtomicUsize = AtomicUsize::new(0);
+
+/// Take a region of simulated memory.
+///
+/// The region is aligned to `align`.
+///
+/// # Panics
+///
+/// This panics if the simulated memory is exhausted.
+pub fn region(size: usize, align: usize) -> Block {
+    // Take room for aligning the region as well.
+    let start = USED.fetch_add(size + align, atomic::Ordering::SeqCst);
+    assert!(start + size + align <= SIZE, "The simulated memory is exhausted.");
+
+    let mut block = unsafe {
+        // The region is within the array, and is handed out only this once.
+        Block::from_raw_parts(Pointer::new(MEMORY.as_mut_ptr().offset(start as isize)),
+                              size + align)
+    };
+
+    let (_, res) = block.align(align).expect("The region has no room for aligning.");
+    res.split(size).0
+}
+
+/// A breaker serving simulated memory.
+///
+/// Each breaker takes a region of simulated memory when created, and hands it out like `Fixed`.
+pub struct Simulated {
+    /// The breaker of the region.
+    inner: Fixed,
+}
+
+impl Simulated {
+    /// Create a breaker of `size` bytes of simulated memory.
+    pub fn new(size: usize) -> Simulated {
+        let region = region(size, 1);
+
+        Simulated {
+            inner: unsafe {
+                // The region is not used by anything else.
+                Fixed::from_raw_parts(Pointer::from(region).get(), size)
+            },
+        }
+    }
+}
+
+unsafe impl Breaker for Simulated {
+    #[inline]
+    fn fresh(&mut self, size: usize) -> Result<(*mut u8, usize), usize> {
+        self.inner.fresh(size)
+    }
+
+    #[inline]
+    fn release(&mut self, ptr: *mut u8, size: usize) -> Result<(), ()> {
+        self.inner.release(ptr, size)
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<*mut u8> {
+        self.inner.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_region() {
+        let a = region(100, 64);
+        let b = region(100, 64);
+
+        assert!(a.aligned_to(64));
+        assert!(b.aligned_to(64));
+        assert!(a.addr() + a.size() <= b.addr());
+    }
+}
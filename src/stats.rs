@@ -0,0 +1,442 @@
+//! Memory usage statistics.
+//!
+//! Two quantities are tracked, along with their peaks: The bytes in use (allocated through the
+//! entry points of the crate, not the arenas, and not freed), and the extent of the heap (the
+//! bytes the global allocator has taken from the OS, and not given back). The peaks can be reset,
+//! e.g. to measure the footprint of each phase of a benchmark. The operations are counted as
+//! well, so their rates can be derived (see the `metrics` module), and so are the live
+//! allocations of each size class, so a snapshot taken before some phase can be compared with
+//! one taken after it. Finally, the merges and splits of the free blocks are counted, to evaluate
+//! changes of the policies of the pool (e.g. the minimum size of a split) on real workloads.
+//!
+//! The counters of the operations are updated with relaxed atomics, so they cost no lock. With the
+//! `stats` feature, they are sharded as well: Each thread counts into one of `SHARDS` shards, and
+//! the shards are summed up on read, so threads allocating at once do not contend on the counters.
+//! The bytes in use and the extent of the heap stay single counters, as their peaks follow them.
+
+use core::{cmp, isize, mem};
+use core::convert::TryFrom;
+use core::sync::atomic::{self, AtomicUsize};
+
+use hook::Event;
+
+#[cfg(feature = "stats")]
+use cell::MoveCell;
+#[cfg(feature = "stats")]
+use tls;
+
+/// The number of bytes in use.
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+/// The peak number of bytes in use.
+static PEAK_IN_USE: AtomicUsize = AtomicUsize::new(0);
+/// The extent of the heap.
+static EXTENT: AtomicUsize = AtomicUsize::new(0);
+/// The peak extent of the heap.
+static PEAK_EXTENT: AtomicUsize = AtomicUsize::new(0);
+/// The shards of the operation counters.
+#[cfg(feature = "stats")]
+static COUNTERS: [Shard; SHARDS] = [Shard::new(), Shard::new(), Shard::new(), Shard::new(),
+                                    Shard::new(), Shard::new(), Shard::new(), Shard::new()];
+/// The operation counters.
+///
+/// Without the `stats` feature, all threads share a single shard.
+#[cfg(not(feature = "stats"))]
+static COUNTERS: [Shard; 1] = [Shard::new()];
+/// The shard, which is given to the next thread.
+#[cfg(feature = "stats")]
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "stats")]
+tls! {
+    /// The shard of the thread (`SHARDS` if the thread has none yet).
+    static THREAD_SHARD: MoveCell<usize> = MoveCell::new(SHARDS);
+}
+
+/// The number of shards of the operation counters, with the `stats` feature.
+pub const SHARDS: usize = 8;
+
+/// The number of size classes of the live allocations.
+///
+/// Class `n` holds the allocations of `2^n` to `2^(n + 1) - 1` bytes (and class 0 the empty
+/// ones), except for the last class, which holds all the larger allocations as well.
+pub const CLASSES: usize = 32;
+
+/// Counters of each size class, all at zero.
+const NO_CLASSES: [AtomicUsize; CLASSES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// The operation counters of some threads.
+struct Shard {
+    /// The number of allocations.
+    allocs: AtomicUsize,
+    /// The number of frees.
+    frees: AtomicUsize,
+    /// The number of reallocations.
+    reallocs: AtomicUsize,
+    /// The number of live allocations of each size class.
+    ///
+    /// The count of a class might be negative (wrapped around) in some shard, as a buffer might
+    /// be freed by another thread than the one allocating it.
+    live: [AtomicUsize; CLASSES],
+    /// The number of allocations of each size class.
+    ///
+    /// Reallocations moving a buffer to another class count as allocations of that class.
+    class_allocs: [AtomicUsize; CLASSES],
+    /// The number of free blocks merged with a neighbor.
+    merges: AtomicUsize,
+    /// The number of free blocks, which had no neighbor to merge with.
+    failed_merges: AtomicUsize,
+    /// The number of free blocks split, with a part going back to the pool.
+    splits: AtomicUsize,
+    /// The number of stubs (see `PoolOp::Stub`) created by splits.
+    stubs: AtomicUsize,
+    /// Padding, so neighboring shards share no cache line.
+    _pad: [usize; 8],
+}
+
+impl Shard {
+    /// Create a shard with all the counters at zero.
+    const fn new() -> Shard {
+        Shard {
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            reallocs: AtomicUsize::new(0),
+            live: NO_CLASSES,
+            class_allocs: NO_CLASSES,
+            merges: AtomicUsize::new(0),
+            failed_merges: AtomicUsize::new(0),
+            splits: AtomicUsize::new(0),
+            stubs: AtomicUsize::new(0),
+            _pad: [0; 8],
+        }
+    }
+}
+
+/// Get the shard of the current thread.
+#[cfg(feature = "stats")]
+#[inline]
+fn shard() -> &'static Shard {
+    let n = THREAD_SHARD.with(|shard| {
+        let mut n = shard.replace(SHARDS);
+        if n == SHARDS {
+            // The threads are spread over the shards in turn.
+            n = NEXT_SHARD.fetch_add(1, atomic::Ordering::Relaxed) % SHARDS;
+        }
+        shard.replace(n);
+
+        n
+    });
+
+    &COUNTERS[n]
+}
+
+/// Get the shard of the current thread.
+///
+/// Without the `stats` feature, this is the only shard.
+#[cfg(not(feature = "stats"))]
+#[inline]
+fn shard() -> &'static Shard {
+    &COUNTERS[0]
+}
+
+/// Sum up some counter over the shards.
+#[inline]
+fn sum<F: Fn(&Shard) -> &AtomicUsize>(counter: F) -> usize {
+    COUNTERS.iter().fold(0, |acc, shard| {
+        acc.wrapping_add(counter(shard).load(atomic::Ordering::Relaxed))
+    })
+}
+
+/// The memory usage of the program.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Usage {
+    /// The number of bytes in use.
+    pub in_use: usize,
+    /// The peak number of bytes in use, since the start or the last `reset_peak`.
+    pub peak_in_use: usize,
+    /// The number of bytes taken from the OS by the global allocator.
+    pub extent: usize,
+    /// The peak number of bytes taken from the OS, since the start or the last `reset_peak`.
+    pub peak_extent: usize,
+}
+
+/// The number of operations performed through the entry points, since the start.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Operations {
+    /// The number of allocations.
+    pub allocs: usize,
+    /// The number of frees.
+    pub frees: usize,
+    /// The number of reallocations (including the inplace ones).
+    pub reallocs: usize,
+}
+
+/// The number of operations on the free blocks of the pools, since the start.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PoolOperations {
+    /// The number of free blocks merged with a neighbor.
+    pub merges: usize,
+    /// The number of free blocks, which had no neighbor to merge with.
+    ///
+    /// This counts freed blocks inserted on their own, and inplace reallocations finding no free
+    /// block to grow into.
+    pub failed_merges: usize,
+    /// The number of free blocks split, with a part going back to the pool.
+    pub splits: usize,
+    /// The number of stubs created by splits (see `PoolOp::Stub`).
+    pub stubs: usize,
+}
+
+/// An operation on the free blocks of a pool.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PoolOp {
+    /// A free block was merged with a neighbor.
+    Merge,
+    /// A free block had no neighbor to merge with.
+    FailedMerge,
+    /// A free block was split, and a part went back to the pool.
+    Split,
+    /// A split left a free block smaller than `config::MIN_SPLIT` in the pool.
+    ///
+    /// Such stubs are too small to serve most requests, and fragment the pool.
+    Stub,
+}
+
+/// A snapshot of the statistics.
+///
+/// Take one before and one after some phase of the program, and compare them with `diff`, to see
+/// how the phase changed the heap.
+#[derive(Clone, Copy)]
+pub struct Stats {
+    /// The memory usage.
+    pub usage: Usage,
+    /// The number of operations.
+    pub operations: Operations,
+    /// The number of live allocations of each size class (see `CLASSES`).
+    pub live: [usize; CLASSES],
+}
+
+impl Stats {
+    /// Take a snapshot of the statistics.
+    ///
+    /// Like `peak`, the counters are read without synchronization, so under concurrent
+    /// allocation, the values might be slightly off each other.
+    pub fn snapshot() -> Stats {
+        let mut live = [0; CLASSES];
+        for (class, n) in live.iter_mut().enumerate() {
+            *n = sum(|shard| &shard.live[class]);
+        }
+
+        Stats {
+            usage: peak(),
+            operations: operations(),
+            live: live,
+        }
+    }
+
+    /// Get the number of live allocations.
+    pub fn live_allocations(&self) -> usize {
+        self.live.iter().fold(0, |acc, &n| acc.wrapping_add(n))
+    }
+
+    /// Compare this snapshot with a later one.
+    ///
+    /// The differences are positive, where the later snapshot has more.
+    pub fn diff(&self, later: &Stats) -> Delta {
+        let mut live = [0; CLASSES];
+        for class in 0..CLASSES {
+            live[class] = change(self.live[class], later.live[class]);
+        }
+
+        Delta {
+            in_use: change(self.usage.in_use, later.usage.in_use),
+            extent: change(self.usage.extent, later.usage.extent),
+            live_allocations: change(self.live_allocations(), later.live_allocations()),
+            allocs: later.operations.allocs.wrapping_sub(self.operations.allocs),
+            frees: later.operations.frees.wrapping_sub(self.operations.frees),
+            reallocs: later.operations.reallocs.wrapping_sub(self.operations.reallocs),
+            live: live,
+        }
+    }
+}
+
+/// Get the signed change of a counter from `old` to `new`.
+///
+/// The change saturates at the bounds of `isize`.
+fn change(old: usize, new: usize) -> isize {
+    if new >= old {
+        isize::try_from(new - old).unwrap_or(isize::MAX)
+    } else {
+        isize::try_from(old - new).map(|x| -x).unwrap_or(isize::MIN)
+    }
+}
+
+/// The differences between two snapshots of the statistics.
+#[derive(Clone, Copy)]
+pub struct Delta {
+    /// The change in bytes in use.
+    pub in_use: isize,
+    /// The change in bytes taken from the OS.
+    pub extent: isize,
+    /// The change in live allocations.
+    pub live_allocations: isize,
+    /// The number of allocations in between.
+    pub allocs: usize,
+    /// The number of frees in between.
+    pub frees: usize,
+    /// The number of reallocations in between.
+    pub reallocs: usize,
+    /// The change in live allocations of each size class.
+    pub live: [isize; CLASSES],
+}
+
+/// Get the size class of an allocation.
+#[inline]
+fn class(size: usize) -> usize {
+    if size == 0 {
+        0
+    } else {
+        cmp::min(mem::size_of::<usize>() * 8 - 1 - size.leading_zeros() as usize, CLASSES - 1)
+    }
+}
+
+/// Add to some counter, raising its peak.
+#[inline]
+fn add(counter: &AtomicUsize, peak: &AtomicUsize, n: usize) {
+    let value = counter.fetch_add(n, atomic::Ordering::Relaxed) + n;
+
+    let mut old = peak.load(atomic::Ordering::Relaxed);
+    while value > old {
+        let prev = peak.compare_and_swap(old, value, atomic::Ordering::Relaxed);
+        if prev == old {
+            break;
+        }
+
+        old = prev;
+    }
+}
+
+/// Subtract from some counter.
+#[inline]
+fn sub(counter: &AtomicUsize, n: usize) {
+    counter.fetch_sub(n, atomic::Ordering::Relaxed);
+}
+
+/// Account for an operation performed through the entry points.
+#[inline]
+pub fn record(event: &Event) {
+    let shard = shard();
+
+    match *event {
+        Event::Alloc { size, .. } => {
+            shard.allocs.fetch_add(1, atomic::Ordering::Relaxed);
+            add(&IN_USE, &PEAK_IN_USE, size);
+            shard.live[class(size)].fetch_add(1, atomic::Ordering::Relaxed);
+            shard.class_allocs[class(size)].fetch_add(1, atomic::Ordering::Relaxed);
+        },
+        Event::Free { size, .. } => {
+            shard.frees.fetch_add(1, atomic::Ordering::Relaxed);
+            sub(&IN_USE, size);
+            shard.live[class(size)].fetch_sub(1, atomic::Ordering::Relaxed);
+        },
+        Event::Realloc { old_size, size, .. } => {
+            shard.reallocs.fetch_add(1, atomic::Ordering::Relaxed);
+            if class(size) != class(old_size) {
+                shard.live[class(old_size)].fetch_sub(1, atomic::Ordering::Relaxed);
+                shard.live[class(size)].fetch_add(1, atomic::Ordering::Relaxed);
+                shard.class_allocs[class(size)].fetch_add(1, atomic::Ordering::Relaxed);
+            }
+            if size > old_size {
+                add(&IN_USE, &PEAK_IN_USE, size - old_size);
+            } else {
+                sub(&IN_USE, old_size - size);
+            }
+        },
+    }
+}
+
+/// Account for an operation on the free blocks of a pool.
+#[inline]
+pub fn record_pool(op: PoolOp) {
+    let shard = shard();
+    let counter = match op {
+        PoolOp::Merge => &shard.merges,
+        PoolOp::FailedMerge => &shard.failed_merges,
+        PoolOp::Split => &shard.splits,
+        PoolOp::Stub => &shard.stubs,
+    };
+
+    counter.fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Account for the heap growing by `size` bytes.
+#[inline]
+pub fn grow_heap(size: usize) {
+    add(&EXTENT, &PEAK_EXTENT, size);
+}
+
+/// Account for the heap shrinking by `size` bytes.
+#[inline]
+pub fn shrink_heap(size: usize) {
+    sub(&EXTENT, size);
+}
+
+/// Get the current and peak memory usage.
+///
+/// The counters are updated without synchronization, so under concurrent allocation, the values
+/// might be slightly off each other.
+pub fn peak() -> Usage {
+    Usage {
+        in_use: IN_USE.load(atomic::Ordering::Relaxed),
+        peak_in_use: PEAK_IN_USE.load(atomic::Ordering::Relaxed),
+        extent: EXTENT.load(atomic::Ordering::Relaxed),
+        peak_extent: PEAK_EXTENT.load(atomic::Ordering::Relaxed),
+    }
+}
+
+/// Get the number of operations performed through the entry points.
+///
+/// With the `stats` feature, the shards are summed up, so this is somewhat slower.
+pub fn operations() -> Operations {
+    Operations {
+        allocs: sum(|shard| &shard.allocs),
+        frees: sum(|shard| &shard.frees),
+        reallocs: sum(|shard| &shard.reallocs),
+    }
+}
+
+/// Get the number of merges and splits of the free blocks of the pools.
+///
+/// Like `operations`, the shards are summed up with the `stats` feature.
+pub fn pool_operations() -> PoolOperations {
+    PoolOperations {
+        merges: sum(|shard| &shard.merges),
+        failed_merges: sum(|shard| &shard.failed_merges),
+        splits: sum(|shard| &shard.splits),
+        stubs: sum(|shard| &shard.stubs),
+    }
+}
+
+/// Get the statistics of a size class (see `CLASSES`).
+///
+/// The number of allocations of the class, and of the live allocations are returned. The number
+/// of frees is their difference.
+pub fn class_stats(class: usize) -> (usize, usize) {
+    (sum(|shard| &shard.class_allocs[class]), sum(|shard| &shard.live[class]))
+}
+
+/// Reset the peaks to the current memory usage.
+pub fn reset_peak() {
+    // Logging.
+    log!(NOTE, "Resetting the peak memory usage.");
+
+    PEAK_IN_USE.store(IN_USE.load(atomic::Ordering::Relaxed), atomic::Ordering::Relaxed);
+    PEAK_EXTENT.store(EXTENT.load(atomic::Ordering::Relaxed), atomic::Ordering::Relaxed);
+}
@@ -0,0 +1,127 @@
+//! Storage of the block pools.
+//!
+//! The metadata of a pool (the segment list, the block lists of the segments, and the leaves of
+//! the radix map) is taken from a `PoolStorage`, chosen by the policy of the bookkeeper (see
+//! `policy::Policy`). `MetaStorage` takes it from the metadata arena, which maps chunks as the
+//! pools grow. `StaticStorage` carves it from a fixed buffer in the data segment instead, so the
+//! bookkeeping never maps memory, nor extends the program break, at the cost of a bounded number
+//! of free blocks.
+
+use prelude::*;
+
+use core::sync::atomic::{self, AtomicBool};
+
+use shim::config;
+
+use fail;
+use meta::{self, MetaArena};
+
+/// A source of metadata for the block pools.
+///
+/// # Safety
+///
+/// The pieces given out by `alloc` must be valid for reads and writes, and must not overlap any
+/// other piece given out (unless freed in the meantime).
+pub unsafe trait PoolStorage {
+    /// Allocate a piece of at least `size` bytes, aligned to `align`.
+    ///
+    /// The start and the size of the piece are returned, or `None` if the storage is exhausted.
+    fn alloc(size: usize, align: usize) -> Option<(*mut u8, usize)>;
+
+    /// Give back a piece allocated through `alloc`.
+    ///
+    /// # Safety
+    ///
+    /// The piece must be unused, and `size` must be the size returned by `alloc`.
+    unsafe fn free(ptr: *mut u8, size: usize);
+}
+
+/// Take the metadata from the metadata arena (see the `meta` module).
+///
+/// This grows as needed, and is the default.
+pub struct MetaStorage;
+
+unsafe impl PoolStorage for MetaStorage {
+    #[inline]
+    fn alloc(size: usize, align: usize) -> Option<(*mut u8, usize)> {
+        let piece = meta::alloc(size, align);
+        let size = piece.size();
+
+        Some((Pointer::from(piece).get(), size))
+    }
+
+    #[inline]
+    unsafe fn free(ptr: *mut u8, size: usize) {
+        meta::free(Block::from_raw_parts(Pointer::new(ptr), size));
+    }
+}
+
+/// Take the metadata from a fixed buffer of `config::STATIC_META_SIZE` bytes.
+///
+/// The buffer is shared by all the pools using this storage. When it is exhausted, the OOM
+/// handler is called.
+pub struct StaticStorage;
+
+/// The buffer of `StaticStorage`.
+static mut STATIC_BUF: [u64; config::STATIC_META_SIZE / 8] = [0; config::STATIC_META_SIZE / 8];
+/// Has the buffer been given to the arena yet?
+static STATIC_FILLED: AtomicBool = AtomicBool::new(false);
+/// The arena carving the pieces of `StaticStorage`.
+static STATIC_ARENA: Mutex<MetaArena> = Mutex::new(MetaArena::new());
+
+unsafe impl PoolStorage for StaticStorage {
+    fn alloc(size: usize, align: usize) -> Option<(*mut u8, usize)> {
+        let mut arena = STATIC_ARENA.lock();
+
+        if !STATIC_FILLED.swap(true, atomic::Ordering::SeqCst) {
+            arena.refill(unsafe {
+                // The buffer is only ever handed out through the arena.
+                Block::from_raw_parts(Pointer::new(STATIC_BUF.as_mut_ptr() as *mut u8),
+                                      config::STATIC_META_SIZE)
+            }, align);
+        }
+
+        arena.alloc(size).map(|piece| {
+            debug_assert!(piece.aligned_to(align), "The static metadata is misaligned.");
+
+            let size = piece.size();
+            (Pointer::from(piece).get(), size)
+        })
+    }
+
+    unsafe fn free(ptr: *mut u8, size: usize) {
+        STATIC_ARENA.lock().free(Block::from_raw_parts(Pointer::new(ptr), size));
+    }
+}
+
+/// Allocate a piece of metadata from some storage.
+///
+/// # Failure
+///
+/// The OOM handler is called, if the storage is exhausted.
+pub fn alloc<S: PoolStorage>(size: usize, align: usize) -> Block {
+    match S::alloc(size, align) {
+        Some((ptr, size)) => unsafe {
+            // The storage guarantees that the piece is valid and unused.
+            Block::from_raw_parts(Pointer::new(ptr), size)
+        },
+        None => fail::oom(fail::Error::OutOfMemory {
+            requested: size,
+            available: 0,
+        }),
+    }
+}
+
+/// Give a piece of metadata back to its storage.
+///
+/// Empty blocks (e.g. of unallocated lists) are ignored.
+pub fn free<S: PoolStorage>(block: Block) {
+    if !block.is_empty() {
+        let size = block.size();
+
+        unsafe {
+            // The block was allocated through `alloc`.
+            S::free(Pointer::from(block).get(), size);
+        }
+    }
+}
@@ -1,10 +1,165 @@
 //! Synchronization primitives.
+//!
+//! The mutexes are spinlocks by default. With the `futex_lock` feature, they spin briefly, and
+//! then park the thread on a futex, which behaves better under contention.
 
 use core::cell::UnsafeCell;
-use core::sync::atomic::{self, AtomicBool};
+use core::sync::atomic;
+#[cfg(not(feature = "futex_lock"))]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "futex_lock")]
+use core::sync::atomic::{AtomicU32, AtomicUsize};
+#[cfg(feature = "futex_lock")]
+use core::cmp;
 use core::ops;
 
+#[cfg(any(not(any(feature = "enclave", feature = "seccomp")), feature = "futex_lock"))]
 use shim;
+#[cfg(feature = "futex_lock")]
+use shim::config;
+
+/// The lock of the mutexes.
+#[cfg(not(feature = "futex_lock"))]
+type RawLock = SpinLock;
+/// The lock of the mutexes.
+#[cfg(feature = "futex_lock")]
+type RawLock = FutexLock;
+
+/// A spinlock.
+///
+/// Waiting threads yield their time slice, and try again.
+#[cfg(not(feature = "futex_lock"))]
+pub struct SpinLock {
+    /// The lock boolean.
+    ///
+    /// This is true, if and only if the lock is currently held.
+    locked: AtomicBool,
+}
+
+#[cfg(not(feature = "futex_lock"))]
+impl SpinLock {
+    /// Create a new, unlocked spinlock.
+    #[inline]
+    pub const fn new() -> SpinLock {
+        SpinLock {
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Acquire the lock.
+    #[inline]
+    pub fn lock(&self) {
+        while self.locked.compare_and_swap(false, true, atomic::Ordering::SeqCst) {
+            // ,___,
+            // {O,o}
+            // |)``)
+            // SRSLY?
+            #[cfg(not(any(feature = "enclave", feature = "seccomp")))]
+            shim::syscalls::sched_yield();
+        }
+    }
+
+    /// Acquire the lock, if it is not held.
+    ///
+    /// `true` is returned, if the lock was acquired.
+    #[inline]
+    pub fn try_lock(&self) -> bool {
+        !self.locked.compare_and_swap(false, true, atomic::Ordering::SeqCst)
+    }
+
+    /// Release the lock.
+    #[inline]
+    pub fn unlock(&self) {
+        self.locked.store(false, atomic::Ordering::SeqCst);
+    }
+}
+
+/// The state of an unlocked futex lock.
+#[cfg(feature = "futex_lock")]
+const UNLOCKED: u32 = 0;
+/// The state of a futex lock, which is held, and has no waiters.
+#[cfg(feature = "futex_lock")]
+const LOCKED: u32 = 1;
+/// The state of a futex lock, which is held, and might have waiters.
+#[cfg(feature = "futex_lock")]
+const CONTENDED: u32 = 2;
+
+/// An adaptive lock, spinning briefly, and then parking the thread on a futex.
+///
+/// The number of spins adapts to how long the lock took to acquire recently (like glibc's
+/// adaptive mutexes), so locks held briefly are taken without sleeping, while the waiters of
+/// locks held long don't burn the CPU.
+#[cfg(feature = "futex_lock")]
+pub struct FutexLock {
+    /// The state (`UNLOCKED`, `LOCKED` or `CONTENDED`).
+    state: AtomicU32,
+    /// The running average of the spins it took to acquire the lock.
+    spins: AtomicUsize,
+}
+
+#[cfg(feature = "futex_lock")]
+impl FutexLock {
+    /// Create a new, unlocked futex lock.
+    #[inline]
+    pub const fn new() -> FutexLock {
+        FutexLock {
+            state: AtomicU32::new(UNLOCKED),
+            spins: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquire the lock.
+    #[inline]
+    pub fn lock(&self) {
+        if self.state.compare_and_swap(UNLOCKED, LOCKED, atomic::Ordering::SeqCst) != UNLOCKED {
+            self.lock_slow();
+        }
+    }
+
+    /// Acquire the lock, if it is not held.
+    ///
+    /// `true` is returned, if the lock was acquired.
+    #[inline]
+    pub fn try_lock(&self) -> bool {
+        self.state.compare_and_swap(UNLOCKED, LOCKED, atomic::Ordering::SeqCst) == UNLOCKED
+    }
+
+    /// Acquire the lock, which is held elsewhere.
+    #[cold]
+    fn lock_slow(&self) {
+        let spins = self.spins.load(atomic::Ordering::Relaxed);
+        let max = cmp::min(config::LOCK_SPINS, spins * 2 + 10);
+
+        for n in 0..max {
+            if self.state.compare_and_swap(UNLOCKED, LOCKED, atomic::Ordering::SeqCst) == UNLOCKED {
+                // Move the average an eighth towards this acquisition.
+                self.spins.store(spins - spins / 8 + n / 8, atomic::Ordering::Relaxed);
+                return;
+            }
+        }
+        self.spins.store(spins - spins / 8 + max / 8, atomic::Ordering::Relaxed);
+
+        // Mark the lock contended, so the holder wakes us, and sleep.
+        while self.state.swap(CONTENDED, atomic::Ordering::SeqCst) != UNLOCKED {
+            unsafe {
+                // The state outlives the wait.
+                shim::syscalls::futex_wait(&self.state as *const AtomicU32 as *const u32,
+                                           CONTENDED);
+            }
+        }
+    }
+
+    /// Release the lock.
+    #[inline]
+    pub fn unlock(&self) {
+        if self.state.swap(UNLOCKED, atomic::Ordering::SeqCst) == CONTENDED {
+            unsafe {
+                // The state outlives the wake.
+                shim::syscalls::futex_wake(&self.state as *const AtomicU32 as *const u32, 1);
+            }
+        }
+    }
+}
 
 /// A mutual exclusive container.
 ///
@@ -14,10 +169,8 @@ use shim;
 pub struct Mutex<T> {
     /// The inner value.
     inner: UnsafeCell<T>,
-    /// The lock boolean.
-    ///
-    /// This is true, if and only if the lock is currently held.
-    locked: AtomicBool,
+    /// The lock.
+    lock: RawLock,
 }
 
 impl<T> Mutex<T> {
@@ -26,7 +179,7 @@ impl<T> Mutex<T> {
     pub const fn new(inner: T) -> Mutex<T> {
         Mutex {
             inner: UnsafeCell::new(inner),
-            locked: AtomicBool::new(false),
+            lock: RawLock::new(),
         }
     }
 
@@ -37,18 +190,49 @@ impl<T> Mutex<T> {
     pub fn lock(&self) -> MutexGuard<T> {
         // Lock the mutex.
         #[cfg(not(feature = "unsafe_no_mutex_lock"))]
-        while self.locked.compare_and_swap(false, true, atomic::Ordering::SeqCst) {
-            // ,___,
-            // {O,o}
-            // |)``)
-            // SRSLY?
-            shim::syscalls::sched_yield();
-        }
+        self.lock.lock();
 
         MutexGuard {
             mutex: self,
         }
     }
+
+    /// Lock this mutex, if it is not locked.
+    ///
+    /// `None` is returned, if another lock is held. This never blocks, so it is meant for
+    /// diagnostics in situations where locking could deadlock (e.g. OOM handlers).
+    #[inline]
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if cfg!(feature = "unsafe_no_mutex_lock") || self.lock.try_lock() {
+            Some(MutexGuard {
+                mutex: self,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Get the inner value without locking.
+    ///
+    /// # Safety
+    ///
+    /// The value might be mutated concurrently. This is meant for diagnostics in situations where
+    /// locking could deadlock (e.g. signal handlers).
+    #[inline]
+    pub unsafe fn peek(&self) -> &T {
+        &*self.inner.get()
+    }
+
+    /// Release the lock, without a guard.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by a guard, which was forgotten (e.g. to hold the lock across calls
+    /// from C), and is not used anymore.
+    #[inline]
+    pub unsafe fn force_unlock(&self) {
+        self.lock.unlock();
+    }
 }
 
 /// A mutex guard.
@@ -64,7 +248,7 @@ pub struct MutexGuard<'a, T: 'a> {
 impl<'a, T> Drop for MutexGuard<'a, T> {
     #[inline]
     fn drop(&mut self) {
-        self.mutex.locked.store(false, atomic::Ordering::SeqCst);
+        self.mutex.lock.unlock();
     }
 }
 
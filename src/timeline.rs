@@ -0,0 +1,187 @@
+//! Timeline export.
+//!
+//! The events of the allocator are written with timestamps in the trace event format of Chrome
+//! (the JSON array format), so the memory timeline of a program can be loaded into
+//! `about://tracing` or Perfetto:
+//!
+//! ```json
+//! [
+//! {"name":"alloc","cat":"ralloc","ph":"i","s":"t","ts":1042.517,"pid":1,"tid":1,
+//!  "args":{"ptr":"0x55d0c1a2e010","size":100,"align":8}},
+//! {"name":"in_use","ph":"C","ts":1042.517,"pid":1,"args":{"bytes":100}},
+//! {"name":"brk","cat":"ralloc","ph":"i","s":"g","ts":1043.002,"pid":1,"tid":1,
+//!  "args":{"ptr":"0x55d0c1a4e000","size":266240}},
+//! ```
+//!
+//! (Broken into lines here for readability; each event is a single line.) Allocations, frees and
+//! reallocations are instant events, followed by a counter event of the bytes in use, which the
+//! viewers draw as a graph. Heap growth (`brk`) and memory given back to the OS (`trim`) are
+//! global instant events. The timestamps are in microseconds of the monotonic clock. The array is
+//! never closed, which the viewers accept.
+
+use core::{cmp, fmt};
+use core::fmt::Write;
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::syscalls;
+
+use hook::{self, Event};
+use stats;
+
+/// The size of the buffer of an event.
+///
+/// This fits the longest event, along with its counter.
+const RECORD_SIZE: usize = 512;
+
+/// The file descriptor of the timeline being recorded (zero if none).
+static TIMELINE_FD: AtomicUsize = AtomicUsize::new(0);
+
+/// An event being formatted.
+///
+/// Every event is formatted on the stack, as the recorder must not allocate.
+struct Record {
+    /// The buffer.
+    buf: [u8; RECORD_SIZE],
+    /// The length of the event.
+    len: usize,
+}
+
+impl Record {
+    /// Create an empty record.
+    fn new() -> Record {
+        Record {
+            buf: [0; RECORD_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Start an instant event named `name`, with the scope `scope` (`t` for the thread, and `g`
+    /// for the whole trace), stamped with the current time.
+    fn instant(&mut self, name: &str, scope: char, ts: u64) -> fmt::Result {
+        write!(self, "{{\"name\":\"{}\",\"cat\":\"ralloc\",\"ph\":\"i\",\"s\":\"{}\",\
+                \"ts\":{}.{:03},\"pid\":1,\"tid\":1,\"args\":{{", name, scope, ts / 1000, ts % 1000)
+    }
+
+    /// Add the counter event of the bytes in use.
+    fn counter(&mut self, ts: u64) -> fmt::Result {
+        write!(self, "{{\"name\":\"in_use\",\"ph\":\"C\",\"ts\":{}.{:03},\"pid\":1,\
+                \"args\":{{\"bytes\":{}}}}},\n", ts / 1000, ts % 1000, stats::peak().in_use)
+    }
+
+    /// Format an event of the hook.
+    fn event(&mut self, event: Event, ts: u64) -> fmt::Result {
+        match event {
+            Event::Alloc { ptr, size, align } => {
+                self.instant("alloc", 't', ts)?;
+                write!(self, "\"ptr\":\"0x{:x}\",\"size\":{},\"align\":{}}}}},\n", ptr as usize,
+                       size, align)?;
+            },
+            Event::Free { ptr, size } => {
+                self.instant("free", 't', ts)?;
+                write!(self, "\"ptr\":\"0x{:x}\",\"size\":{}}}}},\n", ptr as usize, size)?;
+            },
+            Event::Realloc { old_ptr, old_size, ptr, size, align } => {
+                self.instant("realloc", 't', ts)?;
+                write!(self, "\"old_ptr\":\"0x{:x}\",\"old_size\":{},\"ptr\":\"0x{:x}\",\
+                        \"size\":{},\"align\":{}}}}},\n", old_ptr as usize, old_size, ptr as usize,
+                       size, align)?;
+            },
+        }
+
+        self.counter(ts)
+    }
+
+    /// Write the record to the timeline.
+    fn write(&self, fd: usize) {
+        let mut buf = &self.buf[..self.len];
+
+        while !buf.is_empty() {
+            match unsafe { syscalls::write(fd, buf) } {
+                Ok(n) if n > 0 => buf = &buf[n..],
+                _ => {
+                    log!(WARNING, "Unable to write to the timeline; dropping the rest of the \
+                         event.");
+                    return;
+                },
+            }
+        }
+    }
+}
+
+impl fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = cmp::min(s.len(), RECORD_SIZE - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        if len < s.len() { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
+/// The hook recording the timeline.
+fn record(event: Event) {
+    let fd = TIMELINE_FD.load(atomic::Ordering::Relaxed);
+    let mut record = Record::new();
+
+    if fd != 0 && record.event(event, syscalls::clock_monotonic()).is_ok() {
+        record.write(fd);
+    }
+}
+
+/// Record a change of the extent of the heap, if a timeline is being recorded.
+fn heap(name: &str, ptr: *mut u8, size: usize) {
+    let fd = TIMELINE_FD.load(atomic::Ordering::Relaxed);
+    if fd == 0 {
+        return;
+    }
+
+    let mut record = Record::new();
+    let res = record.instant(name, 'g', syscalls::clock_monotonic()).and_then(|()| {
+        write!(record, "\"ptr\":\"0x{:x}\",\"size\":{}}}}},\n", ptr as usize, size)
+    });
+
+    if res.is_ok() {
+        record.write(fd);
+    }
+}
+
+/// Record the heap growing by `size` bytes at `ptr`, if a timeline is being recorded.
+#[inline]
+pub fn brk(ptr: *mut u8, size: usize) {
+    heap("brk", ptr, size);
+}
+
+/// Record `size` bytes at `ptr` being given back to the OS, if a timeline is being recorded.
+#[inline]
+pub fn trim(ptr: *mut u8, size: usize) {
+    heap("trim", ptr, size);
+}
+
+/// Start recording a timeline of the allocator to a file descriptor.
+///
+/// The events are written in Chrome's trace event format, which `about://tracing` and Perfetto
+/// load (see the module documentation). This sets the hook (see `set_hook`), and recording stops
+/// with `stop_timeline`. Every event is written with a single write (unless interrupted), so the
+/// events of different threads do not interleave.
+pub fn record_timeline(fd: usize) {
+    // Logging.
+    log!(NOTE, "Recording a timeline to file descriptor {}.", fd);
+
+    let mut record = Record::new();
+    let _ = record.write_str("[\n");
+    record.write(fd);
+
+    TIMELINE_FD.store(fd, atomic::Ordering::Relaxed);
+    hook::set_hook(Some(self::record));
+}
+
+/// Stop recording the timeline.
+///
+/// This removes the hook. The file descriptor is left open.
+pub fn stop_timeline() {
+    // Logging.
+    log!(NOTE, "Stopping the timeline.");
+
+    hook::set_hook(None);
+    TIMELINE_FD.store(0, atomic::Ordering::Relaxed);
+}
@@ -0,0 +1,419 @@
+//! Allocation traces.
+//!
+//! A trace is a compact record of the operations of the allocator, which can be recorded in one
+//! process (e.g. on a user's machine), and replayed in another, to reproduce fragmentation and
+//! performance problems.
+//!
+//! The trace starts with the magic bytes `RATR`, followed by the events. Each event is a tag byte
+//! (0 for allocations, 1 for frees, and 2 for reallocations) followed by the fields of the event
+//! (see `Event`) in order, each encoded as an LEB128 integer.
+
+use prelude::*;
+
+use core::mem;
+use core::sync::atomic::{self, AtomicUsize};
+
+use shim::syscalls;
+
+use hook::{self, Event};
+use vec::Vec;
+use {allocator, meta};
+
+/// The magic bytes starting a trace.
+const MAGIC: &'static [u8] = b"RATR";
+
+/// The file descriptor of the trace being recorded.
+static TRACE_FD: AtomicUsize = AtomicUsize::new(0);
+
+/// An encoded event.
+struct Record {
+    /// The buffer.
+    ///
+    /// This fits the longest event: A tag and five integers.
+    buf: [u8; 64],
+    /// The length of the event.
+    len: usize,
+}
+
+impl Record {
+    /// Encode an event.
+    fn new(event: Event) -> Record {
+        let mut res = Record {
+            buf: [0; 64],
+            len: 0,
+        };
+
+        match event {
+            Event::Alloc { ptr, size, align } => {
+                res.push(0);
+                res.int(ptr as usize);
+                res.int(size);
+                res.int(align);
+            },
+            Event::Free { ptr, size } => {
+                res.push(1);
+                res.int(ptr as usize);
+                res.int(size);
+            },
+            Event::Realloc { old_ptr, old_size, ptr, size, align } => {
+                res.push(2);
+                res.int(old_ptr as usize);
+                res.int(old_size);
+                res.int(ptr as usize);
+                res.int(size);
+                res.int(align);
+            },
+        }
+
+        res
+    }
+
+    /// Push a byte.
+    #[inline]
+    fn push(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// Push an LEB128 integer.
+    fn int(&mut self, mut x: usize) {
+        while x >= 0x80 {
+            self.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.push(x as u8);
+    }
+}
+
+/// Write a buffer to the trace.
+fn write(fd: usize, mut buf: &[u8]) {
+    while !buf.is_empty() {
+        match unsafe { syscalls::write(fd, buf) } {
+            Ok(n) if n > 0 => buf = &buf[n..],
+            _ => {
+                log!(WARNING, "Unable to write to the trace; dropping the rest of the event.");
+                return;
+            },
+        }
+    }
+}
+
+/// The hook recording the trace.
+fn record(event: Event) {
+    let record = Record::new(event);
+
+    write(TRACE_FD.load(atomic::Ordering::Relaxed), &record.buf[..record.len]);
+}
+
+/// Start recording a trace of the allocator to a file descriptor.
+///
+/// This sets the hook (see `set_hook`), so recording stops when the hook is removed with
+/// `set_hook(None)`. Every event is written with a single write (unless interrupted), so the
+/// events of different threads do not interleave, but the order of the events of different
+/// threads is only approximate.
+pub fn record_trace(fd: usize) {
+    // Logging.
+    log!(NOTE, "Recording a trace to file descriptor {}.", fd);
+
+    write(fd, MAGIC);
+
+    TRACE_FD.store(fd, atomic::Ordering::Relaxed);
+    hook::set_hook(Some(record));
+}
+
+/// A reader of a trace.
+struct Reader<'a> {
+    /// The rest of the trace.
+    trace: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Read a byte.
+    fn byte(&mut self) -> Result<u8, ()> {
+        match self.trace.split_first() {
+            Some((&byte, rest)) => {
+                self.trace = rest;
+                Ok(byte)
+            },
+            None => Err(()),
+        }
+    }
+
+    /// Read an LEB128 integer.
+    fn int(&mut self) -> Result<usize, ()> {
+        let mut res = 0usize;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            if shift >= mem::size_of::<usize>() * 8 {
+                // The integer overflows.
+                return Err(());
+            }
+
+            res |= ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+    }
+
+    /// Read an event.
+    ///
+    /// `None` is returned at the end of the trace.
+    fn event(&mut self) -> Result<Option<Event>, ()> {
+        if self.trace.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(match self.byte()? {
+            0 => Event::Alloc {
+                ptr: self.int()? as *mut u8,
+                size: self.int()?,
+                align: self.int()?,
+            },
+            1 => Event::Free {
+                ptr: self.int()? as *mut u8,
+                size: self.int()?,
+            },
+            2 => Event::Realloc {
+                old_ptr: self.int()? as *mut u8,
+                old_size: self.int()?,
+                ptr: self.int()? as *mut u8,
+                size: self.int()?,
+                align: self.int()?,
+            },
+            _ => return Err(()),
+        }))
+    }
+}
+
+/// An entry of the buffer table.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The address of the buffer in the trace (`EMPTY` or `REMOVED` if unused).
+    key: usize,
+    /// The address of the buffer in the replay.
+    ptr: *mut u8,
+    /// The size of the buffer.
+    size: usize,
+}
+
+/// The key of an entry, which was never used.
+const EMPTY: usize = 0;
+/// The key of an entry, which was removed.
+const REMOVED: usize = !0;
+
+/// A table mapping the buffers of a trace to the buffers of the replay.
+///
+/// This is an open addressing hash table in metadata, as the replay cannot rely on any other
+/// allocator.
+struct Table {
+    /// The entries.
+    ///
+    /// The length is a power of two.
+    entries: Vec<Entry>,
+    /// The number of entries in use (including the removed ones).
+    used: usize,
+}
+
+impl Table {
+    /// Create a table with some capacity.
+    fn with_capacity(cap: usize) -> Table {
+        let mut entries = unsafe {
+            // The block is fresh metadata, and the vector is empty.
+            Vec::from_raw_parts(meta::alloc(cap * mem::size_of::<Entry>(),
+                                            mem::align_of::<Entry>()), 0)
+        };
+        for _ in 0..cap {
+            entries.push(Entry {
+                key: EMPTY,
+                ptr: 0 as *mut u8,
+                size: 0,
+            }).expect("The table was allocated too small.");
+        }
+
+        Table {
+            entries: entries,
+            used: 0,
+        }
+    }
+
+    /// Get the index, where the search for some key starts.
+    #[inline]
+    fn home(&self, key: usize) -> usize {
+        ((key as u64).wrapping_mul(0x9E3779B97F4A7C15) >> 32) as usize & (self.entries.len() - 1)
+    }
+
+    /// Find the index of some key.
+    fn find(&self, key: usize) -> Option<usize> {
+        let mut i = self.home(key);
+
+        loop {
+            match self.entries[i].key {
+                EMPTY => return None,
+                k if k == key => return Some(i),
+                _ => i = (i + 1) & (self.entries.len() - 1),
+            }
+        }
+    }
+
+    /// Insert a buffer.
+    ///
+    /// The key must not be in the table.
+    fn insert(&mut self, key: usize, ptr: *mut u8, size: usize) {
+        // Keep the table at most half full, so searches stay short and always terminate.
+        if 2 * (self.used + 1) > self.entries.len() {
+            self.grow();
+        }
+
+        let mut i = self.home(key);
+        while self.entries[i].key != EMPTY && self.entries[i].key != REMOVED {
+            i = (i + 1) & (self.entries.len() - 1);
+        }
+
+        if self.entries[i].key == EMPTY {
+            self.used += 1;
+        }
+        self.entries[i] = Entry {
+            key: key,
+            ptr: ptr,
+            size: size,
+        };
+    }
+
+    /// Remove a buffer, returning it.
+    fn remove(&mut self, key: usize) -> Option<(*mut u8, usize)> {
+        self.find(key).map(|i| {
+            self.entries[i].key = REMOVED;
+            (self.entries[i].ptr, self.entries[i].size)
+        })
+    }
+
+    /// Double the capacity of the table, dropping the removed entries.
+    fn grow(&mut self) {
+        let old = mem::replace(self, Table::with_capacity(2 * self.entries.len()));
+
+        for e in old.entries.iter().filter(|e| e.key != EMPTY && e.key != REMOVED) {
+            self.insert(e.key, e.ptr, e.size);
+        }
+
+        meta::free(Block::from(old.entries));
+    }
+
+    /// Free every buffer of the table, and give its metadata back.
+    fn free_all(self) {
+        for e in self.entries.iter().filter(|e| e.key != EMPTY && e.key != REMOVED) {
+            unsafe {
+                // The buffer was allocated by the replay, and is not used by anything else.
+                allocator::free(e.ptr, e.size);
+            }
+        }
+
+        meta::free(Block::from(self.entries));
+    }
+}
+
+/// Replay a trace.
+///
+/// The operations of the trace are performed on the allocator in order (the buffers are merely
+/// allocated, not written). Operations on buffers unknown to the trace (e.g. allocated before
+/// recording started) are skipped. The buffers left at the end are freed.
+///
+/// `Err(())` is returned if the trace is malformed, in which case the replay stops.
+pub fn replay_trace(trace: &[u8]) -> Result<(), ()> {
+    // Logging.
+    log!(NOTE, "Replaying a trace of {} bytes.", trace.len());
+
+    if !trace.starts_with(MAGIC) {
+        return Err(());
+    }
+
+    let mut reader = Reader {
+        trace: &trace[MAGIC.len()..],
+    };
+    let mut table = Table::with_capacity(64);
+
+    let res = replay_events(&mut reader, &mut table);
+    table.free_all();
+
+    res
+}
+
+/// Replay the events of a trace.
+fn replay_events(reader: &mut Reader, table: &mut Table) -> Result<(), ()> {
+    while let Some(event) = reader.event()? {
+        match event {
+            Event::Alloc { ptr, size, align } => {
+                if !align.is_power_of_two() || !allocator::is_possible(size, align) {
+                    return Err(());
+                }
+
+                if size != 0 {
+                    // A buffer still known at this address was freed out of order.
+                    if let Some((old, old_size)) = table.remove(ptr as usize) {
+                        unsafe { allocator::free(old, old_size); }
+                    }
+
+                    table.insert(ptr as usize, allocator::alloc(size, align), size);
+                }
+            },
+            Event::Free { ptr, size } => {
+                if size != 0 {
+                    if let Some((old, old_size)) = table.remove(ptr as usize) {
+                        unsafe { allocator::free(old, old_size); }
+                    }
+                }
+            },
+            Event::Realloc { old_ptr, old_size, ptr, size, align } => {
+                if !align.is_power_of_two() || !allocator::is_possible(size, align) {
+                    return Err(());
+                }
+
+                let old = if old_size == 0 { None } else { table.remove(old_ptr as usize) };
+                let res = match old {
+                    Some((old, old_size)) => unsafe { allocator::realloc(old, old_size, size, align) },
+                    None => allocator::alloc(size, align),
+                };
+
+                if size != 0 {
+                    table.insert(ptr as usize, res, size);
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        let event = Event::Realloc {
+            old_ptr: 0x1234 as *mut u8,
+            old_size: 300,
+            ptr: !0 as *mut u8,
+            size: 0,
+            align: 8,
+        };
+
+        let record = Record::new(event);
+        let mut reader = Reader {
+            trace: &record.buf[..record.len],
+        };
+
+        assert_eq!(reader.event(), Ok(Some(event)));
+        assert_eq!(reader.event(), Ok(None));
+
+        // Truncated events are malformed.
+        let mut reader = Reader {
+            trace: &record.buf[..record.len - 1],
+        };
+        assert!(reader.event().is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Typed allocation.
+//!
+//! This is a thin layer over the byte-oriented entry points, computing the size and alignment
+//! from the type, so callers (e.g. kernels and embedded code using the allocator directly) need
+//! not do the arithmetic, nor the casts, themselves.
+//!
+//! The memory is uninitialized, and freeing does not drop the contents.
+
+use core::{mem, slice};
+use core::ptr::Unique;
+
+use {allocator, fail};
+
+/// Allocate memory for a value of type `T`.
+///
+/// The memory is uninitialized. Zero-sized types take no memory.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions.
+#[inline]
+pub fn alloc_one<T>() -> Unique<T> {
+    let ptr = allocator::alloc(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+
+    unsafe {
+        // The entry points never return null.
+        Unique::new_unchecked(ptr)
+    }
+}
+
+/// Allocate memory for an array of `len` values of type `T`.
+///
+/// The memory is uninitialized.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions, as well as arrays too big for the address
+/// space (`Error::LimitExceeded`).
+#[inline]
+pub fn alloc_array<T>(len: usize) -> Unique<[T]> {
+    let size = match mem::size_of::<T>().checked_mul(len) {
+        Some(size) => size,
+        None => fail::oom(fail::Error::LimitExceeded),
+    };
+
+    unsafe {
+        // The entry points never return null, and the buffer holds `len` elements.
+        let ptr = allocator::alloc(size, mem::align_of::<T>()) as *mut T;
+        Unique::new_unchecked(slice::from_raw_parts_mut(ptr, len) as *mut [T])
+    }
+}
+
+/// Free memory allocated through `alloc_one`.
+///
+/// The value is not dropped.
+///
+/// # Safety
+///
+/// The memory must have been allocated through `alloc_one::<T>`, and must not be used
+/// afterwards.
+#[inline]
+pub unsafe fn dealloc_one<T>(ptr: Unique<T>) {
+    allocator::free(ptr.as_ptr() as *mut u8, mem::size_of::<T>());
+}
+
+/// Free memory allocated through `alloc_array`.
+///
+/// The values are not dropped.
+///
+/// # Safety
+///
+/// The memory must have been allocated through `alloc_array::<T>`, and must not be used
+/// afterwards.
+#[inline]
+pub unsafe fn dealloc_array<T>(ptr: Unique<[T]>) {
+    let len = (*ptr.as_ptr()).len();
+    allocator::free(ptr.as_ptr() as *mut T as *mut u8, mem::size_of::<T>() * len);
+}
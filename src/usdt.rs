@@ -0,0 +1,90 @@
+//! Static tracepoints.
+//!
+//! With the `usdt` feature, the allocator events are emitted as USDT probes (the SystemTap SDT
+//! ABI, as with `<sys/sdt.h>`) of the `ralloc` provider, so tools like bpftrace and perf can be
+//! attached to a running program (e.g. `bpftrace -e 'usdt:./app:ralloc:alloc { @[arg1] =
+//! count(); }'`). A probe is a single `nop` in the code, and a note in `.note.stapsdt` naming it
+//! and locating its arguments, so an unattached probe costs next to nothing.
+//!
+//! The probes are:
+//!
+//! - `alloc(ptr, size, align)`
+//! - `free(ptr, size)`
+//! - `realloc(old_ptr, old_size, ptr, size)`
+//! - `brk(ptr, size)`, when the heap grows.
+//! - `trim(ptr, size)`, when memory is given back to the OS.
+
+use hook::Event;
+
+/// Emit a probe.
+///
+/// The arguments are described by `$args` (e.g. `"8@$0 8@$1"`: eight bytes in the first and the
+/// second operand), which the assembler substitutes with the registers holding them.
+macro_rules! probe {
+    ($name:expr, $args:expr; $($operand:tt)*) => {
+        asm!(concat!("990: nop
+                      .pushsection .note.stapsdt,\"?\",\"note\"
+                      .balign 4
+                      .4byte 992f-991f, 994f-993f, 3
+                      991: .asciz \"stapsdt\"
+                      992: .balign 4
+                      993: .8byte 990b
+                      .8byte _.stapsdt.base
+                      .8byte 0
+                      .asciz \"ralloc\"
+                      .asciz \"", $name, "\"
+                      .asciz \"", $args, "\"
+                      994: .balign 4
+                      .popsection
+                      .ifndef _.stapsdt.base
+                      .pushsection .stapsdt.base,\"aG\",\"progbits\",.stapsdt.base,comdat
+                      .weak _.stapsdt.base
+                      .hidden _.stapsdt.base
+                      _.stapsdt.base: .space 1
+                      .size _.stapsdt.base, 1
+                      .popsection
+                      .endif")
+             :
+             : $($operand)*
+             :
+             : "volatile")
+    };
+}
+
+/// Emit the probe of an event.
+#[inline]
+pub fn emit(event: &Event) {
+    unsafe {
+        // The probes only read their operands.
+        match *event {
+            Event::Alloc { ptr, size, align } => {
+                probe!("alloc", "8@$0 8@$1 8@$2"; "r"(ptr), "r"(size), "r"(align));
+            },
+            Event::Free { ptr, size } => {
+                probe!("free", "8@$0 8@$1"; "r"(ptr), "r"(size));
+            },
+            Event::Realloc { old_ptr, old_size, ptr, size, .. } => {
+                probe!("realloc", "8@$0 8@$1 8@$2 8@$3"; "r"(old_ptr), "r"(old_size), "r"(ptr),
+                       "r"(size));
+            },
+        }
+    }
+}
+
+/// Emit the probe of the heap growing by `size` bytes at `ptr`.
+#[inline]
+pub fn brk(ptr: *mut u8, size: usize) {
+    unsafe {
+        // The probe only reads its operands.
+        probe!("brk", "8@$0 8@$1"; "r"(ptr), "r"(size));
+    }
+}
+
+/// Emit the probe of `size` bytes at `ptr` being given back to the OS.
+#[inline]
+pub fn trim(ptr: *mut u8, size: usize) {
+    unsafe {
+        // The probe only reads its operands.
+        probe!("trim", "8@$0 8@$1"; "r"(ptr), "r"(size));
+    }
+}
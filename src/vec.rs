@@ -125,6 +125,65 @@ impl<T: Leak> Vec<T> {
         }
     }
 
+    /// Insert an element at some index, shifting all the elements after it to the right.
+    ///
+    /// On success, return `Ok(())`. On failure (not enough capacity), return `Err(())`.
+    ///
+    /// # Panics
+    ///
+    /// Panics on out-of-bound.
+    #[allow(cast_possible_wrap)]
+    pub fn insert(&mut self, ind: usize, elem: T) -> Result<(), ()> {
+        // Bound check.
+        assert!(ind <= self.len, "Out of bound.");
+
+        if self.len == self.cap {
+            Err(())
+        } else {
+            unsafe {
+                // By the invariants of this type (the size is bounded by the address space), this
+                // conversion isn't overflowing. The capacity check above makes sure that the
+                // shifted elements stay inside the buffer.
+                let ptr = self.ptr.get().offset(ind as isize);
+
+                // Memmove the elements to make a gap for the new element.
+                ptr::copy(ptr, ptr.offset(1), self.len - ind);
+                ptr::write(ptr, elem);
+            }
+
+            // Increment the length.
+            self.len += 1;
+            Ok(())
+        }
+    }
+
+    /// Remove the element at some index, shifting all the elements after it to the left.
+    ///
+    /// # Panics
+    ///
+    /// Panics on out-of-bound.
+    #[allow(cast_possible_wrap)]
+    pub fn remove(&mut self, ind: usize) -> T {
+        // Bound check.
+        assert!(ind < self.len, "Out of bound.");
+
+        unsafe {
+            // By the invariants of this type (the size is bounded by the address space), this
+            // conversion isn't overflowing.
+            let ptr = self.ptr.get().offset(ind as isize);
+
+            // We use `ptr::read` since the element is unaccessible after the shift.
+            let res = ptr::read(ptr);
+            // Memmove the elements to close the gap.
+            ptr::copy(ptr.offset(1), ptr, self.len - ind - 1);
+
+            // Decrement the length. This won't underflow due to the bound check above.
+            self.len -= 1;
+
+            res
+        }
+    }
+
     /// Truncate this vector.
     ///
     /// This is O(1).
@@ -253,6 +312,13 @@ mod test {
         assert_eq!(&*vec, b".aaaaaaaaaaaaaaabc_____________@");
         assert_eq!(vec.capacity(), 32);
 
+        assert_eq!(vec.remove(0), b'.');
+        assert_eq!(vec.remove(30), b'@');
+        vec.insert(30, b'#').unwrap();
+        vec.insert(0, b',').unwrap();
+        vec.insert(0, b'!').unwrap_err();
+        assert_eq!(&*vec, b",aaaaaaaaaaaaaaabc_____________#");
+
         for _ in 0..32 { vec.pop().unwrap(); }
 
         assert!(vec.pop().is_none());
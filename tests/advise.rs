@@ -0,0 +1,29 @@
+extern crate ralloc;
+
+mod util;
+
+use ralloc::Advice;
+
+#[test]
+fn advise() {
+    util::multiply(|| {
+        let ptr = ralloc::alloc(1 << 16, 4096);
+
+        unsafe {
+            util::acid(|| {
+                *ptr = 1;
+                *ptr.offset((1 << 16) - 1) = 2;
+            });
+
+            assert!(ralloc::advise(ptr, 1 << 16, Advice::Sequential).is_ok());
+            assert!(ralloc::advise(ptr, 1 << 16, Advice::WillNeed).is_ok());
+            assert_eq!(*ptr.offset((1 << 16) - 1), 2);
+
+            // Discarding zeroes the whole pages.
+            assert!(ralloc::advise(ptr, 1 << 16, Advice::DontNeed).is_ok());
+            assert_eq!(*ptr, 0);
+
+            ralloc::free(ptr, 1 << 16);
+        }
+    });
+}
@@ -0,0 +1,33 @@
+extern crate ralloc;
+
+use std::ptr;
+
+#[test]
+fn alloc_at() {
+    let buf = ralloc::alloc(200, 8);
+
+    unsafe {
+        ralloc::free(buf, 200);
+
+        // The range is free again, so it can be taken back.
+        let res = ralloc::alloc_at(buf.offset(40), 100, 8).unwrap();
+        assert_eq!(res, buf.offset(40));
+        ptr::write_bytes(res, 0, 100);
+
+        // But only once.
+        assert!(ralloc::alloc_at(buf.offset(40), 100, 8).is_err());
+
+        ralloc::free(res, 100);
+    }
+}
+
+#[test]
+fn alloc_at_misaligned() {
+    let buf = ralloc::alloc(64, 16);
+
+    unsafe {
+        ralloc::free(buf, 64);
+
+        assert_eq!(ralloc::alloc_at(buf.offset(1), 8, 16), Err(ralloc::Error::LimitExceeded));
+    }
+}
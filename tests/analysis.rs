@@ -0,0 +1,63 @@
+extern crate ralloc;
+
+use ralloc::analysis::{ParseError, Snapshot};
+
+/// Take a snapshot of the global allocator.
+fn snapshot() -> Vec<u8> {
+    let mut buf = vec![0; 16];
+    loop {
+        match ralloc::debug::write_snapshot(&mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                return buf;
+            },
+            Err(len) => buf.resize(2 * len, 0),
+        }
+    }
+}
+
+#[test]
+fn snapshots() {
+    let before = snapshot();
+
+    let a = ralloc::alloc(1000, 8);
+    let b = ralloc::alloc(1000, 8);
+    unsafe {
+        ralloc::free(a, 1000);
+    }
+
+    let after = snapshot();
+
+    let before = Snapshot::parse(&before).unwrap();
+    let after = Snapshot::parse(&after).unwrap();
+
+    assert!(after.in_use >= 1000);
+    assert_eq!(after.iter().count(), after.blocks);
+    assert_eq!(after.iter().map(|(_, size)| size).sum::<usize>(), after.free);
+    assert!(after.fragmentation() >= 0.0 && after.fragmentation() < 1.0);
+    // The blocks are in address order, and do not overlap.
+    let blocks: Vec<_> = after.iter().collect();
+    for w in blocks.windows(2) {
+        assert!(w[0].0 + w[0].1 <= w[1].0);
+    }
+
+    let hist = after.histogram();
+    assert_eq!(hist.counts.iter().sum::<usize>(), blocks.iter().filter(|b| b.1 != 0).count());
+    assert_eq!(hist.bytes.iter().sum::<usize>(), after.free);
+
+    let diff = before.diff(&after);
+    assert_eq!(diff.in_use, after.in_use as isize - before.in_use as isize);
+    assert_eq!(diff.blocks, after.blocks as isize - before.blocks as isize);
+
+    // Snapshots of other versions are rejected.
+    let mut other = snapshot();
+    other[4] = 0xFF;
+    assert_eq!(Snapshot::parse(&other).unwrap_err(), ParseError::Version(0xFF));
+    other.truncate(5);
+    other[4] = ralloc::analysis::VERSION;
+    assert_eq!(Snapshot::parse(&other).unwrap_err(), ParseError::Malformed);
+
+    unsafe {
+        ralloc::free(b, 1000);
+    }
+}
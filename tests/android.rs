@@ -0,0 +1,44 @@
+#![cfg(feature = "android")]
+
+extern crate ralloc;
+
+extern {
+    fn ralloc_malloc(size: usize) -> *mut u8;
+    fn ralloc_realloc(ptr: *mut u8, size: usize) -> *mut u8;
+    fn ralloc_free(ptr: *mut u8);
+    fn ralloc_malloc_usable_size(ptr: *const u8) -> usize;
+    fn ralloc_malloc_iterate(base: usize, size: usize,
+                             callback: extern fn(usize, usize, *mut u8), arg: *mut u8) -> i32;
+    fn ralloc_malloc_disable();
+    fn ralloc_malloc_enable();
+}
+
+/// Count an allocation of 100 bytes.
+extern fn count(_: usize, size: usize, arg: *mut u8) {
+    if size == 100 {
+        unsafe {
+            *(arg as *mut usize) += 1;
+        }
+    }
+}
+
+// The iteration blocks the other threads, so the cases must not run in parallel.
+#[test]
+fn android() {
+    unsafe {
+        let ptr = ralloc_malloc(100);
+        assert_eq!(ralloc_malloc_usable_size(ptr), 100);
+
+        let mut found = 0usize;
+        ralloc_malloc_disable();
+        ralloc_malloc_iterate(ptr as usize, 1, count, &mut found as *mut usize as *mut u8);
+        ralloc_malloc_enable();
+        assert_eq!(found, 1);
+
+        let ptr = ralloc_realloc(ptr, 200);
+        assert_eq!(ralloc_malloc_usable_size(ptr), 200);
+
+        ralloc_free(ptr);
+        assert_eq!(ralloc_malloc_usable_size(ptr), 0);
+    }
+}
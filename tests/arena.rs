@@ -0,0 +1,348 @@
+extern crate ralloc;
+
+mod util;
+
+use ralloc::{AllocOptions, Arena, Breaker, CACHE_LINE, Chain, Concealed, Error, Fixed, Hybrid,
+             Mmap, Reserved, System};
+
+/// The buffer of the fixed breaker.
+static mut BUF: [u8; 1 << 16] = [0; 1 << 16];
+/// The buffer of the fixed breaker spanning segments of the pool (which are 1 MiB).
+static mut SEGMENTS_BUF: [u8; 3 << 20] = [0; 3 << 20];
+
+#[test]
+fn fixed() {
+    let mut arena = Arena::new(Fixed::new(unsafe { &mut BUF }));
+    let (start, end) = unsafe { (BUF.as_ptr() as usize, BUF.as_ptr() as usize + BUF.len()) };
+
+    let ptr1 = arena.alloc(30, 8);
+    let ptr2 = arena.alloc(500, 16);
+
+    assert_eq!(0, ptr1 as usize % 8);
+    assert_eq!(0, ptr2 as usize % 16);
+    // The memory comes from the buffer.
+    assert!(ptr1 as usize >= start && ptr1 as usize + 30 <= end);
+    assert!(ptr2 as usize >= start && ptr2 as usize + 500 <= end);
+
+    unsafe {
+        util::acid(|| {
+            for i in 0..500 {
+                *ptr2.offset(i) = i as u8;
+            }
+        });
+
+        let ptr2 = arena.realloc(ptr2, 500, 1000, 16);
+        assert_eq!(*ptr2.offset(200), 200);
+        assert!(ptr2 as usize >= start && ptr2 as usize + 1000 <= end);
+
+        arena.free(ptr1, 30);
+        arena.free(ptr2, 1000);
+    }
+}
+
+#[test]
+fn snapshot() {
+    let mut arena = Arena::new(Mmap);
+
+    let kept = arena.alloc(100, 8);
+    let snapshot = arena.snapshot();
+
+    // Allocate enough to acquire fresh memory since the snapshot.
+    let small = arena.alloc(200, 8);
+    let big = arena.alloc(1 << 20, 8);
+    unsafe {
+        arena.free(small, 200);
+        arena.free(big, 1 << 20);
+        arena.free(kept, 100);
+    }
+
+    assert!(arena.restore(snapshot).is_ok());
+
+    // A live buffer allocated since the snapshot makes restoring fail.
+    let snapshot = arena.snapshot();
+    let ptr = arena.alloc(300, 8);
+    assert!(arena.restore(snapshot).is_err());
+
+    unsafe {
+        arena.free(ptr, 300);
+    }
+}
+
+#[test]
+fn epochs() {
+    let mut arena = Arena::new(Mmap);
+
+    let outer = arena.alloc(100, 8);
+
+    arena.epoch_push();
+    let a = arena.alloc(200, 8);
+    let b = arena.alloc(300, 8);
+
+    arena.epoch_push();
+    let c = arena.alloc(400, 8);
+    unsafe {
+        // Freed early.
+        arena.free(a, 200);
+        let _ = arena.realloc(c, 400, 4000, 8);
+    }
+    arena.epoch_pop();
+
+    unsafe {
+        util::acid(|| {
+            *b.offset(299) = 1;
+            *outer.offset(99) = 2;
+        });
+        assert_eq!(*b.offset(299), 1);
+    }
+    arena.epoch_pop();
+
+    // The outer buffer survives the epochs.
+    unsafe {
+        assert_eq!(*outer.offset(99), 2);
+        arena.free(outer, 100);
+    }
+}
+
+#[test]
+fn mmap() {
+    let mut arena = Arena::new(Mmap);
+
+    let ptr = arena.alloc(100000, 64);
+    assert_eq!(0, ptr as usize % 64);
+
+    unsafe {
+        util::acid(|| {
+            *ptr.offset(99999) = 42;
+        });
+        assert_eq!(*ptr.offset(99999), 42);
+
+        arena.free(ptr, 100000);
+    }
+}
+
+#[test]
+fn concealed() {
+    let mut arena = Arena::new(Concealed);
+
+    let ptr = arena.alloc(5000, 8);
+
+    unsafe {
+        util::acid(|| {
+            *ptr.offset(4999) = 42;
+        });
+        assert_eq!(*ptr.offset(4999), 42);
+
+        arena.free(ptr, 5000);
+    }
+}
+
+#[test]
+fn hybrid() {
+    let mut arena = Arena::new(Hybrid::new(1 << 16));
+
+    // One from the program break, and one mapped.
+    let small = arena.alloc(100, 8);
+    let big = arena.alloc(1 << 20, 8);
+
+    unsafe {
+        util::acid(|| {
+            *small.offset(99) = 1;
+            *big.offset((1 << 20) - 1) = 2;
+        });
+        assert_eq!(*small.offset(99), 1);
+        assert_eq!(*big.offset((1 << 20) - 1), 2);
+
+        arena.free(small, 100);
+        arena.free(big, 1 << 20);
+    }
+}
+
+#[test]
+fn chain() {
+    // The first breaker is unsupported, so the platform allocator is used.
+    let mut arena = Arena::new(Chain::new(Fixed::new(&mut []), System));
+
+    let ptr = arena.alloc(1000, 64);
+    assert_eq!(0, ptr as usize % 64);
+
+    unsafe {
+        util::acid(|| {
+            *ptr.offset(999) = 42;
+        });
+        assert_eq!(*ptr.offset(999), 42);
+
+        arena.free(ptr, 1000);
+    }
+}
+
+/// A breaker failing with an OS error.
+struct Failing;
+
+unsafe impl Breaker for Failing {
+    fn fresh(&mut self, _: usize) -> Result<(*mut u8, usize), usize> {
+        Err(12)
+    }
+}
+
+#[test]
+fn breaker_error() {
+    // The error of the breaker is passed on.
+    let mut arena = Arena::new(Failing);
+    assert_eq!(arena.try_alloc(64, 8), Err(Error::BreakerFailed(12)));
+
+    // An exhausted breaker merely runs out of memory.
+    let mut arena = Arena::new(Fixed::new(&mut []));
+    match arena.try_alloc(64, 8) {
+        Err(Error::OutOfMemory { .. }) => (),
+        res => panic!("Unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn reserved() {
+    // Reserve far more than is ever committed.
+    let breaker = Reserved::new(1 << 30).unwrap();
+    let start = breaker.ptr() as usize;
+    let mut arena = Arena::new(breaker);
+
+    let ptr = arena.alloc(1 << 20, 4096);
+    assert!(ptr as usize >= start && (ptr as usize) < start + (1 << 30));
+
+    unsafe {
+        util::acid(|| {
+            *ptr.offset((1 << 20) - 1) = 3;
+        });
+
+        arena.free(ptr, 1 << 20);
+    }
+
+    // Committing by hand.
+    let mut breaker = Reserved::new(1 << 16).unwrap();
+    assert!(breaker.commit(4096, 8192).is_ok());
+    assert!(breaker.commit(1 << 16, 1).is_err());
+
+    unsafe {
+        *breaker.ptr().offset(4096 + 8191) = 1;
+        assert!(breaker.decommit(4096, 8192).is_ok());
+    }
+}
+
+#[test]
+fn prefault() {
+    let options = AllocOptions {
+        prefault: true,
+        ..AllocOptions::new()
+    };
+
+    let mut arena = Arena::new(Mmap);
+    arena.set_options(options);
+
+    unsafe {
+        let ptr = arena.alloc(1 << 16, 8);
+        *ptr.offset(1000) = 7;
+        let ptr = arena.realloc(ptr, 1 << 16, 1 << 17, 8);
+        assert_eq!(*ptr.offset(1000), 7);
+        arena.free(ptr, 1 << 17);
+
+        let ptr = ralloc::alloc_with(1 << 16, &options);
+        *ptr.offset(1000) = 7;
+        ralloc::free(ptr, 1 << 16);
+    }
+}
+
+#[test]
+fn options() {
+    let options = AllocOptions {
+        zeroed: true,
+        min_align: 64,
+        ..AllocOptions::new()
+    };
+
+    util::multiply(|| unsafe {
+        let ptr = ralloc::alloc(1000, 8);
+        *ptr.offset(100) = 7;
+        ralloc::free(ptr, 1000);
+
+        let ptr = ralloc::alloc_with(1000, &options);
+        assert_eq!(ptr as usize % 64, 0);
+        for i in 0..1000 {
+            assert_eq!(*ptr.offset(i), 0);
+        }
+        ralloc::free(ptr, 1000);
+    });
+
+    let mut arena = Arena::new(Mmap);
+    arena.set_options(options);
+
+    unsafe {
+        let ptr = arena.alloc(100, 8);
+        assert_eq!(ptr as usize % 64, 0);
+        assert_eq!(*ptr.offset(99), 0);
+        arena.free(ptr, 100);
+    }
+}
+
+#[test]
+fn cacheline() {
+    util::multiply(|| unsafe {
+        let a = ralloc::alloc_cacheline(10);
+        let b = ralloc::alloc_cacheline(10);
+        assert_eq!(a as usize % CACHE_LINE, 0);
+        assert_eq!(b as usize % CACHE_LINE, 0);
+        // The padding is part of the buffer.
+        *a.offset(CACHE_LINE as isize - 1) = 1;
+        ralloc::free(a, CACHE_LINE);
+        ralloc::free(b, CACHE_LINE);
+    });
+
+    let mut arena = Arena::new(Mmap);
+    arena.set_options(AllocOptions {
+        cacheline: true,
+        ..AllocOptions::new()
+    });
+
+    unsafe {
+        let a = arena.alloc(8, 8);
+        let b = arena.alloc(100, 8);
+        assert_eq!(a as usize % CACHE_LINE, 0);
+        assert_eq!(b as usize % CACHE_LINE, 0);
+        assert!(a as usize + CACHE_LINE <= b as usize || b as usize + 128 <= a as usize);
+
+        let b = arena.realloc(b, 100, 200, 8);
+        assert_eq!(b as usize % CACHE_LINE, 0);
+        arena.free(a, 8);
+        arena.free(b, 200);
+    }
+}
+
+#[test]
+fn segment_boundary() {
+    let mut arena = Arena::new(Fixed::new(unsafe { &mut SEGMENTS_BUF }));
+
+    // Leave a free block spanning a segment boundary.
+    let ptr = arena.alloc(2 << 20, 8);
+    unsafe {
+        arena.free(ptr, 2 << 20);
+    }
+
+    // Take its front, so the rest starts in the next segment.
+    let boundary = (ptr as usize | ((1 << 20) - 1)) + 1;
+    let size = boundary - ptr as usize + 16;
+    let a = arena.alloc(size, 8);
+    assert_eq!(a, ptr);
+
+    // Run enough operations for a full consistency check of the pool, which finds any block
+    // placed in the wrong segment.
+    let mut bufs = Vec::new();
+    for _ in 0..300 {
+        bufs.push(arena.alloc(16, 8));
+    }
+    assert!(bufs.iter().all(|&buf| buf as usize >= boundary));
+
+    unsafe {
+        for buf in bufs {
+            arena.free(buf, 16);
+        }
+        arena.free(a, size);
+    }
+}
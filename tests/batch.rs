@@ -0,0 +1,60 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn batch() {
+    util::multiply(|| {
+        let mut bufs = [ptr::null_mut(); 32];
+        ralloc::alloc_batch(24, 8, 32, &mut bufs);
+
+        for (n, &buf) in bufs.iter().enumerate() {
+            assert_eq!(buf as usize % 8, 0);
+
+            unsafe {
+                util::acid(|| {
+                    ptr::write_bytes(buf, n as u8, 24);
+                });
+            }
+        }
+
+        for (n, &buf) in bufs.iter().enumerate() {
+            unsafe {
+                // No two buffers overlap.
+                assert_eq!(*buf, n as u8);
+                assert_eq!(*buf.offset(23), n as u8);
+
+                ralloc::free(buf, 24);
+            }
+        }
+    });
+}
+
+#[test]
+fn batch_padded() {
+    util::multiply(|| {
+        let mut bufs = [ptr::null_mut(); 8];
+        // Leave the last entries be.
+        ralloc::alloc_batch(5, 16, 6, &mut bufs);
+
+        assert!(bufs[6].is_null() && bufs[7].is_null(), "Entries past the batch were set.");
+
+        for &buf in &bufs[..6] {
+            assert_eq!(buf as usize % 16, 0);
+
+            unsafe {
+                ptr::write_bytes(buf, 0, 5);
+                ralloc::free(buf, 5);
+            }
+        }
+    });
+}
+
+#[test]
+#[should_panic]
+fn batch_too_small() {
+    let mut bufs = [ptr::null_mut(); 2];
+    ralloc::alloc_batch(8, 8, 3, &mut bufs);
+}
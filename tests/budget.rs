@@ -0,0 +1,67 @@
+#![cfg(feature = "tls")]
+
+extern crate ralloc;
+
+use std::sync::atomic::{self, AtomicUsize, ATOMIC_USIZE_INIT};
+
+static EXCEEDED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn handler(net: usize) {
+    EXCEEDED.store(net, atomic::Ordering::SeqCst);
+}
+
+#[test]
+fn within() {
+    // Only the net allocations count.
+    let res = ralloc::budget(4096, || {
+        for _ in 0..100 {
+            let ptr = ralloc::alloc(1024, 8);
+            unsafe {
+                ralloc::free(ptr, 1024);
+            }
+        }
+
+        42
+    });
+
+    assert_eq!(res, Ok(42));
+}
+
+#[test]
+fn exceeded() {
+    let mut ptr = 0 as *mut u8;
+    let res = ralloc::budget_with(1024, handler, || {
+        ptr = ralloc::alloc(2048, 8);
+        7
+    });
+
+    match res {
+        Err(ralloc::Exceeded { value: 7, peak }) => assert!(peak >= 2048),
+        _ => panic!("The budget was not exceeded."),
+    }
+    assert!(EXCEEDED.load(atomic::Ordering::SeqCst) >= 2048);
+
+    unsafe {
+        ralloc::free(ptr, 2048);
+    }
+}
+
+#[test]
+fn nested() {
+    let mut ptr = 0 as *mut u8;
+    let res = ralloc::budget(4096, || {
+        ralloc::budget(1 << 16, || {
+            ptr = ralloc::alloc(8192, 8);
+        })
+    });
+
+    // The inner allocations count towards the outer budget.
+    match res {
+        Err(ralloc::Exceeded { value: Ok(()), .. }) => (),
+        _ => panic!("The outer budget was not exceeded."),
+    }
+
+    unsafe {
+        ralloc::free(ptr, 8192);
+    }
+}
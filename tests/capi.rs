@@ -0,0 +1,39 @@
+#![cfg(feature = "c_api")]
+
+extern crate ralloc;
+
+extern {
+    fn mallocx(size: usize, flags: i32) -> *mut u8;
+    fn sdallocx(ptr: *mut u8, size: usize, flags: i32);
+    fn recallocarray(ptr: *mut u8, oldnmemb: usize, nmemb: usize, size: usize) -> *mut u8;
+    fn malloc_trim(pad: usize) -> i32;
+}
+
+#[test]
+fn recalloc() {
+    unsafe {
+        let ptr = recallocarray(0 as *mut u8, 0, 10, 4);
+        assert!((0..40).all(|i| *ptr.offset(i) == 0));
+
+        *ptr = 1;
+        let ptr = recallocarray(ptr, 10, 1000, 4);
+        assert_eq!(*ptr, 1);
+        assert!((1..4000).all(|i| *ptr.offset(i) == 0));
+
+        // Overflowing sizes fail.
+        assert!(recallocarray(ptr, 1000, !0, 4).is_null());
+
+        sdallocx(ptr, 4000, 0);
+    }
+}
+
+#[test]
+fn trim() {
+    unsafe {
+        let ptr = mallocx(1 << 20, 0);
+        sdallocx(ptr, 1 << 20, 0);
+
+        // Nothing might be left to trim, if the thread caches keep the memory.
+        assert!(malloc_trim(0) >= 0);
+    }
+}
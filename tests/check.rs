@@ -0,0 +1,32 @@
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn check() {
+    util::multiply(|| {
+        let mut bufs = Vec::new();
+
+        for i in 1..300 {
+            bufs.push((ralloc::alloc(i * 3, 8), i * 3));
+
+            if i % 3 == 0 {
+                // Free some, so the pool has entries to check.
+                let (ptr, size) = bufs.swap_remove(i / 5);
+                unsafe {
+                    ralloc::free(ptr, size);
+                }
+            }
+        }
+
+        ralloc::check();
+
+        for (ptr, size) in bufs {
+            unsafe {
+                ralloc::free(ptr, size);
+            }
+        }
+
+        ralloc::check();
+    });
+}
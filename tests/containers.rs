@@ -0,0 +1,75 @@
+extern crate ralloc;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ralloc::{Arena, DroppingArena, Mmap, RBox, RVec};
+
+#[test]
+fn rbox() {
+    let arena = RefCell::new(Arena::new(Mmap));
+
+    let mut a = RBox::new_in(42u64, &arena);
+    *a += 1;
+    assert_eq!(*a, 43);
+
+    // Boxes can be nested.
+    let b = RBox::new_in(RBox::new_in([1u8; 100], &arena), &arena);
+    assert_eq!(b[99], 1);
+
+    assert_eq!(a.into_inner(), 43);
+}
+
+#[test]
+fn rvec() {
+    let arena = RefCell::new(Arena::new(Mmap));
+
+    let mut vec = RVec::new_in(&arena);
+    for i in 0..1000 {
+        vec.push(i);
+    }
+    assert_eq!(vec.len(), 1000);
+    assert!(vec.capacity() >= 1000);
+    assert_eq!(vec[999], 999);
+    assert_eq!(vec.iter().sum::<i32>(), 999 * 1000 / 2);
+
+    assert_eq!(vec.pop(), Some(999));
+    assert_eq!(vec.len(), 999);
+}
+
+#[test]
+fn drop_elements() {
+    let arena = RefCell::new(Arena::new(Mmap));
+    let rc = Rc::new(());
+
+    {
+        let mut vec = RVec::new_in(&arena);
+        for _ in 0..10 {
+            vec.push(rc.clone());
+        }
+        let _b = RBox::new_in(rc.clone(), &arena);
+
+        assert_eq!(Rc::strong_count(&rc), 12);
+    }
+
+    assert_eq!(Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn dropping_arena() {
+    let rc = Rc::new(());
+
+    {
+        let arena = DroppingArena::new(Mmap);
+        let a = arena.alloc(vec![rc.clone(); 10]);
+        let b = arena.alloc(vec![rc.clone()]);
+        a.push(rc.clone());
+        b.clear();
+
+        assert_eq!(arena.len(), 2);
+        assert_eq!(Rc::strong_count(&rc), 12);
+    }
+
+    // The objects were dropped along with the arena.
+    assert_eq!(Rc::strong_count(&rc), 1);
+}
@@ -0,0 +1,43 @@
+extern crate ralloc;
+
+#[test]
+fn bin_stats() {
+    assert_eq!(ralloc::ctl("bin.6.size"), Some(64));
+    assert_eq!(ralloc::ctl("bin.0.size"), Some(0));
+
+    let before = ralloc::ctl("bin.6.nallocs").unwrap();
+    let ptr = ralloc::alloc(100, 8);
+    assert!(ralloc::ctl("bin.6.nallocs").unwrap() > before);
+    assert!(ralloc::ctl("bin.6.curblocks").unwrap() > 0);
+
+    unsafe {
+        ralloc::free(ptr, 100);
+    }
+
+    assert!(ralloc::ctl("bin.6.nfrees").unwrap() > 0);
+    assert!(ralloc::ctl("stats.nallocs").unwrap() > 0);
+}
+
+#[test]
+fn unknown() {
+    assert_eq!(ralloc::ctl("stats.nonsense"), None);
+    assert_eq!(ralloc::ctl("bin.1000.size"), None);
+    assert_eq!(ralloc::ctl("bin.x.size"), None);
+    assert_eq!(ralloc::ctl("bin.6"), None);
+}
+
+#[test]
+fn pool_stats() {
+    let before = ralloc::pool_operations();
+
+    let ptrs: Vec<_> = (1..20).map(|n| (ralloc::alloc(n * 1000, 8), n * 1000)).collect();
+    for &(ptr, size) in &ptrs {
+        unsafe {
+            ralloc::free(ptr, size);
+        }
+    }
+
+    let after = ralloc::pool_operations();
+    assert!(after.merges + after.failed_merges > before.merges + before.failed_merges);
+    assert_eq!(ralloc::ctl("pool.nsplits").map(|n| n >= after.splits), Some(true));
+}
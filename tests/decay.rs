@@ -0,0 +1,46 @@
+extern crate ralloc;
+
+use std::ptr;
+
+use ralloc::Deadline;
+
+#[test]
+fn decay() {
+    ralloc::set_decay(1);
+
+    unsafe {
+        let a = ralloc::alloc(1 << 16, 1);
+        // Keeps the first buffer from being trimmed.
+        let b = ralloc::alloc(1 << 16, 1);
+
+        ptr::write_bytes(a, 1, 1 << 16);
+        ralloc::free(a, 1 << 16);
+        ralloc::flush_thread_cache();
+
+        // Dirty, muzzy, purged.
+        for _ in 0..3 {
+            ralloc::maintain(Deadline::Steps(0));
+        }
+
+        let (_, muzzy, purged) = ralloc::decay_stages();
+        assert!(muzzy + purged >= 1 << 15, "The free pages did not decay.");
+
+        // Decayed pages are reusable.
+        let c = ralloc::alloc(1 << 16, 1);
+        ptr::write_bytes(c, 2, 1 << 16);
+        assert_eq!(*c.offset(1 << 15), 2);
+
+        // Reused pages start over as dirty, even when freed again before the next tick (while
+        // the pages dirty before decay right away).
+        ralloc::free(c, 1 << 16);
+        ralloc::flush_thread_cache();
+        ralloc::maintain(Deadline::Steps(0));
+        let (dirty, _, _) = ralloc::decay_stages();
+        assert!(dirty >= 1 << 15, "The reused pages did not start over as dirty.");
+
+        ralloc::free(b, 1 << 16);
+    }
+
+    ralloc::set_decay(0);
+    ralloc::check();
+}
@@ -0,0 +1,66 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+use ralloc::ViolationPolicy;
+
+#[test]
+fn detach() {
+    util::multiply(|| {
+        let buf = ralloc::alloc(300, 8);
+
+        unsafe {
+            ptr::write_bytes(buf, 9, 300);
+            assert_eq!(ralloc::detach(buf, 300), Ok(()));
+
+            util::acid(|| {
+                // The buffer is ours for good.
+                assert_eq!(*buf.offset(299), 9);
+                *buf = 1;
+            });
+        }
+    });
+}
+
+#[test]
+fn detach_freed() {
+    let mut bufs = [ptr::null_mut(); 2];
+    ralloc::alloc_batch(64, 8, 2, &mut bufs);
+
+    unsafe {
+        ralloc::free(bufs[1], 64);
+
+        // Freed memory cannot be detached.
+        assert_eq!(ralloc::detach(bufs[1], 64), Err(()));
+        ralloc::free(bufs[0], 64);
+    }
+}
+
+#[test]
+fn detach_free_batch() {
+    ralloc::set_violation_policy(ViolationPolicy::Quarantine);
+
+    let buf = ralloc::alloc(512, 8);
+    let start = buf as usize;
+
+    unsafe {
+        assert_eq!(ralloc::detach(buf, 512), Ok(()));
+
+        // The buffer is detached, so it is left be.
+        let mut bufs = [(buf, 512), (ralloc::alloc(512, 8), 512)];
+        ralloc::free_batch(&mut bufs);
+    }
+
+    // It is never handed out again.
+    for _ in 0..64 {
+        let ptr = ralloc::alloc(512, 8);
+        assert!((ptr as usize) + 512 <= start || ptr as usize >= start + 512);
+        unsafe {
+            ralloc::free(ptr, 512);
+        }
+    }
+
+    ralloc::set_violation_policy(ViolationPolicy::Abort);
+}
@@ -0,0 +1,24 @@
+extern crate ralloc;
+
+static mut BUF: [u8; 8192] = [0; 8192];
+
+#[test]
+fn donate() {
+    unsafe {
+        let ptr = BUF.as_mut_ptr();
+
+        assert_eq!(ralloc::donate(ptr, 8192), Ok(()));
+        // It is free memory of the pool now, so it cannot be donated twice.
+        assert_eq!(ralloc::donate(ptr.offset(100), 100), Err(()));
+
+        // Donating nothing is fine.
+        assert_eq!(ralloc::donate(ptr, 0), Ok(()));
+    }
+
+    // The pool keeps working.
+    let buf = ralloc::alloc(4000, 8);
+    unsafe {
+        *buf.offset(3999) = 1;
+        ralloc::free(buf, 4000);
+    }
+}
@@ -0,0 +1,24 @@
+extern crate ralloc;
+
+#[test]
+fn write_dot() {
+    let a = ralloc::alloc(1000, 8);
+    let b = ralloc::alloc(1000, 8);
+    unsafe {
+        ralloc::free(a, 1000);
+    }
+
+    let mut out = String::new();
+    ralloc::debug::write_dot(&mut out).unwrap();
+
+    assert!(out.starts_with("digraph ralloc {\n"));
+    assert!(out.ends_with("}\n"));
+    assert!(out.contains("pool [label=\"global pool\\n"));
+    assert!(out.contains("meta [shape=record"));
+    // The braces are balanced.
+    assert_eq!(out.matches('{').count(), out.matches('}').count());
+
+    unsafe {
+        ralloc::free(b, 1000);
+    }
+}
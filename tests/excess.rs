@@ -0,0 +1,54 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn excess() {
+    util::multiply(|| {
+        for size in 1..200 {
+            let (buf, granted) = ralloc::alloc_excess(size, 8);
+            assert!(granted >= size, "Granted less than requested.");
+            assert_eq!(buf as usize % 8, 0);
+
+            unsafe {
+                util::acid(|| {
+                    // The slack is ours too.
+                    ptr::write_bytes(buf, 0x42, granted);
+                    assert_eq!(*buf.offset(granted as isize - 1), 0x42);
+                });
+
+                ralloc::free(buf, granted);
+            }
+        }
+    });
+}
+
+#[test]
+fn excess_partial_free() {
+    util::multiply(|| {
+        let (buf, granted) = ralloc::alloc_excess(100, 4);
+
+        unsafe {
+            util::acid(|| {
+                ptr::write_bytes(buf, 0, granted);
+            });
+
+            // The slack and the buffer can be freed apart.
+            ralloc::free(buf.offset(100), granted - 100);
+            *buf.offset(99) = 1;
+            ralloc::free(buf, 100);
+        }
+    });
+}
+
+#[test]
+fn excess_zero_size() {
+    let (buf, granted) = ralloc::alloc_excess(0, 16);
+    assert_eq!(granted, 0);
+
+    unsafe {
+        ralloc::free(buf, 0);
+    }
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "std")]
+
+extern crate log;
+extern crate ralloc;
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+struct Counter {
+    records: AtomicUsize,
+}
+
+impl log::Log for Counter {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target() == "ralloc"
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            // The logger may allocate.
+            let msg = format!("{}", record.args());
+            assert!(!msg.is_empty());
+
+            self.records.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Counter = Counter {
+    records: ATOMIC_USIZE_INIT,
+};
+
+#[test]
+fn facade() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let ptr = ralloc::alloc(100, 8);
+    unsafe {
+        ralloc::free(ptr, 100);
+    }
+
+    assert!(LOGGER.records.load(Ordering::SeqCst) > 0);
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "fast_bins")]
+
+extern crate ralloc;
+
+use std::thread;
+
+#[test]
+fn aligned() {
+    for size in 1..65 {
+        let ptr = ralloc::alloc(size, 1);
+        assert_eq!(ptr as usize % 16, 0);
+
+        unsafe {
+            ralloc::free(ptr, size);
+        }
+    }
+}
+
+#[test]
+fn realloc_across_classes() {
+    unsafe {
+        let ptr = ralloc::alloc(20, 8);
+        *ptr = 42;
+
+        // Within the class, the buffer stays.
+        assert!(ralloc::realloc_inplace(ptr, 20, 32).is_ok());
+        assert!(ralloc::realloc_inplace(ptr, 32, 33).is_err());
+
+        let ptr = ralloc::realloc(ptr, 32, 200, 8);
+        assert_eq!(*ptr, 42);
+        let ptr = ralloc::realloc(ptr, 200, 8, 8);
+        assert_eq!(*ptr, 42);
+
+        ralloc::free(ptr, 8);
+    }
+}
+
+#[test]
+fn contended() {
+    let threads: Vec<_> = (0..8).map(|n| thread::spawn(move || {
+        let mut bufs = Vec::with_capacity(64);
+
+        for i in 0..0x1000 {
+            let size = 1 + i % 64;
+            let ptr = ralloc::alloc(size, 8);
+            unsafe {
+                *ptr = n as u8;
+            }
+            bufs.push((ptr, size));
+
+            if bufs.len() == 64 {
+                for (ptr, size) in bufs.drain(..) {
+                    unsafe {
+                        assert_eq!(*ptr, n as u8);
+                        ralloc::free(ptr, size);
+                    }
+                }
+            }
+        }
+    })).collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn batch_padding() {
+    // The stride is 104 (not a multiple of 16), so the 4 bytes of padding after each buffer are
+    // no object of the bins.
+    let mut bufs = [std::ptr::null_mut(); 8];
+    ralloc::alloc_batch(100, 8, 8, &mut bufs);
+
+    unsafe {
+        for &buf in &bufs {
+            std::ptr::write_bytes(buf, 0xAA, 100);
+        }
+
+        // Small allocations never overlap the buffers.
+        let small: Vec<_> = (0..64).map(|_| ralloc::alloc(16, 16)).collect();
+        for &ptr in &small {
+            assert!(bufs.iter().all(|&buf| ptr as usize + 16 <= buf as usize
+                                    || ptr as usize >= buf as usize + 100));
+            std::ptr::write_bytes(ptr, 0x55, 16);
+        }
+
+        for &buf in &bufs {
+            for i in 0..100 {
+                assert_eq!(*buf.offset(i), 0xAA);
+            }
+            ralloc::free(buf, 100);
+        }
+        for ptr in small {
+            ralloc::free(ptr, 16);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+extern crate ralloc;
+
+use ralloc::ViolationPolicy;
+
+#[test]
+fn foreign_free() {
+    // The checks are always enabled in debug mode.
+    ralloc::set_checks(true);
+    ralloc::set_violation_policy(ViolationPolicy::Quarantine);
+
+    let mut foreign = [0u8; 256];
+    let start = foreign.as_mut_ptr() as usize;
+
+    unsafe {
+        // The buffer never came from ralloc, so it is left be.
+        ralloc::free(foreign.as_mut_ptr(), 256);
+        let buf = ralloc::realloc(foreign.as_mut_ptr().offset(64), 64, 128, 8);
+        assert!((buf as usize) < start || buf as usize >= start + 256);
+        ralloc::free(buf, 128);
+    }
+
+    // The pool is intact, and never hands out the foreign buffer.
+    for _ in 0..64 {
+        let ptr = ralloc::alloc(256, 8);
+        assert!((ptr as usize) + 256 <= start || ptr as usize >= start + 256);
+        unsafe {
+            ralloc::free(ptr, 256);
+        }
+    }
+    ralloc::check();
+
+    ralloc::set_violation_policy(ViolationPolicy::Abort);
+}
@@ -0,0 +1,51 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn free_batch() {
+    util::multiply(|| {
+        let mut bufs = Vec::new();
+        for n in 0..64 {
+            let buf = ralloc::alloc(n + 1, 8);
+
+            unsafe {
+                ptr::write_bytes(buf, n as u8, n + 1);
+            }
+
+            bufs.push((buf, n + 1));
+        }
+
+        // Some adjacent buffers, which are merged.
+        let mut adjacent = [ptr::null_mut(); 8];
+        ralloc::alloc_batch(16, 16, 8, &mut adjacent);
+        for &buf in adjacent.iter().rev() {
+            bufs.push((buf, 16));
+        }
+
+        // And a zero-sized one.
+        bufs.push((ralloc::alloc(0, 4), 0));
+
+        unsafe {
+            util::acid(|| {
+                ralloc::free_batch(&mut bufs);
+            });
+        }
+
+        // The memory is reusable.
+        let buf = ralloc::alloc(128, 16);
+        unsafe {
+            ptr::write_bytes(buf, 0, 128);
+            ralloc::free(buf, 128);
+        }
+    });
+}
+
+#[test]
+fn free_batch_empty() {
+    unsafe {
+        ralloc::free_batch(&mut []);
+    }
+}
@@ -0,0 +1,20 @@
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn free_sized() {
+    util::multiply(|| {
+        for align in (0..8).map(|n| 1 << n) {
+            let buf = ralloc::alloc(37, align);
+
+            unsafe {
+                util::acid(|| {
+                    *buf.offset(36) = 1;
+                });
+
+                ralloc::free_sized(buf, 37, align);
+            }
+        }
+    });
+}
@@ -0,0 +1,59 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn freeze() {
+    util::multiply(|| {
+        let buf = ralloc::alloc(4 * 4096, 8);
+
+        unsafe {
+            ptr::write_bytes(buf, 0x33, 4 * 4096);
+            assert_eq!(ralloc::freeze(buf, 4 * 4096), Ok(()));
+
+            util::acid(|| {
+                // Reading is fine.
+                assert_eq!(*buf.offset(2 * 4096), 0x33);
+            });
+
+            // The pages are made writable, before they are reused.
+            ralloc::free(buf, 4 * 4096);
+
+            let buf = ralloc::alloc(4 * 4096, 8);
+            ptr::write_bytes(buf, 0x44, 4 * 4096);
+            ralloc::free(buf, 4 * 4096);
+        }
+    });
+}
+
+#[test]
+fn freeze_realloc() {
+    util::multiply(|| {
+        let buf = ralloc::alloc(3 * 4096, 4096);
+
+        unsafe {
+            assert_eq!(ralloc::freeze(buf, 3 * 4096), Ok(()));
+
+            // Reallocating thaws the buffer.
+            let buf = ralloc::realloc(buf, 3 * 4096, 4096, 4096);
+            *buf.offset(4095) = 1;
+
+            ralloc::free(buf, 4096);
+        }
+    });
+}
+
+#[test]
+fn freeze_small() {
+    // A buffer spanning no whole page is left as is.
+    let buf = ralloc::alloc(16, 8);
+
+    unsafe {
+        assert_eq!(ralloc::freeze(buf, 16), Ok(()));
+        *buf = 2;
+
+        ralloc::free(buf, 16);
+    }
+}
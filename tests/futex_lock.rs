@@ -0,0 +1,27 @@
+#![cfg(feature = "futex_lock")]
+
+extern crate ralloc;
+
+use std::thread;
+
+#[test]
+fn contended() {
+    // Without the thread caches, every allocation takes the lock of the global allocator.
+    let threads: Vec<_> = (0..8).map(|n| thread::spawn(move || {
+        ralloc::disable_thread_cache();
+
+        for i in 0..0x1000 {
+            let ptr = ralloc::alloc(16 + i % 64, 8);
+            unsafe {
+                *ptr = n as u8;
+                assert_eq!(*ptr, n as u8);
+
+                ralloc::free(ptr, 16 + i % 64);
+            }
+        }
+    })).collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+extern crate ralloc;
+
+// The governor is global, so the cases must not run in parallel.
+#[test]
+fn governor() {
+    // Growing faster than allowed fails.
+    ralloc::set_growth_governor(1 << 20, 3600 * 1000, 0);
+    assert!(ralloc::try_alloc(1 << 24, 8).is_err());
+
+    ralloc::set_growth_governor(!0, 1000, 0);
+    let a = ralloc::try_alloc(1 << 24, 8).unwrap();
+    let b = ralloc::try_alloc(1 << 24, 8).unwrap();
+    unsafe {
+        ralloc::free(a, 1 << 24);
+    }
+
+    // At most half of the heap is in use now, so it doesn't grow.
+    ralloc::set_growth_governor(!0, 1000, 90);
+    assert!(ralloc::try_alloc(1 << 26, 8).is_err());
+
+    ralloc::set_growth_governor(!0, 1000, 0);
+    let c = ralloc::try_alloc(1 << 26, 8).unwrap();
+    unsafe {
+        ralloc::free(b, 1 << 24);
+        ralloc::free(c, 1 << 26);
+    }
+    ralloc::check();
+}
@@ -0,0 +1,26 @@
+extern crate ralloc;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static GROWN: AtomicUsize = AtomicUsize::new(0);
+
+fn callback(old_end: *mut u8, new_end: *mut u8, _: ralloc::Growth) {
+    assert!(new_end > old_end, "The heap shrank.");
+
+    GROWN.fetch_add(new_end as usize - old_end as usize, Ordering::SeqCst);
+}
+
+#[test]
+fn callback_fired() {
+    ralloc::on_heap_growth(Some(callback));
+
+    // Far more than the heap holds yet.
+    let ptr = ralloc::alloc(64 << 20, 8);
+    assert!(GROWN.load(Ordering::SeqCst) >= 64 << 20);
+
+    ralloc::on_heap_growth(None);
+
+    unsafe {
+        ralloc::free(ptr, 64 << 20);
+    }
+}
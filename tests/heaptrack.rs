@@ -0,0 +1,45 @@
+#![cfg(feature = "heaptrack")]
+
+extern crate ralloc;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+static FREES: AtomicUsize = AtomicUsize::new(0);
+
+// Stand-ins for the reports of heaptrack, which the weak symbols resolve to.
+
+#[no_mangle]
+pub extern fn heaptrack_report_alloc(_: *mut u8, size: usize) {
+    if size == 12345 {
+        ALLOCS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[no_mangle]
+pub extern fn heaptrack_report_realloc(_: *mut u8, size: usize, _: *mut u8) {
+    if size == 23456 {
+        REALLOCS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[no_mangle]
+pub extern fn heaptrack_report_free(_: *mut u8) {
+    FREES.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn reported() {
+    let frees = FREES.load(Ordering::SeqCst);
+
+    let ptr = ralloc::alloc(12345, 8);
+    unsafe {
+        let ptr = ralloc::realloc(ptr, 12345, 23456, 8);
+        ralloc::free(ptr, 23456);
+    }
+
+    assert!(ALLOCS.load(Ordering::SeqCst) >= 1);
+    assert!(REALLOCS.load(Ordering::SeqCst) >= 1);
+    assert!(FREES.load(Ordering::SeqCst) > frees);
+}
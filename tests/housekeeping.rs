@@ -0,0 +1,91 @@
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn housekeep() {
+    util::multiply(|| {
+        let ptrs: Vec<_> = (0..100).map(|n| ralloc::alloc(n * 10 + 1, 8)).collect();
+
+        ralloc::housekeep();
+
+        for (n, ptr) in ptrs.into_iter().enumerate() {
+            unsafe {
+                ralloc::free(ptr, n * 10 + 1);
+            }
+        }
+
+        ralloc::housekeep();
+        ralloc::check();
+    });
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn thread() {
+    use std::thread;
+    use std::time::Duration;
+
+    assert_eq!(ralloc::start_housekeeping(Duration::from_millis(1)), Ok(()));
+    // Only one thread is started.
+    assert_eq!(ralloc::start_housekeeping(Duration::from_millis(1)), Err(()));
+
+    for _ in 0..100 {
+        let ptr = ralloc::alloc(1000, 8);
+        thread::sleep(Duration::from_millis(1) / 10);
+        unsafe {
+            ralloc::free(ptr, 1000);
+        }
+    }
+
+    ralloc::stop_housekeeping();
+
+    // Restarting right away leaves a single thread, as the stopped one exits when it wakes up.
+    assert_eq!(ralloc::start_housekeeping(Duration::from_millis(1)), Ok(()));
+    thread::sleep(Duration::from_millis(50));
+    #[cfg(target_os = "linux")]
+    assert_eq!(housekeeping_threads(), 1);
+
+    ralloc::stop_housekeeping();
+    thread::sleep(Duration::from_millis(50));
+    #[cfg(target_os = "linux")]
+    assert_eq!(housekeeping_threads(), 0);
+}
+
+/// Count the housekeeping threads of the process.
+#[cfg(all(feature = "std", target_os = "linux"))]
+fn housekeeping_threads() -> usize {
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    fs::read_dir("/proc/self/task").unwrap().filter(|task| {
+        let mut comm = String::new();
+        let _ = File::open(task.as_ref().unwrap().path().join("comm"))
+            .and_then(|mut file| file.read_to_string(&mut comm));
+
+        // The names of the threads are truncated to 15 bytes.
+        comm.trim() == "ralloc-housekee"
+    }).count()
+}
+
+#[test]
+fn maintain() {
+    use ralloc::Deadline;
+
+    fn now() -> bool {
+        true
+    }
+
+    // Nothing is done past the deadline.
+    assert!(!ralloc::maintain(Deadline::Until(now)), "Work was done past the deadline.");
+    assert!(!ralloc::maintain(Deadline::Steps(0)), "Work was done past the deadline.");
+
+    // The work is done in bounded steps.
+    let mut rounds = 0;
+    while !ralloc::maintain(Deadline::Steps(1)) {
+        rounds += 1;
+        assert!(rounds < 1 << 20, "The housekeeping never finishes.");
+    }
+
+    ralloc::check();
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "failure_injection")]
+
+extern crate ralloc;
+
+use std::thread;
+
+use ralloc::{Injected, Injection};
+
+fn is_marked(size: usize) -> bool {
+    size == 12345
+}
+
+fn panicking_oom_handler(_: ralloc::Error) -> ! {
+    panic!("Injected failure.");
+}
+
+// The injection is global, so the cases must not run in parallel.
+#[test]
+fn inject() {
+    // Every other marked allocation takes fresh memory.
+    ralloc::set_injection(Some(Injection {
+        every: 2,
+        predicate: Some(is_marked),
+        action: Injected::Fresh,
+    }));
+    for _ in 0..10 {
+        let ptr = ralloc::alloc(12345, 8);
+        unsafe {
+            *ptr.offset(12344) = 1;
+            ralloc::free(ptr, 12345);
+        }
+    }
+    ralloc::check();
+
+    // Marked allocations fail.
+    ralloc::set_injection(Some(Injection {
+        every: 1,
+        predicate: Some(is_marked),
+        action: Injected::Fail,
+    }));
+    let res = thread::spawn(|| {
+        ralloc::set_thread_oom_handler(panicking_oom_handler);
+        ralloc::alloc(12345, 8)
+    }).join();
+    assert!(res.is_err());
+
+    // Unmarked ones are untouched.
+    let ptr = ralloc::alloc(100, 8);
+    unsafe {
+        ralloc::free(ptr, 100);
+    }
+
+    ralloc::set_injection(None);
+    let ptr = ralloc::alloc(12345, 8);
+    unsafe {
+        ralloc::free(ptr, 12345);
+    }
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "interior_pointers")]
+
+extern crate ralloc;
+
+use std::thread;
+
+// Freed memory is reused, so the cases must not run in parallel.
+#[test]
+fn free_interior() {
+    let a = ralloc::alloc(1000, 8);
+    let b = ralloc::alloc(50, 8);
+
+    unsafe {
+        assert_eq!(ralloc::resolve_interior(a.offset(999)), Some((a, 1000)));
+        assert_eq!(ralloc::resolve_interior(b), Some((b, 50)));
+
+        assert_eq!(ralloc::free_interior(a.offset(500)), Ok(()));
+        assert_eq!(ralloc::resolve_interior(a.offset(500)), None);
+        // Freed buffers cannot be freed again.
+        assert_eq!(ralloc::free_interior(a.offset(500)), Err(()));
+
+        ralloc::free_interior(b.offset(49)).unwrap();
+    }
+
+    let a = ralloc::alloc(300, 8);
+
+    unsafe {
+        // Give back the middle of the buffer.
+        ralloc::free_part(a, 300, 100..200);
+
+        assert_eq!(ralloc::resolve_interior(a.offset(50)), Some((a, 100)));
+        assert_eq!(ralloc::resolve_interior(a.offset(150)), None);
+        assert_eq!(ralloc::resolve_interior(a.offset(250)), Some((a.offset(200), 100)));
+
+        ralloc::free_interior(a.offset(99)).unwrap();
+        ralloc::free_interior(a.offset(200)).unwrap();
+    }
+
+    // The same addresses are freed and reallocated by two threads at once, so a stale entry of
+    // one would shadow the allocations of the other.
+    let threads: Vec<_> = (0..2).map(|n| thread::spawn(move || {
+        for _ in 0..1000 {
+            let ptr = ralloc::alloc(256, 8);
+
+            unsafe {
+                assert_eq!(ralloc::resolve_interior(ptr.offset(100)), Some((ptr, 256)));
+
+                let ptr = if n == 0 {
+                    ptr
+                } else {
+                    let ptr = ralloc::realloc(ptr, 256, 4096, 8);
+                    assert_eq!(ralloc::resolve_interior(ptr.offset(4000)), Some((ptr, 4096)));
+                    ralloc::realloc(ptr, 4096, 256, 8)
+                };
+
+                assert_eq!(ralloc::resolve_interior(ptr.offset(255)), Some((ptr, 256)));
+                ralloc::free(ptr, 256);
+            }
+        }
+    })).collect();
+
+    for i in threads {
+        i.join().unwrap();
+    }
+}
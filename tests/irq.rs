@@ -0,0 +1,56 @@
+extern crate ralloc;
+
+use std::thread;
+
+static POOLS: [ralloc::IrqPool; 2] = [ralloc::IrqPool::new(100, 32), ralloc::IrqPool::new(1, 1)];
+
+#[test]
+fn reservation() {
+    let pool = &POOLS[0];
+    assert!(pool.is_empty());
+    assert!(pool.alloc().is_none());
+
+    pool.refill(16);
+    assert_eq!(pool.len(), 16);
+
+    let objects: Vec<_> = (0..16).map(|_| pool.alloc().unwrap()).collect();
+    for &ptr in &objects {
+        assert_eq!(ptr as usize % 32, 0);
+        unsafe {
+            *ptr.offset(99) = 1;
+        }
+    }
+
+    // Empty pools fail rather than allocate.
+    assert!(pool.alloc().is_none());
+
+    for ptr in objects {
+        unsafe {
+            pool.free(ptr);
+        }
+    }
+    assert_eq!(pool.len(), 16);
+}
+
+#[test]
+fn concurrent() {
+    let pool = &POOLS[1];
+    pool.refill(64);
+
+    let threads: Vec<_> = (0..8).map(|_| thread::spawn(|| {
+        let pool = &POOLS[1];
+        for _ in 0..10000 {
+            if let Some(ptr) = pool.alloc() {
+                unsafe {
+                    pool.free(ptr);
+                }
+            }
+        }
+    })).collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(pool.len(), 64);
+}
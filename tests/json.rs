@@ -0,0 +1,27 @@
+extern crate ralloc;
+
+#[test]
+fn to_json() {
+    let a = ralloc::alloc(1000, 8);
+    let b = ralloc::alloc(1000, 8);
+    unsafe {
+        ralloc::free(a, 1000);
+    }
+
+    let mut out = String::new();
+    ralloc::debug::to_json(&mut out).unwrap();
+
+    assert!(out.starts_with("{\"heap\":{\"start\":"));
+    assert!(out.ends_with("}}\n"));
+    assert_eq!(out.lines().count(), 1);
+    assert!(out.contains("\"pool\":{\"free\":"));
+    assert!(out.contains("\"stats\":{\"in_use\":"));
+    assert!(out.contains("\"config\":{\"checks\":"));
+    // The braces and brackets are balanced.
+    assert_eq!(out.matches('{').count(), out.matches('}').count());
+    assert_eq!(out.matches('[').count(), out.matches(']').count());
+
+    unsafe {
+        ralloc::free(b, 1000);
+    }
+}
@@ -0,0 +1,39 @@
+extern crate ralloc;
+
+mod util;
+
+use ralloc::Allocator;
+use ralloc::layer::{Layer, Poison, Quarantine, Stats, Tracing};
+
+fn sink(_: ralloc::Event) {}
+
+#[test]
+fn stack() {
+    let mut alloc = Stats::new(Tracing::new(Poison::new(Quarantine::new(Allocator)), sink));
+
+    let a = alloc.alloc(100, 8);
+    let b = alloc.alloc(200, 16);
+    assert_eq!(alloc.in_use(), 300);
+
+    unsafe {
+        // Fresh memory is poisoned.
+        assert_eq!(*a.offset(50), 0xAA);
+
+        util::acid(|| {
+            *b.offset(199) = 1;
+        });
+        let b = alloc.realloc(b, 200, 1000, 16);
+        assert_eq!(*b.offset(199), 1);
+        assert_eq!(*b.offset(999), 0xAA);
+
+        alloc.free(a, 100);
+        // Quarantined memory is not reused, and poisoned.
+        assert_eq!(*a.offset(50), 0xDD);
+        alloc.free(b, 1000);
+    }
+
+    assert_eq!(alloc.allocs(), 2);
+    assert_eq!(alloc.frees(), 2);
+    assert_eq!(alloc.in_use(), 0);
+    assert_eq!(alloc.peak(), 1100);
+}
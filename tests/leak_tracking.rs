@@ -0,0 +1,50 @@
+#![cfg(feature = "leak_tracking")]
+
+extern crate ralloc;
+
+use std::{mem, ptr};
+
+#[inline(never)]
+fn leak(size: usize) -> *mut u8 {
+    ralloc::alloc(size, 8)
+}
+
+#[test]
+fn live_allocations() {
+    let ptrs: Vec<_> = (0..10).map(|_| leak(1000)).collect();
+
+    // The buffers come from the same stack.
+    assert!(ralloc::debug::live_allocations().any(|site| site.count >= 10 && site.bytes >= 10000));
+
+    for ptr in ptrs {
+        unsafe {
+            ralloc::free(ptr, 1000);
+        }
+    }
+
+    // Sites are ordered by size.
+    let sites: Vec<_> = ralloc::debug::live_allocations().map(|site| site.bytes).collect();
+    assert!(sites.windows(2).all(|x| x[0] >= x[1]));
+}
+
+#[test]
+fn find_leaks() {
+    unsafe {
+        let kept = leak(100);
+        ptr::write_bytes(kept, 0, 100);
+        // Reached through `kept`.
+        let inner = leak(100);
+        *(kept as *mut usize) = inner.offset(10) as usize;
+        let lost = leak(12345);
+
+        let roots = [(&kept as *const *mut u8 as *const u8, mem::size_of::<*mut u8>())];
+        let leaks: Vec<_> = ralloc::debug::find_leaks(&roots).map(|leaked| leaked.ptr).collect();
+        assert!(leaks.contains(&(lost as usize)));
+        assert!(!leaks.contains(&(kept as usize)));
+        assert!(!leaks.contains(&(inner as usize)));
+
+        ralloc::free(kept, 100);
+        ralloc::free(inner, 100);
+        ralloc::free(lost, 12345);
+    }
+}
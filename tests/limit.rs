@@ -0,0 +1,24 @@
+extern crate ralloc;
+
+// The limit is global, so the cases must not run in parallel.
+#[test]
+fn limit() {
+    ralloc::set_limit(1 << 28);
+
+    // Exceeding the limit fails.
+    assert!(ralloc::try_alloc(1 << 29, 8).is_err());
+
+    // Allocations within the limit are still served.
+    let ptr = ralloc::try_alloc(1 << 20, 8).unwrap();
+    unsafe {
+        *ptr.offset((1 << 20) - 1) = 1;
+        ralloc::free(ptr, 1 << 20);
+    }
+    ralloc::check();
+
+    ralloc::set_limit(!0);
+    let ptr = ralloc::try_alloc(1 << 29, 8).unwrap();
+    unsafe {
+        ralloc::free(ptr, 1 << 29);
+    }
+}
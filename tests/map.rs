@@ -0,0 +1,23 @@
+extern crate ralloc;
+
+#[test]
+fn write_map() {
+    let ptr = ralloc::alloc(1 << 16, 8);
+
+    let mut out = String::new();
+    ralloc::debug::write_map(&mut out, 64).unwrap();
+
+    let mut lines = out.lines();
+    let bar = lines.next().unwrap();
+    assert!(bar.starts_with("0x"));
+
+    let bar = &bar[bar.find('[').unwrap() + 1..bar.find(']').unwrap()];
+    assert!(bar.len() <= 64);
+    assert!(bar.chars().all(|c| ".#+m".contains(c)));
+
+    assert!(lines.next().unwrap().ends_with("blocks."));
+
+    unsafe {
+        ralloc::free(ptr, 1 << 16);
+    }
+}
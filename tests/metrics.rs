@@ -0,0 +1,28 @@
+extern crate ralloc;
+
+#[test]
+fn prometheus() {
+    let ptr = ralloc::alloc(1000, 8);
+
+    let mut out = String::new();
+    ralloc::metrics_prometheus(&mut out).unwrap();
+
+    assert!(out.contains("# TYPE ralloc_in_use_bytes gauge\n"));
+    assert!(out.contains("# TYPE ralloc_allocations_total counter\n"));
+
+    // Every sample is a name and a value.
+    for line in out.lines().filter(|line| !line.starts_with('#')) {
+        let mut words = line.split(' ');
+        assert!(words.next().unwrap().starts_with("ralloc_"));
+        assert!(words.next().unwrap().parse::<usize>().is_ok());
+        assert!(words.next().is_none());
+    }
+
+    let allocs = out.lines().find(|line| line.starts_with("ralloc_allocations_total "))
+        .unwrap()[25..].parse::<usize>().unwrap();
+    assert!(allocs >= 1);
+
+    unsafe {
+        ralloc::free(ptr, 1000);
+    }
+}
@@ -0,0 +1,27 @@
+#![feature(allocator_api)]
+
+extern crate ralloc;
+
+use std::heap::Layout;
+
+#[test]
+fn map_without_blocking() {
+    let ptr = ralloc::alloc(1000, 8);
+
+    ralloc::debug::report_map(32);
+
+    unsafe {
+        ralloc::free(ptr, 1000);
+    }
+}
+
+#[test]
+#[should_panic(expected = "4096 bytes requested")]
+fn alloc_error_handled() {
+    fn handler(err: ralloc::Error) -> ! {
+        panic!("{}", err);
+    }
+
+    ralloc::set_oom_handler(handler);
+    ralloc::handle_alloc_error(Layout::from_size_align(4096, 8).unwrap());
+}
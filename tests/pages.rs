@@ -0,0 +1,46 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn pages() {
+    util::multiply(|| {
+        let buf = ralloc::alloc_pages(3);
+        assert_eq!(buf as usize % 4096, 0);
+
+        unsafe {
+            util::acid(|| {
+                ptr::write_bytes(buf, 0x1F, 3 * 4096);
+                assert_eq!(*buf.offset(3 * 4096 - 1), 0x1F);
+            });
+
+            // Pages can be freed apart.
+            ralloc::free_pages(buf.offset(2 * 4096), 1);
+            *buf.offset(4096) = 2;
+            ralloc::free_pages(buf, 2);
+        }
+    });
+}
+
+#[test]
+fn pages_zero() {
+    let buf = ralloc::alloc_pages(0);
+    assert_eq!(buf as usize % 4096, 0);
+
+    unsafe {
+        ralloc::free_pages(buf, 0);
+    }
+}
+
+#[test]
+fn pages_in_use() {
+    // Other tests run concurrently, so we only check the lower bound.
+    let buf = ralloc::alloc_pages(16);
+    assert!(ralloc::pages_in_use() >= 16, "The pages are not tracked.");
+
+    unsafe {
+        ralloc::free_pages(buf, 16);
+    }
+}
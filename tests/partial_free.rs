@@ -54,3 +54,35 @@ fn partial_free_double() {
         }
     });
 }
+
+#[test]
+fn free_part() {
+    util::multiply(|| {
+        let buf = ralloc::alloc(100, 4);
+
+        unsafe {
+            util::acid(|| {
+                ptr::write_bytes(buf, 7, 100);
+            });
+
+            // Give back the middle.
+            let ((head, head_size), (tail, tail_size)) = ralloc::free_part(buf, 100, 20..70);
+            assert_eq!((head, head_size), (buf, 20));
+            assert_eq!((tail, tail_size), (buf.offset(70), 30));
+
+            util::acid(|| {
+                assert_eq!(*head.offset(19), 7);
+                assert_eq!(*tail.offset(29), 7);
+            });
+
+            ralloc::free(head, head_size);
+
+            // Give back the tail, leaving nothing after it.
+            let (rest, end) = ralloc::free_part(tail, tail_size, 10..30);
+            assert_eq!(rest, (tail, 10));
+            assert_eq!(end.1, 0);
+
+            ralloc::free(rest.0, rest.1);
+        }
+    });
+}
@@ -0,0 +1,24 @@
+extern crate ralloc;
+
+// The peaks are global, so the cases must not run in parallel.
+#[test]
+fn peak() {
+    ralloc::reset_peak();
+    let before = ralloc::peak();
+
+    let ptr = ralloc::alloc(1 << 20, 8);
+    let usage = ralloc::peak();
+    assert!(usage.in_use >= before.in_use + (1 << 20));
+    assert!(usage.extent >= 1 << 20);
+
+    unsafe {
+        ralloc::free(ptr, 1 << 20);
+    }
+    let usage = ralloc::peak();
+    assert!(usage.peak_in_use >= before.in_use + (1 << 20));
+    assert!(usage.peak_extent >= usage.extent);
+
+    // The peak comes down to the current usage.
+    ralloc::reset_peak();
+    assert!(ralloc::peak().peak_in_use < before.in_use + (1 << 20));
+}
@@ -0,0 +1,41 @@
+extern crate ralloc;
+
+use ralloc::Allocator;
+use ralloc::layer::{Layer, Poison, Quarantine, verify_poison};
+
+fn panic(violation: &ralloc::Violation) -> ! {
+    panic!("{}", violation);
+}
+
+#[test]
+fn custom_bytes() {
+    let mut alloc = Poison::with_bytes(Quarantine::verifying(Allocator, 0x5A), 0x11, 0x5A);
+
+    let ptr = alloc.alloc(64, 8);
+    unsafe {
+        assert_eq!(verify_poison(ptr, 64, 0x11), Ok(()));
+        *ptr.offset(10) = 0;
+        assert_eq!(verify_poison(ptr, 64, 0x11), Err(10));
+
+        alloc.free(ptr, 64);
+        assert_eq!(verify_poison(ptr, 64, 0x5A), Ok(()));
+    }
+}
+
+#[test]
+#[should_panic(expected = "Freed memory was written to")]
+fn write_after_free() {
+    ralloc::set_violation_policy(ralloc::ViolationPolicy::Callback(panic));
+
+    let mut alloc = Poison::new(Quarantine::verifying(Allocator, 0xDD));
+
+    let ptr = alloc.alloc(64, 8);
+    unsafe {
+        alloc.free(ptr, 64);
+
+        // The buffer is quarantined, so this is caught on eviction.
+        *ptr.offset(32) = 1;
+    }
+
+    drop(alloc);
+}
@@ -0,0 +1,61 @@
+extern crate ralloc;
+
+use ralloc::{Arena, Fixed};
+use ralloc::policy::{DefaultSecurity, ExactGrowth, FirstFit, HardenedPolicy, Policy,
+                     StaticStorage};
+
+/// The buffer of the exactly growing arena.
+static mut EXACT: [u8; 1 << 16] = [0; 1 << 16];
+/// The buffer of the hardened arena.
+static mut HARDENED: [u8; 1 << 16] = [0; 1 << 16];
+
+/// A policy for scarce memory, which never maps metadata.
+struct Exact;
+
+impl Policy for Exact {
+    type Fit = FirstFit;
+    type Growth = ExactGrowth;
+    type Security = DefaultSecurity;
+    type Storage = StaticStorage;
+}
+
+#[test]
+fn exact() {
+    let mut arena: Arena<_, Exact> = Arena::with_policy(Fixed::new(unsafe { &mut EXACT }));
+
+    let ptr = arena.alloc(100, 1);
+    // Only the allocation (and room for aligning it) is acquired.
+    assert!(arena.acquired() <= 101, "The arena acquired more than needed.");
+
+    unsafe {
+        arena.free(ptr, 100);
+    }
+
+    // The pool takes its metadata from the static storage.
+    let ptrs: Vec<_> = (0..256).map(|_| arena.alloc(16, 1)).collect();
+    for (n, &ptr) in ptrs.iter().enumerate() {
+        // Every other buffer, so the free blocks are not merged.
+        if n % 2 == 0 {
+            unsafe {
+                arena.free(ptr, 16);
+            }
+        }
+    }
+}
+
+#[test]
+fn hardened() {
+    let mut arena: Arena<_, HardenedPolicy> =
+        Arena::with_policy(Fixed::new(unsafe { &mut HARDENED }));
+
+    let ptrs: Vec<_> = (0..64).map(|n| arena.alloc(n * 8 + 8, 8)).collect();
+    for (n, &ptr) in ptrs.iter().enumerate() {
+        unsafe {
+            *ptr = 0xFF;
+            arena.free(ptr, n * 8 + 8);
+
+            // Freed memory is zeroed.
+            assert_eq!(*ptr, 0);
+        }
+    }
+}
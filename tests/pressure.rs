@@ -0,0 +1,36 @@
+extern crate ralloc;
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+const SIZE: usize = 48 << 20;
+
+/// A cache of the application, which can be dropped under memory pressure.
+static CACHE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn drop_cache() {
+    let ptr = CACHE.swap(0, Ordering::SeqCst);
+    if ptr != 0 {
+        unsafe {
+            ralloc::free(ptr as *mut u8, SIZE);
+        }
+    }
+}
+
+// The limit and the handler are global, so the cases must not run in parallel.
+#[test]
+fn pressure() {
+    ralloc::set_limit(ralloc::peak().extent + (64 << 20));
+
+    CACHE.store(ralloc::alloc(SIZE, 8) as usize, Ordering::SeqCst);
+    ralloc::set_pressure_handler(Some(drop_cache));
+
+    // The allocation only fits, when the cache is dropped.
+    let ptr = ralloc::try_alloc(SIZE, 8).unwrap();
+    assert_eq!(CACHE.load(Ordering::SeqCst), 0);
+
+    unsafe {
+        ralloc::free(ptr, SIZE);
+    }
+    ralloc::set_pressure_handler(None);
+    ralloc::set_limit(!0);
+}
@@ -0,0 +1,30 @@
+extern crate ralloc;
+
+use std::thread;
+
+#[test]
+fn deadline_thread() {
+    let mut arena = ralloc::RealtimeArena::reserve(1 << 16);
+
+    thread::spawn(move || {
+        let mut bufs = Vec::with_capacity(64);
+        for i in 0..64 {
+            let ptr = arena.alloc(i * 8 + 1, 8).unwrap();
+            unsafe {
+                *ptr = i as u8;
+            }
+            bufs.push((ptr, i * 8 + 1));
+        }
+
+        for (i, &(ptr, size)) in bufs.iter().enumerate() {
+            unsafe {
+                assert_eq!(*ptr, i as u8);
+                arena.free(ptr, size, 8);
+            }
+        }
+        assert_eq!(arena.in_use(), 0);
+
+        // Running dry fails rather than growing.
+        assert!(arena.alloc(1 << 17, 8).is_none());
+    }).join().unwrap();
+}
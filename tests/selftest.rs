@@ -0,0 +1,20 @@
+#![cfg(feature = "selftest")]
+
+extern crate ralloc;
+
+mod util;
+
+use ralloc::SelfTestConfig;
+
+#[test]
+fn selftest() {
+    util::multiply(|| {
+        let config = SelfTestConfig {
+            rounds: 5000,
+            check_interval: 500,
+            ..SelfTestConfig::new()
+        };
+
+        assert!(ralloc::selftest(&config).is_ok());
+    });
+}
@@ -0,0 +1,66 @@
+extern crate ralloc;
+
+use std::fs::File;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+use ralloc::SharedArena;
+
+#[test]
+fn shared() {
+    let a = SharedArena::create(1 << 20).unwrap();
+
+    // Open the arena a second time, as another process would.
+    let b = unsafe {
+        let file = File::from_raw_fd(a.fd() as i32);
+        let dup = file.try_clone().unwrap().into_raw_fd();
+        file.into_raw_fd();
+
+        SharedArena::open(dup as usize).unwrap()
+    };
+
+    let x = a.alloc(100, 8).unwrap();
+    let y = b.alloc(200, 64).unwrap();
+    assert!(x != y);
+    assert_eq!(0, b.ptr(y) as usize % 64);
+
+    unsafe {
+        // The mappings share the memory.
+        *a.ptr(x) = 42;
+        assert_eq!(*b.ptr(x), 42);
+
+        // Freed memory is merged and reused.
+        b.free(x, 100);
+        a.free(y, 200);
+        assert_eq!(a.alloc(100, 8), Some(x));
+    }
+}
+
+#[test]
+fn persistent() {
+    let path = std::env::temp_dir().join("ralloc-persistent-test");
+    let mut cpath = path.to_str().unwrap().as_bytes().to_vec();
+    cpath.push(0);
+
+    let x = unsafe {
+        let arena = SharedArena::open_file(&cpath, 1 << 20).unwrap();
+        let x = arena.alloc(100, 8).unwrap();
+        *arena.ptr(x) = 42;
+        arena.set_root(x);
+        arena.sync().unwrap();
+
+        x
+    };
+
+    unsafe {
+        // The allocation and the root survive reopening.
+        let arena = SharedArena::open_file(&cpath, 1 << 20).unwrap();
+        assert_eq!(arena.root(), Some(x));
+        assert_eq!(*arena.ptr(x), 42);
+
+        // The allocation is still taken.
+        assert!(arena.alloc(100, 8) != Some(x));
+        arena.free(x, 100);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,57 @@
+extern crate ralloc;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn split_merge() {
+    util::multiply(|| {
+        let buf = ralloc::alloc(96, 8);
+
+        unsafe {
+            util::acid(|| {
+                ptr::write_bytes(buf, 3, 96);
+            });
+
+            let (a, b) = ralloc::split_alloc(buf, 96, 32).unwrap();
+            assert_eq!(a, (buf, 32));
+            assert_eq!(b, (buf.offset(32), 64));
+
+            // Split the second part once more.
+            let (b, c) = ralloc::split_alloc(b.0, b.1, 32).unwrap();
+
+            // Only adjacent buffers are merged.
+            assert_eq!(ralloc::merge_allocs(a, c), Err(()));
+            assert_eq!(ralloc::merge_allocs(b, a), Err(()));
+
+            let ab = ralloc::merge_allocs(a, b).unwrap();
+            assert_eq!(ab, (buf, 64));
+
+            util::acid(|| {
+                assert_eq!(*buf.offset(63), 3);
+            });
+
+            ralloc::free(c.0, c.1);
+            ralloc::free(ab.0, ab.1);
+        }
+    });
+}
+
+#[test]
+fn split_unallocated() {
+    let mut arr = [0u8; 128];
+    let buf = ralloc::alloc(256, 8);
+
+    unsafe {
+        // Foreign buffers were never handed out.
+        assert_eq!(ralloc::split_alloc(arr.as_mut_ptr(), 128, 64), Err(()));
+        assert!(ralloc::advise(arr.as_mut_ptr(), 128, ralloc::Advice::Normal).is_err());
+
+        // Neither were buffers given back to the global pool.
+        ralloc::free(buf, 256);
+        ralloc::flush_thread_cache();
+        assert_eq!(ralloc::split_alloc(buf, 256, 128), Err(()));
+        assert_eq!(ralloc::merge_allocs((buf, 128), (buf.offset(128), 128)), Err(()));
+    }
+}
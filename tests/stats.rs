@@ -0,0 +1,33 @@
+extern crate ralloc;
+
+#[test]
+fn diff() {
+    let before = ralloc::Stats::snapshot();
+
+    let a = ralloc::alloc(100, 8);
+    let b = ralloc::alloc(5000, 8);
+    let c = ralloc::alloc(5000, 8);
+    unsafe {
+        ralloc::free(c, 5000);
+    }
+
+    let after = ralloc::Stats::snapshot();
+    let delta = before.diff(&after);
+
+    assert!(delta.in_use >= 5100);
+    assert!(delta.allocs >= 3);
+    assert!(delta.frees >= 1);
+    // 100 bytes are in class 6, and 5000 bytes in class 12.
+    assert!(delta.live[6] >= 1);
+    assert!(delta.live[12] >= 1);
+    assert_eq!(delta.live.iter().sum::<isize>(), delta.live_allocations);
+
+    unsafe {
+        ralloc::free(a, 100);
+        ralloc::free(b, 5000);
+    }
+
+    let delta = after.diff(&ralloc::Stats::snapshot());
+    assert!(delta.in_use <= -5100);
+    assert!(delta.live[12] <= -1);
+}
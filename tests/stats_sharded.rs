@@ -0,0 +1,27 @@
+#![cfg(feature = "stats")]
+
+extern crate ralloc;
+
+use std::thread;
+
+#[test]
+fn summed_over_threads() {
+    let before = ralloc::Stats::snapshot();
+
+    let threads: Vec<_> = (0..16).map(|_| thread::spawn(|| {
+        for _ in 0..100 {
+            let ptr = ralloc::alloc(300, 8);
+            unsafe {
+                ralloc::free(ptr, 300);
+            }
+        }
+    })).collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let delta = before.diff(&ralloc::Stats::snapshot());
+    assert!(delta.allocs >= 1600);
+    assert!(delta.frees >= 1600);
+}
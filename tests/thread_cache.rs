@@ -0,0 +1,80 @@
+extern crate ralloc;
+
+mod util;
+
+use std::thread;
+
+#[test]
+fn flush() {
+    util::multiply(|| {
+        let ptrs: Vec<_> = (1..100).map(|i| (ralloc::alloc(i * 10, 8), i * 10)).collect();
+        for (ptr, size) in ptrs {
+            unsafe {
+                ralloc::free(ptr, size);
+            }
+        }
+
+        ralloc::flush_thread_cache();
+        ralloc::check();
+
+        // The allocator is still usable.
+        let ptr = ralloc::alloc(100, 8);
+        unsafe {
+            ralloc::free(ptr, 100);
+        }
+    });
+}
+
+#[test]
+fn flush_at_exit() {
+    util::multiply(|| {
+        thread::spawn(|| {
+            let ptr = ralloc::alloc(1000, 8);
+            unsafe {
+                ralloc::free(ptr, 1000);
+            }
+        }).join().unwrap();
+
+        ralloc::check();
+    });
+}
+
+#[test]
+fn limits() {
+    ralloc::set_thread_cache_limits(4096, 16);
+
+    util::multiply(|| {
+        let ptrs: Vec<_> = (1..200).map(|i| (ralloc::alloc(i * 7, 8), i * 7)).collect();
+        // Free every other buffer first, so the cache holds many blocks.
+        for (_, &(ptr, size)) in ptrs.iter().enumerate().filter(|&(i, _)| i % 2 == 0) {
+            unsafe {
+                ralloc::free(ptr, size);
+            }
+        }
+        ralloc::check();
+
+        for (_, &(ptr, size)) in ptrs.iter().enumerate().filter(|&(i, _)| i % 2 != 0) {
+            unsafe {
+                ralloc::free(ptr, size);
+            }
+        }
+        ralloc::check();
+    });
+}
+
+#[test]
+fn disable() {
+    thread::spawn(|| {
+        ralloc::disable_thread_cache();
+
+        let mut vec = Vec::new();
+        for i in 0..1000 {
+            util::acid(|| {
+                vec.push(i);
+            });
+        }
+        assert_eq!(vec.iter().sum::<usize>(), 499500);
+
+        ralloc::check();
+    }).join().unwrap();
+}
@@ -0,0 +1,37 @@
+extern crate ralloc;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+
+#[test]
+fn timeline() {
+    let path = env::temp_dir().join("ralloc-timeline-test");
+    let file = File::create(&path).unwrap();
+
+    ralloc::record_timeline(file.as_raw_fd() as usize);
+
+    let a = ralloc::alloc(100, 8);
+    unsafe {
+        let a = ralloc::realloc(a, 100, 200, 8);
+        ralloc::free(a, 200);
+    }
+
+    ralloc::stop_timeline();
+    drop(file);
+
+    let mut timeline = String::new();
+    File::open(&path).unwrap().read_to_string(&mut timeline).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(timeline.starts_with("[\n"));
+    assert!(timeline.contains("\"name\":\"alloc\""));
+    assert!(timeline.contains("\"name\":\"realloc\""));
+    assert!(timeline.contains("\"name\":\"free\""));
+    assert!(timeline.contains("\"ph\":\"C\""));
+    // Every event is a line of its own.
+    for line in timeline.lines().skip(1) {
+        assert!(line.starts_with('{') && line.ends_with("},"));
+    }
+}
@@ -0,0 +1,66 @@
+extern crate ralloc;
+
+mod util;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+
+fn record_and_replay() {
+    let path = env::temp_dir().join("ralloc-trace-test");
+    let file = File::create(&path).unwrap();
+
+    ralloc::record_trace(file.as_raw_fd() as usize);
+
+    let a = ralloc::alloc(100, 8);
+    let b = ralloc::alloc(2000, 64);
+    unsafe {
+        let b = ralloc::realloc(b, 2000, 4000, 64);
+        ralloc::free(a, 100);
+        ralloc::free(b, 4000);
+    }
+    let _leaked = ralloc::alloc(300, 16);
+
+    ralloc::set_hook(None);
+    drop(file);
+
+    let mut trace = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut trace).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(ralloc::replay_trace(&trace).is_ok());
+    ralloc::check();
+
+    // Malformed traces are rejected.
+    assert!(ralloc::replay_trace(b"RATR\x07").is_err());
+    assert!(ralloc::replay_trace(b"nope").is_err());
+}
+
+fn hook() {
+    fn count(event: ralloc::Event) {
+        if let ralloc::Event::Alloc { size: 12345, .. } = event {
+            // Allocating in the hook is not reported.
+            let ptr = ralloc::alloc(12345, 1);
+            unsafe {
+                ralloc::free(ptr, 12345);
+            }
+        }
+    }
+
+    util::multiply(|| {
+        ralloc::set_hook(Some(count));
+        let ptr = ralloc::alloc(12345, 1);
+        unsafe {
+            ralloc::free(ptr, 12345);
+        }
+        ralloc::set_hook(None);
+    });
+}
+
+// The hook is global, so the tests must not run in parallel.
+#[test]
+fn trace() {
+    record_and_replay();
+    hook();
+}
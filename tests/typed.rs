@@ -0,0 +1,47 @@
+#![feature(unique)]
+
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn one() {
+    let ptr = ralloc::alloc_one::<u64>();
+    assert_eq!(0, ptr.as_ptr() as usize % 8);
+
+    unsafe {
+        util::acid(|| {
+            *ptr.as_ptr() = 42;
+        });
+        assert_eq!(*ptr.as_ptr(), 42);
+
+        ralloc::dealloc_one(ptr);
+    }
+}
+
+#[test]
+fn array() {
+    let ptr = ralloc::alloc_array::<u32>(1000);
+
+    unsafe {
+        let arr = &mut *ptr.as_ptr();
+        assert_eq!(arr.len(), 1000);
+
+        for (i, x) in arr.iter_mut().enumerate() {
+            *x = i as u32;
+        }
+        assert_eq!(arr[999], 999);
+
+        ralloc::dealloc_array(ptr);
+    }
+}
+
+#[test]
+fn zero_sized() {
+    let ptr = ralloc::alloc_array::<()>(!0);
+
+    unsafe {
+        assert_eq!((*ptr.as_ptr()).len(), !0);
+        ralloc::dealloc_array(ptr);
+    }
+}
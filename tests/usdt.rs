@@ -0,0 +1,15 @@
+#![cfg(feature = "usdt")]
+
+extern crate ralloc;
+
+#[test]
+fn probes() {
+    // Every probe is passed, with nothing attached.
+    unsafe {
+        let ptr = ralloc::alloc(100, 8);
+        let ptr = ralloc::realloc(ptr, 100, 100000, 8);
+        ralloc::free(ptr, 100000);
+    }
+
+    ralloc::housekeep();
+}
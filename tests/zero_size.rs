@@ -0,0 +1,29 @@
+extern crate ralloc;
+
+mod util;
+
+#[test]
+fn zero_size() {
+    util::multiply(|| {
+        let ptr = ralloc::alloc(0, 8);
+
+        // Zero-sized allocations are dangling, but aligned.
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % 8);
+
+        unsafe {
+            let ptr = ralloc::realloc(ptr, 0, 16, 8);
+            util::acid(|| {
+                *ptr.offset(15) = 42;
+            });
+            assert_eq!(*ptr.offset(15), 42);
+
+            let ptr = ralloc::realloc(ptr, 16, 0, 8);
+            assert!(!ptr.is_null());
+            assert!(ralloc::realloc_inplace(ptr, 0, 0).is_ok());
+            assert!(ralloc::realloc_inplace(ptr, 0, 1).is_err());
+
+            ralloc::free(ptr, 0);
+        }
+    });
+}